@@ -1,19 +1,75 @@
 //! Module for the [App] struct and surrounding utilities.
 
-mod task;
+pub(crate) mod conformance;
+mod group;
+mod handle;
+pub(crate) mod panic;
+pub(crate) mod reload;
+pub(crate) mod reply;
+mod report;
+mod running;
+mod signal;
+pub(crate) mod task;
+mod topology;
 
-use std::sync::Arc;
+pub use group::HandlerGroup;
+pub use handle::AppHandle;
+pub use report::{BindingReport, DryRunReport, HandlerReport, StartupReport};
+pub use running::RunningApp;
+pub use signal::ShutdownSignal;
+pub use topology::{HandlerTopology, Topology};
 
-use futures::{future::try_join_all, stream::FuturesUnordered, StreamExt};
-use lapin::{self, Connection, ConnectionProperties};
-use metrics::describe_gauge;
-#[cfg(unix)]
-use tokio::signal::unix::{signal, SignalKind};
-use tokio::{sync::broadcast, task::JoinHandle};
+use std::{error::Error as StdError, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use futures::{stream, StreamExt};
+use lapin::{
+    self,
+    message::Delivery,
+    options::ExchangeDeclareOptions,
+    types::{AMQPValue, FieldTable},
+    uri::AMQPUri,
+    Connection, ExchangeKind,
+};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, Unit};
+use rand::Rng;
+use tokio::sync::{broadcast, watch, Notify};
 use tracing::{debug, error, info, trace, warn};
 
-use self::task::TaskFactory;
-use crate::{Error, Handler, HandlerConfig, Respond, Result};
+use self::reload::ReloadHook;
+use self::signal::SignalListener;
+use self::task::{MemoryBudget, TaskContext, TaskFactory};
+#[cfg(feature = "wire-debug")]
+use crate::wire_debug::WireDebug;
+use crate::{
+    audit::AuditSink,
+    canary::Canary,
+    context::{Instrumentation, RequestContext, RequestFuture},
+    control,
+    error::{ErrorRedaction, ExtractErrorHook, ExtractFailure},
+    extract::{ChannelPool, Properties, ReqIdPolicy},
+    handler_config::RedeliveryBackoff,
+    identity::ConnectionIdentity,
+    middleware::{ErasedMiddleware, Middleware},
+    probe,
+    reply_dedup::ReplyDedupStore,
+    reply_store::ReplyStore,
+    schema::SchemaRegistry,
+    shadow::{ShadowSampler, ShadowTarget},
+    stats::{MetricsReply, RequestStats},
+    Error, Handler, HandlerConfig, KaninConfig, KaninConnectionOptions, Respond, Result,
+};
+
+/// A future that initializes the state of an app. See [`App::try_new`].
+type StateFuture<S> =
+    Pin<Box<dyn Future<Output = std::result::Result<S, Box<dyn StdError + Send + Sync>>> + Send>>;
+
+/// The state of an app, which may still need to be initialized. See [`App::try_new`].
+enum StateInit<S> {
+    /// The state is ready.
+    Ready(S),
+    /// The state will be initialized by the future when the app runs.
+    Pending(StateFuture<S>),
+}
 
 /// The central struct of your application.
 #[must_use = "The app will not do anything unless you call `.run`."]
@@ -24,30 +80,137 @@ pub struct App<S> {
     /// This is used to hold the state values that users may want to store before running the app,
     /// and then extract in their handlers. Types that wish to be extracted via `State<T>` must
     /// implement `From<&S>`.
-    state: S,
+    state: StateInit<S>,
     /// Shutdown channel. Used to indicate that we should start graceful shutdown.
     /// The channel has capacity 1 as we only need to signal once to shutdown.
     /// Missing messages on the channel doesn't matter.
     shutdown: broadcast::Sender<()>,
+    /// Called to reload configuration while the app runs. See [`App::on_reload`].
+    reload_hook: Option<ReloadHook>,
+    /// Notified to request a reload, e.g. by a control message. See [`App::on_reload`].
+    reload: Arc<Notify>,
+    /// Formats error details sent back to callers. See [`App::with_error_redaction`].
+    error_redaction: Option<ErrorRedaction>,
+    /// The maximum number of handlers that are set up concurrently. See [`App::with_startup_concurrency`].
+    startup_concurrency: Option<usize>,
+    /// The maximum random delay before setting up each handler. See [`App::with_startup_stagger`].
+    startup_stagger: Option<Duration>,
+    /// The report of what was set up when the app started. See [`App::startup_report`].
+    startup_report: watch::Sender<Option<StartupReport>>,
+    /// Overrides for the configuration of handlers. See [`App::with_config_overlay`].
+    config_overlay: Option<KaninConfig>,
+    /// Handle for controlling the app while it runs. See [`App::handle`].
+    handle: AppHandle,
+    /// Determines how request IDs are read and generated. See [`App::with_req_id_policy`].
+    req_id_policy: ReqIdPolicy,
+    /// Generates new request IDs instead of random UUIDs, if set. See [`App::with_req_id_generator`].
+    req_id_generator: Option<fn() -> AMQPValue>,
+    /// Selects requests to publish shadow copies of. See [`App::with_shadow`].
+    shadow: Option<ShadowSampler>,
+    /// Logs the properties and payloads of messages. See [`App::with_wire_debug`].
+    #[cfg(feature = "wire-debug")]
+    wire_debug: Option<Arc<WireDebug>>,
+    /// Validates the schemas of incoming messages. See [`App::with_schema_registry`].
+    schema_registry: Option<Arc<dyn SchemaRegistry>>,
+    /// Records the outcome of every request. See [`App::with_audit`].
+    audit: Option<Arc<dyn AuditSink>>,
+    /// Pauses consumption while a dependency is unhealthy. See [`App::with_health_gate`].
+    health_gate: Option<watch::Receiver<bool>>,
+    /// Keeps replies that could not be published for re-publishing. See [`App::with_reply_store`].
+    reply_store: Option<Arc<dyn ReplyStore>>,
+    /// Skips duplicate replies to redelivered requests. See [`App::with_reply_dedup`].
+    reply_dedup: Option<Arc<dyn ReplyDedupStore>>,
+    /// Called when extraction fails. See [`App::on_extract_error`].
+    on_extract_error: Option<ExtractErrorHook>,
+    /// The number of channels dedicated to publishing from handlers. See [`App::with_publisher_channels`].
+    publisher_channels: Option<usize>,
+    /// Wraps the future handling each request. See [`App::with_instrumentation`].
+    instrumentation: Option<Instrumentation>,
+    /// Request statistics served by the metrics route. See [`App::with_metrics_route`].
+    stats: Option<Arc<RequestStats>>,
+    /// How the app identifies itself towards the broker. See [`App::with_connection_identity`].
+    connection_identity: Option<ConnectionIdentity>,
+    /// Options for the connection created by [`App::run`]. See [`App::with_connection_options`].
+    connection_options: KaninConnectionOptions,
+    /// Whether handlers are checked when they are registered. See [`App::with_conformance_checks`].
+    conformance_checks: bool,
+    /// The backoff between attempts to reconnect after losing the connection. See [`App::with_reconnect`].
+    reconnect: Option<RedeliveryBackoff>,
+    /// Middleware wrapping every handler, outermost first. See [`App::layer`].
+    middleware: Vec<ErasedMiddleware>,
+    /// The maximum number of payload bytes in flight across all handlers. See [`App::with_memory_budget`].
+    memory_budget: Option<usize>,
 }
 
 impl<S: Default> Default for App<S> {
     fn default() -> Self {
-        Self {
-            handlers: Vec::default(),
-            state: S::default(),
-            shutdown: broadcast::Sender::new(1),
-        }
+        Self::with_state(StateInit::Ready(S::default()))
     }
 }
 
 impl<S> App<S> {
     /// Creates a new kanin app.
     pub fn new(state: S) -> Self {
+        Self::with_state(StateInit::Ready(state))
+    }
+
+    /// Creates a new kanin app whose state is built by the given future when the app runs.
+    ///
+    /// This is useful for state that requires async construction, such as database pools.
+    /// See [`App::try_new`] for state whose construction may fail.
+    pub fn new_with(init: impl Future<Output = S> + Send + 'static) -> Self {
+        Self::with_state(StateInit::Pending(Box::pin(async move { Ok(init.await) })))
+    }
+
+    /// Creates a new kanin app whose state is built by the given fallible future when the app runs.
+    ///
+    /// The state is built as the first step of [`App::run`] (after connecting), before any handlers are set up.
+    /// If the future fails, the app does not start and returns [`Error::StateInitialization`] instead.
+    pub fn try_new<E>(
+        init: impl Future<Output = std::result::Result<S, E>> + Send + 'static,
+    ) -> Self
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Self::with_state(StateInit::Pending(Box::pin(async move {
+            init.await.map_err(Into::into)
+        })))
+    }
+
+    /// Creates a new kanin app with the given state, which may still need to be initialized.
+    fn with_state(state: StateInit<S>) -> Self {
         Self {
             handlers: Vec::new(),
             state,
             shutdown: broadcast::Sender::new(1),
+            reload_hook: None,
+            reload: Arc::default(),
+            error_redaction: None,
+            startup_concurrency: None,
+            startup_stagger: None,
+            startup_report: watch::channel(None).0,
+            config_overlay: None,
+            handle: AppHandle::default(),
+            req_id_policy: ReqIdPolicy::default(),
+            req_id_generator: None,
+            shadow: None,
+            #[cfg(feature = "wire-debug")]
+            wire_debug: None,
+            schema_registry: None,
+            audit: None,
+            health_gate: None,
+            reply_store: None,
+            reply_dedup: None,
+            on_extract_error: None,
+            publisher_channels: None,
+            instrumentation: None,
+            stats: None,
+            connection_identity: None,
+            connection_options: KaninConnectionOptions::default(),
+            conformance_checks: cfg!(debug_assertions),
+            reconnect: None,
+            middleware: Vec::new(),
+            memory_budget: None,
         }
     }
 
@@ -56,57 +219,361 @@ impl<S> App<S> {
         self.shutdown.clone()
     }
 
+    /// Returns an [`AppHandle`] that can be used to control the app while it is running, e.g. to change the prefetch of a handler.
+    pub fn handle(&self) -> AppHandle {
+        self.handle.clone()
+    }
+
+    /// Sets the function used to format error details into the `InvalidRequest` and `InternalError` replies sent to callers.
+    ///
+    /// By default, errors are formatted in full. Use this to ensure internal details (such as connection strings or stack traces)
+    /// never leak to callers. Errors are still logged in full on the server side regardless of this setting.
+    ///
+    /// The function is used by the [`FromError`](crate::FromError) derive macro via [`kanin::error::redact`](crate::error::redact).
+    pub fn with_error_redaction(
+        mut self,
+        redaction: impl Fn(&(dyn StdError + 'static)) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.error_redaction = Some(Arc::new(redaction));
+        self
+    }
+
+    /// Sets the policy for reading request IDs from incoming requests and generating new ones.
+    ///
+    /// By default, request IDs are read from the `req_id` header and any value is accepted, while missing request IDs are generated as random UUIDs.
+    /// See [`ReqIdPolicy`] for the other options, such as using ULIDs for sortable request IDs.
+    pub fn with_req_id_policy(mut self, policy: ReqIdPolicy) -> Self {
+        self.req_id_policy = policy;
+        self
+    }
+
+    /// Generates new request IDs with the given function instead of as random UUIDs, e.g. to use snowflake IDs or embed the identity of the instance.
+    ///
+    /// The generator applies to the requests of this app that have no request ID, unless the [request ID policy](App::with_req_id_policy)
+    /// requires a specific format. kanin's client-side helpers, such as [`BatchPublisher`](crate::batch::BatchPublisher) and
+    /// [`ScatterGather`](crate::scatter_gather::ScatterGather), propagate the ID of the request being handled, so they use the generated IDs as well.
+    /// Other apps in the same process, and [`ReqId::new`](crate::extract::ReqId::new), are not affected.
+    pub fn with_req_id_generator(mut self, generator: fn() -> AMQPValue) -> Self {
+        self.req_id_generator = Some(generator);
+        self
+    }
+
+    /// Publishes shadow copies of incoming requests, for instance to test a new version of a service with real traffic.
+    ///
+    /// The given function is called for every incoming request on every handler. If it returns a [`ShadowTarget`], a copy of the request
+    /// (payload and properties) is published to the target. Return `None` for requests that should not be copied, e.g. to only copy a sample.
+    ///
+    /// The copies are published without a `reply_to` property, so the shadowing service doesn't reply to the original caller,
+    /// and with the [`ShadowTarget::HEADER`] header set. Requests to the [control queue](App::with_control_queue) are never copied.
+    ///
+    /// The copies are published in the background on a channel dedicated to shadowing, so they don't affect the handling of the request.
+    /// If publishing a copy makes the broker close that channel, e.g. because the exchange of the target does not exist,
+    /// no further copies are published until the app reconnects.
+    pub fn with_shadow(
+        mut self,
+        sampler: impl Fn(&Delivery) -> Option<ShadowTarget> + Send + Sync + 'static,
+    ) -> Self {
+        self.shadow = Some(Arc::new(sampler));
+        self
+    }
+
+    /// Logs the properties and payloads of the messages received and published by handlers at trace level.
+    ///
+    /// This is useful when debugging interop issues, e.g. with producers in other languages that set properties differently.
+    /// See the [`wire_debug`](crate::wire_debug) module for details.
+    #[cfg(feature = "wire-debug")]
+    pub fn with_wire_debug(mut self, wire_debug: WireDebug) -> Self {
+        self.wire_debug = Some(Arc::new(wire_debug));
+        self
+    }
+
+    /// Sets the schema registry used to validate the schemas of incoming messages.
+    ///
+    /// Only handlers configured with an expected schema (see [`HandlerConfig::with_expected_schema`]) validate their messages.
+    /// See the [`schema`](crate::schema) module for details.
+    pub fn with_schema_registry(mut self, registry: impl SchemaRegistry + 'static) -> Self {
+        self.schema_registry = Some(Arc::new(registry));
+        self
+    }
+
+    /// Sets the sink that audit records are recorded to.
+    ///
+    /// An [`AuditRecord`](crate::audit::AuditRecord) is recorded for every request once it has been processed,
+    /// including requests that were rejected, requeued or whose handler panicked. See the [`audit`](crate::audit) module for the available sinks.
+    pub fn with_audit(mut self, sink: impl AuditSink + 'static) -> Self {
+        self.audit = Some(Arc::new(sink));
+        self
+    }
+
+    /// Opens the given number of channels dedicated to publishing when the app starts, given out to handlers by the
+    /// [`PublisherChannel`](crate::extract::PublisherChannel) extractor.
+    ///
+    /// This keeps the channels that handlers consume on dedicated to deliveries and acks, so handlers that publish heavily
+    /// do not contend with consumption. The channels are given out round-robin and shared by all handlers.
+    /// Without this, [`PublisherChannel`](crate::extract::PublisherChannel) gives the consumer channel of the handler.
+    pub fn with_publisher_channels(mut self, count: usize) -> Self {
+        self.publisher_channels = Some(count);
+        self
+    }
+
+    /// Wraps the handling of every request in the future returned by the given function, e.g. to run it within a Sentry transaction or time it.
+    ///
+    /// The function is given the context of the request and the future handling it, which covers its whole lifecycle:
+    /// extraction, the handler itself, publishing the reply and acking the request. Unlike instrumenting the handler itself,
+    /// this also captures time spent on the surrounding work, and panics in the handler propagate through the returned future.
+    /// The returned future must await the given future, or the request is never handled.
+    ///
+    /// ```
+    /// # use kanin::App;
+    /// let app = App::new(()).with_instrumentation(|context, request| {
+    ///     let routing_key = context.routing_key.clone();
+    ///     Box::pin(async move {
+    ///         let start = std::time::Instant::now();
+    ///         request.await;
+    ///         println!("Handled request on {routing_key} in {:?}", start.elapsed());
+    ///     })
+    /// });
+    /// ```
+    pub fn with_instrumentation<F>(mut self, instrumentation: F) -> Self
+    where
+        F: Fn(&RequestContext, RequestFuture) -> RequestFuture + Send + Sync + 'static,
+    {
+        self.instrumentation = Some(Arc::new(instrumentation));
+        self
+    }
+
+    /// Wraps every handler of the app in the given [`Middleware`], including handlers registered after the app started.
+    ///
+    /// Middleware added first wraps middleware added later, and the middleware of the app wraps the middleware
+    /// of individual handlers (see [`HandlerConfig::layer`]). Unlike [`App::with_instrumentation`], middleware sees the request
+    /// before the handler is called and the encoded response before it is published, and can fail the request or change the response.
+    pub fn layer<M>(mut self, middleware: M) -> Self
+    where
+        M: Middleware<S>,
+        S: Send + Sync + 'static,
+    {
+        self.middleware.push(ErasedMiddleware::new(middleware));
+        self
+    }
+
+    /// Calls the given hook whenever a handler fails to extract one of its arguments from a request,
+    /// before the error is turned into a response via [`FromError`](crate::error::FromError) (or the request is requeued, for transient errors).
+    ///
+    /// The hook is given the routing key of the handler, the extraction error and a description of the request, including its caller.
+    /// Use this to emit structured events or alert on spikes of invalid requests per caller, without wrapping every handler.
+    /// The hook is called synchronously while handling the request, so it should be quick.
+    pub fn on_extract_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, &dyn StdError, &ExtractFailure<'_>) + Send + Sync + 'static,
+    {
+        self.on_extract_error = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a health gate that pauses consumption on all handlers while it is `false`.
+    ///
+    /// This is useful when a dependency of the app goes down, e.g. if the database connection is lost.
+    /// Rather than failing every request (and causing mass redeliveries), the app stops consuming until the gate is `true` again.
+    /// Consumers are paused by cancelling them, and any deliveries they had already received are requeued.
+    /// Requests that are already being handled are allowed to finish.
+    ///
+    /// If the gate is `false` when the app starts, the app starts paused. If the sender of the gate is dropped, the last value is kept.
+    pub fn with_health_gate(mut self, gate: watch::Receiver<bool>) -> Self {
+        self.health_gate = Some(gate);
+        self
+    }
+
+    /// Limits the total size of the payloads of the requests all handlers process concurrently, protecting apps with tight memory limits
+    /// from being killed when a burst of messages arrives on their queues.
+    ///
+    /// Once the payloads of the requests in flight add up to the budget, every handler stops receiving deliveries until some of the requests
+    /// in flight finish. Unlike [`HandlerConfig::with_in_flight_byte_budget`], this bounds the app as a whole rather than each handler.
+    /// The payload bytes in flight on each handler are recorded in the `kanin.in_flight_bytes` gauge, with or without a budget.
+    /// Only payloads are accounted for, and deliveries already prefetched by the broker are still held in memory by the client,
+    /// so leave room for both. By default, there is no budget.
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes.max(1));
+        self
+    }
+
+    /// Sets a store for replies that could not be published, e.g. because the channel was closed or the broker was down.
+    ///
+    /// Instead of being lost, such replies are kept in the store and re-published when the app starts,
+    /// and every [`REPUBLISH_INTERVAL`](crate::reply_store::REPUBLISH_INTERVAL) while it runs.
+    /// Use a store that persists replies (e.g. on local disk) to keep them across restarts of the app.
+    /// See the [`reply_store`](crate::reply_store) module for details.
+    pub fn with_reply_store(mut self, store: impl ReplyStore + 'static) -> Self {
+        self.reply_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Sets a store of the replies that were published, used to skip replying to redelivered requests that were already replied to.
+    ///
+    /// Requests are delivered at least once, so idempotent handlers (e.g. handlers that cache their results) may reply to the same request twice.
+    /// With a store set, the reply to a redelivered request is skipped if a reply with the same correlation ID was already published to its `reply_to`,
+    /// and counted in the `kanin.duplicate_replies_skipped` counter. The handler is still called.
+    /// See the [`reply_dedup`](crate::reply_dedup) module for details.
+    pub fn with_reply_dedup(mut self, store: impl ReplyDedupStore + 'static) -> Self {
+        self.reply_dedup = Some(Arc::new(store));
+        self
+    }
+
+    /// Sets how the app identifies itself towards the broker when connecting via [`App::run`].
+    ///
+    /// The identity determines the connection name and client properties shown in the RabbitMQ management UI.
+    /// Use [`connection_identity!`](crate::connection_identity) to derive it from the Cargo metadata of your crate.
+    /// By default, the identity is detected from the environment (see [`ConnectionIdentity::detect`]).
+    pub fn with_connection_identity(mut self, identity: ConnectionIdentity) -> Self {
+        self.connection_identity = Some(identity);
+        self
+    }
+
+    /// Sets the options for the connection created by [`App::run`], e.g. the executor used by lapin or the heartbeat interval.
+    ///
+    /// Has no effect if the app is run with [`App::run_with_connection`].
+    pub fn with_connection_options(mut self, options: KaninConnectionOptions) -> Self {
+        self.connection_options = options;
+        self
+    }
+
+    /// Makes [`App::run`] reconnect to the broker when the connection is lost, instead of shutting down.
+    ///
+    /// When the connection is lost (or a consumer is cancelled by the broker), the handlers are shut down gracefully,
+    /// after which the app reconnects and sets up all the handlers again, re-declaring their queues and bindings.
+    /// The app state, the [`AppHandle`] and the [shutdown channel](App::shutdown_channel) are kept across connections.
+    /// Attempts to reconnect are delayed by `base`, doubling with every failed attempt up to `max`, and randomized between half of and the full delay.
+    /// Every reconnection is counted in the `kanin.reconnects` counter.
+    ///
+    /// Errors that reconnecting won't fix (see [`Error::is_retryable`]), e.g. an invalid address or a failing state initialization, are still returned.
+    /// Has no effect if the app is run with [`App::run_with_connection`]. By default, the app shuts down when the connection is lost.
+    pub fn with_reconnect(mut self, base: Duration, max: Duration) -> Self {
+        self.reconnect = Some(RedeliveryBackoff { base, max });
+        self
+    }
+
+    /// Returns a [`tokio::sync::watch::Receiver`] that will contain the [`StartupReport`] once the app has set up all its handlers.
+    ///
+    /// The report contains every queue declared, the bindings created, the prefetch and the consumer tags of each handler.
+    /// The same report is logged when the app starts.
+    pub fn startup_report(&self) -> watch::Receiver<Option<StartupReport>> {
+        self.startup_report.subscribe()
+    }
+
+    /// Describes the exchanges, queues, bindings and handlers registered on the app, without connecting to the broker.
+    ///
+    /// The returned [`Topology`] can be rendered as a Graphviz or Mermaid diagram, e.g. to generate architecture diagrams from code.
+    /// Note that overrides from the config overlay (see [`App::with_config_overlay`]) are not reflected.
+    pub fn topology_graph(&self) -> Topology {
+        Topology {
+            handlers: self
+                .handlers
+                .iter()
+                .map(|task_factory| task_factory.topology())
+                .collect(),
+        }
+    }
+
+    /// Limits how many handlers are set up concurrently when the app starts.
+    ///
+    /// Setting up a handler involves declaring and binding its queue and creating its consumer.
+    /// By default, all handlers are set up at once. For apps with many handlers, this can cause a burst of requests to the broker,
+    /// so limiting the concurrency can smooth startup, especially on shared brokers. A limit of 0 is treated as 1.
+    pub fn with_startup_concurrency(mut self, concurrency: usize) -> Self {
+        self.startup_concurrency = Some(concurrency.max(1));
+        self
+    }
+
+    /// Delays the setup of each handler by a random duration between zero and `max_delay` when the app starts.
+    ///
+    /// This spreads the setup of handlers over time, which is useful in combination with [`App::with_startup_concurrency`]
+    /// or when many instances of the same app start at once.
+    pub fn with_startup_stagger(mut self, max_delay: Duration) -> Self {
+        self.startup_stagger = Some(max_delay);
+        self
+    }
+
+    /// Overrides the [`HandlerConfig`] of handlers with the values in the given configuration, matched by routing key.
+    ///
+    /// The overrides are applied when the app runs, so they apply regardless of whether handlers are registered before or after calling this.
+    /// This allows tuning consumption (prefetch, durability, timeouts) from a configuration file or the environment without redeploying code.
+    /// Overrides for routing keys without a handler are logged as warnings.
+    pub fn with_config_overlay(mut self, config: KaninConfig) -> Self {
+        self.config_overlay = Some(config);
+        self
+    }
+
     /// Sets up signal handling to gracefully shut down the app when
     /// this process receives termination signals from the operating system.
     ///
     /// This is a convenience function. If you want custom shutdown behavior, you can
     /// use the broadcast channel returned from the [`Self::shutdown_channel`] method.
     ///
-    /// This function sets up listeners for shutdown events. For non-Unix platforms, it uses [`tokio::signal::ctrl_c`].
-    /// For Unix platforms, it sets up listeners for SIGTERM, SIGINT and SIGHUP.
+    /// This listens for all of [`ShutdownSignal::ALL`] that exist on the current platform, i.e. SIGTERM, SIGINT and SIGHUP on Unix,
+    /// and CTRL_C, CTRL_CLOSE and CTRL_SHUTDOWN on Windows. Use [`App::graceful_shutdown_on_signals`] to listen for other signals.
     ///
-    /// # Panics
-    /// The background listening task spawned by this function will panic on Unix if it fails to setup any of the signal listeners.
-    /// In this case, signals will not be listened to and graceful shutdown will not start if signals are sent to the process.
+    /// If any of the signals cannot be listened for, the error is logged and no signals are listened for,
+    /// so graceful shutdown will not start if signals are sent to the process.
     pub fn graceful_shutdown_on_signal(self) -> Self {
-        let shutdown = self.shutdown_channel();
-        tokio::spawn(async move {
-            #[cfg(not(unix))]
-            {
-                // This should cover ctrl-c in most platforms.
-                let signal = tokio::signal::ctrl_c().await;
-
-                if let Err(e) = signal {
-                    error!("Failed to listen for ctrl-c: {e}")
-                }
-
-                info!("Received ctrl-c. Attempting to gracefully shut down...");
+        match SignalListener::new(ShutdownSignal::ALL) {
+            Ok(listener) => {
+                tokio::spawn(listener.shutdown_on_signal(self.shutdown_channel()));
             }
+            Err((signal, e)) => error!("Failed to listen for {signal}: {e}"),
+        }
 
-            // We'll be more specific for Unix signal handling.
-            #[cfg(unix)]
-            {
-                // SIGTERM is commonly sent for graceful shutdown of applications, followed by 30 seconds of grace time, then a SIGKILL.
-                let mut sigterm =
-                    signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
-                // SIGINT is usually sent due to ctrl-c in the terminal.
-                let mut sigint =
-                    signal(SignalKind::interrupt()).expect("failed to listen for SIGINT");
-                // SIGHUP is usually sent when the terminal closes or the user logs out (for instance logs out of an SSH session).
-                let mut sighup = signal(SignalKind::hangup()).expect("failed to listen for SIGHUP");
+        self
+    }
 
-                tokio::select! {
-                    _ = sigterm.recv() => info!("Received SIGTERM. Attempting to gracefully shut down..."),
-                    _ = sigint.recv() => info!("Received SIGINT. Attempting to gracefully shut down..."),
-                    _ = sighup.recv() => info!("Received SIGHUP. Attempting to gracefully shut down..."),
-                };
-            }
+    /// Sets up signal handling to gracefully shut down the app when this process receives any of the given signals.
+    ///
+    /// Signals that do not exist on the current platform are ignored. For example, SIGHUP can be left out
+    /// in environments where it is used to signal that configuration should be reloaded:
+    ///
+    /// ```no_run
+    /// # use kanin::{App, app::ShutdownSignal};
+    /// # fn example(app: App<()>) -> kanin::Result<App<()>> {
+    /// app.graceful_shutdown_on_signals(&[ShutdownSignal::Terminate, ShutdownSignal::Interrupt])
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    /// Returns [`Error::SignalListener`] if any of the signals cannot be listened for.
+    pub fn graceful_shutdown_on_signals(self, signals: &[ShutdownSignal]) -> Result<Self> {
+        let listener = SignalListener::new(signals)
+            .map_err(|(signal, source)| Error::SignalListener { signal, source })?;
+        tokio::spawn(listener.shutdown_on_signal(self.shutdown_channel()));
 
-            if let Err(e) = shutdown.send(()) {
-                error!("Failed to send shutdown message: {e}")
-            }
-        });
+        Ok(self)
+    }
+
+    /// Calls the given callback whenever the app is asked to reload its configuration while it runs,
+    /// i.e. when the process receives SIGHUP (on Unix) or a [`control::RELOAD`] control message (see [`App::with_control_queue`]).
+    ///
+    /// The callback is given an [`AppHandle`], through which e.g. the prefetch of handlers can be changed.
+    /// Use it to re-read your configuration and apply what can be changed at runtime without restarting consumers,
+    /// such as log levels (e.g. through a reloadable `tracing` filter) and rate limits kept in your app state.
+    /// Reload requests received while the callback runs are coalesced into a single call once it finishes.
+    ///
+    /// As SIGHUP reloads the configuration, it should not also shut down the app, so use [`App::graceful_shutdown_on_signals`]
+    /// without [`ShutdownSignal::Hangup`] rather than [`App::graceful_shutdown_on_signal`].
+    pub fn on_reload<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(AppHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.reload_hook = Some(Arc::new(move |handle| Box::pin(callback(handle))));
+        self
+    }
 
+    /// Sets whether handlers are checked for mismatches between their response type and configuration when they are registered.
+    ///
+    /// The checks warn about handlers that respond with `()` while replies are enabled (see [`HandlerConfig::with_replies`]),
+    /// and about response types that reply to representative extraction errors with an empty payload (see [`FromError`](crate::error::FromError)),
+    /// as callers can't tell such failures from empty responses. Note that the checks call [`FromError::from_error`](crate::error::FromError::from_error)
+    /// on the response types, so any side effects of it, such as logging, happen at registration.
+    /// Only handlers registered after calling this are affected. By default, the checks are enabled in debug builds.
+    pub fn with_conformance_checks(mut self, enabled: bool) -> Self {
+        self.conformance_checks = enabled;
         self
     }
 
@@ -128,7 +595,7 @@ impl<S> App<S> {
     /// The handler will respond to any messages with `reply_to` and `correlation_id` properties.
     /// This requires that the response type implements Respond (which is automatically implemented for protobuf messages).
     pub fn handler_with_config<H, Args, Res>(
-        mut self,
+        self,
         routing_key: impl Into<String>,
         handler: H,
         config: HandlerConfig,
@@ -139,6 +606,32 @@ impl<S> App<S> {
         S: Send + Sync + 'static,
     {
         let routing_key = routing_key.into();
+        if self.conformance_checks {
+            for issue in conformance::check::<H, Args, Res, S>(&config) {
+                warn!(
+                    "Handler {} on routing key {routing_key:?} does not conform to its configuration: {issue}",
+                    std::any::type_name::<H>()
+                );
+            }
+        }
+
+        self.register(routing_key, handler, config)
+    }
+
+    /// Registers a new handler for the given routing key with the given queue configuration, without checking it.
+    ///
+    /// Used directly for the handlers registered by kanin itself, whose responses deliberately don't conform.
+    fn register<H, Args, Res>(
+        mut self,
+        routing_key: String,
+        handler: H,
+        config: HandlerConfig,
+    ) -> Self
+    where
+        H: Handler<Args, Res, S>,
+        Res: Respond,
+        S: Send + Sync + 'static,
+    {
         debug!(
             "Registering handler {} on routing key {routing_key:?} with config {config:?}",
             std::any::type_name::<H>()
@@ -151,17 +644,241 @@ impl<S> App<S> {
         self
     }
 
+    /// Registers a group of handlers sharing the same configuration and layers. See [`HandlerGroup`].
+    ///
+    /// ```
+    /// use kanin::{error::ServerError, App, Extract, HandlerConfig, HandlerError, Request};
+    ///
+    /// /// Fails unless the caller identified itself.
+    /// struct Authenticated;
+    ///
+    /// #[async_trait::async_trait]
+    /// impl<S: Send + Sync> Extract<S> for Authenticated {
+    ///     type Error = HandlerError;
+    ///
+    ///     async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+    ///         match req.app_id() {
+    ///             Some(_) => Ok(Self),
+    ///             None => Err(HandlerError::InternalError(ServerError::Other("Unknown caller".into()))),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// async fn create() {}
+    /// async fn delete() {}
+    ///
+    /// // Both handlers only run for authenticated callers, and share the same prefetch.
+    /// let app = App::new(()).group(|group| {
+    ///     group
+    ///         .with_config(HandlerConfig::new().with_prefetch(16))
+    ///         .with_layer::<Authenticated>()
+    ///         .handler("user.create", create)
+    ///         .handler("user.delete", delete)
+    /// });
+    /// ```
+    pub fn group<F>(mut self, group: F) -> Self
+    where
+        F: FnOnce(HandlerGroup<S>) -> HandlerGroup<S>,
+        S: Send + Sync + 'static,
+    {
+        let group = group(HandlerGroup::new());
+        debug!("Registering handler group {group:?}");
+
+        self.handlers.extend(group.into_task_factories());
+        self
+    }
+
+    /// Registers a stable and a canary handler on the given routing key, dispatching the given percentage of requests to the canary.
+    ///
+    /// Both handlers consume from the same queue, and each request is dispatched to one of them at random. See [`Canary`] for details.
+    pub fn handler_canary<Stable, StableArgs, Candidate, CandidateArgs, Res>(
+        self,
+        routing_key: impl Into<String>,
+        stable: Stable,
+        canary: Candidate,
+        canary_percent: u8,
+    ) -> Self
+    where
+        Stable: Handler<StableArgs, Res, S> + Sync,
+        Candidate: Handler<CandidateArgs, Res, S> + Sync,
+        StableArgs: 'static,
+        CandidateArgs: 'static,
+        Res: Respond,
+        S: Send + Sync + 'static,
+    {
+        self.handler(routing_key, Canary::new(stable, canary, canary_percent))
+    }
+
+    /// Registers a standardized ping handler on the given routing key.
+    ///
+    /// The ping handler replies to any request with the payload of the request.
+    /// Use [`kanin::probe::ping`](crate::probe::ping) to measure the round-trip latency to the app.
+    pub fn with_ping(self, routing_key: impl Into<String>) -> Self
+    where
+        S: Send + Sync + 'static,
+    {
+        self.register(
+            routing_key.into(),
+            probe::ping_handler,
+            HandlerConfig::default(),
+        )
+    }
+
+    /// Registers a handler on the given routing key that replies with a [`MetricsSnapshot`](crate::stats::MetricsSnapshot)
+    /// of the requests handled by the app, such as the number of requests in flight, the total number of requests and their latencies.
+    ///
+    /// This is useful where scraping metrics over HTTP isn't possible, but AMQP is.
+    /// The snapshot is encoded as protobuf, or as JSON if the request has the `application/json` content type and the `serde` feature is enabled.
+    /// Statistics are only recorded once this is called, and are kept in addition to the metrics recorded through the [`metrics`] facade.
+    pub fn with_metrics_route(mut self, routing_key: impl Into<String>) -> Self
+    where
+        S: Send + Sync + 'static,
+    {
+        let stats = self.stats.get_or_insert_with(Default::default).clone();
+
+        self.register(
+            routing_key.into(),
+            move |Properties(properties): Properties| {
+                let stats = stats.clone();
+                async move { MetricsReply::new(stats.snapshot(), &properties) }
+            },
+            HandlerConfig::default(),
+        )
+    }
+
+    /// Registers a handler for control messages on the given routing key, allowing the app to be operated over AMQP.
+    ///
+    /// Control messages must be signed with the given secret, see [`kanin::control`](crate::control) for details.
+    /// This is useful where sending OS signals to the app isn't possible, e.g. to gracefully shut down the app.
+    ///
+    /// Note that if several instances of the app share the control queue, only one of them receives each control message.
+    /// Use a routing key per instance to target specific instances.
+    pub fn with_control_queue(
+        self,
+        routing_key: impl Into<String>,
+        secret: impl Into<Vec<u8>>,
+    ) -> Self
+    where
+        S: Send + Sync + 'static,
+    {
+        let secret: Arc<[u8]> = secret.into().into();
+        let shutdown = self.shutdown_channel();
+        let reload = self.reload.clone();
+        // Control messages are signed, so they must never be copied to a shadow target.
+        let mut config = HandlerConfig::default();
+        config.shadowable = false;
+
+        self.register(
+            routing_key.into(),
+            move |message: control::ControlMessage| async move {
+                control::control_handler(message, &secret, &shutdown, &reload).await
+            },
+            config,
+        )
+    }
+
     /// Connects to AMQP with the given address and calls [`run_with_connection`][App::run_with_connection] with the resulting connection.
     /// See [`run_with_connection`][App::run_with_connection] for more details.
+    ///
+    /// If [`App::with_reconnect`] is set, the app reconnects whenever the connection is lost, until it is shut down.
     #[allow(clippy::missing_errors_doc)]
     #[inline]
     pub async fn run(self, amqp_addr: &str) -> Result<()> {
-        debug!("Connecting to AMQP on address: {amqp_addr:?} ...");
-        let conn = Connection::connect(amqp_addr, ConnectionProperties::default())
-            .await
-            .map_err(Error::Lapin)?;
-        trace!("Connected to AMQP on address: {amqp_addr:?}");
-        self.run_with_connection(&conn).await
+        let identity = self
+            .connection_identity
+            .clone()
+            .unwrap_or_else(ConnectionIdentity::detect);
+        let properties = identity.apply(self.connection_options.properties());
+        let mut uri: AMQPUri = amqp_addr.parse().map_err(Error::InvalidAddress)?;
+        self.connection_options.apply_to_uri(&mut uri);
+
+        debug!(
+            "Connecting to AMQP on address: {amqp_addr:?} as {:?} ...",
+            identity.connection_name()
+        );
+        let reconnect = match self.reconnect {
+            Some(reconnect) => reconnect,
+            None => {
+                let conn = Connection::connect_uri(uri, properties)
+                    .await
+                    .map_err(Error::from)?;
+                trace!("Connected to AMQP on address: {amqp_addr:?}");
+                return self.run_with_connection(&conn).await;
+            }
+        };
+
+        describe_metrics();
+        panic::install_hook();
+        let app = self.prepare().await?;
+        // Subscribed once, so shutdown signals sent while reconnecting are not missed.
+        let mut app_shutdown = app.shutdown.subscribe();
+        let mut failures = 0;
+        loop {
+            if failures > 0 {
+                let delay = reconnect.delay(failures);
+                info!("Reconnecting to AMQP on address: {amqp_addr:?} in {delay:?} ...");
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = app_shutdown.recv() => return Ok(()),
+                }
+            }
+
+            let conn = match Connection::connect_uri(uri.clone(), properties.clone())
+                .await
+                .map_err(Error::from)
+            {
+                Ok(conn) => conn,
+                Err(e) if e.is_retryable() => {
+                    warn!("Failed to connect to AMQP on address: {amqp_addr:?}: {e:#}");
+                    failures += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            trace!("Connected to AMQP on address: {amqp_addr:?}");
+
+            // Each connection gets its own shutdown channel, so losing the connection only shuts down the handlers set up on it.
+            let (session_shutdown, _) = broadcast::channel(1);
+            let mut shutdown_requested = false;
+            let result = match app
+                .duplicate(session_shutdown.clone())
+                .setup_handlers(&conn)
+                .await
+            {
+                Ok(running) => {
+                    failures = 0;
+                    let wait = running.wait();
+                    tokio::pin!(wait);
+                    tokio::select! {
+                        result = &mut wait => result,
+                        _ = app_shutdown.recv() => {
+                            shutdown_requested = true;
+                            if let Err(e) = session_shutdown.send(()) {
+                                warn!("Could not send shutdown signal; are all handlers shut down already? Error: {e:#}");
+                            }
+                            wait.await
+                        }
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            let connection_lost = !conn.status().connected()
+                || result.as_ref().err().map_or(false, Error::is_retryable);
+            if shutdown_requested || !connection_lost {
+                return result;
+            }
+
+            match &result {
+                Ok(()) => warn!("Lost connection to AMQP broker, reconnecting..."),
+                Err(e) => warn!("Lost connection to AMQP broker, reconnecting: {e:#}"),
+            }
+            if let Err(e) = conn.close(0, "Reconnecting").await {
+                debug!("Failed to close connection before reconnecting: {e:#}");
+            }
+            counter!("kanin.reconnects").increment(1);
+            failures += 1;
+        }
     }
 
     /// Runs the app with all the handlers that have been registered.
@@ -173,8 +890,10 @@ impl<S> App<S> {
     /// # Errors
     /// Returns an `Err` on any of the below conditions:
     /// * No handlers were registered.
+    /// * The app state could not be initialized (see [`App::try_new`]).
     /// * A connection to the AMQP broker could not be established.
-    /// * Queue/consumer declaration or binding failed while setting up a handler.
+    /// * Queue/consumer declaration or binding failed while setting up a handler (see [`Error::HandlerSetup`]).
+    ///   Handlers that were already set up are shut down in this case.
     ///
     /// On connection errors, the app will attempt to gracefully shutdown.
     ///
@@ -184,53 +903,288 @@ impl<S> App<S> {
     /// Internal panics inside kanin's code will however shut down the app. This shouldn't happen though (please report it if it does).
     #[inline]
     pub async fn run_with_connection(self, conn: &Connection) -> Result<()> {
-        // Describe metrics (just need to do it somewhere once as we run the app).
-        describe_gauge!("kanin.prefetch_capacity", "A gauge that measures how much prefetch is available on a certain queue, based on the prefetch of its consumers.");
-
-        let shutdown_channel = self.shutdown_channel();
-        let mut handles = self.setup_handlers(conn).await?;
-
-        let mut ret = Ok(());
-        while let Some(returning_handler) = handles.next().await {
-            match returning_handler {
-                Ok(Ok(())) => {
-                    // Graceful handler shutdown, do nothing.
-                    // If all goes well, all handlers will go into this branch
-                    // and eventually we'll be done.
-                }
-                Ok(Err(e)) => {
-                    // Consumer cancellation from AMQP broker.
-                    if let Err(e) = shutdown_channel.send(()) {
-                        error!("Failed to send shutdown signal to other tasks on consumer cancellation: {e}");
+        self.start(conn).await?.wait().await
+    }
+
+    /// Sets up all the handlers that have been registered, returning a [`RunningApp`] once they are consuming.
+    ///
+    /// This is the first half of [`App::run_with_connection`], which is equivalent to calling [`RunningApp::wait`] on the result.
+    /// Use it when the app needs to be interacted with after it started, e.g. to register handlers that can only be known once connected.
+    ///
+    /// # Errors
+    /// Returns an `Err` on any of the below conditions:
+    /// * No handlers were registered.
+    /// * The app state could not be initialized (see [`App::try_new`]).
+    /// * Queue/consumer declaration or binding failed while setting up a handler (see [`Error::HandlerSetup`]).
+    ///   Handlers that were already set up are shut down in this case.
+    #[inline]
+    pub async fn start(self, conn: &Connection) -> Result<RunningApp<'_, S>> {
+        describe_metrics();
+        panic::install_hook();
+        self.prepare().await?.setup_handlers(conn).await
+    }
+
+    /// Applies the config overlay (if any) to the handlers.
+    ///
+    /// Returns the routing keys the overlay contains overrides for, but that no handler is registered on.
+    fn apply_config_overlay(&mut self) -> Vec<String> {
+        let overlay = match &self.config_overlay {
+            Some(overlay) => overlay,
+            None => return Vec::new(),
+        };
+
+        for task_factory in &mut self.handlers {
+            if let Some(handler_overlay) = overlay.handlers.get(task_factory.routing_key()) {
+                let config = std::mem::take(task_factory.config_mut());
+                let config = handler_overlay.apply(config);
+                debug!(
+                    "Overriding config of handler on routing key {:?} with {config:?}",
+                    task_factory.routing_key()
+                );
+                *task_factory.config_mut() = config;
+            }
+        }
+
+        overlay
+            .handlers
+            .keys()
+            .filter(|routing_key| {
+                !self
+                    .handlers
+                    .iter()
+                    .any(|task_factory| task_factory.routing_key() == *routing_key)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Performs all the validation and config resolution that [`App::run`] does, without connecting to the broker or consuming any messages.
+    ///
+    /// This is useful in CI to detect routing and config regressions before deployment.
+    /// The config overlay (see [`App::with_config_overlay`]) is applied and the app state is initialized (see [`App::try_new`]),
+    /// after which the state is dropped again. The returned [`DryRunReport`] describes the resulting topology
+    /// along with any problems found; see [`DryRunReport::is_ok`].
+    ///
+    /// Use [`App::dry_run_with_connection`] to additionally check the topology against a broker.
+    ///
+    /// # Errors
+    /// Returns an `Err` if no handlers were registered or if the app state could not be initialized.
+    pub async fn dry_run(mut self) -> Result<DryRunReport> {
+        if self.handlers.is_empty() {
+            return Err(Error::NoHandlers);
+        }
+
+        let unknown_overlay_routing_keys = self.apply_config_overlay();
+
+        if let StateInit::Pending(init) = self.state {
+            debug!("Initializing app state...");
+            init.await.map_err(Error::StateInitialization)?;
+        }
+
+        Ok(DryRunReport {
+            topology: Topology {
+                handlers: self
+                    .handlers
+                    .iter()
+                    .map(|task_factory| task_factory.topology())
+                    .collect(),
+            },
+            unknown_overlay_routing_keys,
+            missing_exchanges: Vec::new(),
+            config_issues: self
+                .handlers
+                .iter()
+                .flat_map(|task_factory| {
+                    task_factory
+                        .config()
+                        .validate()
+                        .into_iter()
+                        .map(|issue| (task_factory.routing_key().to_string(), issue))
+                })
+                .collect(),
+        })
+    }
+
+    /// Performs a [dry run](App::dry_run) of the app, and additionally checks passively that the exchanges the handlers rely on exist on the broker.
+    ///
+    /// Nothing is declared, bound or consumed on the broker.
+    /// Exchanges that the app declares itself (i.e. [consistent hash exchanges](crate::consistent_hash)) are not checked.
+    ///
+    /// # Errors
+    /// Returns an `Err` if no handlers were registered, if the app state could not be initialized
+    /// or if a channel could not be opened on the connection.
+    pub async fn dry_run_with_connection(self, conn: &Connection) -> Result<DryRunReport> {
+        let mut exchanges: Vec<String> = self
+            .handlers
+            .iter()
+            .flat_map(|task_factory| task_factory.required_exchanges())
+            .collect();
+        exchanges.sort();
+        exchanges.dedup();
+
+        let mut report = self.dry_run().await?;
+
+        for exchange in exchanges {
+            // A failed passive declaration closes the channel, so each check gets its own channel.
+            let channel = conn.create_channel().await.map_err(Error::from)?;
+            let options = ExchangeDeclareOptions {
+                passive: true,
+                ..Default::default()
+            };
+            match channel
+                .exchange_declare(
+                    &exchange,
+                    ExchangeKind::Direct,
+                    options,
+                    FieldTable::default(),
+                )
+                .await
+            {
+                Ok(()) => {
+                    if let Err(e) = channel.close(200, "Dry run").await {
+                        warn!(
+                            "Failed to close channel after checking exchange {exchange:?}: {e:#}"
+                        );
                     }
-                    ret = Err(e);
                 }
                 Err(e) => {
-                    // Panic from kanin's own internal task handling.
-                    // This is not a panic in the downstream user-created handlers,
-                    // those don't cause an exit from the app.
-                    panic!("A kanin task panicked: {e:#}");
+                    warn!("Exchange {exchange:?} could not be found on the broker: {e:#}");
+                    report.missing_exchanges.push(exchange);
                 }
             }
         }
 
-        match &ret {
-            Ok(()) => info!("Gracefully shutdown. Goodbye."),
-            Err(e) => error!("Unexpected shutdown: {e}"),
-        }
-
-        ret
+        Ok(report)
     }
 
-    /// Set up all the handlers, returning a collection of all the join handles.
-    pub(crate) async fn setup_handlers(
-        self,
-        conn: &Connection,
-    ) -> Result<FuturesUnordered<JoinHandle<Result<()>>>> {
+    /// Prepares the parts of the app that outlive a connection, e.g. by initializing the state.
+    async fn prepare(mut self) -> Result<PreparedApp<S>> {
         if self.handlers.is_empty() {
             return Err(Error::NoHandlers);
         }
 
+        for routing_key in self.apply_config_overlay() {
+            warn!("Config overlay contains overrides for routing key {routing_key:?}, but no handler is registered on it.");
+        }
+
+        for task_factory in &self.handlers {
+            for issue in task_factory.config().validate() {
+                warn!(
+                    "Handler on routing key {:?} is misconfigured: {issue}.",
+                    task_factory.routing_key()
+                );
+            }
+        }
+
+        let state = match self.state {
+            StateInit::Ready(state) => state,
+            StateInit::Pending(init) => {
+                debug!("Initializing app state...");
+                init.await.map_err(Error::StateInitialization)?
+            }
+        };
+        let startup_concurrency = self.startup_concurrency.unwrap_or(self.handlers.len());
+        // The reload hook runs until the app shuts down, not just until a connection is lost.
+        let reload_shutdown = self.shutdown.subscribe();
+        let context = TaskContext {
+            error_redaction: self.error_redaction,
+            req_id_policy: Arc::new(self.req_id_policy.with_generator(self.req_id_generator)),
+            shadow: self.shadow,
+            #[cfg(feature = "wire-debug")]
+            wire_debug: self.wire_debug,
+            schema_registry: self.schema_registry,
+            audit: self.audit,
+            health_gate: self.health_gate,
+            reply_store: self.reply_store.clone(),
+            reply_dedup: self.reply_dedup,
+            on_extract_error: self.on_extract_error,
+            // The publisher channels are opened on each connection.
+            publisher_channels: None,
+            // Likewise the shadow channel.
+            shadow_channel: None,
+            instrumentation: self.instrumentation,
+            stats: self.stats,
+            middleware: self.middleware.into(),
+            memory_budget: self
+                .memory_budget
+                .map(|bytes| Arc::new(MemoryBudget::new(bytes))),
+        };
+
+        Ok(PreparedApp {
+            handlers: self.handlers,
+            state: Arc::new(state),
+            context,
+            publisher_channels: self.publisher_channels,
+            startup_concurrency,
+            startup_stagger: self.startup_stagger,
+            shutdown: self.shutdown,
+            handle: self.handle,
+            config_overlay: self.config_overlay,
+            startup_report: Arc::new(self.startup_report),
+            reply_store: self.reply_store,
+            reload: self
+                .reload_hook
+                .map(|hook| (hook, self.reload, reload_shutdown)),
+        })
+    }
+}
+
+/// The parts of an app that outlive a connection, from which the handlers are set up on a connection.
+///
+/// When reconnecting (see [`App::with_reconnect`]), the handlers are set up again from a duplicate of this on every connection.
+struct PreparedApp<S> {
+    /// The task factories of the handlers.
+    handlers: Vec<TaskFactory<S>>,
+    /// The state of the app, shared with the handlers.
+    state: Arc<S>,
+    /// App-wide settings that are given to every handler task.
+    context: TaskContext,
+    /// The number of publisher channels to open on the connection. See [`App::with_publisher_channels`].
+    publisher_channels: Option<usize>,
+    /// How many handlers are set up concurrently. See [`App::with_startup_concurrency`].
+    startup_concurrency: usize,
+    /// The maximum random delay before setting up each handler. See [`App::with_startup_stagger`].
+    startup_stagger: Option<Duration>,
+    /// Signals the handlers to shut down gracefully.
+    shutdown: broadcast::Sender<()>,
+    /// Handle for controlling the app.
+    handle: AppHandle,
+    /// Overrides for the configuration of handlers. See [`App::with_config_overlay`].
+    config_overlay: Option<KaninConfig>,
+    /// The report of what was set up. See [`App::startup_report`].
+    startup_report: Arc<watch::Sender<Option<StartupReport>>>,
+    /// Keeps replies that could not be published for re-publishing.
+    reply_store: Option<Arc<dyn ReplyStore>>,
+    /// The reload hook, along with the notifications of reload requests and the shutdown of the app. See [`App::on_reload`].
+    /// The hook is started once the handlers have been set up for the first time.
+    reload: Option<(ReloadHook, Arc<Notify>, broadcast::Receiver<()>)>,
+}
+
+impl<S> PreparedApp<S> {
+    /// Duplicates the app, so its handlers can be set up on a new connection, shutting down on the given channel.
+    ///
+    /// The reload hook is not duplicated, as it only runs once.
+    fn duplicate(&self, shutdown: broadcast::Sender<()>) -> Self {
+        Self {
+            handlers: self.handlers.iter().map(TaskFactory::duplicate).collect(),
+            state: self.state.clone(),
+            context: self.context.clone(),
+            publisher_channels: self.publisher_channels,
+            startup_concurrency: self.startup_concurrency,
+            startup_stagger: self.startup_stagger,
+            shutdown,
+            handle: self.handle.clone(),
+            config_overlay: self.config_overlay.clone(),
+            startup_report: self.startup_report.clone(),
+            reply_store: self.reply_store.clone(),
+            reload: None,
+        }
+    }
+
+    /// Set up all the handlers, returning the running app once they are all set up.
+    async fn setup_handlers(self, conn: &Connection) -> Result<RunningApp<'_, S>> {
+        // We subscribe to shutdown right away, so we don't miss any shutdown signals while the handlers are set up.
+        let shutdown_receiver = self.shutdown.subscribe();
         let conn_err_shutdown = self.shutdown.clone();
         // If the connection fails, we try to signal for a graceful shutdown.
         conn.on_error(move |e| {
@@ -240,23 +1194,102 @@ impl<S> App<S> {
             }
         });
 
-        let state = Arc::new(self.state);
-        let join_handles = try_join_all(self.handlers.into_iter().map(|task_factory| async {
-            debug!(
-                "Spawning handler task for routing key: {:?} ...",
-                task_factory.routing_key()
-            );
+        // Handlers set up on a previous connection can no longer be controlled.
+        self.handle.clear();
 
-            // Construct the task from the factory. This produces a pinned future which we can then spawn.
-            let task = task_factory
-                .build(conn, state.clone(), self.shutdown.subscribe())
-                .await
-                .map_err(Error::Lapin)?;
+        let state = self.state;
+        let mut context = self.context;
+        if let Some(size) = self.publisher_channels {
+            debug!("Opening {size} publisher channels...");
+            let pool = ChannelPool::open(conn, size).await.map_err(Error::from)?;
+            context.publisher_channels = Some(Arc::new(pool));
+        }
+        if context.shadow.is_some() {
+            debug!("Opening shadow channel...");
+            let pool = ChannelPool::open(conn, 1).await.map_err(Error::from)?;
+            context.shadow_channel = Some(Arc::new(pool));
+        }
+        let startup_stagger = self.startup_stagger;
+        // We subscribe to shutdown for every handler right away, so we don't miss any shutdown signals while we wait to set up the handlers.
+        // The setups below are only started as the concurrency allows, so subscribing in them would be too late.
+        let handlers: Vec<_> = self
+            .handlers
+            .into_iter()
+            .map(|task_factory| (task_factory, self.shutdown.subscribe()))
+            .collect();
+        let mut setups = stream::iter(handlers)
+            .map(|(task_factory, shutdown)| {
+                let state = state.clone();
+                let context = context.clone();
+                let handle = &self.handle;
+                async move {
+                    if let Some(max_delay) = startup_stagger {
+                        let delay = rand::thread_rng().gen_range(Duration::ZERO..=max_delay);
+                        debug!(
+                            "Delaying setup of handler on routing key {:?} by {delay:?} ...",
+                            task_factory.routing_key()
+                        );
+                        tokio::time::sleep(delay).await;
+                    }
+
+                    debug!(
+                        "Spawning handler task for routing key: {:?} ...",
+                        task_factory.routing_key()
+                    );
+
+                    // Construct the task from the factory. This produces a pinned future which we can then spawn.
+                    let routing_key = task_factory.routing_key().to_string();
+                    let queue = task_factory.queue().to_string();
+                    let (task, report) = task_factory
+                        .build(conn, state, shutdown, context, handle)
+                        .await
+                        .map_err(|source| Error::HandlerSetup {
+                            routing_key,
+                            queue,
+                            source,
+                        })?;
+
+                    // Spawn the task and save the join handle.
+                    Ok::<_, Error>((tokio::spawn(task), report))
+                }
+            })
+            .buffer_unordered(self.startup_concurrency);
+
+        let mut handlers = Vec::new();
+        while let Some(setup) = setups.next().await {
+            match setup {
+                Ok(handler) => handlers.push(handler),
+                Err(e) => {
+                    // We don't want to leave the handlers that were already set up running, so we shut them down gracefully.
+                    drop(setups);
+                    error!(
+                        "{e:#}. Shutting down {} handlers that were already set up...",
+                        handlers.len()
+                    );
+                    if !handlers.is_empty() {
+                        if let Err(e) = self.shutdown.send(()) {
+                            error!("Failed to send shutdown signal to handlers that were already set up: {e}");
+                        }
+                    }
+                    for (join_handle, _report) in handlers {
+                        match join_handle.await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => warn!("Handler exited with an error while shutting down after failed setup: {e:#}"),
+                            Err(e) => error!("Handler panicked while shutting down after failed setup: {e:#}"),
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        // The setups borrow the state and context, which are handed over to the running app below.
+        drop(setups);
 
-            // Spawn the task and save the join handle.
-            Ok(tokio::spawn(task))
-        }))
-        .await?;
+        let (join_handles, reports): (Vec<_>, Vec<_>) = handlers.into_iter().unzip();
+        let report = StartupReport { handlers: reports };
+        info!("Startup report: {report:?}");
+        self.startup_report.send_replace(Some(report));
 
         info!(
             "Connected to AMQP broker. Listening on {} handler{}.",
@@ -264,6 +1297,127 @@ impl<S> App<S> {
             if join_handles.len() == 1 { "" } else { "s" }
         );
 
-        Ok(join_handles.into_iter().collect())
+        if let Some((hook, reload, shutdown)) = self.reload {
+            tokio::spawn(reload::reload_on_request(
+                hook,
+                self.handle.clone(),
+                reload,
+                shutdown,
+            ));
+        }
+
+        Ok(RunningApp {
+            conn,
+            handles: join_handles.into_iter().collect(),
+            state,
+            context,
+            shutdown: self.shutdown,
+            shutdown_receiver,
+            handle: self.handle,
+            config_overlay: self.config_overlay,
+            startup_report: self.startup_report,
+            reply_store: self.reply_store,
+        })
     }
 }
+
+/// Describes the metrics recorded by kanin. This just needs to happen somewhere once as we run the app.
+fn describe_metrics() {
+    describe_gauge!("kanin.prefetch_capacity", "A gauge that measures how much prefetch is available on a certain queue, based on the prefetch of its consumers.");
+    describe_histogram!(
+        "kanin.shutdown_duration_seconds",
+        Unit::Seconds,
+        "The time it took the app to gracefully shut down, from the shutdown signal until all handlers finished."
+    );
+    describe_counter!(
+        "kanin.shutdown_requests_finished",
+        "The number of requests on a certain queue that were finished while draining during graceful shutdown."
+    );
+    describe_gauge!(
+        "kanin.in_flight_bytes",
+        "The total size of the payloads of the requests currently being handled on a certain routing key."
+    );
+    describe_gauge!(
+        "kanin.in_flight_requests",
+        "The number of requests currently being handled on a certain routing key."
+    );
+    describe_counter!(
+        "kanin.tasks_spawned_total",
+        "The number of tasks spawned to handle requests on a certain routing key."
+    );
+    describe_counter!(
+        "kanin.canary_requests",
+        "The number of requests dispatched to a certain handler of a canary rollout, by variant (stable or canary)."
+    );
+    describe_counter!(
+        "kanin.redeliveries",
+        "The number of redelivered messages received on a certain queue."
+    );
+    describe_counter!(
+        "kanin.redelivery_storms",
+        "The number of redelivered messages on a certain queue that were part of a redelivery storm, i.e. were redelivered too many times within a short window."
+    );
+    describe_counter!(
+        "kanin.expired_messages_skipped",
+        "The number of messages on a certain queue that were dropped without being handled because they expired in flight."
+    );
+    describe_counter!(
+        "kanin.caller_limited",
+        "The number of requests on a certain queue that were requeued because their caller had too many requests in flight."
+    );
+    describe_counter!(
+        "kanin.replies_stored",
+        "The number of replies that could not be published and were kept in the reply store."
+    );
+    describe_counter!(
+        "kanin.replies_republished",
+        "The number of stored replies that were re-published."
+    );
+    describe_counter!(
+        "kanin.replies_returned",
+        "The number of mandatory replies from the handler on a certain routing key that were returned by the broker, as they could not be delivered."
+    );
+    describe_counter!(
+        "kanin.duplicate_replies_skipped",
+        "The number of replies to redelivered requests on a certain routing key that were skipped, as the request was already replied to."
+    );
+    describe_histogram!(
+        "kanin.request_size_bytes",
+        Unit::Bytes,
+        "The size of the payloads of requests on a certain routing key."
+    );
+    describe_histogram!(
+        "kanin.response_size_bytes",
+        Unit::Bytes,
+        "The size of the payloads of responses produced by the handler on a certain routing key."
+    );
+    describe_histogram!(
+        "kanin.ack_latency_seconds",
+        Unit::Seconds,
+        "The time from receiving a request on a certain routing key until it was acknowledged or rejected."
+    );
+    describe_counter!(
+        "kanin.large_messages",
+        "The number of requests or responses on a certain routing key with payloads exceeding the handler's large message threshold."
+    );
+    describe_histogram!(
+        "kanin.batch_size",
+        "The number of messages in each batch published by a batch publisher."
+    );
+    describe_counter!(
+        "kanin.schema_checks",
+        "The number of schema checks of messages on a certain queue, by outcome."
+    );
+    describe_counter!(
+        "kanin.shadow_requests",
+        "The number of shadow copies of requests on a certain queue that were published."
+    );
+    describe_counter!(
+        "kanin.shutdown_requests_aborted",
+        "The number of requests on a certain queue that were aborted (requeued or panicked) while draining during graceful shutdown."
+    );
+    describe_counter!(
+        "kanin.reconnects",
+        "The number of times the app reconnected to the AMQP broker after losing the connection."
+    );
+}
@@ -0,0 +1,67 @@
+//! Checks of handlers when they are registered. See [`App::with_conformance_checks`](crate::App::with_conformance_checks).
+
+use std::any::type_name;
+
+use prost::DecodeError;
+use thiserror::Error as ThisError;
+
+use crate::{
+    error::{RequestError, ServerError},
+    Handler, HandlerConfig, HandlerError, Respond,
+};
+
+/// A mismatch between a handler's response type and its configuration.
+#[derive(Debug, Clone, PartialEq, Eq, ThisError)]
+pub(crate) enum ConformanceIssue {
+    /// The handler responds with `()`, but replies are enabled, so callers only ever receive empty replies.
+    #[error("The handler responds with `()` but replies are enabled, so callers receive empty replies. Use `HandlerConfig::with_replies(false)` if the handler is not meant to reply")]
+    UnitResponseWithReplies,
+    /// The response type responds to an error with an empty payload, so callers can't tell the failure from an empty response.
+    #[error("The response type {response} replies with an empty payload to the error \"{error}\", so callers can't tell the failure from an empty response")]
+    EmptyErrorResponse {
+        /// The name of the response type.
+        response: &'static str,
+        /// The error that was responded to.
+        error: String,
+    },
+}
+
+/// Returns representative errors that handlers may respond to, e.g. when they fail to extract their arguments.
+fn representative_errors() -> Vec<HandlerError> {
+    vec![
+        HandlerError::InvalidRequest(RequestError::DecodeError(DecodeError::new(
+            "representative decode error",
+        ))),
+        HandlerError::InternalError(ServerError::Other("representative internal error".into())),
+    ]
+}
+
+/// Checks that the response type of the handler `H` matches the given configuration.
+pub(crate) fn check<H, Args, Res, S>(config: &HandlerConfig) -> Vec<ConformanceIssue>
+where
+    H: Handler<Args, Res, S>,
+    Res: Respond,
+{
+    // Handlers that don't reply can respond with anything.
+    if !config.should_reply {
+        return Vec::new();
+    }
+
+    if type_name::<Res>() == type_name::<()>() {
+        return vec![ConformanceIssue::UnitResponseWithReplies];
+    }
+
+    representative_errors()
+        .into_iter()
+        .filter_map(|error| {
+            let description = error.to_string();
+            let payload = H::error_response(error)?.respond();
+            payload
+                .is_empty()
+                .then_some(ConformanceIssue::EmptyErrorResponse {
+                    response: type_name::<Res>(),
+                    error: description,
+                })
+        })
+        .collect()
+}
@@ -0,0 +1,183 @@
+//! Groups of handlers sharing configuration and layers. See [`App::group`](crate::App::group).
+
+use std::{any::type_name, fmt, future::Future, pin::Pin, sync::Arc};
+
+use async_trait::async_trait;
+use tracing::{error, warn};
+
+use super::task::TaskFactory;
+use crate::{error::FromError, Extract, Handler, HandlerConfig, HandlerError, Request, Respond};
+
+/// The future running a layer on a request.
+type LayerFuture<'a> = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send + 'a>>;
+
+/// Runs before the handlers of a group, failing the request if it returns an error. See [`HandlerGroup::with_layer`].
+type Layer<S> = Arc<dyn for<'a> Fn(&'a mut Request<S>) -> LayerFuture<'a> + Send + Sync>;
+
+/// Creates the task factory of a handler in a group, given the configuration and layers of the group.
+type GroupHandler<S> = Box<dyn FnOnce(String, HandlerConfig, Arc<[Layer<S>]>) -> TaskFactory<S>>;
+
+/// A group of handlers sharing the same configuration and layers, registered with [`App::group`](crate::App::group).
+///
+/// Layers are extractors that run before each handler in the group, in the order they were added.
+/// If a layer fails to extract, the handler is not called and the error is responded with, just as if the handler itself had failed to extract it.
+/// This makes extractors such as authentication checks apply to related routes declaratively, rather than repeating them as arguments of every handler.
+///
+/// The configuration and layers apply to all handlers of the group, regardless of the order they are added in.
+pub struct HandlerGroup<S> {
+    /// The configuration of the handlers.
+    config: HandlerConfig,
+    /// The layers run before each handler.
+    layers: Vec<Layer<S>>,
+    /// The handlers, along with their routing keys.
+    handlers: Vec<(String, GroupHandler<S>)>,
+}
+
+impl<S> HandlerGroup<S> {
+    /// Creates an empty group with the default configuration.
+    pub(super) fn new() -> Self {
+        Self {
+            config: HandlerConfig::default(),
+            layers: Vec::new(),
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Sets the configuration of the handlers in the group.
+    pub fn with_config(mut self, config: HandlerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds a layer that extracts `E` before calling each handler in the group. The extracted value is discarded.
+    ///
+    /// If the extraction fails, the handler is not called, and the request is responded to with the error
+    /// (or requeued, if the error is transient; see [`Extract::is_transient`]).
+    pub fn with_layer<E>(mut self) -> Self
+    where
+        E: Extract<S> + Send + 'static,
+        E::Error: Into<HandlerError>,
+        S: Send + Sync + 'static,
+    {
+        self.layers.push(Arc::new(extract_layer::<E, S>));
+        self
+    }
+
+    /// Adds a handler for the given routing key to the group.
+    pub fn handler<H, Args, Res>(mut self, routing_key: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<Args, Res, S> + Sync,
+        Args: 'static,
+        Res: Respond + FromError<HandlerError>,
+        S: Send + Sync + 'static,
+    {
+        self.handlers.push((
+            routing_key.into(),
+            Box::new(move |routing_key, config, layers| {
+                TaskFactory::new(routing_key, Layered { handler, layers }, config)
+            }),
+        ));
+        self
+    }
+
+    /// Creates the task factories of the handlers in the group.
+    pub(super) fn into_task_factories(self) -> impl Iterator<Item = TaskFactory<S>> {
+        let config = self.config;
+        let layers: Arc<[Layer<S>]> = self.layers.into();
+        self.handlers
+            .into_iter()
+            .map(move |(routing_key, handler)| handler(routing_key, config.clone(), layers.clone()))
+    }
+}
+
+impl<S> fmt::Debug for HandlerGroup<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandlerGroup")
+            .field("config", &self.config)
+            .field("layers", &self.layers.len())
+            .field(
+                "routing_keys",
+                &self
+                    .handlers
+                    .iter()
+                    .map(|(routing_key, _)| routing_key)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// Extracts `E` from the request, as a layer.
+fn extract_layer<E, S>(req: &mut Request<S>) -> LayerFuture<'_>
+where
+    E: Extract<S> + Send + 'static,
+    E::Error: Into<HandlerError>,
+    S: Send + Sync + 'static,
+{
+    Box::pin(async move {
+        match req.extract_with_timeout::<E>().await {
+            Ok(Ok(_)) => Ok(()),
+            // Transient errors are requeued, unless the request was already acked (e.g. by extracting an acker).
+            Ok(Err(error)) if !req.acked && E::is_transient(&error) => {
+                req.report_extract_error::<E>(&error, true);
+                warn!(
+                    "Transient failure to extract layer {}, requeueing request: {error}",
+                    type_name::<E>()
+                );
+                req.requeued = true;
+                Err(error.into())
+            }
+            Ok(Err(error)) => {
+                req.report_extract_error::<E>(&error, false);
+                error!("Failed to extract layer {}: {error}", type_name::<E>());
+                Err(error.into())
+            }
+            // The extractor timed out.
+            Err(error) => {
+                req.report_extract_error::<E>(&error, false);
+                error!("Failed to extract layer {}: {error}", type_name::<E>());
+                Err(error)
+            }
+        }
+    })
+}
+
+/// A handler in a group, which runs the layers of the group before calling the handler.
+struct Layered<H, S> {
+    /// The handler.
+    handler: H,
+    /// The layers of the group.
+    layers: Arc<[Layer<S>]>,
+}
+
+impl<H: Clone, S> Clone for Layered<H, S> {
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            layers: self.layers.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<H, Args, Res, S> Handler<Args, Res, S> for Layered<H, S>
+where
+    H: Handler<Args, Res, S> + Sync,
+    Args: 'static,
+    Res: Respond + FromError<HandlerError>,
+    S: Send + Sync + 'static,
+{
+    async fn call(self, req: &mut Request<S>) -> Res {
+        for layer in self.layers.iter() {
+            if let Err(error) = layer(req).await {
+                return Res::from_error(error);
+            }
+        }
+
+        self.handler.call(req).await
+    }
+
+    fn error_response(error: HandlerError) -> Option<Res> {
+        Some(Res::from_error(error))
+    }
+}
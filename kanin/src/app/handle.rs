@@ -0,0 +1,114 @@
+//! A handle for controlling a running app.
+
+use std::sync::{
+    atomic::{AtomicU16, Ordering},
+    Arc, Mutex,
+};
+
+use lapin::{options::BasicQosOptions, Channel};
+use metrics::gauge;
+use tokio::sync::Notify;
+use tracing::info;
+
+use crate::{Error, Result};
+
+/// The prefetch of a running handler, shared between its handler task and the [`AppHandle`].
+#[derive(Debug)]
+pub(crate) struct Prefetch {
+    /// The current prefetch.
+    pub(crate) current: AtomicU16,
+    /// Notified when the prefetch changed, so the handler task can consume again with the new prefetch.
+    pub(crate) changed: Notify,
+}
+
+impl Prefetch {
+    /// Creates the shared prefetch of a handler with the given initial prefetch.
+    pub(crate) fn new(prefetch: u16) -> Self {
+        Self {
+            current: AtomicU16::new(prefetch),
+            changed: Notify::new(),
+        }
+    }
+}
+
+/// The parts of a running handler that can be controlled through an [`AppHandle`].
+#[derive(Clone, Debug)]
+pub(crate) struct HandlerControl {
+    /// The routing key of the handler.
+    pub(crate) routing_key: String,
+    /// The queue the handler consumes from.
+    pub(crate) queue: String,
+    /// The dedicated channel of the handler.
+    pub(crate) channel: Channel,
+    /// The current prefetch of the handler, shared with the handler task.
+    pub(crate) prefetch: Arc<Prefetch>,
+}
+
+/// A handle that allows controlling the app while it is running. Obtained via [`App::handle`](crate::App::handle).
+///
+/// Handlers become controllable once they are set up, i.e. once the app has started.
+#[derive(Clone, Debug, Default)]
+pub struct AppHandle {
+    /// Controls for each of the handlers that have been set up.
+    handlers: Arc<Mutex<Vec<HandlerControl>>>,
+}
+
+impl AppHandle {
+    /// Registers the given handler as controllable through this handle.
+    pub(crate) fn register(&self, control: HandlerControl) {
+        self.handlers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(control);
+    }
+
+    /// Forgets all handlers, e.g. because their channels were closed along with the connection they were set up on.
+    pub(crate) fn clear(&self) {
+        self.handlers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+    }
+
+    /// Changes the prefetch of the handler(s) on the given routing key while the app is running.
+    ///
+    /// This re-issues `basic.qos` on the dedicated channel of the handler and updates the `kanin.prefetch_capacity` gauge accordingly.
+    /// As the broker only applies a new prefetch to new consumers, the handler then cancels its consumer and consumes again.
+    /// Deliveries the old consumer had received but not yet handled are requeued in the process.
+    /// Use this to throttle or open up consumption during incidents. The change lasts until the app shuts down.
+    ///
+    /// # Errors
+    /// Returns [`Error::NoSuchHandler`] if no handler has been set up on the given routing key,
+    /// and [`Error::Protocol`] or [`Error::Connection`] if the broker rejects the new prefetch.
+    pub async fn set_prefetch(&self, routing_key: &str, prefetch: u16) -> Result<()> {
+        let controls: Vec<_> = self
+            .handlers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .filter(|control| control.routing_key == routing_key)
+            .cloned()
+            .collect();
+
+        if controls.is_empty() {
+            return Err(Error::NoSuchHandler(routing_key.to_string()));
+        }
+
+        for control in controls {
+            control
+                .channel
+                .basic_qos(prefetch, BasicQosOptions::default())
+                .await
+                .map_err(Error::from)?;
+
+            let previous = control.prefetch.current.swap(prefetch, Ordering::Relaxed);
+            control.prefetch.changed.notify_one();
+            let difference = f64::from(prefetch) - f64::from(previous);
+            gauge!("kanin.prefetch_capacity", "queue" => control.queue).increment(difference);
+
+            info!("Changed prefetch of handler on routing key {routing_key:?} from {previous} to {prefetch}.");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+//! Capturing the message and backtrace of panics in request tasks.
+
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::RefCell,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use futures::FutureExt;
+use tracing::error;
+
+tokio::task_local! {
+    /// The backtrace of the latest panic in the current request task, captured by the panic hook.
+    static PANIC_BACKTRACE: RefCell<Option<Backtrace>>;
+}
+
+/// Installs a panic hook that captures backtraces of panics in request tasks. The hook is only installed once.
+///
+/// The previously installed hook is still called for every panic, so this does not change how panics are reported elsewhere.
+pub(crate) fn install_hook() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // Outside of request tasks, there is nowhere to put the backtrace, so it is not captured.
+            let _outside_request_task = PANIC_BACKTRACE.try_with(|backtrace| {
+                if let Ok(mut backtrace) = backtrace.try_borrow_mut() {
+                    *backtrace = Some(Backtrace::force_capture());
+                }
+            });
+            previous(info);
+        }));
+    });
+}
+
+/// Runs the given request task, logging the message and backtrace of the panic if it panics.
+///
+/// The panic is resumed afterwards, so the task still ends in a panic as before.
+pub(crate) async fn capture<F>(handler: &str, task: F) -> F::Output
+where
+    F: Future,
+{
+    PANIC_BACKTRACE
+        .scope(RefCell::new(None), async move {
+            match catch(handler, task).await {
+                Ok(output) => output,
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        })
+        .await
+}
+
+/// Runs the given future, logging the message and backtrace of the panic if it panics, and returning the panic's payload.
+///
+/// The backtrace is only available within a request task run by [`capture`].
+pub(crate) async fn catch<F>(handler: &str, task: F) -> Result<F::Output, Box<dyn Any + Send>>
+where
+    F: Future,
+{
+    AssertUnwindSafe(task)
+        .catch_unwind()
+        .await
+        .map_err(|payload| {
+            let backtrace = PANIC_BACKTRACE
+                .try_with(|backtrace| backtrace.take())
+                .ok()
+                .flatten();
+            let message = panic_message(payload.as_ref());
+            match backtrace {
+                Some(backtrace) => {
+                    error!("Handler {handler} panicked: {message}\nBacktrace:\n{backtrace}")
+                }
+                None => error!("Handler {handler} panicked: {message}"),
+            }
+            payload
+        })
+}
+
+/// Returns the message of the panic with the given payload, if it is a string as it is for `panic!` with a message.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "<non-string panic payload>"
+    }
+}
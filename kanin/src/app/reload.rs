@@ -0,0 +1,80 @@
+//! Reloading the configuration of a running app on SIGHUP or a control message. See [`App::on_reload`](crate::App::on_reload).
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use tokio::sync::{broadcast, Notify};
+use tracing::{debug, info};
+
+use super::AppHandle;
+
+/// A callback that is called whenever the app is asked to reload its configuration.
+pub(crate) type ReloadHook =
+    Arc<dyn Fn(AppHandle) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Calls the hook whenever a reload is requested, until the app shuts down.
+///
+/// Reloads are requested by SIGHUP on Unix and by notifying `requests`, e.g. from a control message.
+/// Requests received while the hook runs are coalesced into a single call once it finishes.
+pub(crate) async fn reload_on_request(
+    hook: ReloadHook,
+    handle: AppHandle,
+    requests: Arc<Notify>,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let mut hangup = listen_for_hangup();
+
+    loop {
+        let trigger = tokio::select! {
+            _ = shutdown.recv() => break,
+            _ = requests.notified() => "control message",
+            _ = recv_hangup(&mut hangup) => "SIGHUP",
+        };
+
+        info!("Reloading configuration on {trigger}...");
+        hook(handle.clone()).await;
+        debug!("Reloaded configuration.");
+    }
+}
+
+/// Listens for SIGHUP, if possible.
+#[cfg(unix)]
+type Hangup = Option<tokio::signal::unix::Signal>;
+
+/// SIGHUP does not exist outside of Unix.
+#[cfg(not(unix))]
+type Hangup = ();
+
+/// Starts listening for SIGHUP. Failing to do so is logged, in which case reloads can only be requested via control messages.
+#[cfg(unix)]
+fn listen_for_hangup() -> Hangup {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            tracing::error!("Failed to listen for SIGHUP, configuration can only be reloaded via control messages: {e}");
+            None
+        }
+    }
+}
+
+/// SIGHUP does not exist outside of Unix.
+#[cfg(not(unix))]
+fn listen_for_hangup() -> Hangup {}
+
+/// Waits for SIGHUP. Never completes if SIGHUP is not listened for.
+#[cfg(unix)]
+async fn recv_hangup(hangup: &mut Hangup) {
+    if let Some(hangup) = hangup {
+        if hangup.recv().await.is_some() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// SIGHUP does not exist outside of Unix, so this never completes.
+#[cfg(not(unix))]
+async fn recv_hangup(_hangup: &mut Hangup) {
+    std::future::pending().await
+}
@@ -0,0 +1,279 @@
+//! Publishing the replies of handlers.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use lapin::{
+    options::BasicPublishOptions,
+    publisher_confirm::Confirmation,
+    types::{FieldTable, ShortString},
+    BasicProperties, Channel,
+};
+use metrics::counter;
+use tracing::{debug, error, info, warn, Level};
+
+#[cfg(feature = "gzip")]
+use crate::compression;
+#[cfg(feature = "wire-debug")]
+use crate::wire_debug::WireDebug;
+use crate::{
+    error::ReplyError,
+    handler_config::{ReplyHook, ReplyResult},
+    reply_dedup::ReplyDedupStore,
+    reply_store::{ReplyStore, StoredReply},
+    HandlerConfig, Request,
+};
+
+/// The settings the replies of a handler are published with, shared by all of its requests.
+#[derive(Clone)]
+pub(crate) struct ReplySettings {
+    /// The routing key of the handler, used in metrics.
+    pub(crate) routing_key: String,
+    /// The default time-to-live of replies. See [`HandlerConfig::with_reply_ttl`].
+    pub(crate) reply_ttl: Option<Duration>,
+    /// Called with the result of publishing each reply. See [`HandlerConfig::on_reply_result`].
+    pub(crate) on_reply_result: Option<ReplyHook>,
+    /// The options replies are published with. See [`HandlerConfig::with_reply_publish_options`].
+    pub(crate) publish_options: BasicPublishOptions,
+    /// The level of the per-request logs of the handler. See [`HandlerConfig::with_log_level`].
+    pub(crate) log_level: Level,
+    /// Keeps replies that could not be published. See [`App::with_reply_store`](crate::App::with_reply_store).
+    pub(crate) reply_store: Option<Arc<dyn ReplyStore>>,
+    /// Remembers the requests that were replied to. See [`App::with_reply_dedup`](crate::App::with_reply_dedup).
+    pub(crate) reply_dedup: Option<Arc<dyn ReplyDedupStore>>,
+}
+
+impl ReplySettings {
+    /// Creates the reply settings of the handler on the given routing key with the given config, using the given stores of the app.
+    pub(crate) fn new(
+        routing_key: &str,
+        config: &HandlerConfig,
+        reply_store: Option<Arc<dyn ReplyStore>>,
+        reply_dedup: Option<Arc<dyn ReplyDedupStore>>,
+    ) -> Self {
+        Self {
+            routing_key: routing_key.to_string(),
+            reply_ttl: config.reply_ttl,
+            on_reply_result: config.on_reply_result.clone(),
+            publish_options: config.reply_publish_options,
+            log_level: config.log_level,
+            reply_store,
+            reply_dedup,
+        }
+    }
+}
+
+impl fmt::Debug for ReplySettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplySettings")
+            .field("routing_key", &self.routing_key)
+            .field("reply_ttl", &self.reply_ttl)
+            .field("on_reply_result", &self.on_reply_result.is_some())
+            .field("publish_options", &self.publish_options)
+            .field("log_level", &self.log_level)
+            .field("reply_store", &self.reply_store.is_some())
+            .field("reply_dedup", &self.reply_dedup.is_some())
+            .finish()
+    }
+}
+
+impl ReplySettings {
+    /// Returns true if the given request was redelivered and the caller was already sent a reply to it.
+    /// See [`App::with_reply_dedup`](crate::App::with_reply_dedup).
+    pub(crate) fn already_replied(&self, target: &ReplyTarget) -> bool {
+        match (&self.reply_dedup, &target.correlation_id) {
+            (Some(reply_dedup), Some(correlation_id)) => {
+                target.redelivered
+                    && reply_dedup.contains(target.reply_to.as_str(), correlation_id.as_str())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Where the reply to a request is published, along with the properties of the request that affect the reply.
+#[derive(Debug, Clone)]
+pub(crate) struct ReplyTarget {
+    /// The exchange to publish the reply to. See [`HandlerConfig::with_reply_mode`].
+    pub(crate) exchange: ShortString,
+    /// The routing key to publish the reply with. This is usually the `reply_to` property of the request.
+    pub(crate) reply_to: ShortString,
+    /// The correlation ID of the request, if it had one.
+    pub(crate) correlation_id: Option<ShortString>,
+    /// Whether the request was redelivered.
+    pub(crate) redelivered: bool,
+    /// Whether the caller accepts replies compressed with gzip. See [`kanin::compression`](crate::compression).
+    #[cfg(feature = "gzip")]
+    pub(crate) accepts_gzip: bool,
+    /// Logs the reply, if wire debugging is enabled for the handler. See [`App::with_wire_debug`](crate::App::with_wire_debug).
+    #[cfg(feature = "wire-debug")]
+    pub(crate) wire_debug: Option<Arc<WireDebug>>,
+}
+
+impl ReplyTarget {
+    /// Returns the target of the reply to the given request, if the request can be replied to.
+    pub(crate) fn of_request<S>(req: &Request<S>) -> Option<Self> {
+        let (exchange, reply_to) = req.reply_target()?;
+
+        Some(Self {
+            exchange,
+            reply_to,
+            correlation_id: req.properties().correlation_id().clone(),
+            redelivered: req.delivery().redelivered,
+            #[cfg(feature = "gzip")]
+            accepts_gzip: compression::accepts_gzip(req.properties()),
+            #[cfg(feature = "wire-debug")]
+            wire_debug: req.wire_debug.clone(),
+        })
+    }
+}
+
+/// An encoded reply, ready to be published.
+#[derive(Debug)]
+pub(crate) struct Reply {
+    /// The encoded response.
+    pub(crate) payload: Vec<u8>,
+    /// The headers of the reply.
+    pub(crate) headers: FieldTable,
+    /// The content type of the reply. See [`Respond::content_type`](crate::Respond::content_type).
+    pub(crate) content_type: ShortString,
+    /// The time-to-live of the reply given by the response, if any. The default of the handler is used otherwise.
+    pub(crate) reply_ttl: Option<Duration>,
+}
+
+/// Publishes the given reply to the given target on the given channel, with the given settings of the handler.
+///
+/// This is how both the responses of handlers and deferred replies (see [`ReplyHandle`](crate::extract::ReplyHandle)) are published:
+/// the reply is compressed if the caller accepts it, published with the reply publish options of the handler,
+/// kept in the reply store if publishing fails, and the result is given to the reply result hook of the handler.
+pub(crate) async fn publish(
+    settings: &ReplySettings,
+    channel: &Channel,
+    target: &ReplyTarget,
+    reply: Reply,
+) -> Result<(), ReplyError> {
+    let reply_to = &target.reply_to;
+    let mut props = BasicProperties::default();
+
+    if let Some(correlation_id) = &target.correlation_id {
+        props = props.with_correlation_id(correlation_id.clone());
+    }
+
+    if let Some(reply_ttl) = reply.reply_ttl.or(settings.reply_ttl) {
+        props = props.with_expiration(reply_ttl.as_millis().to_string().into());
+    }
+
+    if !reply.headers.inner().is_empty() {
+        props = props.with_headers(reply.headers);
+    }
+
+    // Responses are encoded protobuf (octet-stream) unless the response says otherwise.
+    props = props.with_content_type(reply.content_type);
+
+    let payload = reply.payload;
+    // The reply is compressed if the caller accepts it, and sent uncompressed if compression fails.
+    #[cfg(feature = "gzip")]
+    let payload = if !payload.is_empty() && target.accepts_gzip {
+        match compression::gzip(&payload) {
+            Ok(compressed) => {
+                debug!(
+                    "Compressed reply from {} to {} bytes with gzip.",
+                    payload.len(),
+                    compressed.len()
+                );
+                props = props.with_content_encoding(ShortString::from(compression::GZIP));
+                compressed
+            }
+            Err(e) => {
+                warn!("Failed to compress reply with gzip, publishing it uncompressed: {e:#}");
+                payload
+            }
+        }
+    } else {
+        payload
+    };
+
+    #[cfg(feature = "wire-debug")]
+    if let Some(wire_debug) = &target.wire_debug {
+        wire_debug.log_publish(
+            &settings.routing_key,
+            target.exchange.as_str(),
+            reply_to.as_str(),
+            &props,
+            &payload,
+        );
+    }
+
+    let publish_options = settings.publish_options;
+    let publish = channel
+        .basic_publish(
+            target.exchange.as_str(),
+            reply_to.as_str(),
+            publish_options,
+            &payload,
+            props.clone(),
+        )
+        .await;
+
+    // With the mandatory flag, the channel is in confirm mode, and the broker returns the reply before confirming it if it can't be delivered.
+    let publish = match publish {
+        Ok(confirm) if publish_options.mandatory => confirm.await,
+        Ok(_confirm) => Ok(Confirmation::NotRequested),
+        Err(e) => Err(e),
+    };
+
+    let result = match publish {
+        Ok(Confirmation::Ack(Some(returned)) | Confirmation::Nack(Some(returned))) => {
+            warn!(
+                "Reply to routing key \"{reply_to}\" was returned by the broker ({}): {}",
+                returned.reply_code, returned.reply_text
+            );
+            counter!("kanin.replies_returned", "routing_key" => settings.routing_key.clone())
+                .increment(1);
+            Err(ReplyError::Returned {
+                reply_code: returned.reply_code,
+                reply_text: returned.reply_text.to_string(),
+            })
+        }
+        Ok(Confirmation::Nack(None)) => {
+            error!("Reply to routing key \"{reply_to}\" was not confirmed by the broker.");
+            Err(ReplyError::NotConfirmed)
+        }
+        Ok(_confirmation) => {
+            debug!("Successfully published reply to routing key \"{reply_to}\"");
+            if let (Some(reply_dedup), Some(correlation_id)) =
+                (&settings.reply_dedup, &target.correlation_id)
+            {
+                reply_dedup.insert(reply_to.as_str(), correlation_id.as_str());
+            }
+            Ok(())
+        }
+        // We tried to reply but somehow our response never got published.
+        // We'll log an error in this case. Panicking probably doesn't help much.
+        Err(e) => {
+            error!("Error when publishing reply to routing key \"{reply_to}\": {e:#}");
+            // If we have a reply store, the reply is kept so it can be re-published later.
+            if let Some(reply_store) = &settings.reply_store {
+                info!("Storing reply to routing key \"{reply_to}\" for re-publishing.");
+                counter!("kanin.replies_stored").increment(1);
+                reply_store.store(StoredReply {
+                    exchange: target.exchange.to_string(),
+                    routing_key: reply_to.to_string(),
+                    properties: props,
+                    payload,
+                });
+            }
+            Err(ReplyError::Publish(e))
+        }
+    };
+
+    let reply_result = ReplyResult {
+        routing_key: reply_to.to_string(),
+        correlation_id: target.correlation_id.as_ref().map(|id| id.to_string()),
+        result,
+    };
+    if let Some(on_reply_result) = &settings.on_reply_result {
+        on_reply_result(&reply_result);
+    }
+
+    reply_result.result
+}
@@ -0,0 +1,88 @@
+//! Structured reports about the topology set up by the app.
+
+use std::time::Duration;
+
+use lapin::types::FieldTable;
+
+use super::Topology;
+use crate::handler_config::ConfigIssue;
+
+/// A summary of everything that was set up when the app started.
+///
+/// The report is logged once all handlers are set up, and can be retrieved programmatically via [`App::startup_report`](crate::App::startup_report).
+/// With the `serde` feature enabled, the report can be serialized (e.g. to JSON), making it easy to diff topology across deployments.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct StartupReport {
+    /// Reports for each of the handlers of the app.
+    pub handlers: Vec<HandlerReport>,
+}
+
+/// A summary of what was set up for a single handler.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct HandlerReport {
+    /// The type name of the handler.
+    pub handler: String,
+    /// The routing key the handler was registered with.
+    pub routing_key: String,
+    /// The name of the queue that was declared.
+    pub queue: String,
+    /// Whether the queue was declared as durable.
+    pub durable: bool,
+    /// Whether the queue was declared as auto-delete.
+    pub auto_delete: bool,
+    /// Whether the queue was declared as exclusive.
+    pub exclusive: bool,
+    /// The arguments (aka. x-arguments) the queue was declared with.
+    pub arguments: FieldTable,
+    /// The bindings that were created for the queue.
+    pub bindings: Vec<BindingReport>,
+    /// The prefetch of the handler's channel.
+    pub prefetch: u16,
+    /// The tag of the consumer that was created on the queue.
+    ///
+    /// If the handler has a start delay, this is the tag the consumer will be created with once the delay has passed.
+    pub consumer_tag: String,
+    /// How long after startup the handler starts consuming. See [`HandlerConfig::with_start_delay`](crate::HandlerConfig::with_start_delay).
+    pub start_delay: Option<Duration>,
+}
+
+/// A binding of a queue to an exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct BindingReport {
+    /// The exchange the queue was bound to.
+    pub exchange: String,
+    /// The routing key the queue was bound with.
+    pub routing_key: String,
+}
+
+/// The outcome of a dry run of the app. See [`App::dry_run`](crate::App::dry_run).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct DryRunReport {
+    /// The topology the app would set up, with overrides from the config overlay applied.
+    pub topology: Topology,
+    /// Routing keys that the config overlay contains overrides for, but that no handler is registered on.
+    pub unknown_overlay_routing_keys: Vec<String>,
+    /// Exchanges that the handlers rely on, but that do not exist on the broker.
+    ///
+    /// Only checked by [`App::dry_run_with_connection`](crate::App::dry_run_with_connection); always empty otherwise.
+    pub missing_exchanges: Vec<String>,
+    /// Contradictory handler configurations, along with the routing key of the handler. See [`HandlerConfig::validate`](crate::HandlerConfig::validate).
+    pub config_issues: Vec<(String, ConfigIssue)>,
+}
+
+impl DryRunReport {
+    /// Returns true if the dry run found no problems.
+    pub fn is_ok(&self) -> bool {
+        self.unknown_overlay_routing_keys.is_empty()
+            && self.missing_exchanges.is_empty()
+            && self.config_issues.is_empty()
+    }
+}
@@ -0,0 +1,213 @@
+//! An app that has been started, but is not yet waited on.
+
+use std::{sync::Arc, time::Instant};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use lapin::Connection;
+use metrics::histogram;
+use tokio::{
+    sync::{broadcast, watch},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
+
+use super::{task::TaskContext, task::TaskFactory, AppHandle, StartupReport};
+use crate::{
+    reply_store::{self, ReplyStore},
+    Error, Handler, HandlerConfig, KaninConfig, Respond, Result,
+};
+
+/// An app whose handlers have been set up and are consuming. Obtained via [`App::start`](crate::App::start).
+///
+/// Unlike [`App`](crate::App), which is only used to configure the app before it starts,
+/// this exposes what can be done with a running app: registering additional handlers
+/// (e.g. handlers that depend on information only available once connected) and controlling the app via [`RunningApp::handle`].
+/// Call [`RunningApp::wait`] to wait for the app to shut down.
+/// If this is dropped instead, the handlers keep running in the background until the app is shut down through the shutdown channel.
+#[must_use = "Call `.wait` to wait for the app to shut down."]
+pub struct RunningApp<'conn, S> {
+    /// The connection the handlers consume on.
+    pub(super) conn: &'conn Connection,
+    /// The join handles of the handler tasks.
+    pub(super) handles: FuturesUnordered<JoinHandle<Result<()>>>,
+    /// The state of the app, shared with the handlers.
+    pub(super) state: Arc<S>,
+    /// App-wide settings that are given to every handler task.
+    pub(super) context: TaskContext,
+    /// Shutdown channel. See [`App::shutdown_channel`](crate::App::shutdown_channel).
+    pub(super) shutdown: broadcast::Sender<()>,
+    /// Receives the shutdown signal. Subscribed before the handlers were set up, so no signals are missed.
+    pub(super) shutdown_receiver: broadcast::Receiver<()>,
+    /// Handle for controlling the app.
+    pub(super) handle: AppHandle,
+    /// Overrides for the configuration of handlers, also applied to handlers registered after the app started.
+    pub(super) config_overlay: Option<KaninConfig>,
+    /// The report of what has been set up.
+    pub(super) startup_report: Arc<watch::Sender<Option<StartupReport>>>,
+    /// Keeps replies that could not be published for re-publishing.
+    pub(super) reply_store: Option<Arc<dyn ReplyStore>>,
+}
+
+impl<S> RunningApp<'_, S> {
+    /// Returns an [`AppHandle`] that can be used to control the app, e.g. to change the prefetch of a handler.
+    pub fn handle(&self) -> AppHandle {
+        self.handle.clone()
+    }
+
+    /// Returns a [`tokio::sync::broadcast::Sender`]. If you send a message on this channel, the app will gracefully shut down.
+    pub fn shutdown_channel(&self) -> broadcast::Sender<()> {
+        self.shutdown.clone()
+    }
+
+    /// Sets up a new handler for the given routing key while the app is running. See [`App::handler`](crate::App::handler).
+    ///
+    /// # Errors
+    /// Returns [`Error::HandlerSetup`] if the queue, binding or consumer could not be set up. The rest of the app keeps running in this case.
+    pub async fn handler<H, Args, Res>(
+        &mut self,
+        routing_key: impl Into<String>,
+        handler: H,
+    ) -> Result<()>
+    where
+        H: Handler<Args, Res, S>,
+        Res: Respond,
+        S: Send + Sync + 'static,
+    {
+        self.handler_with_config(routing_key, handler, Default::default())
+            .await
+    }
+
+    /// Sets up a new handler for the given routing key with the given queue configuration while the app is running.
+    /// See [`App::handler_with_config`](crate::App::handler_with_config).
+    ///
+    /// Overrides from the config overlay (see [`App::with_config_overlay`](crate::App::with_config_overlay)) are applied to the handler as well.
+    ///
+    /// # Errors
+    /// Returns [`Error::HandlerSetup`] if the queue, binding or consumer could not be set up. The rest of the app keeps running in this case.
+    pub async fn handler_with_config<H, Args, Res>(
+        &mut self,
+        routing_key: impl Into<String>,
+        handler: H,
+        config: HandlerConfig,
+    ) -> Result<()>
+    where
+        H: Handler<Args, Res, S>,
+        Res: Respond,
+        S: Send + Sync + 'static,
+    {
+        let routing_key = routing_key.into();
+        let config = match self
+            .config_overlay
+            .as_ref()
+            .and_then(|overlay| overlay.handlers.get(&routing_key))
+        {
+            Some(handler_overlay) => handler_overlay.apply(config),
+            None => config,
+        };
+
+        debug!(
+            "Setting up handler {} on running app on routing key {routing_key:?} with config {config:?}",
+            std::any::type_name::<H>()
+        );
+
+        for issue in config.validate() {
+            warn!("Handler on routing key {routing_key:?} is misconfigured: {issue}.");
+        }
+
+        let task_factory = TaskFactory::new(routing_key.clone(), handler, config);
+        let queue = task_factory.queue().to_string();
+        let (task, report) = task_factory
+            .build(
+                self.conn,
+                self.state.clone(),
+                self.shutdown.subscribe(),
+                self.context.clone(),
+                &self.handle,
+            )
+            .await
+            .map_err(|source| Error::HandlerSetup {
+                routing_key,
+                queue,
+                source,
+            })?;
+
+        self.handles.push(tokio::spawn(task));
+        info!("Set up handler on running app: {report:?}");
+        self.startup_report.send_modify(|startup_report| {
+            startup_report
+                .get_or_insert_with(StartupReport::default)
+                .handlers
+                .push(report);
+        });
+
+        Ok(())
+    }
+
+    /// Waits for the app to shut down, e.g. after a signal on the [shutdown channel](RunningApp::shutdown_channel).
+    ///
+    /// # Errors
+    /// Returns an `Err` if a consumer was cancelled by the broker, in which case the rest of the app is shut down gracefully.
+    ///
+    /// # Panics
+    /// Panics in your handlers does not cause the app to shutdown. Requests will be rejected in this case.
+    ///
+    /// Internal panics inside kanin's code will however shut down the app. This shouldn't happen though (please report it if it does).
+    pub async fn wait(mut self) -> Result<()> {
+        // Replies that could not be published are re-published at startup and then periodically.
+        let mut republish = tokio::time::interval(reply_store::REPUBLISH_INTERVAL);
+
+        let mut ret = Ok(());
+        let mut shutdown_started = None;
+        loop {
+            let returning_handler = tokio::select! {
+                // Note when the shutdown started, so we can measure how long it takes.
+                _ = self.shutdown_receiver.recv(), if shutdown_started.is_none() => {
+                    shutdown_started = Some(Instant::now());
+                    continue;
+                }
+                returning_handler = self.handles.next() => match returning_handler {
+                    Some(returning_handler) => returning_handler,
+                    None => break,
+                },
+                _ = republish.tick(), if self.reply_store.is_some() => {
+                    if let Some(store) = &self.reply_store {
+                        reply_store::republish(store.as_ref(), self.conn).await;
+                    }
+                    continue;
+                }
+            };
+
+            match returning_handler {
+                Ok(Ok(())) => {
+                    // Graceful handler shutdown, do nothing.
+                    // If all goes well, all handlers will go into this branch
+                    // and eventually we'll be done.
+                }
+                Ok(Err(e)) => {
+                    // Consumer cancellation from AMQP broker.
+                    if let Err(e) = self.shutdown.send(()) {
+                        error!("Failed to send shutdown signal to other tasks on consumer cancellation: {e}");
+                    }
+                    ret = Err(e);
+                }
+                Err(e) => {
+                    // Panic from kanin's own internal task handling.
+                    // This is not a panic in the downstream user-created handlers,
+                    // those don't cause an exit from the app.
+                    panic!("A kanin task panicked: {e:#}");
+                }
+            }
+        }
+
+        if let Some(shutdown_started) = shutdown_started {
+            histogram!("kanin.shutdown_duration_seconds").record(shutdown_started.elapsed());
+        }
+
+        match &ret {
+            Ok(()) => info!("Gracefully shutdown. Goodbye."),
+            Err(e) => error!("Unexpected shutdown: {e}"),
+        }
+
+        ret
+    }
+}
@@ -0,0 +1,158 @@
+//! Listening for operating system signals to gracefully shut down the app.
+
+use std::{fmt, future::Future, io, pin::Pin};
+
+use futures::future::select_all;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info};
+
+/// An operating system signal that can start a graceful shutdown of the app. See [`App::graceful_shutdown_on_signals`](crate::App::graceful_shutdown_on_signals).
+///
+/// Signals that do not exist on the current platform are ignored, so the same set of signals can be used on all platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ShutdownSignal {
+    /// `SIGTERM` on Unix. This is commonly sent for graceful shutdown of applications, followed by some grace time, then a `SIGKILL`.
+    Terminate,
+    /// `SIGINT` on Unix and `CTRL_C` on Windows. This is usually sent due to ctrl-c in the terminal.
+    Interrupt,
+    /// `SIGHUP` on Unix. This is usually sent when the terminal closes or the user logs out (for instance logs out of an SSH session).
+    ///
+    /// Some environments use this to signal that configuration should be reloaded instead, in which case it should be left out.
+    Hangup,
+    /// `CTRL_CLOSE` on Windows, sent when the console window is closed.
+    ///
+    /// Windows terminates the process shortly after this is received, so there is only little time for the shutdown.
+    CtrlClose,
+    /// `CTRL_SHUTDOWN` on Windows, sent when the system shuts down. This is only received by services.
+    CtrlShutdown,
+}
+
+impl ShutdownSignal {
+    /// All the signals, which are listened for by [`App::graceful_shutdown_on_signal`](crate::App::graceful_shutdown_on_signal).
+    pub const ALL: &'static [ShutdownSignal] = &[
+        ShutdownSignal::Terminate,
+        ShutdownSignal::Interrupt,
+        ShutdownSignal::Hangup,
+        ShutdownSignal::CtrlClose,
+        ShutdownSignal::CtrlShutdown,
+    ];
+}
+
+impl fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ShutdownSignal::Terminate => "SIGTERM",
+            #[cfg(windows)]
+            ShutdownSignal::Interrupt => "CTRL_C",
+            #[cfg(not(windows))]
+            ShutdownSignal::Interrupt => "SIGINT",
+            ShutdownSignal::Hangup => "SIGHUP",
+            ShutdownSignal::CtrlClose => "CTRL_CLOSE",
+            ShutdownSignal::CtrlShutdown => "CTRL_SHUTDOWN",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A future that completes when the given signal is received.
+type SignalFuture = Pin<Box<dyn Future<Output = ShutdownSignal> + Send>>;
+
+/// Listens for a set of signals.
+///
+/// The listeners are registered when this is created, so any errors are returned right away
+/// and signals received before [`SignalListener::shutdown_on_signal`] is polled are not missed.
+pub(super) struct SignalListener {
+    /// The signals that are listened for. Signals that do not exist on the current platform are left out.
+    signals: Vec<SignalFuture>,
+}
+
+impl SignalListener {
+    /// Starts listening for the given signals.
+    ///
+    /// Returns the signal that could not be listened for and the reason on failure.
+    pub(super) fn new(signals: &[ShutdownSignal]) -> Result<Self, (ShutdownSignal, io::Error)> {
+        let mut listeners = Vec::new();
+        for &signal in signals {
+            if let Some(listener) = listen(signal).map_err(|e| (signal, e))? {
+                listeners.push(listener);
+            }
+        }
+
+        Ok(Self { signals: listeners })
+    }
+
+    /// Waits for any of the signals, then sends a shutdown signal on the given channel.
+    pub(super) async fn shutdown_on_signal(self, shutdown: broadcast::Sender<()>) {
+        // `select_all` panics if given no futures.
+        if self.signals.is_empty() {
+            debug!("None of the given shutdown signals exist on this platform, so none are listened for.");
+            return;
+        }
+
+        let (signal, _, _) = select_all(self.signals).await;
+        info!("Received {signal}. Attempting to gracefully shut down...");
+
+        if let Err(e) = shutdown.send(()) {
+            error!("Failed to send shutdown message: {e}")
+        }
+    }
+}
+
+/// Starts listening for the given signal on Unix. Returns `None` if the signal does not exist on Unix.
+#[cfg(unix)]
+fn listen(signal: ShutdownSignal) -> io::Result<Option<SignalFuture>> {
+    use tokio::signal::unix::{signal as unix_signal, SignalKind};
+
+    let kind = match signal {
+        ShutdownSignal::Terminate => SignalKind::terminate(),
+        ShutdownSignal::Interrupt => SignalKind::interrupt(),
+        ShutdownSignal::Hangup => SignalKind::hangup(),
+        ShutdownSignal::CtrlClose | ShutdownSignal::CtrlShutdown => return Ok(None),
+    };
+
+    let mut listener = unix_signal(kind)?;
+    Ok(Some(Box::pin(async move {
+        listener.recv().await;
+        signal
+    })))
+}
+
+/// Starts listening for the given signal on Windows. Returns `None` if the signal does not exist on Windows.
+#[cfg(windows)]
+fn listen(signal: ShutdownSignal) -> io::Result<Option<SignalFuture>> {
+    use tokio::signal::windows;
+
+    let future: SignalFuture = match signal {
+        ShutdownSignal::Interrupt => {
+            let mut listener = windows::ctrl_c()?;
+            Box::pin(async move {
+                listener.recv().await;
+                signal
+            })
+        }
+        ShutdownSignal::CtrlClose => {
+            let mut listener = windows::ctrl_close()?;
+            Box::pin(async move {
+                listener.recv().await;
+                signal
+            })
+        }
+        ShutdownSignal::CtrlShutdown => {
+            let mut listener = windows::ctrl_shutdown()?;
+            Box::pin(async move {
+                listener.recv().await;
+                signal
+            })
+        }
+        ShutdownSignal::Terminate | ShutdownSignal::Hangup => return Ok(None),
+    };
+
+    Ok(Some(future))
+}
+
+/// Signals are only supported on Unix and Windows, so none are listened for elsewhere.
+#[cfg(not(any(unix, windows)))]
+fn listen(_signal: ShutdownSignal) -> io::Result<Option<SignalFuture>> {
+    Ok(None)
+}
@@ -1,21 +1,83 @@
 //! Types and utilities for the App's tokio tasks.
 
-use std::{any::type_name, pin::Pin, sync::Arc, time::Instant};
+use std::{
+    any::type_name,
+    collections::HashMap,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
 
-use futures::{stream::FuturesUnordered, Future, StreamExt};
+use futures::{future::Either, stream::FuturesUnordered, Future, StreamExt};
 use lapin::{
     options::{
-        BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicPublishOptions,
-        BasicQosOptions,
+        BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicQosOptions,
+        BasicRejectOptions, ConfirmSelectOptions, ExchangeDeclareOptions,
+    },
+    types::{AMQPValue, FieldTable, ShortString},
+    Channel, Connection, Consumer,
+};
+use metrics::{counter, gauge, histogram};
+use tokio::{
+    sync::{broadcast, watch},
+    task::{JoinError, JoinHandle},
+};
+use tracing::{debug, error, error_span, field, info, trace, warn, Instrument, Level};
+
+use super::{
+    handle::{AppHandle, HandlerControl, Prefetch},
+    panic,
+    reply::{self, Reply, ReplySettings, ReplyTarget},
+    report::{BindingReport, HandlerReport},
+    topology::HandlerTopology,
+};
+#[cfg(feature = "wire-debug")]
+use crate::wire_debug::WireDebug;
+use crate::{
+    audit::{AuditGuard, AuditOutcome, AuditSink},
+    consistent_hash,
+    context::{Instrumentation, RequestContext, REQUEST_CONTEXT},
+    error::{
+        ErrorRedaction, ExtractErrorHook, HandlerExtractErrorHook, ServerError, ERROR_REDACTION,
     },
-    types::{FieldTable, ShortString},
-    BasicProperties, Channel, Connection, Consumer,
+    extract::{delivery_count, expired_in_flight, Baggage, ChannelPool, ReqIdPolicy, BAGGAGE},
+    handler_config::RedeliveryBackoff,
+    instance::Instance,
+    middleware::{self, EncodedResponse, ErasedMiddleware},
+    redelivery::{self, RedeliveryTracker},
+    reply_dedup::ReplyDedupStore,
+    reply_store::ReplyStore,
+    request::{self, AckTiming},
+    schema::{self, SchemaRegistry},
+    shadow::{self, ShadowSampler},
+    spawn::BackgroundTasks,
+    stats::RequestStats,
+    Error, Handler, HandlerConfig, HandlerError, Request, Respond, Result,
 };
-use metrics::gauge;
-use tokio::sync::broadcast;
-use tracing::{debug, error, error_span, info, trace, warn, Instrument};
 
-use crate::{Error, Handler, HandlerConfig, Request, Respond, Result};
+/// Logs an event at a level that is only known at runtime, as `tracing`'s macros require the level to be a constant.
+///
+/// Used for the per-request logs of handlers, see [`HandlerConfig::with_log_level`].
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {{
+        let level: Level = $level;
+        if level == Level::ERROR {
+            error!($($arg)+)
+        } else if level == Level::WARN {
+            warn!($($arg)+)
+        } else if level == Level::INFO {
+            info!($($arg)+)
+        } else if level == Level::DEBUG {
+            debug!($($arg)+)
+        } else {
+            trace!($($arg)+)
+        }
+    }};
+}
 
 /// Handler tasks are the async functions that are run in the tokio tasks to perform handlers.
 ///
@@ -29,20 +91,389 @@ type HandlerTask = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
 ///
 /// Upon creating an app and registering handlers, factories are inserted into the app. It is only upon running the app that the
 /// factories are turned into actual handler tasks and run in the asynchronous runtime.
-type HandlerTaskFactory<S> =
-    Box<dyn FnOnce(Channel, Consumer, f64, Arc<S>, broadcast::Receiver<()>) -> HandlerTask + Send>;
+type HandlerTaskFactory<S> = Box<
+    dyn FnOnce(
+            Channel,
+            Option<Consumer>,
+            Arc<Prefetch>,
+            Arc<S>,
+            broadcast::Receiver<()>,
+            TaskContext,
+            HandlerConfig,
+        ) -> HandlerTask
+        + Send,
+>;
+
+/// Makes the [`HandlerTaskFactory`] of a handler. It can be duplicated, so the handler can be set up again, e.g. after reconnecting.
+trait MakeHandlerTaskFactory<S>: Send {
+    /// Makes the factory.
+    fn make(&self) -> HandlerTaskFactory<S>;
+
+    /// Duplicates this, so more factories can be made independently.
+    fn duplicate(&self) -> Box<dyn MakeHandlerTaskFactory<S>>;
+}
+
+impl<S, F> MakeHandlerTaskFactory<S> for F
+where
+    F: Fn() -> HandlerTaskFactory<S> + Clone + Send + 'static,
+{
+    fn make(&self) -> HandlerTaskFactory<S> {
+        self()
+    }
+
+    fn duplicate(&self) -> Box<dyn MakeHandlerTaskFactory<S>> {
+        Box::new(self.clone())
+    }
+}
+
+/// App-wide settings that are given to every handler task.
+#[derive(Clone)]
+pub(super) struct TaskContext {
+    /// Formats error details sent back to callers. See [`App::with_error_redaction`](crate::App::with_error_redaction).
+    pub(super) error_redaction: Option<ErrorRedaction>,
+    /// Determines how request IDs are read and generated. See [`App::with_req_id_policy`](crate::App::with_req_id_policy).
+    pub(super) req_id_policy: Arc<ReqIdPolicy>,
+    /// Selects requests to publish shadow copies of. See [`App::with_shadow`](crate::App::with_shadow).
+    pub(super) shadow: Option<ShadowSampler>,
+    /// Logs the properties and payloads of messages. See [`App::with_wire_debug`](crate::App::with_wire_debug).
+    #[cfg(feature = "wire-debug")]
+    pub(super) wire_debug: Option<Arc<WireDebug>>,
+    /// Validates the schemas of incoming messages. See [`App::with_schema_registry`](crate::App::with_schema_registry).
+    pub(super) schema_registry: Option<Arc<dyn SchemaRegistry>>,
+    /// Records the outcome of every request. See [`App::with_audit`](crate::App::with_audit).
+    pub(super) audit: Option<Arc<dyn AuditSink>>,
+    /// Pauses consumption while a dependency is unhealthy. See [`App::with_health_gate`](crate::App::with_health_gate).
+    pub(super) health_gate: Option<watch::Receiver<bool>>,
+    /// Keeps replies that could not be published for re-publishing. See [`App::with_reply_store`](crate::App::with_reply_store).
+    pub(super) reply_store: Option<Arc<dyn ReplyStore>>,
+    /// Skips duplicate replies to redelivered requests. See [`App::with_reply_dedup`](crate::App::with_reply_dedup).
+    pub(super) reply_dedup: Option<Arc<dyn ReplyDedupStore>>,
+    /// Called when extraction fails. See [`App::on_extract_error`](crate::App::on_extract_error).
+    pub(super) on_extract_error: Option<ExtractErrorHook>,
+    /// Channels dedicated to publishing from handlers. See [`App::with_publisher_channels`](crate::App::with_publisher_channels).
+    pub(super) publisher_channels: Option<Arc<ChannelPool>>,
+    /// The channel shadow copies are published on, opened if the app has a shadow sampler. See [`App::with_shadow`](crate::App::with_shadow).
+    pub(super) shadow_channel: Option<Arc<ChannelPool>>,
+    /// Wraps the future handling each request. See [`App::with_instrumentation`](crate::App::with_instrumentation).
+    pub(super) instrumentation: Option<Instrumentation>,
+    /// Request statistics served by the metrics route. See [`App::with_metrics_route`](crate::App::with_metrics_route).
+    pub(super) stats: Option<Arc<RequestStats>>,
+    /// Middleware wrapping every handler, outermost first. See [`App::layer`](crate::App::layer).
+    pub(super) middleware: Arc<[ErasedMiddleware]>,
+    /// Limits the payload bytes in flight across all handlers. See [`App::with_memory_budget`](crate::App::with_memory_budget).
+    pub(super) memory_budget: Option<Arc<MemoryBudget>>,
+}
+
+/// A spawned task handling a single request.
+pub(crate) struct RequestTask {
+    /// The handle of the spawned task.
+    pub(crate) handle: JoinHandle<()>,
+    /// The instant after which the request should be requeued rather than finished during graceful shutdown.
+    /// See [`HandlerConfig::with_drain_safety_margin`].
+    pub(crate) drain_deadline: Option<Instant>,
+    /// Set once the handler has returned. From then on, the reply may already be published, so the task is no longer requeued by aborting it.
+    pub(crate) handled: Arc<AtomicBool>,
+}
+
+impl Future for RequestTask {
+    type Output = std::result::Result<(), JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx)
+    }
+}
+
+/// Counts a request as in flight in the `kanin.in_flight_requests` gauge until dropped.
+struct InFlightGuard {
+    /// The routing key of the handler handling the request.
+    routing_key: String,
+}
+
+impl InFlightGuard {
+    /// Counts a request on the given routing key as in flight.
+    fn new(routing_key: &str) -> Self {
+        gauge!("kanin.in_flight_requests", "routing_key" => routing_key.to_string()).increment(1.0);
+        Self {
+            routing_key: routing_key.to_string(),
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        gauge!("kanin.in_flight_requests", "routing_key" => self.routing_key.clone())
+            .decrement(1.0);
+    }
+}
+
+/// Counts the payload bytes of a request as in flight until dropped, in the handler's `kanin.in_flight_bytes` gauge and in the memory budget of the app, if any.
+/// See [`HandlerConfig::with_in_flight_byte_budget`] and [`App::with_memory_budget`](crate::App::with_memory_budget).
+pub(crate) struct InFlightBytesGuard {
+    /// The size of the payload of the request.
+    bytes: usize,
+    /// The routing key of the handler handling the request.
+    routing_key: String,
+    /// The number of payload bytes in flight on the handler.
+    in_flight_bytes: Arc<AtomicUsize>,
+    /// The memory budget of the app, if it has one.
+    memory_budget: Option<Arc<MemoryBudget>>,
+}
+
+impl InFlightBytesGuard {
+    /// Counts the given number of payload bytes as in flight on the handler on the given routing key.
+    pub(crate) fn new(
+        bytes: usize,
+        routing_key: &str,
+        in_flight_bytes: &Arc<AtomicUsize>,
+        memory_budget: Option<&Arc<MemoryBudget>>,
+    ) -> Self {
+        in_flight_bytes.fetch_add(bytes, Ordering::Relaxed);
+        gauge!("kanin.in_flight_bytes", "routing_key" => routing_key.to_string())
+            .increment(f64::from(u32::try_from(bytes).unwrap_or(u32::MAX)));
+        if let Some(memory_budget) = memory_budget {
+            memory_budget
+                .in_flight
+                .send_modify(|in_flight| *in_flight += bytes);
+        }
+
+        Self {
+            bytes,
+            routing_key: routing_key.to_string(),
+            in_flight_bytes: in_flight_bytes.clone(),
+            memory_budget: memory_budget.cloned(),
+        }
+    }
+}
+
+impl Drop for InFlightBytesGuard {
+    fn drop(&mut self) {
+        self.in_flight_bytes
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+        gauge!("kanin.in_flight_bytes", "routing_key" => self.routing_key.clone())
+            .decrement(f64::from(u32::try_from(self.bytes).unwrap_or(u32::MAX)));
+        if let Some(memory_budget) = &self.memory_budget {
+            memory_budget
+                .in_flight
+                .send_modify(|in_flight| *in_flight = in_flight.saturating_sub(self.bytes));
+        }
+    }
+}
+
+/// Limits the payload bytes of requests in flight across all handlers of an app. See [`App::with_memory_budget`](crate::App::with_memory_budget).
+pub(crate) struct MemoryBudget {
+    /// The maximum number of payload bytes in flight.
+    budget: usize,
+    /// The number of payload bytes in flight. Handlers watch this to resume receiving deliveries once enough requests have finished.
+    in_flight: watch::Sender<usize>,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of the given number of bytes.
+    pub(crate) fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            in_flight: watch::channel(0).0,
+        }
+    }
+
+    /// Returns the budget along with a receiver of the payload bytes in flight, for [`within_memory_budget`] and [`memory_released`].
+    pub(crate) fn watch(&self) -> (usize, watch::Receiver<usize>) {
+        (self.budget, self.in_flight.subscribe())
+    }
+}
+
+/// Returns whether the payloads in flight are within the memory budget of the app, marking the current amount as seen.
+///
+/// Always true if there is no memory budget.
+pub(crate) fn within_memory_budget(memory: &mut Option<(usize, watch::Receiver<usize>)>) -> bool {
+    match memory {
+        Some((budget, in_flight)) => *in_flight.borrow_and_update() < *budget,
+        None => true,
+    }
+}
+
+/// Waits for the payload bytes in flight to change since they were last seen. See [`within_memory_budget`].
+///
+/// Never returns if there is no memory budget.
+pub(crate) async fn memory_released(memory: &mut Option<(usize, watch::Receiver<usize>)>) {
+    if let Some((_, in_flight)) = memory {
+        // The sender lives as long as the app, so this only fails once the app is gone.
+        if in_flight.changed().await.is_ok() {
+            return;
+        }
+    }
+
+    std::future::pending().await
+}
+
+/// Records the sizes of request and response payloads on a routing key, flagging unusually large ones.
+/// See [`HandlerConfig::with_large_message_threshold`].
+#[derive(Clone)]
+pub(crate) struct PayloadSizes {
+    /// The routing key of the handler.
+    pub(crate) routing_key: String,
+    /// Payloads larger than this number of bytes are flagged as large, if set.
+    pub(crate) large_message_threshold: Option<usize>,
+}
+
+impl PayloadSizes {
+    /// Records the size of a request payload.
+    pub(crate) fn record_request(&self, size: usize) {
+        self.record("kanin.request_size_bytes", "request", size);
+    }
+
+    /// Records the size of a response payload.
+    pub(crate) fn record_response(&self, size: usize) {
+        self.record("kanin.response_size_bytes", "response", size);
+    }
+
+    /// Records the size of a payload in the given histogram, flagging it if it is large.
+    fn record(&self, metric: &'static str, direction: &'static str, size: usize) {
+        histogram!(metric, "routing_key" => self.routing_key.clone())
+            .record(f64::from(u32::try_from(size).unwrap_or(u32::MAX)));
+
+        if let Some(threshold) = self.large_message_threshold {
+            if size > threshold {
+                warn!("Large {direction} of {size} bytes on routing key {:?} exceeds the threshold of {threshold} bytes.", self.routing_key);
+                counter!("kanin.large_messages", "routing_key" => self.routing_key.clone(), "direction" => direction)
+                    .increment(1);
+            }
+        }
+    }
+}
+
+/// Limits the number of requests in flight per caller. See [`HandlerConfig::with_per_caller_limit`].
+#[derive(Clone)]
+pub(crate) struct CallerLimiter {
+    /// The maximum number of requests in flight per caller.
+    limit: usize,
+    /// The number of requests in flight, keyed by caller. Callers without requests in flight are removed.
+    in_flight: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl CallerLimiter {
+    /// The delay before requeueing requests over the limit, unless the handler has a [redelivery backoff](HandlerConfig::with_redelivery_backoff).
+    const REQUEUE_BACKOFF: RedeliveryBackoff = RedeliveryBackoff {
+        base: Duration::from_millis(100),
+        max: Duration::from_secs(5),
+    };
+
+    /// Creates a limiter with the given limit per caller.
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Counts a request from the given caller as in flight, unless the caller already has as many requests in flight as the limit.
+    pub(crate) fn try_acquire(&self, caller: &str) -> Option<CallerPermit> {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count = in_flight.entry(caller.to_string()).or_default();
+        if *count >= self.limit {
+            return None;
+        }
+        *count += 1;
+
+        Some(CallerPermit {
+            limiter: self.clone(),
+            caller: caller.to_string(),
+        })
+    }
+}
+
+/// Counts a request as in flight for its caller until dropped. See [`CallerLimiter`].
+pub(crate) struct CallerPermit {
+    /// The limiter the request is counted in.
+    limiter: CallerLimiter,
+    /// The caller of the request.
+    caller: String,
+}
+
+impl Drop for CallerPermit {
+    fn drop(&mut self) {
+        let mut in_flight = self
+            .limiter
+            .in_flight
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(count) = in_flight.get_mut(&self.caller) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                in_flight.remove(&self.caller);
+            }
+        }
+    }
+}
+
+/// Paces the deliveries of a handler, like a token bucket holding a single token. See [`HandlerConfig::with_max_rate`].
+struct Pacer {
+    /// The time between deliveries.
+    interval: Duration,
+    /// When the next delivery may be received.
+    next: Instant,
+}
+
+impl Pacer {
+    /// Creates a pacer allowing the given number of deliveries per second, the first one right away.
+    fn new(msgs_per_sec: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / msgs_per_sec.max(1),
+            next: Instant::now(),
+        }
+    }
+
+    /// Returns whether a delivery may be received now.
+    fn ready(&self) -> bool {
+        Instant::now() >= self.next
+    }
+
+    /// Counts a delivery as received, so the next one may only be received an interval later.
+    fn take(&mut self) {
+        self.next = self.next.max(Instant::now()) + self.interval;
+    }
+}
+
+/// Waits until the pacer allows the next delivery.
+///
+/// Never returns if there is no pacer.
+async fn pacer_ready(pacer: &Option<Pacer>) {
+    if let Some(pacer) = pacer {
+        tokio::time::sleep_until(pacer.next.into()).await;
+        return;
+    }
+
+    std::future::pending().await
+}
+
+/// Computes the drain deadline of a request received at the given instant. See [`HandlerConfig::with_drain_safety_margin`].
+///
+/// Only the consumer timeout counts: the broker doesn't expire messages while they are delivered,
+/// but a requeued message expires once its TTL has passed since it was enqueued, so requeueing near the TTL would lose it.
+pub(crate) fn drain_deadline(config: &HandlerConfig, received: Instant) -> Option<Instant> {
+    let margin = config.drain_safety_margin?;
+    let consumer_timeout = config.duration_argument("x-consumer-timeout")?;
+
+    Some(received + consumer_timeout.saturating_sub(margin))
+}
 
 /// Creates the handler task for the given handler and routing key. See [`HandlerTask`].
+///
+/// Handlers without a consumer have a start delay, after which the task creates the consumer. See [`HandlerConfig::with_start_delay`].
 #[allow(clippy::too_many_arguments)]
 fn handler_task<H, S, Args, Res>(
     routing_key: String,
     handler: H,
     channel: Channel,
-    mut consumer: Consumer,
-    prefetch: f64,
+    consumer: Option<Consumer>,
+    prefetch: Arc<Prefetch>,
     state: Arc<S>,
     mut shutdown: broadcast::Receiver<()>,
-    should_reply: bool,
+    context: TaskContext,
+    config: HandlerConfig,
 ) -> HandlerTask
 where
     H: Handler<Args, Res, S>,
@@ -50,11 +481,73 @@ where
     S: Send + Sync + 'static,
 {
     Box::pin(async move {
+        let mut consumer = match consumer {
+            Some(consumer) => consumer,
+            None => {
+                let delay = config.start_delay.unwrap_or_default();
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.recv() => {
+                        info!("Shutting down handler on routing key {routing_key:?} before its start delay passed.");
+                        return Ok(());
+                    }
+                }
+
+                let queue_name = config.queue.as_deref().unwrap_or(&routing_key).to_string();
+                let consumer = create_consumer(&channel, &queue_name, &routing_key, &config)
+                    .await
+                    .map_err(Error::from)?;
+                info!("Handler on routing key {routing_key:?} started consuming from queue {queue_name:?} after {delay:?}.");
+                consumer
+            }
+        };
+
         // We keep a set of handles to all outstanding spawned tasks.
         let mut tasks = FuturesUnordered::new();
+        let max_in_flight = config.max_in_flight;
+        let in_flight_byte_budget = config.in_flight_byte_budget;
+        let in_flight_bytes = Arc::new(AtomicUsize::new(0));
+        let mut memory = context
+            .memory_budget
+            .as_ref()
+            .map(|memory_budget| memory_budget.watch());
+        let queue = consumer.queue();
+        let consumer_tag = consumer.tag();
+        let background = Arc::new(BackgroundTasks::new());
+        let mut redeliveries = config.redelivery_storm.map(RedeliveryTracker::new);
+        let caller_limiter = config.per_caller_limit.map(CallerLimiter::new);
+        // The middleware of the app wraps the middleware of the handler. Their state types were checked when they were added.
+        let middleware: middleware::Chain<S> = context
+            .middleware
+            .iter()
+            .chain(&config.middleware)
+            .filter_map(ErasedMiddleware::downcast)
+            .collect();
+        let mut pacer = config.max_rate.map(Pacer::new);
+        let replies = Arc::new(ReplySettings::new(
+            &routing_key,
+            &config,
+            context.reply_store.clone(),
+            context.reply_dedup.clone(),
+        ));
+        let config = Arc::new(config);
+        let on_extract_error = context
+            .on_extract_error
+            .clone()
+            .map(|hook| HandlerExtractErrorHook::new(hook, &routing_key));
+
+        // Consumption starts paused if the app is already unhealthy.
+        let mut health_gate = context.health_gate.clone();
+        let mut healthy = true;
+        if let Some(gate) = &mut health_gate {
+            if !*gate.borrow_and_update() {
+                healthy = !pause_consumer(&channel, &mut consumer, &routing_key).await;
+            }
+        }
 
         // We keep listening for requests from the consumer until the consumer cancels or we're instructed to shut down.
         let ret = loop {
+            let within_memory_budget = within_memory_budget(&mut memory);
             let delivery = tokio::select! {
                 // "Biased" here means that instead of randomly selecting a path, Tokio will check from top to bottom.
                 // This ensures that we check for shutdown before receiving a new message.
@@ -71,8 +564,8 @@ where
                 // Check return values of previously spawned handlers.
                 Some(result) = tasks.next() => if let Err(e) = result {
                     // A handler panicked. We won't shut down the whole system in this case, we'll just continue with the next call.
-                    // The hope is that the panic is a temporary thing.
-                    error!("Handler {} panicked: {}", type_name::<H>().to_string(), e);
+                    // The hope is that the panic is a temporary thing. The panic itself has already been logged with its backtrace.
+                    debug!("Handler {} panicked: {}", type_name::<H>().to_string(), e);
                     continue
                 } else {
                     // If the inner result is not an error, we just ignore it,
@@ -80,8 +573,69 @@ where
                     continue;
                 },
 
-                // Listen on new deliveries.
-                delivery = consumer.next() => match delivery {
+                // Pause or resume consumption when the health of the app changes.
+                now_healthy = health_changed(&mut health_gate) => {
+                    if now_healthy == healthy {
+                        continue;
+                    }
+
+                    if now_healthy {
+                        info!("App is healthy again, resuming consumption on routing key {routing_key}.");
+                        consumer = match consume_again(&channel, queue.as_str(), consumer_tag.as_str(), &config).await {
+                            Ok(consumer) => consumer,
+                            Err(e) => {
+                                error!("Failed to resume consumption on routing key {routing_key}, attempting to gracefully shut down...");
+                                break Err(Error::from(e));
+                            }
+                        };
+                    } else {
+                        info!("App is unhealthy, pausing consumption on routing key {routing_key}.");
+                        if !pause_consumer(&channel, &mut consumer, &routing_key).await {
+                            // The consumer is still running, so we keep handling its deliveries.
+                            continue;
+                        }
+                    }
+                    healthy = now_healthy;
+                    continue;
+                }
+
+                // Consume again when the prefetch changed, as the broker only applies it to new consumers.
+                // While paused, there is no consumer, and resuming will consume with the new prefetch.
+                _ = prefetch.changed.notified() => {
+                    if !healthy {
+                        continue;
+                    }
+
+                    info!("Prefetch changed, consuming again on routing key {routing_key}.");
+                    if !pause_consumer(&channel, &mut consumer, &routing_key).await {
+                        continue;
+                    }
+
+                    consumer = match consume_again(&channel, queue.as_str(), consumer_tag.as_str(), &config).await {
+                        Ok(consumer) => consumer,
+                        Err(e) => {
+                            error!("Failed to consume again on routing key {routing_key} after the prefetch changed, attempting to gracefully shut down...");
+                            // The consumer is cancelled, so there is nothing left to cancel during shutdown.
+                            healthy = false;
+                            break Err(Error::from(e));
+                        }
+                    };
+                    continue;
+                }
+
+                // Wait for requests to finish (on any handler), if the app is over its memory budget.
+                _ = memory_released(&mut memory), if !within_memory_budget => continue,
+
+                // Wait until the pacer allows the next delivery, if we're receiving deliveries too fast.
+                _ = pacer_ready(&pacer), if !pacer.as_ref().map_or(true, Pacer::ready) => continue,
+
+                // Listen on new deliveries, unless we're paused or already handling as many requests (or bytes) as we're allowed to.
+                // While the set is full, we only wait for handlers to finish (or for shutdown), so the consumer is paused.
+                delivery = consumer.next(), if healthy
+                    && within_memory_budget
+                    && pacer.as_ref().map_or(true, Pacer::ready)
+                    && max_in_flight.map_or(true, |max| tasks.len() < max)
+                    && in_flight_byte_budget.map_or(true, |budget| in_flight_bytes.load(Ordering::Relaxed) < budget) => match delivery {
                     // Received a delivery successfully, just unwrap it from the option.
                     Some(delivery) => delivery,
 
@@ -95,35 +649,319 @@ where
                 },
             };
 
-            let req = match delivery {
+            let received = Instant::now();
+            if let Some(pacer) = &mut pacer {
+                pacer.take();
+            }
+            let mut req = match delivery {
                 Err(e) => {
                     error!("Error when receiving delivery on routing key \"{routing_key}\": {e:#}");
                     continue;
                 }
                 // Construct the request by bundling the channel, the delivery and the app state.
-                Ok(delivery) => Request::new(channel.clone(), delivery, state.clone()),
+                Ok(delivery) => {
+                    if let Some(sampler) = context.shadow.as_ref().filter(|_| config.shadowable) {
+                        shadow::shadow(
+                            sampler,
+                            context.shadow_channel.as_deref(),
+                            &delivery,
+                            queue.as_str(),
+                        );
+                    }
+
+                    #[cfg(feature = "wire-debug")]
+                    if let Some(wire_debug) = &context.wire_debug {
+                        wire_debug.log_delivery(&routing_key, &delivery);
+                    }
+
+                    Request::with_req_id_policy(
+                        channel.clone(),
+                        delivery,
+                        state.clone(),
+                        &context.req_id_policy,
+                    )
+                }
+            };
+
+            if req.delivery().redelivered {
+                counter!("kanin.redeliveries", "queue" => queue.to_string()).increment(1);
+
+                if let Some(tracker) = &mut redeliveries {
+                    let key = redelivery_key(&req);
+
+                    if let Some(count) = tracker.record(&key, received) {
+                        warn!(
+                            "Message {key:?} on queue {queue} was redelivered {count} times within {:?}. This is likely a requeue loop, e.g. due to a panicking handler.",
+                            tracker.storm.window
+                        );
+                        counter!("kanin.redelivery_storms", "queue" => queue.to_string())
+                            .increment(1);
+
+                        if tracker.storm.dead_letter {
+                            match req.reject(BasicRejectOptions { requeue: false }).await {
+                                Ok(()) => info!("Rejected message {key:?} without requeueing it to break the redelivery storm."),
+                                Err(e) => error!("Failed to reject message {key:?} of redelivery storm: {e:#}"),
+                            }
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            // Messages that expired while in flight are dropped, as their callers have already given up on them.
+            if config.skip_expired && expired_in_flight(req.properties(), SystemTime::now()) {
+                debug!(
+                    "Request {} on queue {queue} expired in flight, dropping it.",
+                    req.req_id()
+                );
+                counter!("kanin.expired_messages_skipped", "queue" => queue.to_string())
+                    .increment(1);
+                if let Err(e) = req.ack(BasicAckOptions::default()).await {
+                    error!("Failed to ack expired request: {e:#}");
+                }
+                continue;
+            }
+
+            // Callers with too many requests in flight are turned away, leaving room for other callers.
+            let caller_permit = match &caller_limiter {
+                Some(limiter) => {
+                    let caller = req.app_id().unwrap_or_default().to_string();
+                    match limiter.try_acquire(&caller) {
+                        Some(permit) => Some(permit),
+                        None => {
+                            // Requeued requests go back to the head of the queue, so they are held for a while first.
+                            // Otherwise the consumer would spin on redeliveries of the same caller's requests.
+                            let delivery = req.delivery();
+                            let previous_deliveries =
+                                delivery_count(delivery).max(u64::from(delivery.redelivered));
+                            let delay = config
+                                .redelivery_backoff
+                                .unwrap_or(CallerLimiter::REQUEUE_BACKOFF)
+                                .delay(previous_deliveries + 1);
+                            debug!("Caller {caller:?} has too many requests in flight on queue {queue}, requeueing request in {delay:?}.");
+                            counter!("kanin.caller_limited", "queue" => queue.to_string())
+                                .increment(1);
+                            // The redelivery of the requeued request is not part of a requeue loop.
+                            if let Some(tracker) = &mut redeliveries {
+                                tracker.ignore_next(&redelivery_key(&req), received);
+                            }
+                            // The held request counts towards the limits of the handler like any other request in flight.
+                            // It is requeued right away on graceful shutdown, as its drain deadline has already passed.
+                            let in_flight_bytes = InFlightBytesGuard::new(
+                                req.delivery().data.len(),
+                                &routing_key,
+                                &in_flight_bytes,
+                                context.memory_budget.as_ref(),
+                            );
+                            let handle = tokio::spawn(async move {
+                                let _in_flight_bytes = in_flight_bytes;
+                                tokio::time::sleep(delay).await;
+                                if let Err(e) = req.requeue().await {
+                                    error!("Failed to requeue request from caller {caller:?} over its limit: {e:#}");
+                                }
+                            });
+                            tasks.push(RequestTask {
+                                handle,
+                                drain_deadline: Some(received),
+                                handled: Arc::default(),
+                            });
+                            continue;
+                        }
+                    }
+                }
+                None => None,
             };
 
+            req.payload_diagnostics = config.payload_diagnostics;
+            req.reply_cc = config.reply_cc;
+            req.reply_mode = config.reply_mode;
+            req.extract_timeout = config.extract_timeout;
+            req.on_extract_error = on_extract_error.clone();
+            req.publisher_channels = context.publisher_channels.clone();
+            req.reply_settings = Some(replies.clone());
+            #[cfg(feature = "wire-debug")]
+            {
+                req.wire_debug = context
+                    .wire_debug
+                    .clone()
+                    .filter(|wire_debug| wire_debug.matches(&routing_key));
+            }
+            let ack_timing = AckTiming::new(
+                received,
+                routing_key.clone(),
+                type_name::<H>(),
+                req.req_id().clone(),
+                config.duration_argument("x-consumer-timeout"),
+            );
+            req.ack_timing = Some(ack_timing.clone());
+            req.background = Some(background.clone());
+            let drain_deadline = drain_deadline(&config, received);
+            let handled = Arc::new(AtomicBool::new(false));
+            let backoff = config.redelivery_backoff.map(|backoff| {
+                let delivery = req.delivery();
+                let previous_deliveries =
+                    delivery_count(delivery).max(u64::from(delivery.redelivered));
+                backoff.delay(previous_deliveries)
+            });
+
             // Now handle the request.
             let handler = handler.clone();
             let channel = channel.clone();
+            let error_redaction = context.error_redaction.clone();
+            let config = config.clone();
+            let replies = replies.clone();
+            let middleware = middleware.clone();
+            let log_target = config.log_target.clone();
+            let request_context =
+                RequestContext::of_request(&req, context.req_id_policy.header(), &routing_key);
+            // Custom instrumentation is given the context of the request, as it wraps the future that makes it available.
+            let instrumentation = context
+                .instrumentation
+                .clone()
+                .map(|instrumentation| (instrumentation, request_context.clone()));
+            let payload_sizes = PayloadSizes {
+                routing_key: routing_key.clone(),
+                large_message_threshold: config.large_message_threshold,
+            };
+            payload_sizes.record_request(req.delivery().data.len());
+            let schema_check = match (&context.schema_registry, &config.expected_schema) {
+                (Some(registry), Some(expected)) => {
+                    Some((registry.clone(), expected.clone(), queue.to_string()))
+                }
+                _ => None,
+            };
+            let mut audit = context.audit.as_ref().map(|sink| {
+                AuditGuard::new(
+                    sink.clone(),
+                    routing_key.clone(),
+                    req.req_id().to_string(),
+                    req.app_id().map(str::to_string),
+                    &req.delivery().data,
+                )
+            });
             // Requests are handled and replied to concurrently.
             // This allows each handler task to process multiple requests at once.
-            tasks.push(tokio::spawn(async move {
-                let span = error_span!("request", req_id = %req.req_id());
+            counter!("kanin.tasks_spawned_total", "routing_key" => routing_key.clone())
+                .increment(1);
+            let in_flight = InFlightGuard::new(&routing_key);
+            let stats = context
+                .stats
+                .as_ref()
+                .map(|stats| stats.start(&routing_key));
+            let in_flight_bytes = InFlightBytesGuard::new(
+                req.delivery().data.len(),
+                &routing_key,
+                &in_flight_bytes,
+                context.memory_budget.as_ref(),
+            );
+            let request_handled = handled.clone();
+            let handle = tokio::spawn(async move {
+                // The guards are dropped when the task ends, even if it panics or is aborted.
+                let _in_flight = in_flight;
+                let _stats = stats;
+                let _in_flight_bytes = in_flight_bytes;
+                let _caller_permit = caller_permit;
+                let span =
+                    error_span!("request", req_id = %req.req_id(), log_target = field::Empty);
+                if let Some(log_target) = &log_target {
+                    span.record("log_target", log_target.as_str());
+                }
+
+                // Panics are logged with their message and backtrace within the span, so they can be traced to the request.
+                let handling = panic::capture(type_name::<H>(), async move {
+                    // The audit guard records its outcome when dropped, including if the handler panics or is aborted.
+                    let audit = &mut audit;
+
+                    // Redelivered messages are delayed to avoid retry storms.
+                    if let Some(backoff) = backoff.filter(|backoff| !backoff.is_zero()) {
+                        debug!("Delaying redelivered request by {backoff:?}.");
+                        tokio::time::sleep(backoff).await;
+                    }
+
+                    // Messages with incompatible schemas are rejected before they reach the handler.
+                    if let Some((registry, expected, queue)) = schema_check {
+                        if !schema::check(registry.as_ref(), req.delivery(), &expected, &queue)
+                            .await
+                        {
+                            if let Err(e) = req.reject(BasicRejectOptions::default()).await {
+                                error!("Failed to reject request with incompatible schema: {e:#}");
+                            }
+                            if let Some(audit) = audit {
+                                audit.set_outcome(AuditOutcome::Rejected);
+                            }
+                            return;
+                        }
+                    }
 
-                handle_request(req, handler, channel, should_reply)
-                    .instrument(span)
-                    .await;
-            }));
+                    // The error redaction is made available to `kanin::error::redact` for the duration of the request,
+                    // the baggage of the request is made available for propagation via `Baggage::current`,
+                    // and the context of the request is made available via `RequestContext::current`.
+                    let baggage = Baggage::of_request(&req);
+                    let outcome = REQUEST_CONTEXT
+                        .scope(
+                            request_context,
+                            BAGGAGE.scope(
+                                baggage,
+                                ERROR_REDACTION.scope(
+                                    error_redaction,
+                                    handle_request(
+                                        req,
+                                        handler,
+                                        channel,
+                                        config,
+                                        replies,
+                                        middleware,
+                                        payload_sizes,
+                                        &request_handled,
+                                    ),
+                                ),
+                            ),
+                        )
+                        .await;
+                    if let Some(audit) = audit {
+                        audit.set_outcome(outcome);
+                    }
+                });
+
+                // Custom instrumentation wraps the whole lifecycle of the request, including panics.
+                let handling = match instrumentation {
+                    Some((instrumentation, request_context)) => {
+                        Either::Right(instrumentation(&request_context, Box::pin(handling)))
+                    }
+                    None => Either::Left(handling),
+                };
+
+                // Warn if the request is still being handled when it gets close to the consumer timeout.
+                async move {
+                    tokio::pin!(handling);
+                    tokio::select! {
+                        () = &mut handling => {}
+                        () = sleep_until(Some(ack_timing.warning_deadline())) => {
+                            ack_timing.warn_unacked();
+                            handling.await;
+                        }
+                    }
+                }
+                .instrument(span)
+                .await;
+            });
+            tasks.push(RequestTask {
+                handle,
+                drain_deadline,
+                handled,
+            });
         };
 
+        // Let background tasks know that we're shutting down, so they can finish early.
+        background.shut_down();
+
         // We won't process any further requests, so we'll cancel the consumer.
-        let queue = consumer.queue();
-        let consumer_tag = consumer.tag();
+        // If consumption is paused, the consumer has already been cancelled.
         let tag = consumer_tag.as_str();
 
-        if let Err(e) = channel
+        if !healthy {
+            debug!("Consumer with tag {tag} was already cancelled as consumption is paused.");
+        } else if let Err(e) = channel
             .basic_cancel(tag, BasicCancelOptions::default())
             .await
         {
@@ -133,6 +971,8 @@ where
         // We'll update the prefetch capacity gauge here.
         // That means that if this queue takes a long time to shut down,
         // it won't still appear as if it has capacity for many messages.
+        // The prefetch may have been changed while running, so we use the current one.
+        let prefetch: f64 = prefetch.current.load(Ordering::Relaxed).into();
         gauge!("kanin.prefetch_capacity", "queue" => queue.to_string()).decrement(prefetch);
 
         if tasks.is_empty() {
@@ -144,83 +984,329 @@ where
                 tasks.len()
             );
 
-            // Wait for the outstanding tasks to finish.
-            let start = Instant::now();
-            while let Some(res) = tasks.next().await {
-                if let Err(e) = res {
-                    error!(
-                        "Handler {} panicked during graceful shutdown (graceful shutdown will continue): {}",
-                        type_name::<H>().to_string(),
-                        e
-                    );
-                }
+            drain(&mut tasks, type_name::<H>(), queue.as_str()).await;
+        }
 
-                if !tasks.is_empty() {
-                    info!(
-                        "Handler {} still working on {} requests ({:?})...",
-                        type_name::<H>(),
-                        tasks.len(),
-                        start.elapsed(),
-                    )
-                }
+        // Background tasks spawned by requests are waited for as well.
+        let running = background.running();
+        if running > 0 {
+            info!(
+                "Handler {} waiting for {running} background tasks...",
+                type_name::<H>()
+            );
+        }
+        background.finished().await;
+
+        // Now that everything is drained, we make sure the prefetch capacity doesn't linger,
+        // for instance in case the decrement above was not exact.
+        gauge!("kanin.prefetch_capacity", "queue" => queue.to_string()).set(0.0);
+
+        ret
+    })
+}
+
+/// Returns the key the redeliveries of the given request are tracked by. See [`RedeliveryTracker`].
+fn redelivery_key<S>(req: &Request<S>) -> String {
+    let delivery = req.delivery();
+    redelivery::message_key(
+        &delivery.properties,
+        delivery.routing_key.as_str(),
+        &delivery.data,
+    )
+}
+
+/// Sleeps until the given instant. Never completes if no instant is given.
+async fn sleep_until(instant: Option<Instant>) {
+    match instant {
+        Some(instant) => tokio::time::sleep_until(instant.into()).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Aborts all tasks whose drain deadline has passed, unless their handler has already returned.
+///
+/// Aborting a task drops its request, which rejects the message with requeue (see the [`Drop`] implementation of [`Request`]).
+pub(crate) fn requeue_expired(tasks: &mut FuturesUnordered<RequestTask>) {
+    let now = Instant::now();
+    for task in tasks.iter_mut() {
+        if task.handled.load(Ordering::Acquire) {
+            // The task is left to finish, so it isn't considered again.
+            task.drain_deadline = None;
+            continue;
+        }
+        if matches!(task.drain_deadline, Some(deadline) if deadline <= now) {
+            warn!("Request is too close to its deadline, requeueing it instead of finishing it during graceful shutdown.");
+            task.handle.abort();
+            task.drain_deadline = None;
+        }
+    }
+}
+
+/// Waits for the outstanding request tasks of the given handler on the given queue to finish during graceful shutdown.
+///
+/// Tasks that are not finished by their drain deadline are requeued (see [`requeue_expired`]). The outcomes are counted in the
+/// `kanin.shutdown_requests_finished` and `kanin.shutdown_requests_aborted` counters.
+pub(crate) async fn drain(tasks: &mut FuturesUnordered<RequestTask>, handler: &str, queue: &str) {
+    let start = Instant::now();
+    loop {
+        // Tasks that are not finished by their drain deadline are aborted.
+        let next_deadline = tasks
+            .iter()
+            .filter_map(|task: &RequestTask| task.drain_deadline)
+            .min();
+
+        let res = tokio::select! {
+            res = tasks.next() => match res {
+                Some(res) => res,
+                None => break,
+            },
+            () = sleep_until(next_deadline), if next_deadline.is_some() => {
+                requeue_expired(tasks);
+                continue;
+            }
+        };
+
+        match res {
+            Err(e) if e.is_cancelled() => {
+                info!("Handler {handler} requeued a request that was too close to its deadline during graceful shutdown.");
+                counter!("kanin.shutdown_requests_aborted", "queue" => queue.to_string())
+                    .increment(1);
+            }
+            Err(e) => {
+                error!("Handler {handler} panicked during graceful shutdown (graceful shutdown will continue): {e}");
+                counter!("kanin.shutdown_requests_aborted", "queue" => queue.to_string())
+                    .increment(1);
             }
+            Ok(()) => {
+                counter!("kanin.shutdown_requests_finished", "queue" => queue.to_string())
+                    .increment(1);
+            }
+        }
+
+        if !tasks.is_empty() {
             info!(
-                "Handler {} finished in {:?}.",
-                type_name::<H>(),
+                "Handler {handler} still working on {} requests ({:?})...",
+                tasks.len(),
                 start.elapsed(),
             )
         }
+    }
+    info!("Handler {handler} finished in {:?}.", start.elapsed())
+}
 
-        ret
-    })
+/// Waits for the health gate to change and returns whether the app is now healthy.
+///
+/// Never returns if there is no health gate or if its sender has been dropped, in which case the health is considered fixed.
+async fn health_changed(gate: &mut Option<watch::Receiver<bool>>) -> bool {
+    if let Some(receiver) = gate {
+        if receiver.changed().await.is_ok() {
+            return *receiver.borrow_and_update();
+        }
+    }
+
+    std::future::pending().await
+}
+
+/// Consumes from the given queue again with the given consumer tag, after the previous consumer was cancelled.
+async fn consume_again(
+    channel: &Channel,
+    queue: &str,
+    consumer_tag: &str,
+    config: &HandlerConfig,
+) -> lapin::Result<Consumer> {
+    channel
+        .basic_consume(
+            queue,
+            consumer_tag,
+            BasicConsumeOptions::default(),
+            config.consumer_arguments(Instance::current()),
+        )
+        .await
+}
+
+/// Pauses consumption by cancelling the given consumer.
+///
+/// Deliveries the consumer had already received but not yet handled are requeued, so they are not handled while paused.
+///
+/// Returns whether the consumer was cancelled. If cancelling failed, the consumer is left running.
+async fn pause_consumer(channel: &Channel, consumer: &mut Consumer, routing_key: &str) -> bool {
+    let consumer_tag = consumer.tag();
+    if let Err(e) = channel
+        .basic_cancel(consumer_tag.as_str(), BasicCancelOptions::default())
+        .await
+    {
+        error!("Failed to cancel consumer on routing key {routing_key} while pausing, consumption continues: {e:#}");
+        return false;
+    }
+
+    // Once cancelled, the consumer yields the deliveries it had already received and then ends.
+    while let Some(delivery) = consumer.next().await {
+        if let Ok(delivery) = delivery {
+            let requeue = BasicRejectOptions { requeue: true };
+            if let Err(e) = delivery.reject(requeue).await {
+                error!(
+                    "Failed to requeue delivery on routing key {routing_key} while pausing: {e:#}"
+                );
+            }
+        }
+    }
+
+    true
+}
+
+/// Calls the handler with the request.
+///
+/// If the handler panics and is configured to reply to panics, the panic is turned into an error response, if the handler can respond to errors.
+async fn call_handler<H, S, Args, Res>(handler: H, req: &mut Request<S>, panic_replies: bool) -> Res
+where
+    H: Handler<Args, Res, S>,
+    Res: Respond,
+{
+    if !panic_replies {
+        return handler.call(req).await;
+    }
+
+    let handler_name = std::any::type_name::<H>();
+    match panic::catch(handler_name, handler.call(req)).await {
+        Ok(response) => response,
+        Err(payload) => {
+            let message = panic::panic_message(payload.as_ref()).to_string();
+            let error = HandlerError::InternalError(ServerError::HandlerPanicked(message));
+            match H::error_response(error) {
+                Some(response) => {
+                    warn!("Replying with an internal error as handler {handler_name} panicked.");
+                    response
+                }
+                None => std::panic::resume_unwind(payload),
+            }
+        }
+    }
 }
 
 /// Handles the given request with the given handler and channel.
 ///
 /// Acks the request and responds if the handler executes normally.
 ///
-/// If the handler panicks, the request will be rejected and instructed to requeue.
+/// If the handler panicks, the request will be rejected and instructed to requeue, unless the handler is configured to reply to panics.
+///
+/// Sets `handled` once the handler has returned, see [`RequestTask::handled`].
+/// Returns the outcome of handling the request, for auditing.
+#[allow(clippy::too_many_arguments)]
 async fn handle_request<H, S, Args, Res>(
     mut req: Request<S>,
     handler: H,
     channel: Channel,
-    should_reply: bool,
-) where
+    config: Arc<HandlerConfig>,
+    replies: Arc<ReplySettings>,
+    middleware: middleware::Chain<S>,
+    payload_sizes: PayloadSizes,
+    handled: &AtomicBool,
+) -> AuditOutcome
+where
     H: Handler<Args, Res, S>,
     Res: Respond,
+    S: Send + Sync + 'static,
 {
     let handler_name = std::any::type_name::<H>();
+    let log_level = replies.log_level;
     let app_id = req.app_id().unwrap_or("<unknown>");
-    info!("Received request on handler {handler_name:?} from {app_id}");
+    log_at!(
+        log_level,
+        "Received request on handler {handler_name:?} from {app_id}"
+    );
 
     if req.delivery().redelivered {
-        info!("Request was redelivered.");
+        log_at!(log_level, "Request was redelivered.");
     }
 
     let t = std::time::Instant::now();
 
-    // Call the handler with the request.
-    let response = handler.call(&mut req).await;
+    // Call the handler with the request, unless the middleware fails the request first.
+    let response = match middleware::before(&middleware, &mut req).await {
+        Ok(()) => call_handler(handler, &mut req, config.panic_replies).await,
+        Err(error) => match H::error_response(error) {
+            Some(response) => response,
+            // The handler can't respond to the error, so the request is rejected instead (unless it should be requeued).
+            None if !req.requeued => {
+                warn!("Middleware failed request to handler {handler_name}, which cannot respond to errors. Rejecting request.");
+                if let Err(e) = req.reject(BasicRejectOptions::default()).await {
+                    error!("Failed to reject request: {e:#}");
+                }
+                return AuditOutcome::Rejected;
+            }
+            None => {
+                if let Err(e) = req.requeue().await {
+                    error!("Failed to requeue request: {e:#}");
+                }
+                return AuditOutcome::Requeued;
+            }
+        },
+    };
+    // From here on, the request is not aborted during graceful shutdown, as that would requeue it after it was replied to.
+    handled.store(true, Ordering::Release);
 
-    let properties = req.properties();
-    let reply_to = properties.reply_to();
-    let correlation_id = properties.correlation_id();
+    // The request should be requeued due to a transient error, so it will be retried and we should not reply.
+    if req.requeued {
+        let elapsed = t.elapsed();
+        match req.requeue().await {
+            Ok(()) => log_at!(
+                log_level,
+                "Handler {handler_name} requeued the request (elapsed={elapsed:?})."
+            ),
+            Err(e) => error!("Failed to requeue request: {e:#}"),
+        }
+        return AuditOutcome::Requeued;
+    }
+
+    // The handler deferred its reply to a reply handle, which will publish the reply instead of us.
+    if req.reply_deferred {
+        debug!("Handler {handler_name:?} deferred its reply.");
+    }
+    let should_reply = config.should_reply && !req.reply_deferred;
 
     debug!("Handler {handler_name:?} produced response {response:?}");
 
-    let bytes_response = response.respond();
+    let reply_ttl = response.reply_ttl();
+    let content_type = ShortString::from(response.content_type());
+    let mut reply_headers = response.reply_headers();
+    // The baggage of the request flows on to the caller.
+    if let Some(baggage) = Baggage::current() {
+        baggage.propagate(&mut reply_headers);
+    }
+    // Additional recipients of the reply are routed to by the broker.
+    if req.reply_cc {
+        request::copy_cc_headers(req.properties(), &mut reply_headers);
+    }
+    // Middleware sees the encoded response before it is published.
+    let mut encoded = EncodedResponse::new(response.respond(), reply_headers);
+    middleware::after(&middleware, &mut req, &mut encoded).await;
+    let EncodedResponse {
+        payload: bytes_response,
+        headers: reply_headers,
+    } = encoded;
+    payload_sizes.record_response(bytes_response.len());
+
+    let properties = req.properties();
+    let reply_target = ReplyTarget::of_request(&req);
 
     // Includes time for decoding request and encoding response, but *not* the time to publish the response.
     let elapsed = t.elapsed();
 
-    match (should_reply, reply_to) {
+    let outcome = match (should_reply, reply_target) {
+        // Replies to redelivered requests are skipped if the caller was already sent a reply to the request.
+        (true, Some(target)) if replies.already_replied(&target) => {
+            log_at!(
+                log_level,
+                "Skipping reply to redelivered request, as a reply was already published to {} (elapsed={elapsed:?}).",
+                target.reply_to
+            );
+            counter!("kanin.duplicate_replies_skipped", "routing_key" => replies.routing_key.clone())
+                .increment(1);
+            AuditOutcome::Handled
+        }
         // We're supposed to reply and we have a reply_to queue: Reply.
-        (true, Some(reply_to)) => {
-            let mut props = BasicProperties::default();
-
-            if let Some(correlation_id) = correlation_id {
-                props = props.with_correlation_id(correlation_id.clone());
-            } else {
+        (true, Some(target)) => {
+            if target.correlation_id.is_none() {
                 warn!("Request from handler {handler_name:?} did not contain a `correlation_id` property. A reply will be published, but the receiver may not recognize it as the reply for their request. (all properties: {properties:?})");
             }
 
@@ -228,34 +1314,23 @@ async fn handle_request<H, S, Args, Res>(
             if bytes_response.is_empty() {
                 warn!("Handler {handler_name:?} produced an empty response to a message with a `reply_to` property. This is probably undesired, as the caller likely expects more of a response (elapsed={elapsed:?})");
             } else {
-                info!(
-                    "Response with {} bytes that will be published to {reply_to} (elapsed={elapsed:?})",
-                    bytes_response.len()
+                log_at!(
+                    log_level,
+                    "Response with {} bytes that will be published to {} (elapsed={elapsed:?})",
+                    bytes_response.len(),
+                    target.reply_to
                 );
             }
 
-            // Since we expect the response to be encoded Protobuf, we set the content type to octet-stream.
-            props = props.with_content_type(ShortString::from("application/octet-stream"));
-
-            let publish = channel
-                .basic_publish(
-                    HandlerConfig::DEFAULT_EXCHANGE,
-                    reply_to.as_str(),
-                    BasicPublishOptions::default(),
-                    &bytes_response,
-                    props,
-                )
-                .await;
-
-            match publish {
-                Ok(_confirm) => {
-                    debug!("Successfully published reply to routing key \"{reply_to}\"");
-                }
-                // We tried to reply but somehow our response never got published.
-                // We'll log an error in this case. Panicking probably doesn't help much.
-                Err(e) => {
-                    error!("Error when publishing reply to routing key \"{reply_to}\": {e:#}");
-                }
+            let reply = Reply {
+                payload: bytes_response,
+                headers: reply_headers,
+                content_type,
+                reply_ttl,
+            };
+            match reply::publish(&replies, &channel, &target, reply).await {
+                Ok(()) => AuditOutcome::Replied,
+                Err(_) => AuditOutcome::ReplyFailed,
             }
         }
         // We are supposed to reply, but the request did not have a reply_to.
@@ -263,20 +1338,25 @@ async fn handle_request<H, S, Args, Res>(
         // In this case, we warn. Empty responses may be produced by non-responding handlers, which is fine.
         (true, None) if !bytes_response.is_empty() => {
             warn!("Received non-empty message from handler {handler_name:?} but the request did not contain a `reply_to` property, so no reply could be published (all properties: {properties:?}, elapsed={elapsed:?}).");
+            AuditOutcome::Handled
         }
         // We are supposed to reply, but the request did not have a reply_to.
         // However we produced an empty response, so it's not like the caller missed any information.
         (true, None) => {
-            info!(
+            log_at!(
+                log_level,
                 "Handler {handler_name} finished (empty, should_reply = true, elapsed={elapsed:?})",
             );
+            AuditOutcome::Handled
         }
         // We are not supposed to reply so we won't.
         (false, _) => {
             let len = bytes_response.len();
-            info!(
+            log_at!(
+                log_level,
                 "Handler {handler_name} finished ({len} bytes, should_reply = false, elapsed={elapsed:?}).",
             );
+            AuditOutcome::Handled
         }
     };
 
@@ -288,6 +1368,8 @@ async fn handle_request<H, S, Args, Res>(
             Err(e) => error!("Failed to ack request: {e:#}"),
         }
     }
+
+    outcome
 }
 
 /// Task factories take a channel, consumer and the app state and produces a task for running in tokio.
@@ -304,62 +1386,193 @@ async fn handle_request<H, S, Args, Res>(
 /// 3. User calls [`App::run`][crate::App::run], creating tasks from all the task factories that are then run in tokio.
 ///
 /// [`App`]: crate::App
-pub(super) struct TaskFactory<S> {
+pub(crate) struct TaskFactory<S> {
+    /// The type name of the handler.
+    handler_name: &'static str,
     /// The routing key of the handler task produced by this task factory.
     routing_key: String,
     /// Configuration for the handler task produced by this task factory.
     config: HandlerConfig,
-    /// The factory function that constructs the handler task from the given channel, consumer and state.
-    factory: HandlerTaskFactory<S>,
+    /// Makes the factory function that constructs the handler task from the given channel, consumer and state.
+    factory: Box<dyn MakeHandlerTaskFactory<S>>,
 }
 
 impl<S> TaskFactory<S> {
     /// Constructs a new task factory from the given routing key and handler.
-    pub(super) fn new<H, Args, Res>(routing_key: String, handler: H, config: HandlerConfig) -> Self
+    pub(crate) fn new<H, Args, Res>(routing_key: String, handler: H, config: HandlerConfig) -> Self
     where
         H: Handler<Args, Res, S>,
         Res: Respond,
         S: Send + Sync + 'static,
     {
-        let should_reply = config.should_reply;
+        // Middleware for another state type would silently never run, e.g. skipping authentication checks.
+        if let Some(middleware) = config
+            .middleware
+            .iter()
+            .find(|middleware| middleware.downcast::<S>().is_none())
+        {
+            panic!(
+                "Middleware of handler on routing key {routing_key:?} is for state type {}, but the app has state type {}",
+                middleware.state(),
+                type_name::<S>()
+            );
+        }
 
         // A task factory is a closure in a box that produces a handler task.
+        // The closure is made anew for every task, so the handler can be set up again after reconnecting.
+        let make_factory = {
+            let routing_key = routing_key.clone();
+            move || -> HandlerTaskFactory<S> {
+                let routing_key = routing_key.clone();
+                let handler = handler.clone();
+                Box::new(
+                    move |channel: Channel,
+                          consumer: Option<Consumer>,
+                          prefetch: Arc<Prefetch>,
+                          state: Arc<S>,
+                          shutdown: broadcast::Receiver<()>,
+                          context: TaskContext,
+                          config: HandlerConfig| {
+                        handler_task(
+                            routing_key,
+                            handler,
+                            channel,
+                            consumer,
+                            prefetch,
+                            state,
+                            shutdown,
+                            context,
+                            config,
+                        )
+                    },
+                )
+            }
+        };
+
         Self {
-            routing_key: routing_key.clone(),
+            handler_name: type_name::<H>(),
+            routing_key,
             config,
-            factory: Box::new(
-                move |channel: Channel,
-                      consumer: Consumer,
-                      prefetch: f64,
-                      state: Arc<S>,
-                      shutdown: broadcast::Receiver<()>| {
-                    handler_task(
-                        routing_key,
-                        handler,
-                        channel,
-                        consumer,
-                        prefetch,
-                        state,
-                        shutdown,
-                        should_reply,
-                    )
-                },
-            ),
+            factory: Box::new(make_factory),
         }
     }
 
+    /// Duplicates this task factory, so the handler can be set up again, e.g. after reconnecting.
+    pub(super) fn duplicate(&self) -> Self {
+        Self {
+            handler_name: self.handler_name,
+            routing_key: self.routing_key.clone(),
+            config: self.config.clone(),
+            factory: self.factory.duplicate(),
+        }
+    }
+
+    /// Retrieves the configuration for this task factory.
+    pub(super) fn config(&self) -> &HandlerConfig {
+        &self.config
+    }
+
+    /// Retrieves a mutable reference to the configuration for this task factory.
+    pub(super) fn config_mut(&mut self) -> &mut HandlerConfig {
+        &mut self.config
+    }
+
     /// Retrieves the routing key for this task factory.
     pub(super) fn routing_key(&self) -> &str {
         &self.routing_key
     }
 
-    /// Builds the task, returning a [`HandlerTask`].
+    /// Retrieves the name of the queue for this task factory. If no queue was specified, this is the routing key.
+    pub(super) fn queue(&self) -> &str {
+        self.config.queue.as_deref().unwrap_or(&self.routing_key)
+    }
+
+    /// Retrieves the keys the queue is bound with. If no binding key was specified, the first of these is the routing key.
+    ///
+    /// Consistent hash exchanges are bound with a weight instead, so the queue is bound just once, with the weight.
+    fn binding_keys(&self) -> Vec<String> {
+        if let Some(weight) = self.config.consistent_hash_weight {
+            return vec![weight.to_string()];
+        }
+
+        let binding_key = self
+            .config
+            .binding_key
+            .as_deref()
+            .unwrap_or(&self.routing_key);
+        std::iter::once(binding_key.to_string())
+            .chain(self.config.extra_binding_keys.iter().cloned())
+            .collect()
+    }
+
+    /// Describes the exchange, binding and queue that will be set up for this task.
+    pub(super) fn topology(&self) -> HandlerTopology {
+        let mut binding_keys = self.binding_keys();
+        let extra_binding_keys = binding_keys.split_off(1);
+        let binding_key = binding_keys.remove(0);
+        let dead_letter_exchange = match self.config.arguments.inner().get("x-dead-letter-exchange")
+        {
+            Some(AMQPValue::LongString(exchange)) => Some(exchange.to_string()),
+            Some(AMQPValue::ShortString(exchange)) => Some(exchange.to_string()),
+            _ => None,
+        };
+
+        HandlerTopology {
+            handler: self.handler_name.to_string(),
+            exchange: self.config.exchange.clone(),
+            binding_key,
+            extra_binding_keys,
+            queue: self.queue().to_string(),
+            dead_letter_exchange,
+        }
+    }
+
+    /// The exchanges this task relies on existing on the broker, i.e. the exchanges that are not declared by the task itself.
+    pub(super) fn required_exchanges(&self) -> Vec<String> {
+        let topology = self.topology();
+        // Consistent hash exchanges are declared by the task, and the default exchange always exists.
+        let exchange = (self.config.consistent_hash_weight.is_none()).then_some(topology.exchange);
+        exchange
+            .into_iter()
+            .chain(topology.dead_letter_exchange)
+            .filter(|exchange| !exchange.is_empty())
+            .collect()
+    }
+
+    /// Returns a report of what is set up for the handler, given the tag of its consumer if it has been created.
+    pub(crate) fn report(&self, consumer_tag: Option<String>) -> HandlerReport {
+        HandlerReport {
+            handler: self.handler_name.to_string(),
+            routing_key: self.routing_key.clone(),
+            queue: self.queue().to_string(),
+            durable: self.config.options.durable,
+            auto_delete: self.config.options.auto_delete,
+            exclusive: self.config.options.exclusive,
+            arguments: self.config.arguments.clone(),
+            bindings: self
+                .binding_keys()
+                .into_iter()
+                .map(|binding_key| BindingReport {
+                    exchange: self.config.exchange.clone(),
+                    routing_key: binding_key,
+                })
+                .collect(),
+            prefetch: self.config.prefetch,
+            // Consumers are created with the routing key as their tag.
+            consumer_tag: consumer_tag.unwrap_or_else(|| self.routing_key.clone()),
+            start_delay: self.config.start_delay,
+        }
+    }
+
+    /// Builds the task, returning a [`HandlerTask`] along with a report of what was set up for it.
     pub(super) async fn build(
         self,
         conn: &Connection,
         state: Arc<S>,
         shutdown: broadcast::Receiver<()>,
-    ) -> lapin::Result<HandlerTask> {
+        context: TaskContext,
+        handle: &AppHandle,
+    ) -> lapin::Result<(HandlerTask, HandlerReport)> {
         debug!(
             "Building task for handler on routing key {:?}",
             self.routing_key(),
@@ -378,8 +1591,16 @@ impl<S> TaskFactory<S> {
             .basic_qos(self.config.prefetch, BasicQosOptions::default())
             .await?;
 
+        // Returned replies are only surfaced through the broker's confirmations.
+        if self.config.reply_publish_options.mandatory {
+            trace!("Enabling publisher confirms for returned replies...");
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+        }
+
         // If no queue was specified, we just use the routing key.
-        let queue_name = self.config.queue.as_deref().unwrap_or(&self.routing_key);
+        let queue_name = self.queue();
 
         // Set prefetch capacity gauge according to the prefetch.
         // This allows one to construct a metric that informs how close a queue is to capacity.
@@ -393,40 +1614,102 @@ impl<S> TaskFactory<S> {
         // Declare and bind the queue. AMQP states that we must do this before creating the consumer.
         trace!("Declaring queue {queue_name:?} prior to binding...");
         channel
-            .queue_declare(queue_name, self.config.options, self.config.arguments)
-            .await?;
-
-        trace!(
-            "Binding to queue {queue_name:?} on exchange {:?} on routing key {:?}...",
-            self.config.exchange,
-            self.routing_key
-        );
-        channel
-            .queue_bind(
+            .queue_declare(
                 queue_name,
-                &self.config.exchange,
-                &self.routing_key,
-                Default::default(),
-                Default::default(),
+                self.config.options,
+                self.config.arguments.clone(),
             )
             .await?;
 
-        trace!("Creating consumer on routing key {}...", self.routing_key);
-        let consumer = channel
-            .basic_consume(
-                queue_name,
-                &self.routing_key,
-                BasicConsumeOptions::default(),
-                FieldTable::default(),
-            )
-            .await?;
+        // Consistent hash exchanges are declared by the task, and bound with a weight instead of the routing key.
+        if self.config.consistent_hash_weight.is_some() {
+            trace!(
+                "Declaring consistent hash exchange {:?}...",
+                self.config.exchange
+            );
+            channel
+                .exchange_declare(
+                    &self.config.exchange,
+                    consistent_hash::exchange_kind(),
+                    ExchangeDeclareOptions {
+                        durable: true,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+        }
+
+        let binding_keys = self.binding_keys();
+        for binding_key in &binding_keys {
+            trace!(
+                "Binding to queue {queue_name:?} on exchange {:?} on routing key {binding_key:?}...",
+                self.config.exchange,
+            );
+            channel
+                .queue_bind(
+                    queue_name,
+                    &self.config.exchange,
+                    binding_key,
+                    Default::default(),
+                    Default::default(),
+                )
+                .await?;
+        }
+
+        // Handlers with a start delay create their consumer once the delay has passed, in the task.
+        let consumer = match self.config.start_delay {
+            Some(delay) => {
+                info!(
+                    "Handler on routing key {:?} will start consuming from queue {queue_name:?} in {delay:?}.",
+                    self.routing_key
+                );
+                None
+            }
+            None => {
+                Some(create_consumer(&channel, queue_name, &self.routing_key, &self.config).await?)
+            }
+        };
+
+        let report = self.report(consumer.as_ref().map(|consumer| consumer.tag().to_string()));
 
-        Ok((self.factory)(
+        // Make the handler controllable while the app is running.
+        let prefetch = Arc::new(Prefetch::new(self.config.prefetch));
+        handle.register(HandlerControl {
+            routing_key: self.routing_key.clone(),
+            queue: queue_name.to_string(),
+            channel: channel.clone(),
+            prefetch: prefetch.clone(),
+        });
+
+        let task = (self.factory.make())(
             channel,
             consumer,
-            prefetch_f64,
+            prefetch,
             state,
             shutdown,
-        ))
+            context,
+            self.config,
+        );
+
+        Ok((task, report))
     }
 }
+
+/// Creates the consumer of the handler on the given routing key, consuming from the given queue.
+async fn create_consumer(
+    channel: &Channel,
+    queue_name: &str,
+    routing_key: &str,
+    config: &HandlerConfig,
+) -> lapin::Result<Consumer> {
+    trace!("Creating consumer on routing key {routing_key}...");
+    channel
+        .basic_consume(
+            queue_name,
+            routing_key,
+            BasicConsumeOptions::default(),
+            config.consumer_arguments(Instance::current()),
+        )
+        .await
+}
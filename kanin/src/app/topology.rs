@@ -0,0 +1,188 @@
+//! Diagrams of the topology of an app.
+
+use std::fmt::Write;
+
+/// The exchanges, queues, bindings and handlers of an app, as registered on the app. See [`App::topology_graph`](crate::App::topology_graph).
+///
+/// The topology can be rendered as a [Graphviz](https://graphviz.org/) DOT graph via [`Topology::to_dot`]
+/// or as a [Mermaid](https://mermaid.js.org/) flowchart via [`Topology::to_mermaid`], e.g. to generate architecture diagrams from code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct Topology {
+    /// The topology of each of the handlers of the app.
+    pub handlers: Vec<HandlerTopology>,
+}
+
+/// The topology of a single handler: the exchange its queue is bound to, and the queue it consumes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct HandlerTopology {
+    /// The type name of the handler.
+    pub handler: String,
+    /// The exchange the queue is bound to.
+    pub exchange: String,
+    /// The routing key the queue is bound with.
+    pub binding_key: String,
+    /// The additional routing keys the queue is bound with. See [`HandlerConfig::with_extra_binding`](crate::HandlerConfig::with_extra_binding).
+    pub extra_binding_keys: Vec<String>,
+    /// The name of the queue the handler consumes from.
+    pub queue: String,
+    /// The exchange that rejected and expired messages are dead-lettered to, if any.
+    pub dead_letter_exchange: Option<String>,
+}
+
+/// A node in a rendered graph.
+#[derive(PartialEq, Eq)]
+enum Node<'a> {
+    /// An exchange with the given name.
+    Exchange(&'a str),
+    /// A queue with the given name.
+    Queue(&'a str),
+    /// A handler with the given type name.
+    Handler(&'a str),
+}
+
+impl Node<'_> {
+    /// The label of the node. The default exchange has no name, so it is given a descriptive label.
+    fn label(&self) -> &str {
+        match self {
+            Node::Exchange("") => "(default exchange)",
+            Node::Exchange(name) | Node::Queue(name) | Node::Handler(name) => name,
+        }
+    }
+}
+
+/// An edge in a rendered graph, between the nodes with the given indices.
+struct Edge<'a> {
+    /// The index of the node the edge starts at.
+    from: usize,
+    /// The index of the node the edge ends at.
+    to: usize,
+    /// The label of the edge, if any.
+    label: Option<&'a str>,
+    /// Whether the edge represents dead-lettering, which is drawn differently.
+    dead_letter: bool,
+}
+
+impl Topology {
+    /// Renders the topology as a Graphviz DOT graph.
+    pub fn to_dot(&self) -> String {
+        let (nodes, edges) = self.graph();
+        let mut dot = String::from("digraph kanin {\n    rankdir=LR;\n");
+
+        for (i, node) in nodes.iter().enumerate() {
+            let shape = match node {
+                Node::Exchange(_) => "box",
+                Node::Queue(_) => "cylinder",
+                Node::Handler(_) => "component",
+            };
+            let label = node.label().replace('"', "\\\"");
+            let _ = writeln!(dot, "    n{i} [label=\"{label}\", shape={shape}];");
+        }
+
+        for edge in edges {
+            let mut attributes = Vec::new();
+            if let Some(label) = edge.label {
+                attributes.push(format!("label=\"{}\"", label.replace('"', "\\\"")));
+            }
+            if edge.dead_letter {
+                attributes.push("style=dashed".to_string());
+            }
+
+            let _ = write!(dot, "    n{} -> n{}", edge.from, edge.to);
+            if !attributes.is_empty() {
+                let _ = write!(dot, " [{}]", attributes.join(", "));
+            }
+            dot.push_str(";\n");
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the topology as a Mermaid flowchart.
+    pub fn to_mermaid(&self) -> String {
+        let (nodes, edges) = self.graph();
+        let mut mermaid = String::from("flowchart LR\n");
+
+        for (i, node) in nodes.iter().enumerate() {
+            let label = node.label().replace('"', "#quot;");
+            let _ = match node {
+                Node::Exchange(_) => writeln!(mermaid, "    n{i}[[\"{label}\"]]"),
+                Node::Queue(_) => writeln!(mermaid, "    n{i}[(\"{label}\")]"),
+                Node::Handler(_) => writeln!(mermaid, "    n{i}[\"{label}\"]"),
+            };
+        }
+
+        for edge in edges {
+            let arrow = if edge.dead_letter { "-.->" } else { "-->" };
+            let _ = match edge.label {
+                Some(label) => writeln!(
+                    mermaid,
+                    "    n{} {arrow}|\"{}\"| n{}",
+                    edge.from,
+                    label.replace('"', "#quot;"),
+                    edge.to
+                ),
+                None => writeln!(mermaid, "    n{} {arrow} n{}", edge.from, edge.to),
+            };
+        }
+
+        mermaid
+    }
+
+    /// Collects the distinct nodes of the topology along with the edges between them.
+    fn graph(&self) -> (Vec<Node<'_>>, Vec<Edge<'_>>) {
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+
+        for handler in &self.handlers {
+            let exchange = node_index(&mut nodes, Node::Exchange(&handler.exchange));
+            let queue = node_index(&mut nodes, Node::Queue(&handler.queue));
+            let handler_node = node_index(&mut nodes, Node::Handler(&handler.handler));
+
+            for binding_key in
+                std::iter::once(&handler.binding_key).chain(&handler.extra_binding_keys)
+            {
+                edges.push(Edge {
+                    from: exchange,
+                    to: queue,
+                    label: Some(binding_key),
+                    dead_letter: false,
+                });
+            }
+            edges.push(Edge {
+                from: queue,
+                to: handler_node,
+                label: None,
+                dead_letter: false,
+            });
+
+            if let Some(dead_letter_exchange) = &handler.dead_letter_exchange {
+                let dead_letter_exchange =
+                    node_index(&mut nodes, Node::Exchange(dead_letter_exchange));
+                edges.push(Edge {
+                    from: queue,
+                    to: dead_letter_exchange,
+                    label: Some("dead letters"),
+                    dead_letter: true,
+                });
+            }
+        }
+
+        (nodes, edges)
+    }
+}
+
+/// Returns the index of the given node, adding it if it is not already present.
+fn node_index<'a>(nodes: &mut Vec<Node<'a>>, node: Node<'a>) -> usize {
+    match nodes.iter().position(|existing| *existing == node) {
+        Some(index) => index,
+        None => {
+            nodes.push(node);
+            nodes.len() - 1
+        }
+    }
+}
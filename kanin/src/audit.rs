@@ -0,0 +1,261 @@
+//! Audit trails of request processing.
+//!
+//! When an app is configured with an [`AuditSink`] (see [`App::with_audit`](crate::App::with_audit)), an [`AuditRecord`]
+//! is recorded for every request once it has been processed, regardless of how processing ended.
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use lapin::{options::BasicPublishOptions, BasicProperties, Channel};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+/// How the processing of a request ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum AuditOutcome {
+    /// The request was handled and a reply was published.
+    Replied,
+    /// The request was handled, but the reply could not be published.
+    ReplyFailed,
+    /// The request was handled and no reply was expected.
+    Handled,
+    /// The request was requeued due to a transient error.
+    Requeued,
+    /// The request was rejected before reaching the handler, e.g. due to an incompatible schema.
+    Rejected,
+    /// The handler panicked. The request was requeued.
+    Panicked,
+    /// Processing was aborted during graceful shutdown. The request was requeued.
+    Aborted,
+}
+
+impl fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self {
+            AuditOutcome::Replied => "replied",
+            AuditOutcome::ReplyFailed => "reply_failed",
+            AuditOutcome::Handled => "handled",
+            AuditOutcome::Requeued => "requeued",
+            AuditOutcome::Rejected => "rejected",
+            AuditOutcome::Panicked => "panicked",
+            AuditOutcome::Aborted => "aborted",
+        };
+        f.write_str(outcome)
+    }
+}
+
+/// A record of the processing of a single request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct AuditRecord {
+    /// The routing key the request was received with.
+    pub routing_key: String,
+    /// The request ID of the request.
+    pub req_id: String,
+    /// The `app_id` property of the request, if any.
+    pub app_id: Option<String>,
+    /// The hex-encoded SHA-256 hash of the payload of the request.
+    pub payload_sha256: String,
+    /// How the processing of the request ended.
+    pub outcome: AuditOutcome,
+    /// How long it took to process the request.
+    pub latency: Duration,
+}
+
+/// Formats the record as a single logfmt line.
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "routing_key={:?} req_id={:?} app_id={:?} payload_sha256={} outcome={} latency_ms={}",
+            self.routing_key,
+            self.req_id,
+            self.app_id.as_deref().unwrap_or_default(),
+            self.payload_sha256,
+            self.outcome,
+            self.latency.as_millis(),
+        )
+    }
+}
+
+/// A destination for audit records.
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    /// Records the given audit record.
+    ///
+    /// Sinks are responsible for handling their own errors, as there is no one to return them to.
+    async fn record(&self, record: AuditRecord);
+}
+
+/// An [`AuditSink`] that logs records with [`tracing`] at the info level.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogAuditSink;
+
+#[async_trait]
+impl AuditSink for LogAuditSink {
+    async fn record(&self, record: AuditRecord) {
+        info!(target: "kanin::audit", "{record}");
+    }
+}
+
+/// An [`AuditSink`] that appends records to a file, one line per record.
+///
+/// Writes are small and synchronous, so this is best suited for local files.
+#[derive(Debug, Clone)]
+pub struct FileAuditSink {
+    /// The file to append to.
+    file: Arc<Mutex<File>>,
+}
+
+impl FileAuditSink {
+    /// Opens the given file for appending, creating it if it doesn't exist.
+    ///
+    /// # Errors
+    /// Returns an error if the file could not be opened.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileAuditSink {
+    async fn record(&self, record: AuditRecord) {
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = writeln!(file, "{record}") {
+            error!("Failed to write audit record {record}: {e:#}");
+        }
+    }
+}
+
+/// An [`AuditSink`] that publishes records to an AMQP exchange, one message per record.
+#[derive(Debug, Clone)]
+pub struct AmqpAuditSink {
+    /// The channel to publish on.
+    channel: Channel,
+    /// The exchange to publish to.
+    exchange: String,
+    /// The routing key to publish with.
+    routing_key: String,
+}
+
+impl AmqpAuditSink {
+    /// Creates a sink that publishes records on the given channel to the given exchange with the given routing key.
+    pub fn new(
+        channel: Channel,
+        exchange: impl Into<String>,
+        routing_key: impl Into<String>,
+    ) -> Self {
+        Self {
+            channel,
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for AmqpAuditSink {
+    async fn record(&self, record: AuditRecord) {
+        let publish = self
+            .channel
+            .basic_publish(
+                &self.exchange,
+                &self.routing_key,
+                BasicPublishOptions::default(),
+                record.to_string().as_bytes(),
+                BasicProperties::default().with_content_type("text/plain".into()),
+            )
+            .await;
+
+        if let Err(e) = publish {
+            error!("Failed to publish audit record {record}: {e:#}");
+        }
+    }
+}
+
+/// Records an audit record for a request when dropped.
+///
+/// Since the guard lives as long as the processing of the request, this ensures a record is made even if the handler
+/// panics or processing is aborted.
+pub(crate) struct AuditGuard {
+    /// The sink to record to.
+    sink: Arc<dyn AuditSink>,
+    /// The record, without its outcome and latency.
+    record: Option<(String, String, Option<String>, String)>,
+    /// The outcome of processing the request, once known.
+    outcome: Option<AuditOutcome>,
+    /// When processing the request started.
+    started: Instant,
+}
+
+impl AuditGuard {
+    /// Starts auditing the processing of a request.
+    pub(crate) fn new(
+        sink: Arc<dyn AuditSink>,
+        routing_key: String,
+        req_id: String,
+        app_id: Option<String>,
+        payload: &[u8],
+    ) -> Self {
+        let payload_sha256 = format!("{:x}", Sha256::digest(payload));
+
+        Self {
+            sink,
+            record: Some((routing_key, req_id, app_id, payload_sha256)),
+            outcome: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Sets the outcome of processing the request.
+    pub(crate) fn set_outcome(&mut self, outcome: AuditOutcome) {
+        self.outcome = Some(outcome);
+    }
+}
+
+impl Drop for AuditGuard {
+    fn drop(&mut self) {
+        let (routing_key, req_id, app_id, payload_sha256) = match self.record.take() {
+            Some(record) => record,
+            None => return,
+        };
+
+        // If no outcome was set, processing ended abruptly, either by a panic or by the task being aborted.
+        let outcome = self.outcome.unwrap_or_else(|| {
+            if std::thread::panicking() {
+                AuditOutcome::Panicked
+            } else {
+                AuditOutcome::Aborted
+            }
+        });
+
+        let record = AuditRecord {
+            routing_key,
+            req_id,
+            app_id,
+            payload_sha256,
+            outcome,
+            latency: self.started.elapsed(),
+        };
+
+        let sink = self.sink.clone();
+        tokio::spawn(async move { sink.record(record).await });
+    }
+}
@@ -0,0 +1,312 @@
+//! Batching of outgoing publishes.
+
+use std::time::Duration;
+
+use lapin::{
+    options::{BasicPublishOptions, ConfirmSelectOptions},
+    publisher_confirm::Confirmation,
+    BasicProperties, Channel, Connection,
+};
+use metrics::histogram;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tracing::{debug, error};
+
+use crate::{
+    extract::{Baggage, Deadline, ReqId},
+    Error, Respond, Result,
+};
+
+/// A message waiting to be published.
+#[derive(Debug)]
+struct PendingPublish {
+    /// The exchange to publish the message to.
+    exchange: String,
+    /// The routing key to publish the message with.
+    routing_key: String,
+    /// The payload of the message.
+    payload: Vec<u8>,
+    /// The properties of the message.
+    properties: BasicProperties,
+    /// The options to publish the message with.
+    options: BasicPublishOptions,
+}
+
+/// Commands sent from [`BatchPublisher`] handles to the task that publishes the batches.
+#[derive(Debug)]
+enum Command {
+    /// Adds a message to the current batch.
+    Publish(Box<PendingPublish>),
+    /// Publishes the current batch right away, replying with the result once the broker has confirmed it.
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+/// Buffers outgoing messages and publishes them in batches on a single channel with publisher confirms.
+///
+/// This improves throughput for handlers that emit many small messages, e.g. events, per request,
+/// as the broker's confirmations are awaited once per batch rather than once per message.
+/// A batch is published once it contains `max_batch_size` messages or `max_delay` after its first message, whichever comes first.
+///
+/// The publisher is cheap to clone, so it can be kept in the app state and shared by all handlers.
+/// The batching happens in a background task, which publishes any remaining messages and stops once all clones of the publisher are dropped.
+///
+/// ```no_run
+/// # use kanin::batch::BatchPublisher;
+/// # use kanin::lapin::BasicProperties;
+/// # async fn example(conn: &kanin::Connection) -> kanin::Result<()> {
+/// let publisher = BatchPublisher::new(conn).await?;
+/// for i in 0..1000 {
+///     publisher
+///         .publish("events", "item.viewed", i.to_string(), BasicProperties::default())
+///         .await?;
+/// }
+/// publisher.flush().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct BatchPublisher {
+    /// Sends commands to the batching task.
+    commands: mpsc::Sender<Command>,
+}
+
+impl BatchPublisher {
+    /// The default maximum number of messages in a batch.
+    pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+    /// The default maximum time a message waits in a batch before it is published.
+    pub const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(50);
+
+    /// Creates a batch publisher on a new channel of the given connection, with the default limits.
+    ///
+    /// # Errors
+    /// Returns `Err` if the channel could not be created or put in confirm mode.
+    pub async fn new(conn: &Connection) -> Result<Self> {
+        Self::with_limits(conn, Self::DEFAULT_MAX_BATCH_SIZE, Self::DEFAULT_MAX_DELAY).await
+    }
+
+    /// Creates a batch publisher on a new channel of the given connection, with the given limits.
+    /// A maximum batch size of 0 is treated as 1.
+    ///
+    /// # Errors
+    /// Returns `Err` if the channel could not be created or put in confirm mode.
+    pub async fn with_limits(
+        conn: &Connection,
+        max_batch_size: usize,
+        max_delay: Duration,
+    ) -> Result<Self> {
+        let channel = conn.create_channel().await.map_err(Error::from)?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .map_err(Error::from)?;
+
+        let max_batch_size = max_batch_size.max(1);
+        let (commands, receiver) = mpsc::channel(max_batch_size);
+        tokio::spawn(run(channel, receiver, max_batch_size, max_delay));
+
+        Ok(Self { commands })
+    }
+
+    /// Adds a message to the current batch, to be published on the given exchange with the given routing key.
+    ///
+    /// This only waits for the message to be added to the batch, not for it to be published.
+    /// Failures to publish are logged, or returned from [`BatchPublisher::flush`] if the message is published by a flush.
+    ///
+    /// # Errors
+    /// Returns [`Error::DeadlineExceeded`] if the deadline of the request currently being handled has been exceeded.
+    /// Returns [`Error::PublisherClosed`] if the publisher's channel has been closed.
+    pub async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: impl Respond,
+        properties: BasicProperties,
+    ) -> Result<()> {
+        self.publish_with_options(
+            exchange,
+            routing_key,
+            payload,
+            properties,
+            BasicPublishOptions::default(),
+        )
+        .await
+    }
+
+    /// Adds a message to the current batch like [`BatchPublisher::publish`], to be published with the given options.
+    ///
+    /// Set the `mandatory` flag to surface messages that can't be routed to any queue rather than having the broker silently drop them:
+    /// returned messages are logged, and [`BatchPublisher::flush`] fails with [`Error::PublishReturned`] if the message is published by a flush.
+    /// Note that RabbitMQ does not support the `immediate` flag, and closes the channel if it is set.
+    ///
+    /// # Errors
+    /// Returns [`Error::DeadlineExceeded`] if the deadline of the request currently being handled has been exceeded.
+    /// Returns [`Error::PublisherClosed`] if the publisher's channel has been closed.
+    pub async fn publish_with_options(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: impl Respond,
+        properties: BasicProperties,
+        options: BasicPublishOptions,
+    ) -> Result<()> {
+        Deadline::current().check()?;
+
+        let publish = PendingPublish {
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+            payload: payload.respond(),
+            // The batch is published from a separate task, so the baggage, request ID and deadline of the current request are added here.
+            properties: Deadline::propagate_current(ReqId::propagate_current(
+                Baggage::propagate_current(properties),
+            )),
+            options,
+        };
+
+        self.commands
+            .send(Command::Publish(Box::new(publish)))
+            .await
+            .map_err(|_| Error::PublisherClosed)
+    }
+
+    /// Publishes the current batch right away and waits for the broker to confirm it.
+    ///
+    /// # Errors
+    /// Returns `Err` if a message in the batch could not be published or was not confirmed by the broker,
+    /// and [`Error::PublisherClosed`] if the publisher's channel has been closed.
+    pub async fn flush(&self) -> Result<()> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(Command::Flush(reply))
+            .await
+            .map_err(|_| Error::PublisherClosed)?;
+
+        result.await.map_err(|_| Error::PublisherClosed)?
+    }
+}
+
+/// Runs the batching task, until all [`BatchPublisher`] handles have been dropped or the channel fails.
+async fn run(
+    channel: Channel,
+    mut commands: mpsc::Receiver<Command>,
+    max_batch_size: usize,
+    max_delay: Duration,
+) {
+    let mut batch = Vec::with_capacity(max_batch_size);
+    // The instant at which the current batch must be published, if it contains any messages.
+    let mut deadline = None;
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Publish(publish)) => {
+                    if batch.is_empty() {
+                        deadline = Some(Instant::now() + max_delay);
+                    }
+                    batch.push(*publish);
+                    if batch.len() < max_batch_size {
+                        continue;
+                    }
+                }
+                Some(Command::Flush(reply)) => {
+                    deadline = None;
+                    let result = publish_batch(&channel, &mut batch).await;
+                    // The caller may have given up on the flush, which is fine.
+                    let _ = reply.send(result);
+                    continue;
+                }
+                None => {
+                    debug!("All batch publishers dropped, publishing remaining messages...");
+                    if let Err(e) = publish_batch(&channel, &mut batch).await {
+                        error!("Failed to publish the last batch: {e:#}");
+                    }
+                    break;
+                }
+            },
+            () = sleep_until(deadline) => {}
+        }
+
+        // The batch is either full or has waited long enough.
+        deadline = None;
+        if let Err(e) = publish_batch(&channel, &mut batch).await {
+            error!("Failed to publish batch: {e:#}");
+        }
+        if !channel.status().connected() {
+            error!("Channel of batch publisher closed, stopping.");
+            break;
+        }
+    }
+}
+
+/// Sleeps until the given instant. Never completes if no instant is given.
+async fn sleep_until(instant: Option<Instant>) {
+    match instant {
+        Some(instant) => tokio::time::sleep_until(instant).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Publishes all messages in the batch and waits for the broker to confirm them. The batch is empty afterwards.
+async fn publish_batch(channel: &Channel, batch: &mut Vec<PendingPublish>) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let size = batch.len();
+    histogram!("kanin.batch_size").record(f64::from(u32::try_from(size).unwrap_or(u32::MAX)));
+
+    // All messages are published before awaiting any confirmations, so the batch only waits for the broker once.
+    let mut confirms = Vec::with_capacity(size);
+    let mut result = Ok(());
+    for publish in batch.drain(..) {
+        match channel
+            .basic_publish(
+                &publish.exchange,
+                &publish.routing_key,
+                publish.options,
+                &publish.payload,
+                publish.properties,
+            )
+            .await
+        {
+            Ok(confirm) => confirms.push((publish.routing_key, confirm)),
+            Err(e) => {
+                error!(
+                    "Failed to publish message to routing key {:?}: {e:#}",
+                    publish.routing_key
+                );
+                result = result.and(Err(Error::from(e)));
+            }
+        }
+    }
+
+    for (routing_key, confirm) in confirms {
+        match confirm.await {
+            // Messages published with the mandatory or immediate flag are returned before being confirmed if they can't be delivered.
+            Ok(Confirmation::Ack(Some(returned)) | Confirmation::Nack(Some(returned))) => {
+                error!(
+                    "Message published to routing key {routing_key:?} was returned by the broker ({}): {}",
+                    returned.reply_code, returned.reply_text
+                );
+                result = result.and(Err(Error::PublishReturned {
+                    routing_key,
+                    reply_code: returned.reply_code,
+                    reply_text: returned.reply_text.to_string(),
+                }));
+            }
+            Ok(confirmation) if confirmation.is_nack() => {
+                error!("Broker did not confirm message published to routing key {routing_key:?}.");
+                result = result.and(Err(Error::PublishNotConfirmed(routing_key)));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("Failed to receive confirmation of message published to routing key {routing_key:?}: {e:#}");
+                result = result.and(Err(Error::from(e)));
+            }
+        }
+    }
+
+    debug!("Published batch of {size} messages.");
+    result
+}
@@ -0,0 +1,75 @@
+//! Canary rollouts: splitting the traffic of a route between a stable and a canary handler.
+
+use std::any::type_name;
+
+use async_trait::async_trait;
+use metrics::counter;
+use rand::Rng;
+use tracing::debug;
+
+use crate::{Handler, HandlerError, Request, Respond};
+
+/// A handler that dispatches a percentage of the requests to a canary handler, and the rest to a stable handler.
+///
+/// This allows rolling out new handler logic within a single binary: register the new logic as the canary
+/// on a small percentage of the traffic, and increase the percentage as confidence grows. Each request is dispatched at random,
+/// and the number of requests dispatched to each handler is counted in the `kanin.canary_requests` counter.
+/// See also [`App::handler_canary`](crate::App::handler_canary).
+#[derive(Debug, Clone)]
+pub struct Canary<Stable, Candidate> {
+    /// The handler receiving most of the traffic.
+    stable: Stable,
+    /// The handler receiving the given percentage of the traffic.
+    canary: Candidate,
+    /// The percentage of requests dispatched to the canary, between 0 and 100.
+    canary_percent: u8,
+}
+
+impl<Stable, Candidate> Canary<Stable, Candidate> {
+    /// Dispatches the given percentage of requests to the canary handler, and the rest to the stable handler.
+    ///
+    /// Percentages above 100 are treated as 100.
+    pub fn new(stable: Stable, canary: Candidate, canary_percent: u8) -> Self {
+        Self {
+            stable,
+            canary,
+            canary_percent: canary_percent.min(100),
+        }
+    }
+
+    /// Returns true if the next request should be dispatched to the canary.
+    pub(crate) fn pick_canary(&self) -> bool {
+        rand::thread_rng().gen_ratio(self.canary_percent.into(), 100)
+    }
+}
+
+#[async_trait]
+impl<Stable, StableArgs, Candidate, CandidateArgs, Res, S>
+    Handler<(StableArgs, CandidateArgs), Res, S> for Canary<Stable, Candidate>
+where
+    Stable: Handler<StableArgs, Res, S> + Sync,
+    Candidate: Handler<CandidateArgs, Res, S> + Sync,
+    StableArgs: 'static,
+    CandidateArgs: 'static,
+    Res: Respond,
+    S: Send + Sync,
+{
+    async fn call(self, req: &mut Request<S>) -> Res {
+        if self.pick_canary() {
+            debug!(
+                "Dispatching request to canary handler {}",
+                type_name::<Candidate>()
+            );
+            counter!("kanin.canary_requests", "handler" => type_name::<Candidate>(), "variant" => "canary").increment(1);
+            self.canary.call(req).await
+        } else {
+            counter!("kanin.canary_requests", "handler" => type_name::<Stable>(), "variant" => "stable").increment(1);
+            self.stable.call(req).await
+        }
+    }
+
+    fn error_response(error: HandlerError) -> Option<Res> {
+        // Both handlers have the same response type, so they respond to errors in the same way.
+        Stable::error_response(error)
+    }
+}
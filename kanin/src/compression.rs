@@ -0,0 +1,54 @@
+//! Compression of replies, negotiated from the `accept-encoding` header of requests.
+//!
+//! When a request has an `accept-encoding` header that accepts gzip (e.g. `gzip` or `br, gzip;q=0.5`),
+//! its reply is compressed with gzip and published with the `content_encoding` property set to `gzip`.
+//! Callers that don't send the header always receive uncompressed replies.
+
+use std::io::{self, Write};
+
+use flate2::{write::GzEncoder, Compression};
+use lapin::{types::AMQPValue, BasicProperties};
+
+/// The header with which callers advertise the content encodings they accept for replies.
+pub const ACCEPT_ENCODING_HEADER: &str = "accept-encoding";
+
+/// The content encoding of gzip-compressed replies.
+pub const GZIP: &str = "gzip";
+
+/// Returns true if the request with the given properties accepts gzip-compressed replies.
+///
+/// Encodings are matched case-insensitively, and encodings with a quality of 0 (e.g. `gzip;q=0`) are not accepted.
+pub fn accepts_gzip(properties: &BasicProperties) -> bool {
+    let accept_encoding = match properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(ACCEPT_ENCODING_HEADER))
+    {
+        Some(AMQPValue::LongString(value)) => value.to_string(),
+        Some(AMQPValue::ShortString(value)) => value.to_string(),
+        _ => return false,
+    };
+
+    accept_encoding.split(',').any(|encoding| {
+        let mut parameters = encoding.split(';').map(str::trim);
+        let name = parameters.next().unwrap_or_default();
+        let rejected = parameters.any(|parameter| {
+            parameter
+                .strip_prefix("q=")
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .map_or(false, |quality| quality <= 0.0)
+        });
+
+        (name.eq_ignore_ascii_case(GZIP) || name == "*") && !rejected
+    })
+}
+
+/// Compresses the given payload with gzip.
+///
+/// # Errors
+/// Returns an error if the payload could not be compressed.
+pub fn gzip(payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(payload)?;
+    encoder.finish()
+}
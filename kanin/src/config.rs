@@ -0,0 +1,111 @@
+//! Configuration overlays that tune handlers without changing code.
+
+use std::{collections::HashMap, time::Duration};
+
+use crate::HandlerConfig;
+
+/// Configuration that overrides the [`HandlerConfig`] of handlers, keyed by their routing key.
+///
+/// With the `serde` feature enabled, this can be deserialized from any format supported by serde, such as TOML, YAML
+/// or environment variables, which allows tuning consumption without redeploying code. See [`App::with_config_overlay`](crate::App::with_config_overlay).
+///
+/// For instance, in TOML:
+/// ```toml
+/// [handlers.my_routing_key]
+/// prefetch = 16
+/// consumer_timeout_ms = 60000
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+#[non_exhaustive]
+pub struct KaninConfig {
+    /// Overrides for the handlers, keyed by the routing key of the handler.
+    pub handlers: HashMap<String, HandlerOverlay>,
+}
+
+impl KaninConfig {
+    /// Creates a new empty [`KaninConfig`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the overrides for the handler on the given routing key.
+    pub fn with_handler(mut self, routing_key: impl Into<String>, overlay: HandlerOverlay) -> Self {
+        self.handlers.insert(routing_key.into(), overlay);
+        self
+    }
+}
+
+/// Overrides for the [`HandlerConfig`] of a single handler. Values that are not set are left as configured in code.
+///
+/// Durations are given in milliseconds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+#[non_exhaustive]
+pub struct HandlerOverlay {
+    /// Overrides the prefetch, see [`HandlerConfig::with_prefetch`].
+    pub prefetch: Option<u16>,
+    /// Overrides the `durable` property of the queue, see [`HandlerConfig::with_durable`].
+    pub durable: Option<bool>,
+    /// Overrides the `auto-delete` property of the queue, see [`HandlerConfig::with_auto_delete`].
+    pub auto_delete: Option<bool>,
+    /// Overrides the consumer timeout, see [`HandlerConfig::with_consumer_timeout`].
+    pub consumer_timeout_ms: Option<u64>,
+    /// Overrides the message TTL, see [`HandlerConfig::with_message_ttl`].
+    pub message_ttl_ms: Option<u64>,
+    /// Overrides the queue expiry, see [`HandlerConfig::with_expires`].
+    pub expires_ms: Option<u64>,
+    /// Overrides the drain safety margin, see [`HandlerConfig::with_drain_safety_margin`].
+    pub drain_safety_margin_ms: Option<u64>,
+    /// Overrides the maximum number of requests in flight, see [`HandlerConfig::with_max_in_flight`].
+    pub max_in_flight: Option<usize>,
+    /// Overrides the maximum number of requests in flight per caller, see [`HandlerConfig::with_per_caller_limit`].
+    pub per_caller_limit: Option<usize>,
+    /// Overrides the maximum number of deliveries received per second, see [`HandlerConfig::with_max_rate`].
+    pub max_rate: Option<u32>,
+}
+
+impl HandlerOverlay {
+    /// Creates a new [`HandlerOverlay`] that overrides nothing.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Applies the overrides to the given handler configuration.
+    pub fn apply(&self, mut config: HandlerConfig) -> HandlerConfig {
+        if let Some(prefetch) = self.prefetch {
+            config = config.with_prefetch(prefetch);
+        }
+        if let Some(durable) = self.durable {
+            config = config.with_durable(durable);
+        }
+        if let Some(auto_delete) = self.auto_delete {
+            config = config.with_auto_delete(auto_delete);
+        }
+        if let Some(millis) = self.consumer_timeout_ms {
+            config = config.with_consumer_timeout(Duration::from_millis(millis));
+        }
+        if let Some(millis) = self.message_ttl_ms {
+            config = config.with_message_ttl(Duration::from_millis(millis));
+        }
+        if let Some(millis) = self.expires_ms {
+            config = config.with_expires(Duration::from_millis(millis));
+        }
+        if let Some(millis) = self.drain_safety_margin_ms {
+            config = config.with_drain_safety_margin(Duration::from_millis(millis));
+        }
+        if let Some(max_in_flight) = self.max_in_flight {
+            config = config.with_max_in_flight(max_in_flight);
+        }
+        if let Some(per_caller_limit) = self.per_caller_limit {
+            config = config.with_per_caller_limit(per_caller_limit);
+        }
+        if let Some(max_rate) = self.max_rate {
+            config = config.with_max_rate(max_rate);
+        }
+
+        config
+    }
+}
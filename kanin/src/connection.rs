@@ -0,0 +1,129 @@
+//! Tuning of the connection created by [`App::run`](crate::App::run).
+
+use std::{fmt, time::Duration};
+
+use executor_trait::FullExecutor;
+use lapin::{types::AMQPValue, uri::AMQPUri, ConnectionProperties};
+use reactor_trait::Reactor;
+
+/// Options for the connection created by [`App::run`](crate::App::run). See [`App::with_connection_options`](crate::App::with_connection_options).
+///
+/// These expose the customization of lapin's [`ConnectionProperties`] and the tuning parameters of the AMQP address,
+/// so tuning the connection does not require connecting manually and using [`App::run_with_connection`](crate::App::run_with_connection).
+/// Parameters set here take precedence over the same parameters given in the query string of the address.
+#[derive(Clone, Default)]
+pub struct KaninConnectionOptions {
+    /// The connection properties given to lapin.
+    properties: ConnectionProperties,
+    /// The heartbeat interval, if set.
+    heartbeat: Option<Duration>,
+    /// The maximum frame size in bytes, if set.
+    frame_max: Option<u32>,
+    /// The maximum number of channels, if set.
+    channel_max: Option<u16>,
+    /// The maximum time to wait for the connection to be established, if set.
+    connection_timeout: Option<Duration>,
+}
+
+impl KaninConnectionOptions {
+    /// Creates options with lapin's defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the executor lapin uses to run its internal tasks.
+    pub fn with_executor(mut self, executor: impl FullExecutor + Send + Sync + 'static) -> Self {
+        self.properties = self.properties.with_executor(executor);
+        self
+    }
+
+    /// Sets the reactor lapin uses for its IO.
+    pub fn with_reactor(mut self, reactor: impl Reactor + Send + Sync + 'static) -> Self {
+        self.properties = self.properties.with_reactor(reactor);
+        self
+    }
+
+    /// Sets the locale requested from the broker. Defaults to `en_US`.
+    pub fn with_locale(mut self, locale: impl Into<String>) -> Self {
+        self.properties.locale = locale.into();
+        self
+    }
+
+    /// Sets a client property, which is shown in the RabbitMQ management UI.
+    ///
+    /// Note that the connection name and a few other client properties are set from the connection identity of the app
+    /// (see [`App::with_connection_identity`](crate::App::with_connection_identity)), overriding properties set here.
+    pub fn with_client_property(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<AMQPValue>,
+    ) -> Self {
+        self.properties
+            .client_properties
+            .insert(key.into().into(), value.into());
+        self
+    }
+
+    /// Sets the heartbeat interval requested from the broker, with a precision of seconds. A heartbeat of zero disables heartbeats.
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// Sets the maximum frame size in bytes requested from the broker.
+    pub fn with_frame_max(mut self, frame_max: u32) -> Self {
+        self.frame_max = Some(frame_max);
+        self
+    }
+
+    /// Sets the maximum number of channels requested from the broker.
+    ///
+    /// Note that every handler uses its own channel, so this must be larger than the number of handlers.
+    pub fn with_channel_max(mut self, channel_max: u16) -> Self {
+        self.channel_max = Some(channel_max);
+        self
+    }
+
+    /// Sets the maximum time to wait for the connection to be established, with a precision of milliseconds.
+    pub fn with_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the connection properties to connect with.
+    pub(crate) fn properties(&self) -> ConnectionProperties {
+        self.properties.clone()
+    }
+
+    /// Applies the tuning parameters to the given address.
+    pub(crate) fn apply_to_uri(&self, uri: &mut AMQPUri) {
+        if let Some(heartbeat) = self.heartbeat {
+            uri.query.heartbeat = Some(u16::try_from(heartbeat.as_secs()).unwrap_or(u16::MAX));
+        }
+        if let Some(frame_max) = self.frame_max {
+            uri.query.frame_max = Some(frame_max);
+        }
+        if let Some(channel_max) = self.channel_max {
+            uri.query.channel_max = Some(channel_max);
+        }
+        if let Some(timeout) = self.connection_timeout {
+            uri.query.connection_timeout =
+                Some(u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX));
+        }
+    }
+}
+
+impl fmt::Debug for KaninConnectionOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KaninConnectionOptions")
+            .field("locale", &self.properties.locale)
+            .field("client_properties", &self.properties.client_properties)
+            .field("executor", &self.properties.executor.is_some())
+            .field("reactor", &self.properties.reactor.is_some())
+            .field("heartbeat", &self.heartbeat)
+            .field("frame_max", &self.frame_max)
+            .field("channel_max", &self.channel_max)
+            .field("connection_timeout", &self.connection_timeout)
+            .finish()
+    }
+}
@@ -0,0 +1,90 @@
+//! Integration with the [consistent hash exchange](https://github.com/rabbitmq/rabbitmq-server/tree/main/deps/rabbitmq_consistent_hash_exchange).
+//!
+//! A consistent hash exchange routes each message to exactly one of its bound queues, based on a hash of the routing key of the message.
+//! Queues are bound with a weight (given as the binding key) that determines their share of the hash space.
+//! Messages with the same routing key always end up in the same queue, as long as the bindings don't change.
+//!
+//! This allows partitioned processing across kanin instances: give each instance its own queue, bind it to the exchange
+//! (see [`HandlerConfig::with_consistent_hash`](crate::HandlerConfig::with_consistent_hash)) and publish messages with [`publish`],
+//! using e.g. a user ID as the hash key. All messages for the same user are then processed by the same instance.
+//!
+//! The `rabbitmq_consistent_hash_exchange` plugin must be enabled on the broker.
+
+use lapin::{
+    options::{BasicPublishOptions, ExchangeDeclareOptions, QueueBindOptions},
+    publisher_confirm::PublisherConfirm,
+    types::FieldTable,
+    BasicProperties, Channel, ExchangeKind,
+};
+
+use crate::{Error, Result};
+
+/// The exchange type of consistent hash exchanges.
+pub const EXCHANGE_TYPE: &str = "x-consistent-hash";
+
+/// Returns the [`ExchangeKind`] of consistent hash exchanges.
+pub fn exchange_kind() -> ExchangeKind {
+    ExchangeKind::Custom(EXCHANGE_TYPE.to_string())
+}
+
+/// Declares a consistent hash exchange with the given name.
+///
+/// # Errors
+/// Returns [`Error::Protocol`] if the exchange could not be declared,
+/// for instance if the plugin is not enabled or an exchange of a different type already exists with the same name,
+/// and [`Error::Connection`] if the connection to the broker failed.
+pub async fn declare_exchange(
+    channel: &Channel,
+    exchange: &str,
+    options: ExchangeDeclareOptions,
+) -> Result<()> {
+    channel
+        .exchange_declare(exchange, exchange_kind(), options, FieldTable::default())
+        .await
+        .map_err(Error::from)
+}
+
+/// Binds the given queue to the given consistent hash exchange with the given weight.
+///
+/// The weight determines the share of the hash space (and thereby of the messages) that the queue receives,
+/// relative to the weights of the other queues bound to the exchange.
+///
+/// # Errors
+/// Returns [`Error::Protocol`] or [`Error::Connection`] if the queue could not be bound.
+pub async fn bind_queue(channel: &Channel, queue: &str, exchange: &str, weight: u32) -> Result<()> {
+    channel
+        .queue_bind(
+            queue,
+            exchange,
+            &weight.to_string(),
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .map_err(Error::from)
+}
+
+/// Publishes the given payload to the given consistent hash exchange, using `hash_key` as the routing key that is hashed.
+///
+/// All messages published with the same hash key are routed to the same queue.
+///
+/// # Errors
+/// Returns [`Error::Connection`] if the message could not be published.
+pub async fn publish(
+    channel: &Channel,
+    exchange: &str,
+    hash_key: &str,
+    payload: &[u8],
+    properties: BasicProperties,
+) -> Result<PublisherConfirm> {
+    channel
+        .basic_publish(
+            exchange,
+            hash_key,
+            BasicPublishOptions::default(),
+            payload,
+            properties,
+        )
+        .await
+        .map_err(Error::from)
+}
@@ -0,0 +1,100 @@
+//! Access to the context of the request currently being handled, e.g. from clients held in the app state.
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use crate::{
+    extract::{Deadline, ReqId},
+    Request,
+};
+
+tokio::task_local! {
+    /// The context of the request currently being handled.
+    pub(crate) static REQUEST_CONTEXT: RequestContext;
+}
+
+/// The context of a request being handled by an app.
+///
+/// The context is available via [`RequestContext::current`] for the duration of the request, without being passed around.
+/// This lets clients held in the app state (HTTP clients, database pools, ...) pick up the request ID for their own logs or headers,
+/// completing end-to-end correlation without plumbing the request ID through every call. See also [`ContextAware`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct RequestContext {
+    /// The ID of the request.
+    pub req_id: ReqId,
+    /// The header the request ID was read from, which outgoing requests should set it in as well.
+    /// See [`ReqIdPolicy::with_header`](crate::extract::ReqIdPolicy::with_header).
+    pub req_id_header: String,
+    /// The routing key of the handler handling the request.
+    pub routing_key: String,
+    /// The `app_id` property of the request, identifying the caller, if it was set.
+    pub app_id: Option<String>,
+    /// The deadline of the request, which client-side helpers check and propagate. See [`Deadline`].
+    pub deadline: Deadline,
+}
+
+impl RequestContext {
+    /// Returns the context of the request currently being handled, if called while handling a request.
+    ///
+    /// Tasks spawned via [`Spawner`](crate::extract::Spawner) see the context of the request that spawned them,
+    /// while tasks spawned directly with [`tokio::spawn`] do not.
+    pub fn current() -> Option<Self> {
+        REQUEST_CONTEXT.try_with(Clone::clone).ok()
+    }
+
+    /// Creates the context of the given request, handled by the handler on the given routing key.
+    pub(crate) fn of_request<S>(req: &Request<S>, req_id_header: &str, routing_key: &str) -> Self {
+        Self {
+            req_id: req.req_id().clone(),
+            req_id_header: req_id_header.to_string(),
+            routing_key: routing_key.to_string(),
+            app_id: req.app_id().map(str::to_string),
+            deadline: Deadline::of_properties(req.properties()),
+        }
+    }
+}
+
+/// The future handling a request, as given to the instrumentation of an app. See [`App::with_instrumentation`](crate::App::with_instrumentation).
+pub type RequestFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Wraps the future handling each request. See [`App::with_instrumentation`](crate::App::with_instrumentation).
+pub(crate) type Instrumentation =
+    Arc<dyn Fn(&RequestContext, RequestFuture) -> RequestFuture + Send + Sync>;
+
+/// A trait for clients that pick up the context of the request currently being handled.
+///
+/// Implement this for clients held in the app state to get access to the request context wherever the client is used:
+///
+/// ```
+/// use kanin::context::ContextAware;
+///
+/// struct BillingClient;
+///
+/// impl ContextAware for BillingClient {}
+///
+/// impl BillingClient {
+///     fn charge(&self) {
+///         // Outside of a handler, there is no request context.
+///         let headers: Vec<_> = self.correlation_header().into_iter().collect();
+///         assert!(headers.is_empty());
+///     }
+/// }
+/// # BillingClient.charge();
+/// ```
+pub trait ContextAware {
+    /// Returns the context of the request currently being handled, if called while handling a request.
+    fn request_context(&self) -> Option<RequestContext> {
+        RequestContext::current()
+    }
+
+    /// Returns the ID of the request currently being handled, if called while handling a request.
+    fn current_req_id(&self) -> Option<ReqId> {
+        self.request_context().map(|context| context.req_id)
+    }
+
+    /// Returns the header name and value to set on outgoing requests to propagate the current request ID, if called while handling a request.
+    fn correlation_header(&self) -> Option<(String, String)> {
+        self.request_context()
+            .map(|context| (context.req_id_header, context.req_id.to_string()))
+    }
+}
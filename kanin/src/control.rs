@@ -0,0 +1,193 @@
+//! Control messages for operating running apps over AMQP.
+//!
+//! Apps can listen for control messages on a dedicated queue via [`App::with_control_queue`](crate::App::with_control_queue).
+//! This is useful where sending OS signals to the app isn't possible.
+//!
+//! A control message carries a command as its payload, the Unix time in seconds at which it was sent in its `timestamp` property,
+//! and an HMAC-SHA256 signature of the timestamp and the payload in the [`SIGNATURE_HEADER`] header,
+//! keyed with a secret shared between the app and the operator. Use [`sign`] to produce the signature.
+//!
+//! Messages with a missing or invalid signature are ignored, as are messages whose timestamp is more than [`MAX_CLOCK_SKEW`] away
+//! from the clock of the app. As the timestamp is signed, an observed control message cannot be replayed once that window has passed.
+//!
+//! The following commands are supported:
+//! - [`SHUTDOWN`]: Gracefully shuts down the app, draining in-flight requests, as if a shutdown signal was received.
+//! - [`RELOAD`]: Reloads the configuration of the app, as if SIGHUP was received. See [`App::on_reload`](crate::App::on_reload).
+
+use std::{
+    convert::Infallible,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use lapin::types::AMQPValue;
+use sha2::Sha256;
+use tokio::sync::{broadcast, Notify};
+use tracing::{error, info, warn};
+
+use crate::{Extract, Request};
+
+/// The header containing the hex-encoded HMAC-SHA256 signature of a control message's timestamp and payload.
+pub const SIGNATURE_HEADER: &str = "x-kanin-signature";
+
+/// The maximum difference between the timestamp of a control message and the clock of the app for the message to be accepted.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// The command for gracefully shutting down the app.
+pub const SHUTDOWN: &[u8] = b"shutdown";
+
+/// The command for reloading the configuration of the app.
+pub const RELOAD: &[u8] = b"reload";
+
+/// HMAC-SHA256, used for signing control messages.
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs the given control message timestamp (in seconds since the Unix epoch) and payload with the given secret,
+/// returning the hex-encoded signature.
+///
+/// Put the signature in the [`SIGNATURE_HEADER`] header of the control message, and the timestamp in its `timestamp` property.
+pub fn sign(secret: &[u8], timestamp: u64, payload: &[u8]) -> String {
+    format!(
+        "{:x}",
+        mac(secret, timestamp, payload).finalize().into_bytes()
+    )
+}
+
+/// Returns the HMAC of the given timestamp and payload with the given secret.
+fn mac(secret: &[u8], timestamp: u64, payload: &[u8]) -> HmacSha256 {
+    // HMAC accepts keys of any length, so this cannot fail.
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap_or_else(|_| unreachable!());
+    // The timestamp is separated from the payload, so the signed bytes of different messages never coincide.
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+    mac
+}
+
+/// Returns true if the given hex-encoded signature is a valid signature of the timestamp and payload with the given secret.
+pub(crate) fn verify(secret: &[u8], timestamp: u64, payload: &[u8], signature: &str) -> bool {
+    let signature = match decode_hex(signature) {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    // Verifying through the MAC compares in constant time.
+    mac(secret, timestamp, payload)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+/// Returns true if the given timestamp (in seconds since the Unix epoch) is within [`MAX_CLOCK_SKEW`] of the given time.
+pub(crate) fn is_fresh(timestamp: u64, now: SystemTime) -> bool {
+    let sent = UNIX_EPOCH + Duration::from_secs(timestamp);
+    let skew = match now.duration_since(sent) {
+        Ok(age) => age,
+        // The message was sent "in the future", i.e. the clock of the sender is ahead of ours.
+        Err(e) => e.duration(),
+    };
+
+    skew <= MAX_CLOCK_SKEW
+}
+
+/// Decodes a hex string into bytes, returning `None` if it is not valid hex.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A control message, i.e. its payload, timestamp and signature.
+#[derive(Debug)]
+pub(crate) struct ControlMessage {
+    /// The command of the control message.
+    pub(crate) payload: Vec<u8>,
+    /// The `timestamp` property of the message, if any.
+    pub(crate) timestamp: Option<u64>,
+    /// The signature in the [`SIGNATURE_HEADER`] header, if any.
+    pub(crate) signature: Option<String>,
+}
+
+#[async_trait]
+impl<S> Extract<S> for ControlMessage
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        let signature = req
+            .properties()
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(SIGNATURE_HEADER))
+            .and_then(|signature| match signature {
+                AMQPValue::LongString(signature) => Some(signature.to_string()),
+                AMQPValue::ShortString(signature) => Some(signature.to_string()),
+                _ => None,
+            });
+
+        Ok(Self {
+            payload: req.delivery().data.clone(),
+            timestamp: *req.properties().timestamp(),
+            signature,
+        })
+    }
+}
+
+/// The control handler. It verifies the control message and executes its command.
+pub(crate) async fn control_handler(
+    message: ControlMessage,
+    secret: &[u8],
+    shutdown: &broadcast::Sender<()>,
+    reload: &Notify,
+) {
+    let signature = match &message.signature {
+        Some(signature) => signature,
+        None => {
+            warn!("Ignoring control message without a {SIGNATURE_HEADER:?} header.");
+            return;
+        }
+    };
+
+    let timestamp = match message.timestamp {
+        Some(timestamp) => timestamp,
+        None => {
+            warn!("Ignoring control message without a timestamp.");
+            return;
+        }
+    };
+
+    if !verify(secret, timestamp, &message.payload, signature) {
+        warn!("Ignoring control message with an invalid signature.");
+        return;
+    }
+
+    // Checked after the signature, so the timestamp can be trusted.
+    if !is_fresh(timestamp, SystemTime::now()) {
+        warn!("Ignoring control message with timestamp {timestamp}, as it is more than {MAX_CLOCK_SKEW:?} off.");
+        return;
+    }
+
+    match message.payload.as_slice() {
+        SHUTDOWN => {
+            info!("Received shutdown control message, shutting down gracefully.");
+            if let Err(e) = shutdown.send(()) {
+                error!("Failed to send shutdown message: {e}");
+            }
+        }
+        RELOAD => {
+            info!("Received reload control message, reloading configuration.");
+            reload.notify_one();
+        }
+        command => warn!(
+            "Ignoring unknown control command {:?}.",
+            String::from_utf8_lossy(command)
+        ),
+    }
+}
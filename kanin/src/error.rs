@@ -1,11 +1,16 @@
 //! Kanin-specific error types.
 
-use std::convert::Infallible;
+use std::{
+    convert::Infallible, error::Error as StdError, fmt, fmt::Write, sync::Arc, time::Duration,
+};
 
+use lapin::protocol::{basic::AMQPProperties, AMQPErrorKind, AMQPHardError, AMQPSoftError};
 use prost::DecodeError;
 use thiserror::Error as ThisError;
 use tracing::{error, warn};
 
+use crate::extract::ReqId;
+
 /// Errors that may be returned by `kanin`, especially when the app runs.
 #[derive(Debug, ThisError)]
 pub enum Error {
@@ -15,9 +20,129 @@ pub enum Error {
     /// The app exited due to a consumer from the AMQP broker cancelling. The routing key of the consumer is given.
     #[error("Consumer cancelled on routing key {0}")]
     ConsumerCancelled(String),
-    /// An error from an underlying [`lapin`] call.
-    #[error("An underlying `lapin` call failed: {0}")]
-    Lapin(lapin::Error),
+    /// An underlying [`lapin`] call failed because the connection to the broker failed or was lost, e.g. because the broker is unreachable or restarting.
+    ///
+    /// These errors are usually temporary, see [`Error::is_retryable`].
+    #[error("The connection to the broker failed: {0}")]
+    Connection(lapin::Error),
+    /// An underlying [`lapin`] call failed because the broker refused it, e.g. due to bad credentials or a queue declared with conflicting arguments.
+    ///
+    /// These errors persist until the configuration is fixed, see [`Error::is_retryable`].
+    #[error("The broker refused an operation: {0}")]
+    Protocol(lapin::Error),
+    /// A ping was replied to with a payload different from the one that was sent. The routing key of the ping is given.
+    #[error("Invalid reply to ping on routing key {0}")]
+    InvalidPingReply(String),
+    /// The broker did not confirm a published message. The routing key of the message is given.
+    #[error("Publish was not confirmed by the broker on routing key {0}")]
+    PublishNotConfirmed(String),
+    /// A message published with the `mandatory` or `immediate` flag was returned by the broker, as it could not be delivered.
+    #[error("Publish was returned by the broker on routing key {routing_key} ({reply_code}): {reply_text}")]
+    PublishReturned {
+        /// The routing key of the message.
+        routing_key: String,
+        /// The AMQP reply code given by the broker, e.g. 312 (no route).
+        reply_code: u16,
+        /// The reason given by the broker, e.g. `NO_ROUTE`.
+        reply_text: String,
+    },
+    /// A [`BatchPublisher`](crate::batch::BatchPublisher) was used after its channel was closed.
+    #[error("The batch publisher has been closed")]
+    PublisherClosed,
+    /// No handler has been set up on the given routing key.
+    #[error("No handler has been set up on routing key {0}")]
+    NoSuchHandler(String),
+    /// The app state could not be initialized. See [`App::try_new`](crate::App::try_new).
+    #[error("App state could not be initialized: {0:#}")]
+    StateInitialization(Box<dyn StdError + Send + Sync>),
+    /// The AMQP address given to [`App::run`](crate::App::run) could not be parsed. The reason is given.
+    #[error("Invalid AMQP address: {0}")]
+    InvalidAddress(String),
+    /// A handler could not be set up when the app started. The routing key and queue of the handler are given.
+    ///
+    /// Any handlers that were already set up are shut down before this error is returned.
+    #[error("Failed to set up handler on routing key {routing_key} with queue {queue}: {source}")]
+    HandlerSetup {
+        /// The routing key of the handler.
+        routing_key: String,
+        /// The queue of the handler.
+        queue: String,
+        /// The error from the underlying [`lapin`] call that failed.
+        #[source]
+        source: lapin::Error,
+    },
+    /// A signal could not be listened for. See [`App::graceful_shutdown_on_signals`](crate::App::graceful_shutdown_on_signals).
+    #[error("Failed to listen for {signal}: {source}")]
+    SignalListener {
+        /// The signal that could not be listened for.
+        signal: crate::app::ShutdownSignal,
+        /// The reason the signal could not be listened for.
+        #[source]
+        source: std::io::Error,
+    },
+    /// The deadline of a request was exceeded. See [`Deadline`](crate::extract::Deadline).
+    #[error("The deadline of the request was exceeded")]
+    DeadlineExceeded,
+    /// A destructive operation was not confirmed, so it was not carried out. The queue it would have affected is given.
+    /// See [`ops::Purge::confirm`](crate::ops::Purge::confirm).
+    #[error("Operation on queue {0} was not confirmed")]
+    NotConfirmed(String),
+}
+
+impl Error {
+    /// Returns true if the error is likely temporary, so running the app again may succeed, e.g. once the broker is reachable again.
+    ///
+    /// Supervisors that run [`App::run`](crate::App::run) in a retry loop can use this to avoid restart loops on permanent failures,
+    /// such as bad credentials or a queue declared with conflicting arguments ("precondition failed"), which need the configuration to be fixed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Connection(_) | Self::ConsumerCancelled(_) | Self::PublishNotConfirmed(_) => true,
+            Self::HandlerSetup { source, .. } => is_connection_error(source),
+            Self::NoHandlers
+            | Self::Protocol(_)
+            | Self::InvalidPingReply(_)
+            | Self::PublishReturned { .. }
+            | Self::PublisherClosed
+            | Self::NoSuchHandler(_)
+            | Self::StateInitialization(_)
+            | Self::InvalidAddress(_)
+            | Self::SignalListener { .. }
+            | Self::DeadlineExceeded
+            | Self::NotConfirmed(_) => false,
+        }
+    }
+}
+
+impl From<lapin::Error> for Error {
+    /// Classifies the error as a [connection error](Error::Connection) or a [protocol error](Error::Protocol).
+    fn from(error: lapin::Error) -> Self {
+        if is_connection_error(&error) {
+            Self::Connection(error)
+        } else {
+            Self::Protocol(error)
+        }
+    }
+}
+
+/// Returns true if the given [`lapin`] error is due to the connection to the broker failing, rather than the broker refusing an operation.
+pub(crate) fn is_connection_error(error: &lapin::Error) -> bool {
+    match error {
+        lapin::Error::IOError(_)
+        | lapin::Error::MissingHeartbeatError
+        | lapin::Error::InvalidConnectionState(_)
+        | lapin::Error::InvalidChannelState(_) => true,
+        // The broker closed the connection because it is shutting down or out of resources,
+        // or a resource is locked by another connection, which will likely be released.
+        lapin::Error::ProtocolError(error) => matches!(
+            error.kind(),
+            AMQPErrorKind::Hard(
+                AMQPHardError::CONNECTIONFORCED
+                    | AMQPHardError::RESOURCEERROR
+                    | AMQPHardError::INTERNALERROR
+            ) | AMQPErrorKind::Soft(AMQPSoftError::RESOURCELOCKED)
+        ),
+        _ => false,
+    }
 }
 
 /// Errors that may be produced by handlers. Failing extractors provided by `kanin` return this error.
@@ -26,6 +151,9 @@ pub enum HandlerError {
     /// Errors due to invalid requests.
     #[error("Invalid Request: {0:#}")]
     InvalidRequest(RequestError),
+    /// Errors due to failures on the server side, i.e. the request may have been fine but we could not process it.
+    #[error("Internal Error: {0:#}")]
+    InternalError(ServerError),
 }
 
 /// All the ways a request might be invalid.
@@ -36,6 +164,218 @@ pub enum RequestError {
     /// This error is left as an opaque error as that is what is provided by [`prost`].
     #[error("Message could not be decoded into the required type: {0:#}")]
     DecodeError(DecodeError),
+    /// A message could not be decoded into the required type. Diagnostics about the payload are given to help with debugging.
+    ///
+    /// This is returned instead of [`RequestError::DecodeError`] if the handler is configured with
+    /// [`HandlerConfig::with_payload_diagnostics`](crate::HandlerConfig::with_payload_diagnostics).
+    #[error("Message could not be decoded into the required type: {error:#} ({diagnostics})")]
+    UndecodableMessage {
+        /// The error produced by [`prost`], including the path to the field that failed to decode.
+        error: DecodeError,
+        /// Diagnostics about the payload that could not be decoded.
+        diagnostics: PayloadDiagnostics,
+    },
+    /// A message had only default fields. See [`NonDefault`](crate::extract::NonDefault).
+    #[error("Message had only default fields; was it published to the wrong route?")]
+    DefaultMessage,
+    /// A protobuf timestamp could not be converted. See [`well_known::to_system_time`](crate::well_known::to_system_time).
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(prost_types::TimestampError),
+    /// A protobuf duration could not be converted. See [`well_known::to_std_duration`](crate::well_known::to_std_duration).
+    #[error("Invalid duration: {0}")]
+    InvalidDuration(prost_types::DurationError),
+    /// A protobuf enum field had a value that is not a known variant of the enum. The value is given.
+    #[error("Unknown enum value: {0}")]
+    UnknownEnumValue(i32),
+    /// A message had a content type that could not be decoded. The content type is given.
+    #[error("Unsupported content type: {0}")]
+    UnsupportedContentType(String),
+    /// The request could not be processed due to a failure on the server side.
+    ///
+    /// This is only given to error types that have no way of reporting internal errors,
+    /// e.g. enums deriving [`FromError`](crate::FromError) without an `InternalError` variant.
+    #[error("{0:#}")]
+    Internal(ServerError),
+    /// A JSON message could not be decoded into the required type.
+    #[cfg(feature = "json")]
+    #[error("JSON message could not be decoded into the required type: {0:#}")]
+    JsonError(serde_json::Error),
+}
+
+/// Diagnostics about a payload that could not be decoded. See [`RequestError::UndecodableMessage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PayloadDiagnostics {
+    /// The length of the payload in bytes.
+    pub length: usize,
+    /// The first bytes of the payload.
+    pub sample: Vec<u8>,
+    /// The content type of the payload, if it had one.
+    pub content_type: Option<String>,
+}
+
+impl PayloadDiagnostics {
+    /// Collects diagnostics about the given payload, sampling at most `sample_len` bytes of it.
+    pub fn new(payload: &[u8], sample_len: usize, content_type: Option<String>) -> Self {
+        Self {
+            length: payload.len(),
+            sample: payload[..payload.len().min(sample_len)].to_vec(),
+            content_type,
+        }
+    }
+}
+
+/// Formats the diagnostics with the sample as hex, e.g. `payload of 3 bytes, content type application/json, starting with 7b2261`.
+impl fmt::Display for PayloadDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "payload of {} bytes, content type {}, starting with ",
+            self.length,
+            self.content_type.as_deref().unwrap_or("<none>"),
+        )?;
+
+        let mut sample = String::with_capacity(self.sample.len() * 2);
+        for byte in &self.sample {
+            let _ = write!(sample, "{byte:02x}");
+        }
+        f.write_str(&sample)
+    }
+}
+
+/// All the ways the server might fail to process a request.
+#[derive(Debug, ThisError)]
+pub enum ServerError {
+    /// The acker of the request was extracted more than once, e.g. by a handler with two [`Acker`](crate::extract::Acker) parameters.
+    ///
+    /// This is a bug in the handler. Only the first acker can acknowledge the request.
+    #[error(
+        "The acker of the request was already taken. Handlers must extract at most one `Acker`."
+    )]
+    AckerAlreadyTaken,
+    /// An extractor took longer than the extract timeout of the handler.
+    /// See [`HandlerConfig::with_extract_timeout`](crate::HandlerConfig::with_extract_timeout).
+    #[error("Extractor {extractor} timed out after {timeout:?}")]
+    ExtractTimeout {
+        /// The type name of the extractor that timed out.
+        extractor: &'static str,
+        /// The timeout that was exceeded.
+        timeout: Duration,
+    },
+    /// The handler panicked. The message of the panic is given.
+    /// See [`HandlerConfig::with_panic_replies`](crate::HandlerConfig::with_panic_replies).
+    #[error("The handler panicked: {0}")]
+    HandlerPanicked(String),
+    /// Converting the app state into a value extracted with [`State`](crate::extract::State) panicked,
+    /// e.g. because a `From<&S>` implementation encountered a poisoned lock.
+    #[error("Extracting state {state} panicked: {message}")]
+    StatePanicked {
+        /// The type name of the value that was extracted.
+        state: &'static str,
+        /// The message of the panic.
+        message: String,
+    },
+    /// Any other internal error, for instance produced by a custom extractor.
+    #[error("{0:#}")]
+    Other(Box<dyn StdError + Send + Sync>),
+}
+
+/// All the ways publishing a reply to a request might fail.
+///
+/// See [`HandlerConfig::on_reply_result`](crate::HandlerConfig::on_reply_result).
+#[derive(Debug, ThisError)]
+pub enum ReplyError {
+    /// The reply could not be published to the broker.
+    #[error("Reply could not be published: {0:#}")]
+    Publish(lapin::Error),
+    /// The reply was returned by the broker, as it was published with the `mandatory` flag but could not be delivered.
+    /// See [`HandlerConfig::with_reply_publish_options`](crate::HandlerConfig::with_reply_publish_options).
+    #[error("Reply was returned by the broker ({reply_code}): {reply_text}")]
+    Returned {
+        /// The AMQP reply code given by the broker, e.g. 312 (no route).
+        reply_code: u16,
+        /// The reason given by the broker, e.g. `NO_ROUTE`.
+        reply_text: String,
+    },
+    /// The broker did not confirm the reply.
+    #[error("Reply was not confirmed by the broker")]
+    NotConfirmed,
+}
+
+/// A function that formats error details into the message sent back to the caller.
+///
+/// See [`App::with_error_redaction`](crate::App::with_error_redaction).
+pub type ErrorRedaction = Arc<dyn Fn(&(dyn StdError + 'static)) -> String + Send + Sync>;
+
+tokio::task_local! {
+    /// The error redaction of the app handling the current request, if any was set.
+    pub(crate) static ERROR_REDACTION: Option<ErrorRedaction>;
+}
+
+/// Formats the given error into a message that can be sent back to the caller.
+///
+/// If the app was configured with [`App::with_error_redaction`](crate::App::with_error_redaction), the error is formatted using that function.
+/// Otherwise, the error is formatted with its alternate `Display` implementation.
+///
+/// This is used by the [`FromError`](crate::FromError) derive macro for `InvalidRequest` and `InternalError` messages.
+pub fn redact(error: &(dyn StdError + 'static)) -> String {
+    ERROR_REDACTION
+        .try_with(|redaction| redaction.as_ref().map(|redact| redact(error)))
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| format!("{error:#}"))
+}
+
+/// Describes a request whose extraction failed, given to the hook set with [`App::on_extract_error`](crate::App::on_extract_error).
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ExtractFailure<'a> {
+    /// The type name of the extractor that failed.
+    pub extractor: &'static str,
+    /// Whether the error is transient, in which case the request is requeued instead of replied to. See [`Extract::is_transient`](crate::Extract::is_transient).
+    pub transient: bool,
+    /// The ID of the request.
+    pub req_id: &'a ReqId,
+    /// The `app_id` property of the request, identifying the caller.
+    pub app_id: Option<&'a str>,
+    /// The properties of the request.
+    pub properties: &'a AMQPProperties,
+}
+
+/// A hook that is called with the routing key of the handler whenever extraction fails. See [`App::on_extract_error`](crate::App::on_extract_error).
+pub(crate) type ExtractErrorHook =
+    Arc<dyn Fn(&str, &dyn StdError, &ExtractFailure<'_>) + Send + Sync>;
+
+/// An [`ExtractErrorHook`] bound to the routing key of a handler, given to each of its requests.
+#[derive(Clone)]
+pub(crate) struct HandlerExtractErrorHook {
+    /// The hook of the app.
+    hook: ExtractErrorHook,
+    /// The routing key of the handler.
+    routing_key: Arc<str>,
+}
+
+impl HandlerExtractErrorHook {
+    /// Binds the given hook to the handler on the given routing key.
+    pub(crate) fn new(hook: ExtractErrorHook, routing_key: &str) -> Self {
+        Self {
+            hook,
+            routing_key: routing_key.into(),
+        }
+    }
+
+    /// Calls the hook with the given extraction error.
+    pub(crate) fn call(&self, error: &dyn StdError, failure: &ExtractFailure<'_>) {
+        (self.hook)(&self.routing_key, error, failure);
+    }
+}
+
+impl fmt::Debug for HandlerExtractErrorHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandlerExtractErrorHook")
+            .field("routing_key", &self.routing_key)
+            .finish_non_exhaustive()
+    }
 }
 
 /// Types that may be constructed from errors.
@@ -77,6 +417,12 @@ impl From<DecodeError> for HandlerError {
     }
 }
 
+impl From<ServerError> for HandlerError {
+    fn from(e: ServerError) -> Self {
+        HandlerError::InternalError(e)
+    }
+}
+
 // This implementation makes it so handlers can return (), in case they don't want to produce a response.
 // In this case, since no response is given to the caller, we should log the error ourselves to make sure it is reported somehow.
 impl FromError<HandlerError> for () {
@@ -85,6 +431,9 @@ impl FromError<HandlerError> for () {
             HandlerError::InvalidRequest(e) => {
                 warn!("Listener handler received an invalid request: {e:#}")
             }
+            HandlerError::InternalError(e) => {
+                error!("Listener handler failed to process a request: {e:#}")
+            }
         }
     }
 }
@@ -2,15 +2,54 @@
 
 mod acker;
 mod app_id;
+mod baggage;
+mod deadline;
+mod delivery_count;
+mod expiration;
+#[cfg(feature = "json")]
+mod json;
 mod message;
+mod message_with_raw;
+mod meta;
+mod non_default;
+pub(crate) mod parallel_message;
+mod parts;
+mod progress;
+mod properties;
+mod publisher_channel;
+mod reply_handle;
 mod req_id;
+mod routing_key;
 mod state;
 
-pub use acker::Acker;
+pub use acker::{Acker, DeliveryTag};
 pub use app_id::AppId;
+pub use baggage::Baggage;
+pub(crate) use baggage::BAGGAGE;
+pub use deadline::Deadline;
+pub(crate) use delivery_count::delivery_count;
+pub use delivery_count::DeliveryCount;
+pub(crate) use expiration::expired_in_flight;
+pub use expiration::Expiration;
+#[cfg(feature = "json")]
+pub use json::Json;
 pub use message::Msg;
-pub use req_id::ReqId;
-pub use state::State;
+pub use message_with_raw::MsgWithRaw;
+pub use meta::Meta;
+pub use non_default::NonDefault;
+pub use parallel_message::{ParallelMsg, DEFAULT_PARALLEL_THRESHOLD};
+pub use parts::Parts;
+pub use progress::Progress;
+pub use properties::Properties;
+pub(crate) use publisher_channel::ChannelPool;
+pub use publisher_channel::PublisherChannel;
+pub use reply_handle::ReplyHandle;
+pub use req_id::{ReqId, ReqIdFormat, ReqIdPolicy};
+pub use routing_key::RoutingKey;
+pub use state::{FromStateAsync, Provider, State};
+
+// Spawning background tasks is its own module, but the spawner is extracted like any other extractor.
+pub use crate::spawn::Spawner;
 
 use std::{convert::Infallible, error::Error};
 
@@ -29,6 +68,16 @@ pub trait Extract<S>: Sized {
 
     /// Extract the type from the request.
     async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error>;
+
+    /// Returns true if the given extraction error is transient, i.e. extraction might succeed if the request is retried later.
+    ///
+    /// This is useful for extractors that depend on external resources that may be momentarily unavailable.
+    /// When extraction fails with a transient error, the request is rejected and requeued instead of being replied to with the error,
+    /// so it can be retried by this or another consumer. Defaults to false, meaning extraction errors are permanent.
+    fn is_transient(error: &Self::Error) -> bool {
+        let _ = error;
+        false
+    }
 }
 
 #[async_trait]
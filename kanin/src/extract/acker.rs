@@ -1,14 +1,19 @@
 //! Manual acknowledgement and rejection.
 
-use std::{convert::Infallible, mem};
+use std::mem;
 
 use async_trait::async_trait;
 use lapin::{
     acker::Acker as LapinAcker,
-    options::{BasicAckOptions, BasicRejectOptions},
+    options::{BasicAckOptions, BasicNackOptions, BasicRejectOptions},
+    Channel,
 };
 
-use crate::{Extract, Request};
+use crate::{
+    error::{HandlerError, ServerError},
+    request::AckTiming,
+    Extract, Request,
+};
 
 /// An extractor that allows you manual control of acknowledgement and rejection of messages.
 ///
@@ -16,11 +21,34 @@ use crate::{Extract, Request};
 /// Neither will it reject the message if your handler panicks.
 ///
 /// When you extract this, you are responsible for acknowledging or rejecting yourself.
+///
+/// A request only has one acker, so a handler can extract at most one `Acker`.
+/// Extracting it again fails with [`ServerError::AckerAlreadyTaken`].
 #[must_use = "You must call .ack or .reject in order to acknowledge or reject the message."]
 #[derive(Debug)]
-pub struct Acker(LapinAcker);
+pub struct Acker {
+    /// The underlying acker of the delivery.
+    acker: LapinAcker,
+    /// The delivery tag of the message.
+    delivery_tag: DeliveryTag,
+    /// The channel the message was delivered on.
+    channel: Channel,
+    /// Tracks the time until the message is acknowledged, if it was received by an app.
+    ack_timing: Option<AckTiming>,
+}
+
+/// The delivery tag of a message, identifying it among the messages delivered on the same channel.
+///
+/// Delivery tags increase with every message delivered on a channel. See [`Acker::ack_multiple_up_to`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeliveryTag(pub u64);
 
 impl Acker {
+    /// Returns the delivery tag of the message that was received for this acker.
+    pub fn delivery_tag(&self) -> DeliveryTag {
+        self.delivery_tag
+    }
+
     /// Acks the message that was received for this acker.
     ///
     /// # Errors
@@ -28,12 +56,14 @@ impl Acker {
     // Note that since we consume the acker, it should not be possible to call this twice.
     // Thus that error possibility is not listed.
     pub async fn ack(self) -> Result<(), lapin::Error> {
-        self.0
+        self.acker
             .ack(BasicAckOptions {
                 // It does not make sense to use this flag with kanin, as it might interfere with handling of other previous messages.
                 multiple: false,
             })
-            .await
+            .await?;
+        self.record_ack();
+        Ok(())
     }
 
     /// Rejects the message that was received for this acker.
@@ -43,7 +73,46 @@ impl Acker {
     // Note that since we consume the acker, it should not be possible to call this twice.
     // Thus that error possibility is not listed.
     pub async fn reject(self, options: BasicRejectOptions) -> Result<(), lapin::Error> {
-        self.0.reject(options).await
+        self.acker.reject(options).await?;
+        self.record_ack();
+        Ok(())
+    }
+
+    /// Negatively acknowledges the message that was received for this acker, with the given options.
+    ///
+    /// Unlike [`Acker::reject`], this allows setting the `multiple` flag. Note that each handler has a dedicated channel,
+    /// so with the `multiple` flag set, this nacks all unacknowledged messages of the handler up to and including this one,
+    /// including messages that other requests are still handling.
+    ///
+    /// # Errors
+    /// Returns `Err` on network failures.
+    pub async fn nack(self, options: BasicNackOptions) -> Result<(), lapin::Error> {
+        self.acker.nack(options).await?;
+        self.record_ack();
+        Ok(())
+    }
+
+    /// Acks all messages delivered on the handler's channel up to and including the message with the given delivery tag.
+    ///
+    /// This is useful for windowed or batched acknowledgement: hold on to the ackers of several requests,
+    /// and ack all of them at once with the highest delivery tag among them.
+    /// Messages acked this way must not be acked, rejected or nacked again through their own ackers, as the broker closes
+    /// the channel when a message is acknowledged twice. As each handler has a dedicated channel, this also acks messages
+    /// that other requests of the handler are still handling, so it should only be used by handlers that ack all their messages this way.
+    ///
+    /// # Errors
+    /// Returns `Err` on network failures.
+    pub async fn ack_multiple_up_to(&self, delivery_tag: DeliveryTag) -> Result<(), lapin::Error> {
+        self.channel
+            .basic_ack(delivery_tag.0, BasicAckOptions { multiple: true })
+            .await
+    }
+
+    /// Records the time it took to acknowledge the message.
+    fn record_ack(&self) {
+        if let Some(ack_timing) = &self.ack_timing {
+            ack_timing.record();
+        }
     }
 }
 
@@ -53,22 +122,26 @@ impl<S> Extract<S> for Acker
 where
     S: Send + Sync,
 {
-    type Error = Infallible;
+    type Error = HandlerError;
 
     async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
         // This is quite a hacky way of taking the acker. We should improve this if/when lapin improves the interface.
         // See also https://github.com/amqp-rs/lapin/issues/402.
         let acker = mem::take(&mut req.delivery_mut().acker);
 
-        // The request will consider itself acked. It is up to the handler to actually ack the request.
-        req.acked = true;
-
+        // A default acker means the acker was already taken, most likely by extracting an acker twice.
         if acker == LapinAcker::default() {
-            panic!(
-                "extracted acker was equal to the default acker - did you extract an acker twice?"
-            );
+            return Err(ServerError::AckerAlreadyTaken.into());
         }
 
-        Ok(Acker(acker))
+        // The request will consider itself acked. It is up to the handler to actually ack the request.
+        req.acked = true;
+
+        Ok(Acker {
+            acker,
+            delivery_tag: DeliveryTag(req.delivery().delivery_tag),
+            channel: req.channel().clone(),
+            ack_timing: req.ack_timing.take(),
+        })
     }
 }
@@ -0,0 +1,124 @@
+//! Baggage, i.e. request metadata that flows across the service graph untouched.
+
+use std::{collections::BTreeMap, convert::Infallible};
+
+use async_trait::async_trait;
+use lapin::{
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use crate::{Extract, Request};
+
+tokio::task_local! {
+    /// The baggage of the request currently being handled.
+    pub(crate) static BAGGAGE: Baggage;
+}
+
+/// Baggage of the request, read from the [`Baggage::HEADER`] header.
+///
+/// Baggage is a set of key-value pairs, such as tenant IDs or experiment flags, that should flow through every service involved in a request.
+/// The header follows the [W3C baggage](https://www.w3.org/TR/baggage/) format, e.g. `tenant=acme,experiment=new-search`.
+/// Properties of baggage members (after `;`) are dropped and values are kept as-is, i.e. still percent-encoded.
+///
+/// kanin propagates the baggage of a request automatically: it is set on the reply, on messages published via
+/// [`Progress`](crate::extract::Progress) and [`ReplyHandle`](crate::extract::ReplyHandle), and on outgoing messages published
+/// while handling the request via [`ScatterGather`](crate::scatter_gather::ScatterGather) and [`BatchPublisher`](crate::batch::BatchPublisher).
+/// Use [`Baggage::current`] to propagate it on other outgoing messages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Baggage(pub BTreeMap<String, String>);
+
+impl Baggage {
+    /// The header containing the baggage.
+    pub const HEADER: &'static str = "baggage";
+
+    /// Parses baggage from the value of a baggage header. Malformed members are ignored.
+    pub fn parse(value: &str) -> Self {
+        let baggage = value
+            .split(',')
+            .filter_map(|member| {
+                // Properties of the member are dropped.
+                let member = member.split(';').next()?;
+                let (key, value) = member.split_once('=')?;
+                let key = key.trim();
+                if key.is_empty() {
+                    return None;
+                }
+                Some((key.to_string(), value.trim().to_string()))
+            })
+            .collect();
+
+        Self(baggage)
+    }
+
+    /// Reads baggage from the given message headers. The baggage is empty if there is no baggage header.
+    pub fn from_headers(headers: &FieldTable) -> Self {
+        match headers.inner().get(Self::HEADER) {
+            Some(AMQPValue::LongString(value)) => Self::parse(&value.to_string()),
+            Some(AMQPValue::ShortString(value)) => Self::parse(value.as_str()),
+            _ => Self::default(),
+        }
+    }
+
+    /// Formats the baggage as the value of a baggage header.
+    pub fn header_value(&self) -> String {
+        self.0
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Returns the baggage of the request currently being handled, if called while handling a request.
+    ///
+    /// Tasks spawned via [`Spawner`](crate::extract::Spawner) see the baggage of the request that spawned them,
+    /// while tasks spawned directly with [`tokio::spawn`] do not.
+    pub fn current() -> Option<Self> {
+        BAGGAGE.try_with(Clone::clone).ok()
+    }
+
+    /// Sets the baggage header in the given headers, unless the baggage is empty or the headers already contain baggage.
+    pub fn propagate(&self, headers: &mut FieldTable) {
+        if self.0.is_empty() || headers.inner().contains_key(Self::HEADER) {
+            return;
+        }
+
+        headers.insert(
+            Self::HEADER.into(),
+            AMQPValue::LongString(self.header_value().into()),
+        );
+    }
+
+    /// Reads the baggage of the given request.
+    pub(crate) fn of_request<S>(req: &Request<S>) -> Self {
+        req.properties()
+            .headers()
+            .as_ref()
+            .map(Self::from_headers)
+            .unwrap_or_default()
+    }
+
+    /// Returns the given properties with the baggage of the request currently being handled, if any. See [`Baggage::propagate`].
+    pub(crate) fn propagate_current(properties: BasicProperties) -> BasicProperties {
+        let baggage = match Self::current() {
+            Some(baggage) if !baggage.0.is_empty() => baggage,
+            _ => return properties,
+        };
+
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        baggage.propagate(&mut headers);
+        properties.with_headers(headers)
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for Baggage
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Ok(Self::of_request(req))
+    }
+}
@@ -0,0 +1,132 @@
+//! Request deadlines.
+
+use std::{
+    convert::Infallible,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use lapin::{
+    protocol::basic::AMQPProperties,
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use crate::{context::RequestContext, Error, Extract, Request};
+
+/// The deadline of a request, as given by the caller in the [`Deadline::HEADER`] header.
+///
+/// The header holds the deadline as milliseconds since the Unix epoch. Since the deadline is absolute,
+/// it can be propagated unchanged to downstream calls (see [`Deadline::propagate`]), and the time spent in this service
+/// is automatically subtracted from the budget of those calls, similar to deadline propagation in gRPC.
+/// Note that this relies on the clocks of the services being reasonably synchronized.
+///
+/// While a request is being handled, kanin's client-side helpers ([`ScatterGather`](crate::scatter_gather::ScatterGather),
+/// [`Replies`](crate::reply_queue::Replies), [`probe::ping`](crate::probe::ping) and [`BatchPublisher`](crate::batch::BatchPublisher))
+/// fail with [`Error::DeadlineExceeded`] instead of publishing once its deadline has been exceeded, and propagate the deadline otherwise.
+///
+/// If the request has no deadline, the deadline is unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Deadline(pub Option<SystemTime>);
+
+impl Deadline {
+    /// The header that holds the deadline of a request.
+    pub const HEADER: &'static str = "x-deadline";
+
+    /// Creates a deadline that is the given duration from now.
+    pub fn after(budget: Duration) -> Self {
+        Self(Some(SystemTime::now() + budget))
+    }
+
+    /// Returns the remaining time until the deadline, or `None` if the deadline is unbounded.
+    ///
+    /// Returns [`Duration::ZERO`] if the deadline has been exceeded.
+    pub fn remaining(&self) -> Option<Duration> {
+        let deadline = self.0?;
+        Some(
+            deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO),
+        )
+    }
+
+    /// Returns true if the deadline has been exceeded.
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+
+    /// Returns an error if the deadline has been exceeded.
+    ///
+    /// Use this to short-circuit downstream calls that could not possibly finish in time.
+    ///
+    /// # Errors
+    /// Returns [`Error::DeadlineExceeded`] if the deadline has been exceeded.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_exceeded() {
+            return Err(Error::DeadlineExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the deadline of the request currently being handled. The deadline is unbounded if no request is being handled.
+    pub(crate) fn current() -> Self {
+        RequestContext::current().map_or_else(Self::default, |context| context.deadline)
+    }
+
+    /// Reads the deadline from the given properties of a request.
+    pub(crate) fn of_properties(properties: &AMQPProperties) -> Self {
+        let millis = properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(Self::HEADER))
+            .and_then(|millis| match millis {
+                AMQPValue::LongLongInt(millis) => u64::try_from(*millis).ok(),
+                AMQPValue::LongInt(millis) => u64::try_from(*millis).ok(),
+                AMQPValue::LongUInt(millis) => Some((*millis).into()),
+                AMQPValue::Timestamp(millis) => Some(*millis),
+                _ => None,
+            });
+
+        Self(millis.and_then(|millis| UNIX_EPOCH.checked_add(Duration::from_millis(millis))))
+    }
+
+    /// Returns the given properties with the deadline of the request currently being handled, for requests published by kanin's client-side helpers.
+    ///
+    /// Properties that already have a deadline are returned as is.
+    pub(crate) fn propagate_current(properties: BasicProperties) -> BasicProperties {
+        let deadline = Self::current();
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        if deadline.0.is_none() || headers.inner().contains_key(Self::HEADER) {
+            return properties;
+        }
+        deadline.propagate(&mut headers);
+        properties.with_headers(headers)
+    }
+
+    /// Sets the deadline header in the given headers, so the deadline is propagated to a downstream call.
+    ///
+    /// Nothing is set if the deadline is unbounded.
+    pub fn propagate(&self, headers: &mut FieldTable) {
+        let millis = self
+            .0
+            .and_then(|deadline| deadline.duration_since(UNIX_EPOCH).ok())
+            .and_then(|since_epoch| i64::try_from(since_epoch.as_millis()).ok());
+
+        if let Some(millis) = millis {
+            headers.insert(Self::HEADER.into(), AMQPValue::LongLongInt(millis));
+        }
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for Deadline
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Ok(Self::of_properties(req.properties()))
+    }
+}
@@ -0,0 +1,57 @@
+//! Delivery counts of messages on quorum queues.
+
+use std::convert::Infallible;
+
+use async_trait::async_trait;
+use lapin::{message::Delivery, types::AMQPValue};
+
+use crate::{Extract, Request};
+
+/// The number of times the message has previously been delivered, as reported by the `x-delivery-count` header.
+///
+/// Quorum queues set this header on redelivered messages. If the header is missing (for instance on the first delivery,
+/// or on queues that do not track delivery counts) the count is 0.
+///
+/// Combined with [`HandlerConfig::with_delivery_limit`](crate::HandlerConfig::with_delivery_limit), this allows handlers
+/// to adjust their behavior on the last attempt, for instance by writing a failure record instead of failing again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryCount(pub u64);
+
+impl DeliveryCount {
+    /// Returns true if this is the last delivery attempt of the message under the given delivery limit.
+    ///
+    /// When a message has been redelivered more times than the limit, the broker drops or dead-letters it.
+    pub fn is_last_attempt(&self, delivery_limit: u32) -> bool {
+        self.0 >= u64::from(delivery_limit)
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for DeliveryCount
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Ok(Self(delivery_count(req.delivery())))
+    }
+}
+
+/// Reads the `x-delivery-count` header of the delivery, defaulting to 0 if it is missing.
+pub(crate) fn delivery_count(delivery: &Delivery) -> u64 {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get("x-delivery-count"))
+        .and_then(|count| match count {
+            AMQPValue::LongLongInt(count) => u64::try_from(*count).ok(),
+            AMQPValue::LongInt(count) => u64::try_from(*count).ok(),
+            AMQPValue::LongUInt(count) => Some((*count).into()),
+            AMQPValue::ShortInt(count) => u64::try_from(*count).ok(),
+            AMQPValue::ShortUInt(count) => Some((*count).into()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
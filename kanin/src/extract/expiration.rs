@@ -0,0 +1,65 @@
+//! Per-message expiration.
+
+use std::{
+    convert::Infallible,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use lapin::BasicProperties;
+
+use crate::{Extract, Request};
+
+/// The per-message time-to-live of a request, as given by the caller in the `expiration` property.
+///
+/// AMQP carries the expiration as a string of milliseconds. If the request has no expiration, or it cannot be parsed, this is `None`.
+/// See also [RabbitMQ's documentation](https://www.rabbitmq.com/ttl.html#per-message-ttl-in-publishers).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Expiration(pub Option<Duration>);
+
+impl Expiration {
+    /// Reads the expiration from the given message properties.
+    pub fn of_properties(properties: &BasicProperties) -> Self {
+        let millis = properties
+            .expiration()
+            .as_ref()
+            .and_then(|expiration| expiration.as_str().trim().parse::<u64>().ok());
+
+        Self(millis.map(Duration::from_millis))
+    }
+
+    /// Returns the time at which the message expires, based on its `timestamp` property.
+    ///
+    /// Returns `None` if the message has no expiration or no timestamp.
+    /// As the timestamp only has a precision of seconds, a second is added to it, so a message is never considered expired too early.
+    pub fn expires_at(&self, properties: &BasicProperties) -> Option<SystemTime> {
+        let ttl = self.0?;
+        let timestamp = (*properties.timestamp())?;
+
+        UNIX_EPOCH
+            .checked_add(Duration::from_secs(timestamp.saturating_add(1)))?
+            .checked_add(ttl)
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for Expiration
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Ok(Self::of_properties(req.properties()))
+    }
+}
+
+/// Returns true if the message with the given properties expired before the given time, i.e. while it was in flight.
+///
+/// The broker only drops expired messages when they reach the head of the queue,
+/// so messages that expire after being delivered (e.g. while waiting in the prefetch buffer) still reach the consumer.
+pub(crate) fn expired_in_flight(properties: &BasicProperties, now: SystemTime) -> bool {
+    Expiration::of_properties(properties)
+        .expires_at(properties)
+        .map_or(false, |expires_at| expires_at <= now)
+}
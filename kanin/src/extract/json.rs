@@ -0,0 +1,76 @@
+//! Allows extracting and responding with JSON messages.
+
+use async_trait::async_trait;
+use derive_more::{Deref, DerefMut};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    error::{FromError, HandlerError, RequestError},
+    response::{self, JSON_CONTENT_TYPE},
+    Extract, Request, Respond,
+};
+
+/// A JSON message, for services whose peers do not speak protobuf.
+///
+/// As an extractor, the payload of the request is decoded as JSON via [`serde_json`].
+/// Messages without a content type are assumed to be JSON, while messages with any content type other than `application/json`
+/// are rejected as invalid requests.
+///
+/// As a response, the inner type is serialized as JSON and the reply has the `application/json` content type.
+/// Responses constructed from errors use the [`FromError`] implementation of the inner type.
+#[derive(Debug, Clone, PartialEq, Eq, Deref, DerefMut)]
+pub struct Json<T>(pub T);
+
+impl<T> Json<T> {
+    /// Returns the inner value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[async_trait]
+impl<S, T> Extract<S> for Json<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Error = HandlerError;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        // Parameters of the content type, such as the charset, are ignored.
+        if let Some(content_type) = req.properties().content_type() {
+            let media_type = content_type.as_str().split(';').next().unwrap_or_default();
+            if !media_type.trim().eq_ignore_ascii_case(JSON_CONTENT_TYPE) {
+                return Err(HandlerError::InvalidRequest(
+                    RequestError::UnsupportedContentType(content_type.to_string()),
+                ));
+            }
+        }
+
+        serde_json::from_slice(&req.delivery().data)
+            .map(Json)
+            .map_err(|e| HandlerError::InvalidRequest(RequestError::JsonError(e)))
+    }
+}
+
+impl<T> Respond for Json<T>
+where
+    T: Serialize + std::fmt::Debug + Send,
+{
+    fn respond(self) -> Vec<u8> {
+        response::encode_json(&self.0)
+    }
+
+    fn content_type(&self) -> &str {
+        JSON_CONTENT_TYPE
+    }
+}
+
+impl<T> FromError<HandlerError> for Json<T>
+where
+    T: FromError<HandlerError>,
+{
+    fn from_error(error: HandlerError) -> Self {
+        Self(T::from_error(error))
+    }
+}
@@ -3,13 +3,73 @@
 use async_trait::async_trait;
 use derive_more::{Deref, DerefMut};
 use prost::Message as ProstMessage;
+use tracing::warn;
 
-use crate::{error::HandlerError, Extract, Request};
+use crate::{
+    error::{HandlerError, PayloadDiagnostics, RequestError},
+    Extract, Request,
+};
 
 /// A simple wrapper that allows you to extract a protobuf message.
+///
+/// Messages without a content type are assumed to be protobuf, while messages with a content type that is not protobuf
+/// are rejected as invalid requests. To extract JSON messages, use `Json` instead, which requires the `json` feature.
 #[derive(Debug, Deref, DerefMut)]
 pub struct Msg<T>(pub T);
 
+/// The content types that are decoded as protobuf.
+const PROTOBUF_CONTENT_TYPES: &[&str] = &[
+    "application/octet-stream",
+    "application/protobuf",
+    "application/x-protobuf",
+    "application/vnd.google.protobuf",
+];
+
+/// Checks that the content type of the message is protobuf, ignoring any parameters (such as `charset`).
+fn check_content_type<S>(req: &Request<S>) -> Result<(), RequestError> {
+    let content_type = match req.properties().content_type() {
+        Some(content_type) => content_type.as_str(),
+        None => return Ok(()),
+    };
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+    if media_type.is_empty()
+        || PROTOBUF_CONTENT_TYPES
+            .iter()
+            .any(|protobuf| media_type.eq_ignore_ascii_case(protobuf))
+    {
+        return Ok(());
+    }
+
+    Err(RequestError::UnsupportedContentType(
+        content_type.to_string(),
+    ))
+}
+
+/// Decodes the payload of the request as protobuf, including payload diagnostics in the error if they are enabled.
+fn decode_protobuf<S, D>(req: &Request<S>) -> Result<D, HandlerError>
+where
+    D: Default + ProstMessage,
+{
+    let payload = &req.delivery().data[..];
+    D::decode(payload).map_err(|error| {
+        let sample_len = match req.payload_diagnostics {
+            Some(sample_len) => sample_len,
+            None => return error.into(),
+        };
+
+        let content_type = req
+            .properties()
+            .content_type()
+            .as_ref()
+            .map(|content_type| content_type.to_string());
+        let diagnostics = PayloadDiagnostics::new(payload, sample_len, content_type);
+        warn!("Failed to decode message: {error:#} ({diagnostics})");
+
+        HandlerError::InvalidRequest(RequestError::UndecodableMessage { error, diagnostics })
+    })
+}
+
 /// Extract implementation for protobuf messages.
 #[async_trait]
 impl<S, D> Extract<S> for Msg<D>
@@ -20,6 +80,7 @@ where
     type Error = HandlerError;
 
     async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
-        Ok(Msg(D::decode(&req.delivery().data[..])?))
+        check_content_type(req).map_err(HandlerError::InvalidRequest)?;
+        decode_protobuf(req).map(Msg)
     }
 }
@@ -0,0 +1,36 @@
+//! Allows extracting protobuf messages along with their original bytes.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{error::HandlerError, extract::Msg, Extract, Request};
+
+/// Like [`Msg`], but also gives access to the raw payload the message was decoded from.
+///
+/// This is useful for handlers that must forward or store the exact original payload while also inspecting it.
+/// Re-encoding the decoded message is not guaranteed to produce the same bytes, e.g. if the message contains unknown fields
+/// or if it was encoded by a different protobuf implementation.
+#[derive(Debug)]
+pub struct MsgWithRaw<T> {
+    /// The decoded message.
+    pub msg: T,
+    /// The raw payload the message was decoded from.
+    pub raw: Bytes,
+}
+
+#[async_trait]
+impl<S, D> Extract<S> for MsgWithRaw<D>
+where
+    S: Send + Sync,
+    Msg<D>: Extract<S, Error = HandlerError>,
+    D: Send,
+{
+    type Error = HandlerError;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        let Msg(msg) = Msg::extract(req).await?;
+        let raw = Bytes::copy_from_slice(&req.delivery().data);
+
+        Ok(Self { msg, raw })
+    }
+}
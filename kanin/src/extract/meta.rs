@@ -0,0 +1,54 @@
+//! Metadata attached to messages. See [`WithMeta`](crate::response::WithMeta).
+
+use std::{collections::BTreeMap, convert::Infallible};
+
+use async_trait::async_trait;
+use lapin::types::{AMQPValue, FieldTable};
+
+use crate::{response::META_HEADER_PREFIX, Extract, Request};
+
+/// Metadata attached to the message, e.g. by a server replying with [`WithMeta`](crate::response::WithMeta).
+///
+/// Use this in handlers that receive replies. The map is empty if the message has no metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Meta(pub BTreeMap<String, String>);
+
+impl Meta {
+    /// Reads the metadata from the given message headers, e.g. of a reply received directly via `lapin`.
+    ///
+    /// Metadata headers that do not contain strings are ignored.
+    pub fn from_headers(headers: &FieldTable) -> Self {
+        let meta = headers
+            .inner()
+            .iter()
+            .filter_map(|(key, value)| {
+                let key = key.as_str().strip_prefix(META_HEADER_PREFIX)?;
+                let value = match value {
+                    AMQPValue::LongString(value) => value.to_string(),
+                    AMQPValue::ShortString(value) => value.to_string(),
+                    _ => return None,
+                };
+                Some((key.to_string(), value))
+            })
+            .collect();
+
+        Self(meta)
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for Meta
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Ok(req
+            .properties()
+            .headers()
+            .as_ref()
+            .map(Self::from_headers)
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,40 @@
+//! Allows extracting protobuf messages that are required to have at least one non-default field.
+
+use async_trait::async_trait;
+use derive_more::{Deref, DerefMut};
+use prost::Message as ProstMessage;
+
+use crate::{
+    error::{HandlerError, RequestError},
+    extract::Msg,
+    Extract, Request,
+};
+
+/// Like [`Msg`], but rejects messages where every field has its default value.
+///
+/// In protobuf, any message decodes successfully from an empty payload. A message with only default fields
+/// is therefore a classic symptom of a message of a different type being published to the wrong route,
+/// which would otherwise go unnoticed. Such messages are rejected with [`RequestError::DefaultMessage`].
+#[derive(Debug, Deref, DerefMut)]
+pub struct NonDefault<T>(pub T);
+
+#[async_trait]
+impl<S, D> Extract<S> for NonDefault<D>
+where
+    S: Send + Sync,
+    D: ProstMessage,
+    Msg<D>: Extract<S, Error = HandlerError>,
+{
+    type Error = HandlerError;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        let Msg(msg) = Msg::<D>::extract(req).await?;
+
+        // Fields with default values are not encoded, so a message with only default fields encodes to nothing.
+        if msg.encoded_len() == 0 {
+            return Err(HandlerError::InvalidRequest(RequestError::DefaultMessage));
+        }
+
+        Ok(NonDefault(msg))
+    }
+}
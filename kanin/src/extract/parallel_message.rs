@@ -0,0 +1,57 @@
+//! Allows extracting large protobuf messages without blocking the async runtime.
+
+use async_trait::async_trait;
+use derive_more::{Deref, DerefMut};
+use prost::Message as ProstMessage;
+
+use crate::{
+    error::{HandlerError, ServerError},
+    Extract, Request,
+};
+
+/// The default payload size in bytes above which [`ParallelMsg`] decodes on a blocking thread (1 MiB).
+pub const DEFAULT_PARALLEL_THRESHOLD: usize = 1024 * 1024;
+
+/// Like [`Msg`](crate::extract::Msg), but decodes payloads larger than `THRESHOLD` bytes using [`tokio::task::spawn_blocking`].
+///
+/// Decoding a message that is several megabytes large (for instance one with large repeated fields) can take long enough
+/// to stall the other tasks on the same runtime thread. Payloads up to the threshold are decoded in place, just like [`Msg`](crate::extract::Msg).
+///
+/// Larger payloads are copied and decoded on a blocking thread instead.
+///
+/// The threshold defaults to [`DEFAULT_PARALLEL_THRESHOLD`], but can be changed through the const parameter, e.g. `ParallelMsg<MyRequest, 65536>`.
+#[derive(Debug, Deref, DerefMut)]
+pub struct ParallelMsg<T, const THRESHOLD: usize = DEFAULT_PARALLEL_THRESHOLD>(pub T);
+
+/// Extract implementation for protobuf messages that are decoded on a blocking thread if they are large.
+#[async_trait]
+impl<S, D, const THRESHOLD: usize> Extract<S> for ParallelMsg<D, THRESHOLD>
+where
+    S: Send + Sync,
+    D: Default + ProstMessage + 'static,
+{
+    type Error = HandlerError;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        decode_parallel(&req.delivery().data, THRESHOLD)
+            .await
+            .map(ParallelMsg)
+    }
+}
+
+/// Decodes the given payload, on a blocking thread if it is larger than the given threshold.
+pub(crate) async fn decode_parallel<D>(data: &[u8], threshold: usize) -> Result<D, HandlerError>
+where
+    D: Default + ProstMessage + 'static,
+{
+    if data.len() <= threshold {
+        return Ok(D::decode(data)?);
+    }
+
+    let data = data.to_vec();
+    let decoded = tokio::task::spawn_blocking(move || D::decode(&data[..]))
+        .await
+        .map_err(|e| ServerError::Other(Box::new(e)))??;
+
+    Ok(decoded)
+}
@@ -0,0 +1,58 @@
+//! Allows extracting the common parts of a request at once.
+
+use async_trait::async_trait;
+use lapin::types::FieldTable;
+
+use crate::{
+    error::HandlerError,
+    extract::{Msg, Properties, ReqId},
+    Extract, Request,
+};
+
+/// The decoded message, headers, properties and request ID of a request, extracted at once.
+///
+/// This is an alternative to stacking [`Msg`], [`Properties`] and [`ReqId`] as separate handler arguments,
+/// and can be destructured right in the handler signature:
+///
+/// ```
+/// # use kanin::extract::Parts;
+/// async fn handler(Parts(msg, headers, properties, req_id): Parts<()>) {
+///     // ...
+/// }
+/// ```
+///
+/// The message is decoded like [`Msg`]. The headers are empty if the request has none.
+#[derive(Debug)]
+pub struct Parts<T>(pub T, pub FieldTable, pub Properties, pub ReqId);
+
+impl<T> Parts<T> {
+    /// Returns the parts as a tuple of the message, headers, properties and request ID.
+    pub fn into_inner(self) -> (T, FieldTable, Properties, ReqId) {
+        (self.0, self.1, self.2, self.3)
+    }
+}
+
+impl<T> From<Parts<T>> for (T, FieldTable, Properties, ReqId) {
+    fn from(parts: Parts<T>) -> Self {
+        parts.into_inner()
+    }
+}
+
+#[async_trait]
+impl<S, D> Extract<S> for Parts<D>
+where
+    S: Send + Sync,
+    Msg<D>: Extract<S, Error = HandlerError>,
+    D: Send,
+{
+    type Error = HandlerError;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        let Msg(msg) = Msg::extract(req).await?;
+        let properties = req.properties().clone();
+        let headers = properties.headers().clone().unwrap_or_default();
+        let req_id = req.req_id().clone();
+
+        Ok(Self(msg, headers, Properties(properties), req_id))
+    }
+}
@@ -0,0 +1,99 @@
+//! Interim progress notifications for long-running requests.
+
+use std::convert::Infallible;
+
+use async_trait::async_trait;
+use lapin::{
+    options::BasicPublishOptions,
+    types::{AMQPValue, FieldTable, ShortString},
+    BasicProperties, Channel,
+};
+use tracing::debug;
+
+use crate::{extract::Baggage, Extract, HandlerConfig, Request, Respond};
+
+/// An extractor for publishing interim progress messages to the caller before the final reply.
+///
+/// Progress messages are published to the `reply_to` queue of the request with the same `correlation_id` as the final reply,
+/// and with the [`Progress::HEADER`] header set, so callers can tell them apart from the final reply.
+/// This is useful for long-running requests, where callers may want to show progress.
+///
+/// If the request has no `reply_to` property, progress messages are silently dropped.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    /// The channel to publish progress messages on.
+    channel: Channel,
+    /// The exchange to publish progress messages to. See [`HandlerConfig::with_reply_mode`].
+    exchange: ShortString,
+    /// The routing key to publish progress messages with, if the request had one. This is usually the `reply_to` property of the request.
+    reply_to: Option<ShortString>,
+    /// The correlation ID of the request, if it had one.
+    correlation_id: Option<ShortString>,
+    /// The baggage of the request, propagated on progress messages.
+    baggage: Baggage,
+}
+
+impl Progress {
+    /// The header set on progress messages. Its value is `true`.
+    pub const HEADER: &'static str = "x-progress";
+
+    /// Publishes the given progress message to the caller.
+    ///
+    /// # Errors
+    /// Returns `Err` if the progress message could not be published.
+    pub async fn publish(&self, progress: impl Respond) -> Result<(), lapin::Error> {
+        let reply_to = match &self.reply_to {
+            Some(reply_to) => reply_to,
+            None => {
+                debug!("Dropping progress message as the request did not contain a `reply_to` property.");
+                return Ok(());
+            }
+        };
+
+        let mut headers = FieldTable::default();
+        headers.insert(Self::HEADER.into(), AMQPValue::Boolean(true));
+        self.baggage.propagate(&mut headers);
+
+        let mut properties = BasicProperties::default()
+            .with_headers(headers)
+            .with_content_type(progress.content_type().into());
+        if let Some(correlation_id) = &self.correlation_id {
+            properties = properties.with_correlation_id(correlation_id.clone());
+        }
+
+        self.channel
+            .basic_publish(
+                self.exchange.as_str(),
+                reply_to.as_str(),
+                BasicPublishOptions::default(),
+                &progress.respond(),
+                properties,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for Progress
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        let (exchange, reply_to) = match req.reply_target() {
+            Some((exchange, reply_to)) => (exchange, Some(reply_to)),
+            None => (HandlerConfig::DEFAULT_EXCHANGE.into(), None),
+        };
+
+        Ok(Self {
+            channel: req.channel().clone(),
+            exchange,
+            reply_to,
+            correlation_id: req.properties().correlation_id().clone(),
+            baggage: Baggage::of_request(req),
+        })
+    }
+}
@@ -0,0 +1,35 @@
+//! AMQP properties of the request.
+
+use std::{convert::Infallible, ops::Deref};
+
+use async_trait::async_trait;
+use lapin::BasicProperties;
+
+use crate::{Extract, Request};
+
+/// All the AMQP properties of the incoming request.
+///
+/// kanin has extractors for commonly used properties (e.g. [`AppId`](crate::extract::AppId)).
+/// Use this to access the rest, such as `message_id`, `type`, `user_id` or `cluster_id`.
+#[derive(Debug, Clone)]
+pub struct Properties(pub BasicProperties);
+
+impl Deref for Properties {
+    type Target = BasicProperties;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for Properties
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Ok(Self(req.properties().clone()))
+    }
+}
@@ -0,0 +1,83 @@
+//! Channels for publishing from handlers.
+
+use std::{
+    convert::Infallible,
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use async_trait::async_trait;
+use lapin::{Channel, Connection};
+use tracing::debug;
+
+use crate::{Extract, Request};
+
+/// A channel for publishing messages from a handler, taken from the publisher channel pool of the app.
+///
+/// Extracting [`Channel`] gives the channel the handler consumes on, so publishing heavily on it contends with
+/// the deliveries and acks of the handler. This extractor instead takes a channel from a pool of channels dedicated to publishing,
+/// see [`App::with_publisher_channels`](crate::App::with_publisher_channels). If the app has no pool,
+/// or all of its channels have been closed (e.g. by publishing to an exchange that does not exist), the consumer channel is given instead.
+#[derive(Debug, Clone)]
+pub struct PublisherChannel(pub Channel);
+
+impl Deref for PublisherChannel {
+    type Target = Channel;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for PublisherChannel
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        let channel = match req.publisher_channels.as_ref().and_then(|pool| pool.get()) {
+            Some(channel) => channel,
+            None => {
+                debug!("No publisher channel available, publishing on the consumer channel.");
+                req.channel().clone()
+            }
+        };
+
+        Ok(Self(channel))
+    }
+}
+
+/// A pool of channels dedicated to publishing, shared by all handlers of an app. See [`PublisherChannel`].
+#[derive(Debug)]
+pub(crate) struct ChannelPool {
+    /// The channels of the pool.
+    channels: Vec<Channel>,
+    /// The index of the channel to give out next. Channels are given out round-robin.
+    next: AtomicUsize,
+}
+
+impl ChannelPool {
+    /// Opens a pool with the given number of channels on the given connection.
+    pub(crate) async fn open(conn: &Connection, size: usize) -> lapin::Result<Self> {
+        let mut channels = Vec::with_capacity(size);
+        for _ in 0..size {
+            channels.push(conn.create_channel().await?);
+        }
+
+        Ok(Self {
+            channels,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the next channel of the pool that is still open, if any.
+    pub(crate) fn get(&self) -> Option<Channel> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.channels.len())
+            .map(|offset| &self.channels[(start + offset) % self.channels.len()])
+            .find(|channel| channel.status().connected())
+            .cloned()
+    }
+}
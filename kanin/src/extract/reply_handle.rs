@@ -0,0 +1,118 @@
+//! Deferred replies, for handlers whose result arrives after the handler returns.
+
+use std::{convert::Infallible, sync::Arc};
+
+use async_trait::async_trait;
+use lapin::{types::FieldTable, Channel};
+use tracing::{debug, warn};
+
+use crate::{
+    app::reply::{self, Reply, ReplySettings, ReplyTarget},
+    error::ReplyError,
+    extract::Baggage,
+    request, Extract, HandlerConfig, Request, Respond,
+};
+
+/// An extractor for replying to a request after the handler has returned.
+///
+/// Extracting a `ReplyHandle` defers the reply: the handler's own response is not published,
+/// and the request is acked as soon as the handler returns. The handle can then be moved to a background task
+/// (see [`Spawner`](crate::extract::Spawner)) which publishes the actual reply with [`ReplyHandle::reply`],
+/// e.g. once a callback from another system arrives. Handlers extracting a `ReplyHandle` will usually return `()`.
+///
+/// The reply is published like the handler's own response would have been: with the handler's reply TTL, reply mode,
+/// reply publish options and `CC` headers, compressed if the caller accepts it, kept in the app's reply store if publishing fails,
+/// and reported to the handler's [reply result hook](HandlerConfig::on_reply_result). Middleware is not applied to deferred replies.
+///
+/// Note that since the request is acked before the reply is published, the request will not be redelivered
+/// if the reply is never published. If the request has no `reply_to` property, the reply is silently dropped.
+#[derive(Debug)]
+pub struct ReplyHandle {
+    /// The channel to publish the reply on.
+    channel: Channel,
+    /// Where to publish the reply, if the request can be replied to.
+    target: Option<ReplyTarget>,
+    /// The reply settings of the handler that extracted the handle.
+    settings: Arc<ReplySettings>,
+    /// The baggage of the request, propagated on the reply.
+    baggage: Baggage,
+    /// The `CC` header of the request, if the handler replies to them. See [`HandlerConfig::with_reply_cc`].
+    cc_headers: FieldTable,
+}
+
+impl ReplyHandle {
+    /// Publishes the given response as the reply to the request.
+    ///
+    /// # Errors
+    /// Returns `Err` if the reply could not be published, or was returned or not confirmed by the broker.
+    pub async fn reply(self, response: impl Respond) -> Result<(), ReplyError> {
+        let target = match &self.target {
+            Some(target) => target,
+            None => {
+                debug!(
+                    "Dropping deferred reply as the request did not contain a `reply_to` property."
+                );
+                return Ok(());
+            }
+        };
+
+        if self.settings.already_replied(target) {
+            debug!(
+                "Skipping deferred reply as the caller was already sent a reply to this request."
+            );
+            return Ok(());
+        }
+
+        if target.correlation_id.is_none() {
+            warn!("Deferred reply is for a request without a `correlation_id` property. The receiver may not recognize it as the reply for their request.");
+        }
+
+        let mut headers = response.reply_headers();
+        self.baggage.propagate(&mut headers);
+        for (key, value) in &self.cc_headers {
+            headers.insert(key.clone(), value.clone());
+        }
+
+        let reply = Reply {
+            content_type: response.content_type().into(),
+            reply_ttl: response.reply_ttl(),
+            payload: response.respond(),
+            headers,
+        };
+        reply::publish(&self.settings, &self.channel, target, reply).await
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for ReplyHandle
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        req.reply_deferred = true;
+
+        // Requests handled outside of an app are replied to with the default settings.
+        let settings = req.reply_settings.clone().unwrap_or_else(|| {
+            Arc::new(ReplySettings::new(
+                req.delivery().routing_key.as_str(),
+                &HandlerConfig::default(),
+                None,
+                None,
+            ))
+        });
+        let mut cc_headers = FieldTable::default();
+        if req.reply_cc {
+            request::copy_cc_headers(req.properties(), &mut cc_headers);
+        }
+
+        Ok(Self {
+            channel: req.channel().clone(),
+            target: ReplyTarget::of_request(req),
+            settings,
+            baggage: Baggage::of_request(req),
+            cc_headers,
+        })
+    }
+}
@@ -7,10 +7,13 @@ use async_trait::async_trait;
 use lapin::{
     message::Delivery,
     types::{AMQPValue, LongString},
+    BasicProperties,
 };
+use tracing::warn;
+use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::{Extract, Request};
+use crate::{context::RequestContext, Extract, Request};
 
 /// Request IDs allow concurrent logs to be associated with a unique request. It can also enable requests
 /// to be traced between different services by propagating the request IDs when calling other services.
@@ -26,18 +29,22 @@ impl ReqId {
         Self(amqp_value)
     }
 
-    /// Create a [`ReqId`] from an AMQP Delivery. If no `req_id` is found in the headers of the
-    /// message then a new one is created.
-    pub(crate) fn from_delivery(delivery: &Delivery) -> Self {
-        let Some(headers) = delivery.properties.headers() else {
-            return Self::new();
+    /// Returns the given properties with a request ID, for requests published by kanin's client-side helpers.
+    ///
+    /// While handling a request, its request ID is propagated in the header it was read from.
+    /// Otherwise, a new request ID is set in [`ReqIdPolicy::DEFAULT_HEADER`]. Properties that already have a request ID are returned as is.
+    pub(crate) fn propagate_current(properties: BasicProperties) -> BasicProperties {
+        let (header, req_id) = match RequestContext::current() {
+            Some(context) => (context.req_id_header, context.req_id),
+            None => (ReqIdPolicy::DEFAULT_HEADER.to_string(), Self::new()),
         };
 
-        let Some(req_id) = headers.inner().get("req_id") else {
-            return Self::new();
-        };
-
-        Self(req_id.clone())
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        if headers.inner().contains_key(header.as_str()) {
+            return properties;
+        }
+        headers.insert(header.into(), req_id.0);
+        properties.with_headers(headers)
     }
 }
 
@@ -85,3 +92,161 @@ where
         Ok(req.req_id().clone())
     }
 }
+
+/// The format of request IDs. See [`ReqIdPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ReqIdFormat {
+    /// Random UUIDs (version 4).
+    UuidV4,
+    /// Time-ordered UUIDs (version 7).
+    UuidV7,
+    /// [ULIDs](https://github.com/ulid/spec), which are lexicographically sortable.
+    Ulid,
+    /// Any incoming request ID is accepted as is. Request IDs are generated by [`ReqId::new`], i.e. as random UUIDs (version 4),
+    /// unless the app has a [generator](crate::App::with_req_id_generator).
+    #[default]
+    PropagateAny,
+}
+
+impl ReqIdFormat {
+    /// Generates a new request ID in this format.
+    pub fn generate(self) -> ReqId {
+        let req_id = match self {
+            ReqIdFormat::PropagateAny => return ReqId::new(),
+            ReqIdFormat::UuidV4 => Uuid::new_v4().to_string(),
+            ReqIdFormat::UuidV7 => Uuid::now_v7().to_string(),
+            ReqIdFormat::Ulid => Ulid::new().to_string(),
+        };
+
+        ReqId(AMQPValue::LongString(LongString::from(req_id)))
+    }
+
+    /// Returns true if the given request ID is in this format.
+    ///
+    /// Request IDs must be strings to be valid in any format but [`ReqIdFormat::PropagateAny`].
+    pub fn is_valid(self, req_id: &AMQPValue) -> bool {
+        if self == ReqIdFormat::PropagateAny {
+            return true;
+        }
+
+        let req_id = match req_id {
+            AMQPValue::LongString(req_id) => String::from_utf8_lossy(req_id.as_bytes()),
+            AMQPValue::ShortString(req_id) => req_id.as_str().into(),
+            _ => return false,
+        };
+
+        match self {
+            ReqIdFormat::UuidV4 => {
+                matches!(Uuid::parse_str(&req_id), Ok(uuid) if uuid.get_version_num() == 4)
+            }
+            ReqIdFormat::UuidV7 => {
+                matches!(Uuid::parse_str(&req_id), Ok(uuid) if uuid.get_version_num() == 7)
+            }
+            ReqIdFormat::Ulid => Ulid::from_string(&req_id).is_ok(),
+            ReqIdFormat::PropagateAny => true,
+        }
+    }
+}
+
+/// Determines how request IDs are read from incoming requests and how new ones are generated.
+///
+/// By default, request IDs are read from the `req_id` header and any value is accepted. Requests without a request ID are given a random UUID.
+/// With any other [`ReqIdFormat`], incoming request IDs that are not in the given format are replaced with a newly generated one.
+///
+/// See [`App::with_req_id_policy`](crate::App::with_req_id_policy).
+#[derive(Debug, Clone)]
+pub struct ReqIdPolicy {
+    /// The header that holds the request ID.
+    header: String,
+    /// The format of request IDs.
+    format: ReqIdFormat,
+    /// Generates new request IDs for [`ReqIdFormat::PropagateAny`], if set. See [`App::with_req_id_generator`](crate::App::with_req_id_generator).
+    generator: Option<fn() -> AMQPValue>,
+}
+
+impl ReqIdPolicy {
+    /// The default header that holds the request ID.
+    pub const DEFAULT_HEADER: &'static str = "req_id";
+
+    /// Creates a new policy for request IDs of the given format, read from the default header.
+    pub fn new(format: ReqIdFormat) -> Self {
+        Self {
+            header: Self::DEFAULT_HEADER.to_string(),
+            format,
+            generator: None,
+        }
+    }
+
+    /// Sets the header that holds the request ID. Defaults to [`ReqIdPolicy::DEFAULT_HEADER`].
+    pub fn with_header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        self
+    }
+
+    /// Returns the header that holds the request ID.
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Returns the format of request IDs.
+    pub fn format(&self) -> ReqIdFormat {
+        self.format
+    }
+
+    /// Sets the generator of new request IDs. See [`App::with_req_id_generator`](crate::App::with_req_id_generator).
+    pub(crate) fn with_generator(mut self, generator: Option<fn() -> AMQPValue>) -> Self {
+        self.generator = generator;
+        self
+    }
+
+    /// Generates a new request ID according to this policy.
+    ///
+    /// The generator is only used when any format is accepted, as it may produce request IDs of any format.
+    pub(crate) fn generate(&self) -> ReqId {
+        match (self.format, self.generator) {
+            (ReqIdFormat::PropagateAny, Some(generator)) => ReqId(generator()),
+            (format, _) => format.generate(),
+        }
+    }
+
+    /// Reads the request ID of the given delivery according to this policy.
+    ///
+    /// If the delivery has no request ID, or its request ID is not in the right format, a new request ID is generated.
+    pub(crate) fn req_id(&self, delivery: &Delivery) -> ReqId {
+        let req_id = delivery
+            .properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(self.header.as_str()));
+
+        match req_id {
+            Some(req_id) if self.format.is_valid(req_id) => ReqId(req_id.clone()),
+            Some(req_id) => {
+                let new_req_id = self.generate();
+                warn!(
+                    "Replacing malformed request ID {} (expected format {:?}) with {new_req_id}.",
+                    ReqId(req_id.clone()),
+                    self.format
+                );
+                new_req_id
+            }
+            None => self.generate(),
+        }
+    }
+}
+
+/// Policies are compared by their header and format. Generators are not compared, as function pointers cannot be compared meaningfully.
+impl PartialEq for ReqIdPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.format == other.format
+    }
+}
+
+impl Eq for ReqIdPolicy {}
+
+impl Default for ReqIdPolicy {
+    fn default() -> Self {
+        Self::new(ReqIdFormat::default())
+    }
+}
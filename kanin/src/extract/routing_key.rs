@@ -0,0 +1,26 @@
+//! The routing key of the incoming request.
+
+use std::convert::Infallible;
+
+use async_trait::async_trait;
+
+use crate::{Extract, Request};
+
+/// The routing key the incoming request was published with.
+///
+/// This is not necessarily the routing key the handler was registered on, e.g. when the queue is bound with
+/// a pattern on a topic exchange (see [`HandlerConfig::with_binding_key`](crate::HandlerConfig::with_binding_key)).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingKey(pub String);
+
+#[async_trait]
+impl<S> Extract<S> for RoutingKey
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Ok(Self(req.delivery().routing_key.to_string()))
+    }
+}
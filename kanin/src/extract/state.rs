@@ -1,11 +1,18 @@
 //! Allows extracting app state.
 
-use std::convert::Infallible;
+use std::{
+    any::type_name,
+    error::Error,
+    panic::{self, AssertUnwindSafe},
+};
 
 use async_trait::async_trait;
 use derive_more::{Deref, DerefMut};
+use tracing::error;
 
-use crate::{Extract, Request};
+use crate::{
+    app::panic::panic_message, error::ServerError, request::Scope, Extract, HandlerError, Request,
+};
 
 /// `State` is an extractor helper struct that allows you to extract app state from the state type added in `App::new`.
 ///
@@ -13,6 +20,11 @@ use crate::{Extract, Request};
 ///
 /// Any type that implements `From<&S>` where `S` is the app state given in `App::new` can be extracted via this type.
 /// These `From` implementations can be derived on a struct via `kanin::AppState`.
+/// If such a `From` implementation panics, the request is responded to with [`ServerError::StatePanicked`] instead.
+/// Values whose creation requires awaiting can be extracted by implementing [`FromStateAsync`] instead.
+///
+/// More generally, any type `T` for which the app state implements [`Provider<T>`] can be extracted via this type.
+/// See [`Provider`] for how to use this for dependency injection with per-request scope.
 ///
 /// # Example
 /// ```
@@ -25,6 +37,26 @@ use crate::{Extract, Request};
 ///     assert_eq!(42, num);
 /// }
 /// ```
+///
+/// State structs can be composed by flattening fields whose types also derive `AppState`:
+/// ```
+/// # use kanin::{AppState, extract::State};
+/// mod db {
+///     #[derive(kanin::AppState)]
+///     pub struct DbState {
+///         pub url: String,
+///     }
+/// }
+///
+/// #[derive(AppState)]
+/// struct AppState {
+///     num: u8,
+///     #[app_state(flatten)]
+///     db: db::DbState,
+/// }
+///
+/// async fn my_handler(State(url): State<String>, State(num): State<u8>) {}
+/// ```
 #[derive(Debug, Deref, DerefMut)]
 pub struct State<T>(pub T);
 
@@ -34,16 +66,172 @@ impl<T: Clone> Clone for State<T> {
     }
 }
 
+/// Types that can provide values of type `T` to handlers, usually implemented by the app state.
+///
+/// This is what [`State<T>`] resolves through. It is implemented automatically for any `T` that implements `From<&S>` or [`FromStateAsync<S>`],
+/// but you can implement it yourself on your app state for other types, in which case you have full control over how values are created.
+/// This enables a dependency injection style where the app state acts as a container of factories.
+///
+/// Every request has its own [`Scope`], which lives until the request has been handled. Providers can store values in the scope
+/// to share them between extractors of the same request, for instance to use the same database transaction for all of them.
+///
+/// # Example
+/// ```
+/// # use std::convert::Infallible;
+/// # use kanin::{extract::{Provider, State}, request::Scope};
+/// #[derive(Clone, Debug)]
+/// struct Transaction(u64);
+///
+/// struct AppState {
+///     database: String,
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Provider<Transaction> for AppState {
+///     type Error = Infallible;
+///
+///     async fn provide(&self, scope: &mut Scope) -> Result<Transaction, Self::Error> {
+///         // Reuse the transaction of this request if we already started one.
+///         if let Some(transaction) = scope.get::<Transaction>() {
+///             return Ok(transaction.clone());
+///         }
+///
+///         let transaction = Transaction(42);
+///         scope.insert(transaction.clone());
+///         Ok(transaction)
+///     }
+/// }
+///
+/// async fn my_handler(State(transaction): State<Transaction>) {
+///     assert_eq!(42, transaction.0);
+/// }
+/// ```
+#[async_trait]
+pub trait Provider<T>: Send + Sync {
+    /// The error to return in case the value could not be provided.
+    type Error: Error + Send;
+
+    /// Provides a value, possibly reusing or storing values in the scope of the current request.
+    async fn provide(&self, scope: &mut Scope) -> Result<T, Self::Error>;
+
+    /// Returns true if the given error is transient, i.e. providing the value might succeed if retried later.
+    ///
+    /// See [`Extract::is_transient`]. Defaults to false.
+    fn is_transient(error: &Self::Error) -> bool {
+        let _ = error;
+        false
+    }
+}
+
+/// Types that can be created from the app state asynchronously, so they can be extracted via [`State<T>`].
+///
+/// This is the asynchronous counterpart of `From<&S>`, for per-request values whose creation requires awaiting,
+/// such as database connections checked out from a pool. Errors are responded to as internal errors.
+/// It is implemented automatically for any `T` that implements `From<&S>`.
+///
+/// # Example
+/// ```
+/// # use kanin::{extract::{FromStateAsync, State}, App};
+/// struct Pool;
+///
+/// impl Pool {
+///     async fn get(&self) -> Result<Connection, std::io::Error> {
+///         Ok(Connection(42))
+///     }
+/// }
+///
+/// struct Connection(u64);
+///
+/// struct AppState {
+///     pool: Pool,
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl FromStateAsync<AppState> for Connection {
+///     type Error = std::io::Error;
+///
+///     async fn from_state(state: &AppState) -> Result<Self, Self::Error> {
+///         state.pool.get().await
+///     }
+/// }
+///
+/// async fn my_handler(State(connection): State<Connection>) {
+///     assert_eq!(42, connection.0);
+/// }
+///
+/// let app = App::new(AppState { pool: Pool }).handler("my_routing_key", my_handler);
+/// ```
+#[async_trait]
+pub trait FromStateAsync<S>: Sized {
+    /// The error to return in case the value could not be created.
+    type Error: Error + Send + Sync + 'static;
+
+    /// Creates a value from the app state.
+    async fn from_state(state: &S) -> Result<Self, Self::Error>;
+}
+
+/// Any type that can be constructed from a reference to the app state can also be created from it asynchronously.
+///
+/// Panics of the conversion are turned into [`ServerError::StatePanicked`] errors, so the request is responded to
+/// rather than the request task dying and the request being requeued over and over again.
+#[async_trait]
+impl<S, T> FromStateAsync<S> for T
+where
+    S: Sync,
+    T: for<'a> From<&'a S>,
+{
+    type Error = ServerError;
+
+    async fn from_state(state: &S) -> Result<Self, Self::Error> {
+        panic::catch_unwind(AssertUnwindSafe(|| T::from(state))).map_err(|payload| {
+            let state = type_name::<T>();
+            let message = panic_message(payload.as_ref()).to_string();
+            error!("Extracting state {state} panicked: {message}");
+            ServerError::StatePanicked { state, message }
+        })
+    }
+}
+
+/// Any type that can be created from the app state can be provided by the app state.
+///
+/// Errors are turned into [internal errors](HandlerError::InternalError).
+#[async_trait]
+impl<S, T> Provider<T> for S
+where
+    S: Send + Sync,
+    T: FromStateAsync<S>,
+{
+    type Error = HandlerError;
+
+    async fn provide(&self, _scope: &mut Scope) -> Result<T, Self::Error> {
+        T::from_state(self).await.map_err(|error| {
+            // Errors that are already server errors, such as panics of `From` conversions, are not wrapped again.
+            let error: Box<dyn Error + Send + Sync> = Box::new(error);
+            let error = match error.downcast::<ServerError>() {
+                Ok(error) => *error,
+                Err(error) => ServerError::Other(error),
+            };
+            HandlerError::InternalError(error)
+        })
+    }
+}
+
 /// Extract implementation for app state.
 #[async_trait]
 impl<S, T> Extract<S> for State<T>
 where
-    S: Send + Sync,
-    T: for<'a> From<&'a S>,
+    S: Provider<T> + Send + Sync,
+    T: Send,
 {
-    type Error = Infallible;
+    type Error = <S as Provider<T>>::Error;
 
     async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
-        Ok(Self(req.state::<T>()))
+        let state = req.app_state().clone();
+        let value = <S as Provider<T>>::provide(&state, req.scope_mut()).await?;
+        Ok(Self(value))
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        <S as Provider<T>>::is_transient(error)
     }
 }
@@ -5,7 +5,9 @@ use std::future::Future;
 
 use async_trait::async_trait;
 
-use crate::{error::FromError, extract::Extract, request::Request, response::Respond};
+use crate::{
+    error::FromError, extract::Extract, request::Request, response::Respond, HandlerError,
+};
 
 /// A trait for functions that can be used as handlers for incoming AMPQ messages.
 ///
@@ -14,6 +16,15 @@ use crate::{error::FromError, extract::Extract, request::Request, response::Resp
 pub trait Handler<Args, Res: Respond, S>: Send + 'static + Clone {
     /// Calls the handler with the given request.
     async fn call(self, req: &mut Request<S>) -> Res;
+
+    /// Returns the response of the handler to the given error, if it can respond to errors.
+    ///
+    /// This is used to respond to panics (see [`HandlerConfig::with_panic_replies`](crate::HandlerConfig::with_panic_replies))
+    /// and to check handlers when they are registered (see [`App::with_conformance_checks`](crate::App::with_conformance_checks)).
+    /// Handlers implemented for functions respond via [`FromError`]. By default, handlers don't respond to errors.
+    fn error_response(_error: HandlerError) -> Option<Res> {
+        None
+    }
 }
 
 /// Special-case the 0-args case to avoid unused variable warnings.
@@ -22,12 +33,16 @@ impl<Func, Fut, Res, S> Handler<(), Res, S> for Func
 where
     Func: FnOnce() -> Fut + Send + 'static + Clone,
     Fut: Future<Output = Res> + Send,
-    Res: Respond,
+    Res: Respond + FromError<HandlerError>,
     S: Send + Sync,
 {
     async fn call(self, _req: &mut Request<S>) -> Res {
         self().await
     }
+
+    fn error_response(error: HandlerError) -> Option<Res> {
+        Some(Res::from_error(error))
+    }
 }
 
 /// Implements the handler trait for any number of parameters for handlers that return a value.
@@ -43,12 +58,28 @@ macro_rules! impl_handler {
             S: Send + Sync,
             $( $ty: Extract<S> + Send,)*
             $( Res: FromError<<$ty as Extract<S>>::Error>,)*
+            Res: FromError<HandlerError>,
         {
             async fn call(self, req: &mut Request<S>) -> Res {
                 $(
-                    let $ty = match $ty::extract(req).await {
-                        Ok(value) => value,
+                    let $ty = match req.extract_with_timeout::<$ty>().await {
+                        Ok(Ok(value)) => value,
+                        // Transient errors are requeued, unless the request was already acked (e.g. by extracting an acker).
+                        Ok(Err(error)) if !req.acked && <$ty as Extract<S>>::is_transient(&error) => {
+                            req.report_extract_error::<$ty>(&error, true);
+                            tracing::warn!("Transient failure to extract {}, requeueing request: {error}", std::any::type_name::<$ty>());
+                            // The request is requeued after the handler returns, so the response is never sent.
+                            req.requeued = true;
+                            return Res::from_error(error);
+                        }
+                        Ok(Err(error)) => {
+                            req.report_extract_error::<$ty>(&error, false);
+                            tracing::error!("Failed to extract {}: {error}", std::any::type_name::<$ty>());
+                            return Res::from_error(error);
+                        }
+                        // The extractor timed out.
                         Err(error) => {
+                            req.report_extract_error::<$ty>(&error, false);
                             tracing::error!("Failed to extract {}: {error}", std::any::type_name::<$ty>());
                             return Res::from_error(error);
                         }
@@ -57,6 +88,10 @@ macro_rules! impl_handler {
 
                 self($($ty,)*).await
             }
+
+            fn error_response(error: HandlerError) -> Option<Res> {
+                Some(Res::from_error(error))
+            }
         }
     };
 }
@@ -1,12 +1,119 @@
 //! Handler configuration.
 
+use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
 
-use lapin::options::QueueDeclareOptions;
+use lapin::options::{BasicPublishOptions, QueueDeclareOptions};
 use lapin::types::{AMQPValue, FieldTable};
+use rand::Rng;
+use thiserror::Error as ThisError;
+use tracing::{warn, Level};
+
+use crate::error::ReplyError;
+use crate::instance::Instance;
+use crate::middleware::{ErasedMiddleware, Middleware};
+use crate::redelivery::RedeliveryStorm;
+
+/// A contradictory handler configuration that causes messages to be silently dropped. See [`HandlerConfig::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, ThisError)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub enum ConfigIssue {
+    /// The queue is durable, but also expires soon after it has no consumers,
+    /// i.e. within [`HandlerConfig::MIN_DURABLE_QUEUE_EXPIRES`].
+    /// If the handler is down for longer than `expires`, e.g. during a slow deploy, the queue is deleted along with any messages on it.
+    #[error("The queue is durable, but expires after {expires:?} without consumers, which deletes any messages published while the handler is down")]
+    DurableQueueExpires {
+        /// The `x-expires` argument of the queue.
+        expires: Duration,
+    },
+}
+
+/// The outcome of publishing a reply to a request, given to the hook set with [`HandlerConfig::on_reply_result`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ReplyResult {
+    /// The routing key the reply was published to, i.e. the `reply_to` property of the request.
+    pub routing_key: String,
+    /// The `correlation_id` property of the request, if it had one.
+    pub correlation_id: Option<String>,
+    /// Whether or not the reply was published.
+    ///
+    /// By default, `Ok` only means that the reply was handed to the channel, not that the broker received or routed it.
+    /// If the reply publish options have the `mandatory` flag set (see [`HandlerConfig::with_mandatory_replies`]),
+    /// `Ok` means that the broker confirmed the reply and did not return it as unroutable.
+    pub result: Result<(), ReplyError>,
+}
+
+/// A function that is called after a handler has tried to publish a reply.
+///
+/// See [`HandlerConfig::on_reply_result`].
+pub type ReplyHook = Arc<dyn Fn(&ReplyResult) + Send + Sync>;
+
+/// Delays the handling of redelivered messages exponentially in the number of previous deliveries.
+///
+/// See [`HandlerConfig::with_redelivery_backoff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedeliveryBackoff {
+    /// The delay before handling a message that has been delivered once before.
+    pub(crate) base: Duration,
+    /// The maximum delay, regardless of the number of previous deliveries.
+    pub(crate) max: Duration,
+}
+
+impl RedeliveryBackoff {
+    /// Returns the maximum delay before handling a message that has previously been delivered the given number of times.
+    ///
+    /// The delay doubles with every previous delivery, up to the maximum. Messages that have not been delivered before are not delayed.
+    pub fn max_delay(&self, previous_deliveries: u64) -> Duration {
+        if previous_deliveries == 0 {
+            return Duration::ZERO;
+        }
+
+        let doublings = u32::try_from(previous_deliveries - 1).unwrap_or(u32::MAX);
+        let factor = 2u32.checked_pow(doublings).unwrap_or(u32::MAX);
+        self.base.saturating_mul(factor).min(self.max)
+    }
+
+    /// Returns a random delay between half of and the full [maximum delay](RedeliveryBackoff::max_delay).
+    ///
+    /// The randomness spreads out retries of messages that failed at the same time, e.g. during an outage.
+    pub(crate) fn delay(&self, previous_deliveries: u64) -> Duration {
+        let max_delay = self.max_delay(previous_deliveries);
+        rand::thread_rng().gen_range(max_delay / 2..=max_delay)
+    }
+}
+
+/// The header of requests that gives the routing key to reply on with [`ReplyMode::OriginalExchange`].
+pub const REPLY_ROUTING_KEY_HEADER: &str = "x-reply-routing-key";
+
+/// Determines where replies to requests are published. See [`HandlerConfig::with_reply_mode`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReplyMode {
+    /// Replies are published to the default exchange with the `reply_to` property of the request as routing key,
+    /// i.e. directly to the reply queue of the caller. This is the usual AMQP RPC convention.
+    #[default]
+    ReplyTo,
+    /// Replies are published to the exchange the request was published to, with the routing key given in the
+    /// [`REPLY_ROUTING_KEY_HEADER`] header of the request. The `reply_to` property of the request is ignored.
+    ///
+    /// This suits ecosystems doing RPC over topic exchanges, where callers bind their reply queues to the same exchange as the services they call.
+    OriginalExchange,
+}
+
+/// Determines the priority of the consumer of a handler. See [`HandlerConfig::with_consumer_priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConsumerPriority {
+    /// The consumer has the given priority.
+    Fixed(i32),
+    /// The consumer has a high priority on the instance with the given index, and the default priority on other instances.
+    PreferredInstance(u32),
+}
 
 /// Detailed configuration of a handler.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct HandlerConfig {
     /// Queue name to bind to. By default, this will be the same as whatever routing key is used for the handler.
     pub(crate) queue: Option<String>,
@@ -24,6 +131,63 @@ pub struct HandlerConfig {
     /// Note that using `()` as the response type from a handler is not sufficient for making the handler not respond,
     /// as `()` implements [`prost::Message`], making it a valid protobuf response message.
     pub(crate) should_reply: bool,
+    /// If set, in-flight messages are requeued during graceful shutdown once they get within this margin
+    /// of their consumer timeout.
+    pub(crate) drain_safety_margin: Option<Duration>,
+    /// The time-to-live of reply messages, if any.
+    pub(crate) reply_ttl: Option<Duration>,
+    /// Called after each attempt at publishing a reply.
+    pub(crate) on_reply_result: Option<ReplyHook>,
+    /// The schema that incoming messages must be compatible with, if any.
+    pub(crate) expected_schema: Option<String>,
+    /// The maximum number of requests handled concurrently. Unbounded if not set.
+    pub(crate) max_in_flight: Option<usize>,
+    /// The maximum number of payload bytes of requests in flight. See [`HandlerConfig::with_in_flight_byte_budget`].
+    pub(crate) in_flight_byte_budget: Option<usize>,
+    /// The maximum number of requests in flight per caller. See [`HandlerConfig::with_per_caller_limit`].
+    pub(crate) per_caller_limit: Option<usize>,
+    /// The maximum number of deliveries received per second. See [`HandlerConfig::with_max_rate`].
+    pub(crate) max_rate: Option<u32>,
+    /// The key the queue is bound with, if different from the routing key. See [`HandlerConfig::with_binding_key`].
+    pub(crate) binding_key: Option<String>,
+    /// Additional keys the queue is bound with. See [`HandlerConfig::with_extra_binding`].
+    pub(crate) extra_binding_keys: Vec<String>,
+    /// If set, the exchange is declared as a consistent hash exchange and the queue is bound to it with this weight.
+    pub(crate) consistent_hash_weight: Option<u32>,
+    /// If set, redelivered messages are delayed before being handled.
+    pub(crate) redelivery_backoff: Option<RedeliveryBackoff>,
+    /// The priority of the consumer, if any.
+    consumer_priority: Option<ConsumerPriority>,
+    /// Additional arguments to create the consumer with. See [`HandlerConfig::with_consumer_arg`].
+    consumer_args: FieldTable,
+    /// The number of payload bytes to include in diagnostics for undecodable messages, if diagnostics are enabled.
+    pub(crate) payload_diagnostics: Option<usize>,
+    /// Detects messages that are redelivered over and over again. See [`HandlerConfig::with_redelivery_storm_detection`].
+    pub(crate) redelivery_storm: Option<RedeliveryStorm>,
+    /// Whether replies are also published to the routing keys in the `CC` header of requests. See [`HandlerConfig::with_reply_cc`].
+    pub(crate) reply_cc: bool,
+    /// Where replies are published. See [`HandlerConfig::with_reply_mode`].
+    pub(crate) reply_mode: ReplyMode,
+    /// Whether messages that expired in flight are dropped without being handled. See [`HandlerConfig::with_skip_expired`].
+    pub(crate) skip_expired: bool,
+    /// The level of the per-request logs of the handler. See [`HandlerConfig::with_log_level`].
+    pub(crate) log_level: Level,
+    /// Identifies the logs of the handler's requests. See [`HandlerConfig::with_log_target`].
+    pub(crate) log_target: Option<String>,
+    /// Payloads larger than this number of bytes are flagged. See [`HandlerConfig::with_large_message_threshold`].
+    pub(crate) large_message_threshold: Option<usize>,
+    /// How long after the app starts the handler starts consuming. See [`HandlerConfig::with_start_delay`].
+    pub(crate) start_delay: Option<Duration>,
+    /// How long each extractor may take. See [`HandlerConfig::with_extract_timeout`].
+    pub(crate) extract_timeout: Option<Duration>,
+    /// The options replies are published with. See [`HandlerConfig::with_reply_publish_options`].
+    pub(crate) reply_publish_options: BasicPublishOptions,
+    /// Whether panics of the handler are replied to instead of requeueing the request. See [`HandlerConfig::with_panic_replies`].
+    pub(crate) panic_replies: bool,
+    /// Whether requests to the handler may be shadowed. Only disabled for the control queue, see [`App::with_control_queue`](crate::App::with_control_queue).
+    pub(crate) shadowable: bool,
+    /// The middleware wrapping the handler, outermost first. See [`HandlerConfig::layer`].
+    pub(crate) middleware: Vec<ErasedMiddleware>,
 }
 
 impl HandlerConfig {
@@ -37,6 +201,13 @@ impl HandlerConfig {
     /// The direct exchange. See <`https://www.rabbitmq.com/tutorials/tutorial-four-python.html`> for more information.
     pub const DIRECT_EXCHANGE: &'static str = "amq.direct";
 
+    /// The shortest `x-expires` argument of durable queues that is not reported by [`HandlerConfig::validate`].
+    /// Handlers are routinely down for minutes during deploys and incidents, and a durable queue should outlive that.
+    pub const MIN_DURABLE_QUEUE_EXPIRES: Duration = Duration::from_secs(60 * 60);
+
+    /// The consumer priority used for preferred instances. See [`HandlerConfig::with_preferred_instance`].
+    pub const PREFERRED_CONSUMER_PRIORITY: i32 = 10;
+
     /// The topic exchange. See <`https://www.rabbitmq.com/tutorials/tutorial-five-python.html`> for more information.
     pub const TOPIC_EXCHANGE: &'static str = "amq.topic";
 
@@ -140,6 +311,30 @@ impl HandlerConfig {
         self
     }
 
+    /// Declares the queue as a [quorum queue](https://www.rabbitmq.com/quorum-queues.html) by setting the `x-queue-type` argument.
+    ///
+    /// Quorum queues must be durable and cannot be auto-deleted, so this also sets `durable` to `true` and `auto-delete` to `false`.
+    pub fn with_quorum_queue(mut self) -> Self {
+        self.options.durable = true;
+        self.options.auto_delete = false;
+        self.arguments.insert(
+            "x-queue-type".into(),
+            AMQPValue::LongString("quorum".into()),
+        );
+        self
+    }
+
+    /// Sets the `x-delivery-limit` argument on the queue. This only has an effect on quorum queues (see [`HandlerConfig::with_quorum_queue`]).
+    ///
+    /// Messages that are redelivered more times than the limit are dropped or dead-lettered.
+    /// Use the [`DeliveryCount`](crate::extract::DeliveryCount) extractor to find out how many times a message has been delivered before.
+    /// See also [RabbitMQ's documentation](https://www.rabbitmq.com/quorum-queues.html#poison-message-handling).
+    pub fn with_delivery_limit(mut self, delivery_limit: u32) -> Self {
+        self.arguments
+            .insert("x-delivery-limit".into(), i64::from(delivery_limit).into());
+        self
+    }
+
     /// Set any argument with any value.
     ///
     /// Prefer the more specific methods if you can, but you can use this for any specific argument you might want to set.
@@ -153,6 +348,391 @@ impl HandlerConfig {
         self.should_reply = should_reply;
         self
     }
+
+    /// Requeues in-flight messages during graceful shutdown if they get within `margin` of their consumer timeout
+    /// (see [`HandlerConfig::with_consumer_timeout`]), counted from when the message was received.
+    ///
+    /// If the broker closes the channel while a message is being processed, the processing is wasted anyway. By rejecting the message
+    /// with requeue before that happens, another consumer can pick up the message while this one finishes its graceful shutdown.
+    /// Messages whose handler has already returned are left to finish, as they may already have been replied to.
+    /// Message TTLs don't count, as the broker doesn't expire messages while they are delivered, but would expire them once requeued.
+    /// By default, and for handlers without a consumer timeout, messages are never requeued during graceful shutdown.
+    pub fn with_drain_safety_margin(mut self, margin: Duration) -> Self {
+        self.drain_safety_margin = Some(margin);
+        self
+    }
+
+    /// Limits how many requests the handler processes concurrently. A limit of 0 is treated as 1.
+    ///
+    /// Normally, the number of requests in flight is bounded by the prefetch (see [`HandlerConfig::with_prefetch`]).
+    /// However, requests that are acked early (see [`Acker`](crate::extract::Acker)) or a prefetch of 0 (unlimited) lift that bound.
+    /// When the limit is reached, the handler stops receiving deliveries until some of the requests in flight finish,
+    /// which bounds the memory used by slow handlers. By default, there is no limit.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight.max(1));
+        self
+    }
+
+    /// Limits the total size of the payloads of the requests the handler processes concurrently.
+    ///
+    /// Once the payloads of the requests in flight add up to the budget, the handler stops receiving deliveries
+    /// until some of the requests in flight finish. A single request larger than the budget is still handled, on its own.
+    /// This protects the memory of handlers that receive few but very large messages, where the prefetch count alone is a poor control.
+    /// Note that deliveries already prefetched by the broker are still held in memory by the client. By default, there is no budget.
+    pub fn with_in_flight_byte_budget(mut self, bytes: usize) -> Self {
+        self.in_flight_byte_budget = Some(bytes.max(1));
+        self
+    }
+
+    /// Limits how many deliveries the handler receives per second. A rate of 0 is treated as 1.
+    ///
+    /// Deliveries are received evenly spaced, at most one every `1 / msgs_per_sec` seconds, regardless of how many requests are in flight.
+    /// This is meant for handlers calling rate-limited third-party APIs, where limiting the concurrency alone
+    /// (see [`HandlerConfig::with_max_in_flight`]) still lets fast requests through in bursts.
+    /// Deliveries prefetched by the broker wait in the client until it is their turn, so keep the prefetch low. By default, there is no limit.
+    pub fn with_max_rate(mut self, msgs_per_sec: u32) -> Self {
+        self.max_rate = Some(msgs_per_sec.max(1));
+        self
+    }
+
+    /// Checks the schema of incoming messages against the given expected schema, using the schema registry of the app.
+    ///
+    /// See the [`schema`](crate::schema) module and [`App::with_schema_registry`](crate::App::with_schema_registry).
+    /// Has no effect if the app has no schema registry.
+    pub fn with_expected_schema(mut self, expected: impl Into<String>) -> Self {
+        self.expected_schema = Some(expected.into());
+        self
+    }
+
+    /// Binds the queue with the given binding key instead of the routing key of the handler.
+    ///
+    /// This is mostly useful with topic exchanges, where the binding key may be a pattern such as `orders.*.created` or `logs.#`.
+    /// The routing key of the handler is then still used as the default queue name and as consumer tag,
+    /// and the actual routing key of each delivery can be extracted with [`RoutingKey`](crate::extract::RoutingKey).
+    ///
+    /// Has no effect on handlers bound to a consistent hash exchange (see [`HandlerConfig::with_consistent_hash`]).
+    pub fn with_binding_key(mut self, binding_key: impl Into<String>) -> Self {
+        self.binding_key = Some(binding_key.into());
+        self
+    }
+
+    /// Additionally binds the queue with the given binding key, on the same exchange.
+    ///
+    /// This lets a single handler consume messages published under several routing keys, e.g. `user.created` and `user.updated`.
+    /// May be called multiple times to add more bindings. Use the [`RoutingKey`](crate::extract::RoutingKey) extractor to tell the messages apart.
+    ///
+    /// Has no effect on handlers bound to a consistent hash exchange (see [`HandlerConfig::with_consistent_hash`]).
+    pub fn with_extra_binding(mut self, binding_key: impl Into<String>) -> Self {
+        self.extra_binding_keys.push(binding_key.into());
+        self
+    }
+
+    /// Binds the queue to the given [consistent hash exchange](crate::consistent_hash) with the given weight, instead of binding on the routing key.
+    ///
+    /// The exchange is declared (as durable) if it does not exist already. The routing key of the handler is then only used as consumer tag.
+    /// Each instance of the app should consume from its own queue (see [`HandlerConfig::with_queue`]), so messages are partitioned across instances.
+    pub fn with_consistent_hash(mut self, exchange: impl Into<String>, weight: u32) -> Self {
+        self.exchange = exchange.into();
+        self.consistent_hash_weight = Some(weight);
+        self
+    }
+
+    /// Sets the time-to-live of reply messages, as their `expiration` property.
+    ///
+    /// This prevents stale replies from piling up in reply queues that were abandoned by their callers.
+    /// Individual responses can override this via [`Respond::reply_ttl`](crate::Respond::reply_ttl), e.g. by using [`Expiring`](crate::response::Expiring).
+    pub fn with_reply_ttl(mut self, ttl: Duration) -> Self {
+        self.reply_ttl = Some(ttl);
+        self
+    }
+
+    /// Sets a hook that is called with the outcome of every attempt at publishing a reply.
+    ///
+    /// kanin only logs an error when a reply fails to publish, as there is no one to return the error to.
+    /// Use this if you need to act on failed replies, for instance by recording them for later reconciliation.
+    /// Unless replies are published with the `mandatory` flag (see [`HandlerConfig::with_mandatory_replies`]),
+    /// the broker does not confirm replies, so replies it drops are still reported as successful.
+    ///
+    /// The hook is called from within the request task, so it should not block. Spawn a task if you need to do async work.
+    pub fn on_reply_result(mut self, hook: impl Fn(&ReplyResult) + Send + Sync + 'static) -> Self {
+        self.on_reply_result = Some(Arc::new(hook));
+        self
+    }
+
+    /// Delays the handling of redelivered messages, doubling the delay with every previous delivery, starting at `base` and capped at `max`.
+    ///
+    /// This smooths out retry storms, e.g. when a dependency comes back after an outage and every message is redelivered at once.
+    /// The number of previous deliveries is read from the `x-delivery-count` header (see [`DeliveryCount`](crate::extract::DeliveryCount)),
+    /// which is only set by quorum queues. On other queues, redelivered messages are treated as having been delivered once before.
+    /// The actual delay is randomized between half of and the full delay.
+    ///
+    /// Messages are held (and count against the prefetch) while delayed, so keep `max` well below the consumer timeout.
+    /// By default, redelivered messages are handled immediately.
+    pub fn with_redelivery_backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.redelivery_backoff = Some(RedeliveryBackoff { base, max });
+        self
+    }
+
+    /// Sets the priority of the consumer of the handler, via the `x-priority` consumer argument.
+    ///
+    /// The broker delivers messages to the consumers with the highest priority that have prefetch capacity,
+    /// and only falls back to lower priority consumers when those are busy. The default priority is 0.
+    /// See also [RabbitMQ's documentation](https://www.rabbitmq.com/consumer-priority.html).
+    pub fn with_consumer_priority(mut self, priority: i32) -> Self {
+        self.consumer_priority = Some(ConsumerPriority::Fixed(priority));
+        self
+    }
+
+    /// Makes the instance with the given index the preferred consumer of the handler's queue.
+    ///
+    /// On that instance, the consumer gets the priority [`HandlerConfig::PREFERRED_CONSUMER_PRIORITY`], while other instances keep the default priority.
+    /// The other instances thus only receive messages when the preferred instance is busy or down.
+    /// The index of the running instance is determined by [`Instance::current`](crate::instance::Instance::current).
+    pub fn with_preferred_instance(mut self, index: u32) -> Self {
+        self.consumer_priority = Some(ConsumerPriority::PreferredInstance(index));
+        self
+    }
+
+    /// Set any consumer argument with any value, e.g. `x-stream-offset` to choose where to start consuming a stream.
+    ///
+    /// These are the arguments of `basic.consume`, not to be confused with the queue arguments set by [`HandlerConfig::with_arg`].
+    /// The consumer priority set by [`HandlerConfig::with_consumer_priority`] or [`HandlerConfig::with_preferred_instance`]
+    /// takes precedence over an `x-priority` argument set here.
+    pub fn with_consumer_arg(
+        mut self,
+        arg: impl Into<String>,
+        value: impl Into<AMQPValue>,
+    ) -> Self {
+        self.consumer_args.insert(arg.into().into(), value.into());
+        self
+    }
+
+    /// Limits how many requests from a single caller the handler processes concurrently. A limit of 0 is treated as 1.
+    ///
+    /// Callers are identified by the `app_id` property of their requests, and requests without an `app_id` count as a single caller.
+    /// When a caller has as many requests in flight as the limit, further requests from it are rejected and requeued,
+    /// leaving the handler to other callers. This keeps a single misbehaving upstream from monopolizing a shared queue.
+    /// Rejected requests are held before being requeued, so the handler doesn't spin on redeliveries of the caller's requests:
+    /// with the [redelivery backoff](HandlerConfig::with_redelivery_backoff) of the handler if it has one, and otherwise
+    /// with a delay starting at 100 milliseconds and doubling with every redelivery up to 5 seconds.
+    /// Held requests count against the prefetch and the [limits on requests in flight](HandlerConfig::with_max_in_flight) of the handler,
+    /// so the limit works best with a prefetch well above it or several consumers on the queue. On graceful shutdown, held requests are requeued right away.
+    /// Their redeliveries do not count towards [redelivery storms](HandlerConfig::with_redelivery_storm_detection). Rejections are counted in the `kanin.caller_limited` counter. By default, there is no limit per caller.
+    pub fn with_per_caller_limit(mut self, limit: usize) -> Self {
+        self.per_caller_limit = Some(limit.max(1));
+        self
+    }
+
+    /// Includes diagnostics about the payload when a message cannot be decoded by [`Msg`](crate::extract::Msg).
+    ///
+    /// The diagnostics contain the length and content type of the payload, along with its first `sample_len` bytes (as hex).
+    /// They are logged and included in the error given to the handler's [`FromError`](crate::error::FromError) implementation,
+    /// which usually means they are sent back to the caller (see [`RequestError::UndecodableMessage`](crate::error::RequestError::UndecodableMessage)).
+    /// This greatly helps with debugging schema drift between producers and consumers, but may leak payload contents to callers.
+    /// By default, no diagnostics are included.
+    pub fn with_payload_diagnostics(mut self, sample_len: usize) -> Self {
+        self.payload_diagnostics = Some(sample_len);
+        self
+    }
+
+    /// Detects messages that are redelivered more times than a threshold within a window, e.g. due to a requeue loop caused by a panicking handler.
+    ///
+    /// Messages are identified by their `message_id` property, falling back to their `correlation_id` property,
+    /// and to a hash of their routing key and payload if they have neither.
+    /// When a message is part of such a redelivery storm, a warning is logged and the `kanin.redelivery_storms` counter is incremented.
+    /// With [`RedeliveryStorm::with_dead_lettering`], the message is also rejected without being requeued instead of being handled.
+    /// Redeliveries are counted per instance, so a message that alternates between instances takes longer to be detected.
+    /// By default, redelivery storms are not detected.
+    pub fn with_redelivery_storm_detection(mut self, storm: RedeliveryStorm) -> Self {
+        self.redelivery_storm = Some(storm);
+        self
+    }
+
+    /// Also publishes replies to the routing keys listed in the `CC` header of requests, in addition to `reply_to`.
+    ///
+    /// This follows the convention of some legacy ecosystems, where callers list additional recipients of the reply in this header.
+    /// The header is copied onto the reply, and RabbitMQ routes it to each of the listed routing keys
+    /// (see [sender-selected distribution](https://www.rabbitmq.com/sender-selected.html)).
+    /// The header must be an array of strings. By default, the header is ignored.
+    ///
+    /// A `BCC` header can't be replied to, as RabbitMQ strips it from requests before delivering them to the handler.
+    pub fn with_reply_cc(mut self, reply_cc: bool) -> Self {
+        self.reply_cc = reply_cc;
+        self
+    }
+
+    /// Sets where replies are published. Defaults to [`ReplyMode::ReplyTo`], i.e. the `reply_to` property of requests.
+    ///
+    /// Requests that give no routing key to reply on for the chosen mode are not replied to, just as requests without `reply_to` normally aren't.
+    /// This also applies to replies published via a [`ReplyHandle`](crate::extract::ReplyHandle).
+    pub fn with_reply_mode(mut self, reply_mode: ReplyMode) -> Self {
+        self.reply_mode = reply_mode;
+        self
+    }
+
+    /// Acks and drops messages that expired while in flight, without handling them.
+    ///
+    /// A message has expired if its `timestamp` property plus its per-message `expiration` property (see [`Expiration`](crate::extract::Expiration)) lies in the past.
+    /// The broker only drops expired messages at the head of the queue, so messages can still expire after delivery,
+    /// e.g. while waiting in the prefetch buffer. Skipping them avoids wasting work on requests whose callers have already given up.
+    /// Skipped messages are counted in the `kanin.expired_messages_skipped` counter.
+    /// Messages without a timestamp or expiration are always handled. By default, expired messages are handled.
+    pub fn with_skip_expired(mut self, skip_expired: bool) -> Self {
+        self.skip_expired = skip_expired;
+        self
+    }
+
+    /// Delays consumption from the queue by the given duration after the app starts.
+    ///
+    /// The queue is still declared and bound when the app starts, so no messages are lost in the meantime; they just wait in the queue.
+    /// This is useful for handlers that should not start before something else has happened, e.g. a cache has warmed up
+    /// or another handler has drained a migration queue. The delay is shown in the [`StartupReport`](crate::app::StartupReport).
+    /// If the app is shut down during the delay, the handler never starts consuming.
+    pub fn with_start_delay(mut self, delay: Duration) -> Self {
+        self.start_delay = Some(delay);
+        self
+    }
+
+    /// Limits how long each extractor of the handler may take, e.g. custom extractors that perform I/O such as authentication lookups.
+    ///
+    /// If an extractor takes longer, the handler is not called, and the request is responded to with an internal error
+    /// ([`ServerError::ExtractTimeout`](crate::error::ServerError::ExtractTimeout)) naming the extractor that timed out.
+    /// By default, extractors may take as long as they need.
+    pub fn with_extract_timeout(mut self, timeout: Duration) -> Self {
+        self.extract_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the options replies are published with.
+    ///
+    /// If the `mandatory` flag is set, the handler's channel is put in confirm mode and each reply waits for the broker's confirmation,
+    /// so replies that the broker returns (e.g. because the caller's reply queue no longer exists) are surfaced:
+    /// they are logged as a warning, counted in the `kanin.replies_returned` counter and reported as [`ReplyError::Returned`] to the hook set with [`HandlerConfig::on_reply_result`].
+    /// This also applies to replies published via a [`ReplyHandle`](crate::extract::ReplyHandle).
+    /// By default, replies are published with no flags set, and unroutable replies are silently dropped by the broker.
+    ///
+    /// RabbitMQ does not support the `immediate` flag, and closes the handler's channel if it is set, so it is cleared with a warning.
+    pub fn with_reply_publish_options(mut self, mut options: BasicPublishOptions) -> Self {
+        if options.immediate {
+            warn!("Clearing the `immediate` flag of the reply publish options, as RabbitMQ does not support it and would close the handler's channel.");
+            options.immediate = false;
+        }
+        self.reply_publish_options = options;
+        self
+    }
+
+    /// Publishes replies with the `mandatory` flag, so replies that can't be routed to a queue are surfaced rather than silently dropped.
+    ///
+    /// See [`HandlerConfig::with_reply_publish_options`] for how returned replies are surfaced. By default, replies are not mandatory.
+    pub fn with_mandatory_replies(mut self, mandatory: bool) -> Self {
+        self.reply_publish_options.mandatory = mandatory;
+        self
+    }
+
+    /// Replies to requests that the handler panicked on with an internal error, rather than rejecting and requeueing them.
+    ///
+    /// By default, a panicking handler's request is requeued, so RPC callers only find out when their own timeout expires,
+    /// and the request may keep panicking on every redelivery. With panic replies, the panic is turned into a response
+    /// via [`FromError`](crate::error::FromError) with [`ServerError::HandlerPanicked`](crate::error::ServerError::HandlerPanicked)
+    /// (formatted with the app's [error redaction](crate::App::with_error_redaction), if any), and the request is acked, so callers fail fast.
+    /// The panic is still logged along with its backtrace.
+    pub fn with_panic_replies(mut self, panic_replies: bool) -> Self {
+        self.panic_replies = panic_replies;
+        self
+    }
+
+    /// Wraps the handler in the given [`Middleware`]. Middleware added first wraps middleware added later.
+    ///
+    /// Middleware added to the app with [`App::layer`](crate::App::layer) wraps the middleware added here.
+    /// The state type `S` must be the state type of the app the handler is registered on. It is inferred for middleware that
+    /// only implements [`Middleware`] for a single state type, while middleware that is generic over the state needs it spelled out,
+    /// e.g. `config.layer::<MyState, _>(RequestLogging)`. Registering the handler on an app with another state type panics.
+    pub fn layer<S, M>(mut self, middleware: M) -> Self
+    where
+        S: Send + Sync + 'static,
+        M: Middleware<S>,
+    {
+        self.middleware.push(ErasedMiddleware::new(middleware));
+        self
+    }
+
+    /// Sets the level at which kanin logs the handling of each request, e.g. receiving it and replying to it.
+    ///
+    /// Use this to log noisy, high-volume handlers at debug level while business-critical handlers keep logging at info level,
+    /// without changing the global filter. Warnings and errors are always logged at their own level. Defaults to info.
+    pub fn with_log_level(mut self, level: Level) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    /// Records the given log target in the `log_target` field of the span of each request handled by the handler.
+    ///
+    /// `tracing` requires event targets to be known at compile time, so the target is recorded on the `request` span instead.
+    /// This lets subscribers route or filter the logs of the handler separately, e.g. with an `EnvFilter` directive like
+    /// `kanin[request{log_target=noisy}]=debug`. By default, no log target is recorded.
+    pub fn with_log_target(mut self, target: &str) -> Self {
+        self.log_target = Some(target.to_string());
+        self
+    }
+
+    /// Flags requests and responses with payloads larger than the given number of bytes.
+    ///
+    /// The sizes of all payloads are recorded in the `kanin.request_size_bytes` and `kanin.response_size_bytes` histograms regardless.
+    /// Payloads exceeding the threshold are additionally logged as a warning and counted in the `kanin.large_messages` counter,
+    /// which helps catch payload bloat before it becomes a problem. By default, no payloads are flagged.
+    pub fn with_large_message_threshold(mut self, bytes: usize) -> Self {
+        self.large_message_threshold = Some(bytes);
+        self
+    }
+
+    /// Checks the configuration for contradictions that cause messages to be silently dropped.
+    ///
+    /// This is done for every handler when the app starts, logging each issue as a warning, and by [`App::dry_run`](crate::App::dry_run),
+    /// which reports them in [`DryRunReport::config_issues`](crate::app::DryRunReport::config_issues).
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+
+        if self.options.durable {
+            if let Some(expires) = self.duration_argument("x-expires") {
+                if expires < Self::MIN_DURABLE_QUEUE_EXPIRES {
+                    issues.push(ConfigIssue::DurableQueueExpires { expires });
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Returns the arguments to create the consumer of the handler with, when running as the given instance.
+    pub(crate) fn consumer_arguments(&self, instance: Instance) -> FieldTable {
+        let priority = match self.consumer_priority {
+            Some(ConsumerPriority::Fixed(priority)) => Some(priority),
+            Some(ConsumerPriority::PreferredInstance(index)) => {
+                (instance.index() == Some(index)).then_some(Self::PREFERRED_CONSUMER_PRIORITY)
+            }
+            None => None,
+        };
+
+        let mut arguments = self.consumer_args.clone();
+        if let Some(priority) = priority {
+            arguments.insert("x-priority".into(), AMQPValue::LongInt(priority));
+        }
+        arguments
+    }
+
+    /// Returns the queue argument with the given key as a duration, interpreting the value as milliseconds.
+    pub(crate) fn duration_argument(&self, key: &str) -> Option<Duration> {
+        let millis = match self.arguments.inner().get(key)? {
+            AMQPValue::LongLongInt(millis) => u64::try_from(*millis).ok()?,
+            AMQPValue::LongInt(millis) => u64::try_from(*millis).ok()?,
+            AMQPValue::LongUInt(millis) => (*millis).into(),
+            AMQPValue::ShortInt(millis) => u64::try_from(*millis).ok()?,
+            AMQPValue::ShortUInt(millis) => (*millis).into(),
+            _ => return None,
+        };
+
+        Some(Duration::from_millis(millis))
+    }
 }
 
 impl Default for HandlerConfig {
@@ -167,6 +747,75 @@ impl Default for HandlerConfig {
             },
             arguments: Default::default(),
             should_reply: true,
+            drain_safety_margin: None,
+            reply_ttl: None,
+            on_reply_result: None,
+            binding_key: None,
+            extra_binding_keys: Vec::new(),
+            consistent_hash_weight: None,
+            max_in_flight: None,
+            in_flight_byte_budget: None,
+            per_caller_limit: None,
+            max_rate: None,
+            expected_schema: None,
+            redelivery_backoff: None,
+            consumer_priority: None,
+            consumer_args: Default::default(),
+            payload_diagnostics: None,
+            redelivery_storm: None,
+            reply_cc: false,
+            reply_mode: ReplyMode::default(),
+            skip_expired: false,
+            log_level: Level::INFO,
+            log_target: None,
+            large_message_threshold: None,
+            start_delay: None,
+            extract_timeout: None,
+            reply_publish_options: BasicPublishOptions::default(),
+            panic_replies: false,
+            shadowable: true,
+            middleware: Vec::new(),
         }
     }
 }
+
+impl fmt::Debug for HandlerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandlerConfig")
+            .field("queue", &self.queue)
+            .field("exchange", &self.exchange)
+            .field("prefetch", &self.prefetch)
+            .field("options", &self.options)
+            .field("arguments", &self.arguments)
+            .field("should_reply", &self.should_reply)
+            .field("drain_safety_margin", &self.drain_safety_margin)
+            .field("reply_ttl", &self.reply_ttl)
+            .field("on_reply_result", &self.on_reply_result.is_some())
+            .field("binding_key", &self.binding_key)
+            .field("extra_binding_keys", &self.extra_binding_keys)
+            .field("consistent_hash_weight", &self.consistent_hash_weight)
+            .field("max_in_flight", &self.max_in_flight)
+            .field("in_flight_byte_budget", &self.in_flight_byte_budget)
+            .field("per_caller_limit", &self.per_caller_limit)
+            .field("max_rate", &self.max_rate)
+            .field("expected_schema", &self.expected_schema)
+            .field("redelivery_backoff", &self.redelivery_backoff)
+            .field("consumer_priority", &self.consumer_priority)
+            .field("consumer_args", &self.consumer_args)
+            .field("payload_diagnostics", &self.payload_diagnostics)
+            .field("redelivery_storm", &self.redelivery_storm)
+            .field("reply_cc", &self.reply_cc)
+            .field("reply_mode", &self.reply_mode)
+            .field("skip_expired", &self.skip_expired)
+            .field("log_level", &self.log_level)
+            .field("log_target", &self.log_target)
+            .field("large_message_threshold", &self.large_message_threshold)
+            .field("start_delay", &self.start_delay)
+            .field("extract_timeout", &self.extract_timeout)
+            .field("reply_publish_options", &self.reply_publish_options)
+            .field("panic_replies", &self.panic_replies)
+            .field("shadowable", &self.shadowable)
+            .field("middleware", &self.middleware.len())
+            .finish()
+    }
+}
@@ -0,0 +1,115 @@
+//! Identification of kanin services towards the broker.
+//!
+//! By default, RabbitMQ shows connections by their address only, which makes it hard to tell which service a connection belongs to.
+//! kanin sets the connection name and a few client properties on the connections it creates, so they show up with
+//! meaningful names in the management UI. See [`App::with_connection_identity`](crate::App::with_connection_identity).
+
+use lapin::{types::AMQPValue, ConnectionProperties};
+
+/// Creates a [`ConnectionIdentity`] from the Cargo metadata of the calling crate, i.e. its package name and version.
+///
+/// ```
+/// let identity = kanin::connection_identity!();
+/// ```
+#[macro_export]
+macro_rules! connection_identity {
+    () => {
+        $crate::identity::ConnectionIdentity::new(env!("CARGO_PKG_NAME"))
+            .with_version(env!("CARGO_PKG_VERSION"))
+    };
+}
+
+/// How a kanin service identifies itself towards the broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionIdentity {
+    /// The name of the service.
+    service: String,
+    /// The version of the service, if known.
+    version: Option<String>,
+    /// The host the service is running on, if known.
+    host: Option<String>,
+    /// Overrides the connection name, which is otherwise derived from the service name and host.
+    connection_name: Option<String>,
+}
+
+impl ConnectionIdentity {
+    /// Creates an identity for the service with the given name. The host is detected from the environment.
+    ///
+    /// Use [`connection_identity!`](crate::connection_identity) to take the name and version from the Cargo metadata of your crate.
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+            version: None,
+            host: detect_host(),
+            connection_name: None,
+        }
+    }
+
+    /// Detects the identity of the service from the environment, using the name of the running executable as the service name.
+    ///
+    /// This is used by [`App::run`](crate::App::run) if no identity has been set.
+    pub fn detect() -> Self {
+        let service = std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.file_stem()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "kanin".to_string());
+
+        Self::new(service)
+    }
+
+    /// Sets the version of the service.
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Sets the host the service is running on, overriding the detected host.
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Sets the connection name, overriding the name derived from the service name and host.
+    pub fn with_connection_name(mut self, connection_name: impl Into<String>) -> Self {
+        self.connection_name = Some(connection_name.into());
+        self
+    }
+
+    /// Returns the connection name, which is `service@host` unless set explicitly.
+    pub fn connection_name(&self) -> String {
+        match (&self.connection_name, &self.host) {
+            (Some(connection_name), _) => connection_name.clone(),
+            (None, Some(host)) => format!("{}@{host}", self.service),
+            (None, None) => self.service.clone(),
+        }
+    }
+
+    /// Sets the connection name and the `service`, `service_version` and `host` client properties on the given properties.
+    pub fn apply(&self, properties: ConnectionProperties) -> ConnectionProperties {
+        let mut properties = properties.with_connection_name(self.connection_name().into());
+
+        let client_properties = [
+            ("service", Some(&self.service)),
+            ("service_version", self.version.as_ref()),
+            ("host", self.host.as_ref()),
+        ];
+        for (key, value) in client_properties {
+            if let Some(value) = value {
+                properties
+                    .client_properties
+                    .insert(key.into(), AMQPValue::LongString(value.as_str().into()));
+            }
+        }
+
+        properties
+    }
+}
+
+/// Detects the host name from the `HOSTNAME` environment variable, falling back to `/etc/hostname`.
+fn detect_host() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+}
@@ -0,0 +1,48 @@
+//! Identity of the running instance among the replicas of a service.
+//!
+//! When running several replicas of a service, it can be useful for specific replicas to prefer specific queues,
+//! e.g. for workloads that benefit from cache locality. Each replica finds its index via [`Instance::current`],
+//! which can then be used to set consumer priorities (see [`HandlerConfig::with_preferred_instance`](crate::HandlerConfig::with_preferred_instance)).
+
+/// The identity of the running instance among the replicas of a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Instance {
+    /// The index of the instance, if known.
+    index: Option<u32>,
+}
+
+impl Instance {
+    /// The environment variable the instance index is read from.
+    pub const INDEX_VAR: &'static str = "KANIN_INSTANCE_INDEX";
+
+    /// Creates an instance with the given index.
+    pub fn new(index: u32) -> Self {
+        Self { index: Some(index) }
+    }
+
+    /// Determines the identity of the running instance from the environment.
+    ///
+    /// The index is read from the [`Instance::INDEX_VAR`] environment variable. If it is not set, the index is taken from
+    /// the ordinal suffix of the `HOSTNAME` environment variable, as set for pods of a Kubernetes StatefulSet (e.g. `my-service-2`).
+    /// If neither is available, the index is unknown.
+    pub fn current() -> Self {
+        Self::from_env(
+            std::env::var(Self::INDEX_VAR).ok().as_deref(),
+            std::env::var("HOSTNAME").ok().as_deref(),
+        )
+    }
+
+    /// Determines the identity of the instance from the given values of the index variable and the host name.
+    pub(crate) fn from_env(index: Option<&str>, hostname: Option<&str>) -> Self {
+        let index = index
+            .and_then(|index| index.trim().parse().ok())
+            .or_else(|| hostname?.rsplit_once('-')?.1.parse().ok());
+
+        Self { index }
+    }
+
+    /// The index of the instance, if known.
+    pub fn index(&self) -> Option<u32> {
+        self.index
+    }
+}
@@ -13,6 +13,7 @@
 //! #         #[prost(string, tag="1")]
 //! #         pub error: ::prost::alloc::string::String,
 //! #     }
+//! #     #[derive(kanin::FromError)]
 //! #     #[derive(Clone, PartialEq, ::prost::Message)]
 //! #     pub struct InternalError {
 //! #         #[prost(string, tag="1")]
@@ -123,16 +124,48 @@ pub use lapin;
 pub use lapin::Connection;
 
 pub mod app;
+pub mod audit;
+pub mod batch;
+pub mod canary;
+#[cfg(feature = "gzip")]
+pub mod compression;
+pub mod config;
+pub mod connection;
+pub mod consistent_hash;
+pub mod context;
+pub mod control;
 pub mod error;
 pub mod extract;
 pub mod handler;
 pub mod handler_config;
+pub mod identity;
+pub mod instance;
+pub mod middleware;
+pub mod migration;
+pub mod ops;
+pub mod probe;
+pub mod redelivery;
+pub mod reply_dedup;
+pub mod reply_queue;
+pub mod reply_store;
 pub mod request;
 pub mod response;
+pub mod scatter_gather;
+pub mod schema;
+pub mod shadow;
+pub mod spawn;
+pub mod stats;
+#[cfg(feature = "test-util")]
+pub mod test;
+pub mod well_known;
+#[cfg(feature = "wire-debug")]
+pub mod wire_debug;
 
 // pub-using every name::Name to avoid having to have kanin::name::Name repetition.
 // This way you can just do kanin::Name.
 pub use app::App;
+pub use config::KaninConfig;
+pub use connection::KaninConnectionOptions;
 pub use error::Error;
 pub use error::HandlerError;
 pub use extract::Extract;
@@ -140,41 +173,357 @@ pub use handler::Handler;
 pub use handler_config::HandlerConfig;
 pub use kanin_derive::AppState;
 pub use kanin_derive::FromError;
+pub use kanin_derive::Respond;
+pub use middleware::Middleware;
 pub use request::Request;
 pub use response::Respond;
+pub use spawn::spawn;
 
 /// Convenience type for a result with `kanin`'s error.
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[cfg(test)]
 mod tests {
+    mod ack_timing;
+    mod acker;
+    mod audit;
+    mod backoff;
+    mod baggage;
     mod basic;
+    mod byte_budget;
+    mod caller_limit;
+    mod canary;
+    #[cfg(feature = "gzip")]
+    mod compression;
+    mod config;
+    mod conformance;
+    mod connection;
+    mod context;
+    mod control;
+    mod deadline;
+    mod delivery_count;
+    mod diagnostics;
+    mod drain;
+    mod error;
+    mod expiration;
+    mod extract_error;
+    #[cfg(feature = "test-util")]
+    mod flaky;
+    mod group;
+    mod health_gate;
+    mod identity;
+    mod instance;
+    mod instrumentation;
+    #[cfg(feature = "json")]
+    mod json;
+    mod log_level;
+    mod memory_budget;
+    mod message_with_raw;
+    mod meta;
+    mod middleware;
+    mod migration;
+    mod non_default;
+    mod ops;
+    mod panic;
+    mod parallel_message;
+    mod parts;
+    mod payload_sizes;
+    mod prefetch;
+    mod probe;
+    mod progress;
+    mod properties;
+    mod publisher_channel;
+    mod redaction;
+    mod redelivery;
+    mod reload;
+    mod reply_cc;
+    mod reply_dedup;
+    mod reply_handle;
+    mod reply_mode;
+    mod reply_queue;
+    mod reply_result;
+    mod reply_store;
+    mod reply_ttl;
+    mod req_id;
+    mod routing_key;
+    mod schema;
     mod send_recv;
+    mod shadow;
+    mod signal;
+    mod spawn;
+    mod start_delay;
+    mod startup;
+    mod state;
+    mod stats;
+    mod topology;
+    mod well_known;
+    #[cfg(feature = "wire-debug")]
+    mod wire_debug;
 
-    use std::time::Duration;
+    use std::{
+        collections::HashMap,
+        future::Future,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
 
-    use lapin::{Connection, ConnectionProperties};
+    use lapin::{
+        options::{BasicPublishOptions, QueueBindOptions},
+        types::{AMQPValue, FieldTable},
+        BasicProperties, Connection, ConnectionProperties,
+    };
+    use metrics::{
+        Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString,
+        Unit,
+    };
     use tracing::warn;
 
+    use crate::{error::FromError, reply_queue::ReplyQueue, App, HandlerError, Respond};
+
+    /// The address of the broker the tests run against, unless the `test-broker` feature is enabled.
+    /// See [`test_broker`].
+    #[cfg(not(feature = "test-broker"))]
     const TEST_AMQP_ADDR: &str = "amqp://localhost";
 
+    /// Returns the address of a broker to run tests against, along with the broker itself if one was started.
+    ///
+    /// With the `test-broker` feature, a broker is started in a container and removed once the returned broker is dropped.
+    /// Otherwise, the tests require a RabbitMQ instance running on localhost.
+    #[cfg(feature = "test-broker")]
+    async fn test_broker() -> (String, Option<crate::test::TestBroker>) {
+        let broker = crate::test::broker().await;
+        (broker.amqp_addr().to_string(), Some(broker))
+    }
+
+    /// Returns the address of a broker to run tests against, along with the broker itself if one was started.
+    ///
+    /// With the `test-broker` feature, a broker is started in a container and removed once the returned broker is dropped.
+    /// Otherwise, the tests require a RabbitMQ instance running on localhost.
+    #[cfg(not(feature = "test-broker"))]
+    async fn test_broker() -> (String, Option<()>) {
+        (TEST_AMQP_ADDR.to_string(), None)
+    }
+
+    /// A text reply for test handlers. Failed requests are replied to with `error: ` followed by the error.
+    #[derive(Debug)]
+    struct Reply(String);
+
+    impl Respond for Reply {
+        fn respond(self) -> Vec<u8> {
+            self.0.into()
+        }
+    }
+
+    impl FromError<HandlerError> for Reply {
+        fn from_error(error: HandlerError) -> Self {
+            Reply(format!("error: {error}"))
+        }
+    }
+
+    /// Runs the given app on the given connection while the given requests are made, then shuts the app down.
+    ///
+    /// The requests are only made once all handlers of the app are consuming. Returns the output of the requests.
+    async fn while_running<S, T>(
+        app: App<S>,
+        conn: &Connection,
+        requests: impl Future<Output = T>,
+    ) -> T {
+        let shutdown = app.shutdown_channel();
+        let running = app.start(conn).await.expect("failed to start app");
+
+        let output = requests.await;
+
+        shutdown.send(()).expect("failed to shut down app");
+        running.wait().await.expect("app failed");
+        output
+    }
+
+    /// Publishes a request with the given payload and properties to the queue of the handler on the given routing key,
+    /// and returns the properties and payload of its reply.
+    ///
+    /// The request is published on the default exchange with `reply_to` and `correlation_id` set.
+    /// Panics if no reply is received within 10 seconds.
+    async fn request(
+        conn: &Connection,
+        routing_key: &str,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> (BasicProperties, Vec<u8>) {
+        request_on(conn, "", routing_key, payload, properties).await
+    }
+
+    /// Like [`request`], but publishes the request to the given exchange.
+    ///
+    /// Unless the exchange is the default exchange, the reply queue is also bound to the exchange with its own name as binding key,
+    /// and the name is given in the [`REPLY_ROUTING_KEY_HEADER`](crate::handler_config::REPLY_ROUTING_KEY_HEADER) header of the request,
+    /// so replies of handlers with [`ReplyMode::OriginalExchange`](crate::handler_config::ReplyMode::OriginalExchange) reach it too.
+    async fn request_on(
+        conn: &Connection,
+        exchange: &str,
+        routing_key: &str,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> (BasicProperties, Vec<u8>) {
+        let replies = ReplyQueue::new()
+            .declare(conn)
+            .await
+            .expect("failed to declare reply queue");
+        let reply = replies.expect_correlation_id::<()>(uuid::Uuid::new_v4().to_string());
+        let mut properties = properties
+            .with_reply_to(replies.name().into())
+            .with_correlation_id(reply.correlation_id().into());
+
+        let channel = conn
+            .create_channel()
+            .await
+            .expect("failed to create channel");
+        if !exchange.is_empty() {
+            let mut headers = properties.headers().clone().unwrap_or_default();
+            headers.insert(
+                crate::handler_config::REPLY_ROUTING_KEY_HEADER.into(),
+                AMQPValue::LongString(replies.name().into()),
+            );
+            properties = properties.with_headers(headers);
+            channel
+                .queue_bind(
+                    replies.name(),
+                    exchange,
+                    replies.name(),
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+                .expect("failed to bind reply queue");
+        }
+        channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                payload,
+                properties,
+            )
+            .await
+            .expect("failed to publish request");
+
+        let reply = tokio::time::timeout(Duration::from_secs(10), reply.recv_raw())
+            .await
+            .expect("no reply within 10 seconds")
+            .expect("failed to receive reply");
+        let _ = replies.close().await;
+        reply
+    }
+
+    /// A metrics recorder that keeps the values of all metrics, for checking the metrics recorded by a test.
+    ///
+    /// Metrics are keyed by their name followed by their labels, e.g. `kanin.requests{routing_key=abc}`.
+    /// Use it with [`metrics::with_local_recorder`].
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: Mutex<HashMap<String, Arc<AtomicU64>>>,
+        gauges: Mutex<HashMap<String, Arc<AtomicU64>>>,
+        histograms: Mutex<HashMap<String, Arc<Samples>>>,
+    }
+
+    /// The values recorded in a histogram of a [`TestRecorder`].
+    #[derive(Default)]
+    struct Samples(Mutex<Vec<f64>>);
+
+    impl HistogramFn for Samples {
+        fn record(&self, value: f64) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    impl TestRecorder {
+        /// Returns the value of the given counter, or 0 if it was never recorded.
+        fn counter(&self, key: &str) -> u64 {
+            self.counters
+                .lock()
+                .unwrap()
+                .get(key)
+                .map_or(0, |counter| counter.load(Ordering::Relaxed))
+        }
+
+        /// Returns the value of the given gauge, or 0 if it was never recorded.
+        fn gauge(&self, key: &str) -> f64 {
+            self.gauges
+                .lock()
+                .unwrap()
+                .get(key)
+                .map_or(0.0, |gauge| f64::from_bits(gauge.load(Ordering::Relaxed)))
+        }
+
+        /// Returns the values recorded in the given histogram, in the order they were recorded.
+        fn histogram(&self, key: &str) -> Vec<f64> {
+            self.histograms
+                .lock()
+                .unwrap()
+                .get(key)
+                .map(|samples| samples.0.lock().unwrap().clone())
+                .unwrap_or_default()
+        }
+
+        /// Returns the name and labels of the given key, as used by the accessors.
+        fn key(key: &Key) -> String {
+            let labels: Vec<_> = key
+                .labels()
+                .map(|label| format!("{}={}", label.key(), label.value()))
+                .collect();
+            format!("{}{{{}}}", key.name(), labels.join(","))
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {
+        }
+
+        fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+        fn describe_histogram(
+            &self,
+            _key: KeyName,
+            _unit: Option<Unit>,
+            _description: SharedString,
+        ) {
+        }
+
+        fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+            let mut counters = self.counters.lock().unwrap();
+            Counter::from_arc(counters.entry(Self::key(key)).or_default().clone())
+        }
+
+        fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+            let mut gauges = self.gauges.lock().unwrap();
+            Gauge::from_arc(gauges.entry(Self::key(key)).or_default().clone())
+        }
+
+        fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+            let mut histograms = self.histograms.lock().unwrap();
+            Histogram::from_arc(histograms.entry(Self::key(key)).or_default().clone())
+        }
+    }
+
     /// Initializes test logging.
     fn init_logging() {
         std::env::set_var("RUST_LOG", "debug");
         let _ = tracing_subscriber::fmt().try_init();
     }
 
-    /// Returns a connection to AMQP. This will retry until a succesful connection is established.
-    async fn amqp_connect() -> Connection {
+    /// Returns a connection to AMQP on the given address. This will retry until a succesful connection is established.
+    async fn amqp_connect(amqp_addr: &str) -> Connection {
         let mut attempts = 0;
         let conn = loop {
-            match Connection::connect(TEST_AMQP_ADDR, ConnectionProperties::default()).await {
+            match Connection::connect(amqp_addr, ConnectionProperties::default()).await {
                 Ok(conn) => break conn,
                 Err(e) => {
                     warn!("Retrying connection");
                     attempts += 1;
                     if attempts > 8 {
-                        panic!("Failed to establish a connection to AMQP. Ensure that a RabbitMQ instance is running on the 5672 port on localhost. Error: {e}")
+                        panic!("Failed to establish a connection to AMQP. Ensure that a RabbitMQ instance is running on {amqp_addr}, or enable the `test-broker` feature. Error: {e}")
                     }
                     tokio::time::sleep(Duration::from_secs(1)).await;
                 }
@@ -0,0 +1,129 @@
+//! Middleware wrapping the handling of requests. See [`Middleware`].
+
+use std::{
+    any::{type_name, Any},
+    fmt,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use lapin::types::FieldTable;
+
+use crate::{HandlerError, Request};
+
+/// Cross-cutting behavior that wraps the handling of requests, such as authentication checks, metrics or request logging.
+///
+/// Middleware is added to every handler of an app with [`App::layer`](crate::App::layer),
+/// or to a single handler (or group of handlers) with [`HandlerConfig::layer`](crate::HandlerConfig::layer).
+/// The middleware of the app wraps the middleware of the handler, and middleware added first wraps middleware added later:
+/// [`Middleware::before`] is called in the order the middleware was added, and [`Middleware::after`] in the reverse order.
+///
+/// Both methods do nothing by default, so implementations only need to implement the one they need.
+#[async_trait]
+pub trait Middleware<S>: Send + Sync + 'static
+where
+    S: Send + Sync + 'static,
+{
+    /// Called with the request before the handler is called.
+    ///
+    /// If this returns an error, neither the handler nor any further middleware is called,
+    /// and the request is responded to with the error, just as if the handler had failed to extract one of its arguments.
+    /// Handlers that cannot respond to errors reject the request instead.
+    async fn before(&self, req: &mut Request<S>) -> Result<(), HandlerError> {
+        let _ = req;
+        Ok(())
+    }
+
+    /// Called with the encoded response after the handler returned, before the response is published.
+    ///
+    /// The payload and headers of the response may be changed. This is called even if the handler does not reply.
+    async fn after(&self, req: &mut Request<S>, response: &mut EncodedResponse) {
+        let _ = (req, response);
+    }
+}
+
+/// The encoded response of a handler, given to [`Middleware::after`].
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct EncodedResponse {
+    /// The payload of the reply.
+    pub payload: Vec<u8>,
+    /// The headers of the reply.
+    pub headers: FieldTable,
+}
+
+impl EncodedResponse {
+    /// Creates an encoded response with the given payload and headers.
+    pub fn new(payload: Vec<u8>, headers: FieldTable) -> Self {
+        Self { payload, headers }
+    }
+}
+
+/// The middleware wrapping a handler, outermost first.
+pub(crate) type Chain<S> = Arc<[Arc<dyn Middleware<S>>]>;
+
+/// Middleware with its state type erased, so it can be stored in a [`HandlerConfig`](crate::HandlerConfig).
+#[derive(Clone)]
+pub(crate) struct ErasedMiddleware {
+    /// The `Arc<dyn Middleware<S>>`.
+    middleware: Arc<dyn Any + Send + Sync>,
+    /// The name of the state type of the middleware.
+    state: &'static str,
+}
+
+impl ErasedMiddleware {
+    /// Erases the state type of the given middleware.
+    pub(crate) fn new<S, M>(middleware: M) -> Self
+    where
+        S: Send + Sync + 'static,
+        M: Middleware<S>,
+    {
+        let middleware: Arc<dyn Middleware<S>> = Arc::new(middleware);
+        Self {
+            middleware: Arc::new(middleware),
+            state: type_name::<S>(),
+        }
+    }
+
+    /// Returns the middleware, if its state type is `S`.
+    pub(crate) fn downcast<S: Send + Sync + 'static>(&self) -> Option<Arc<dyn Middleware<S>>> {
+        self.middleware
+            .downcast_ref::<Arc<dyn Middleware<S>>>()
+            .cloned()
+    }
+
+    /// Returns the name of the state type of the middleware.
+    pub(crate) fn state(&self) -> &'static str {
+        self.state
+    }
+}
+
+impl fmt::Debug for ErasedMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErasedMiddleware")
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// Calls [`Middleware::before`] on every middleware of the chain in order, stopping at the first error.
+pub(crate) async fn before<S>(chain: &Chain<S>, req: &mut Request<S>) -> Result<(), HandlerError>
+where
+    S: Send + Sync + 'static,
+{
+    for middleware in chain.iter() {
+        middleware.before(req).await?;
+    }
+
+    Ok(())
+}
+
+/// Calls [`Middleware::after`] on every middleware of the chain in reverse order.
+pub(crate) async fn after<S>(chain: &Chain<S>, req: &mut Request<S>, response: &mut EncodedResponse)
+where
+    S: Send + Sync + 'static,
+{
+    for middleware in chain.iter().rev() {
+        middleware.after(req, response).await;
+    }
+}
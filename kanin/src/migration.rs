@@ -0,0 +1,195 @@
+//! Zero-downtime queue migrations.
+//!
+//! A [`QueueMigration`] moves all messages from one queue to another queue or exchange, without losing any messages.
+//! This is useful for renaming queues or switching exchange types.
+//!
+//! A typical migration looks like this:
+//! 1. Double-bind the new queue with the same bindings as the old queue (see [`QueueMigration::with_binding`]),
+//!    so new messages are routed to both queues.
+//! 2. Deploy the handlers consuming from the new queue and stop consuming from the old queue.
+//! 3. Run the migration to move the remaining messages from the old queue to the new queue.
+
+use lapin::{
+    options::{
+        BasicAckOptions, BasicGetOptions, BasicPublishOptions, BasicRejectOptions,
+        ConfirmSelectOptions, QueueBindOptions,
+    },
+    publisher_confirm::Confirmation,
+    types::FieldTable,
+    Connection,
+};
+use metrics::{counter, gauge};
+use tracing::{debug, info};
+
+use crate::{Error, HandlerConfig, Result};
+
+/// Configuration of a migration of messages from one queue to another queue or exchange.
+#[derive(Clone, Debug)]
+#[must_use = "The migration will not do anything unless you call `.run`."]
+pub struct QueueMigration {
+    /// The queue to move messages from.
+    from_queue: String,
+    /// The exchange to republish messages to.
+    to_exchange: String,
+    /// The routing key to republish messages with.
+    to_routing_key: String,
+    /// Bindings to create before migrating messages, as (queue, exchange, routing key).
+    bindings: Vec<(String, String, String)>,
+}
+
+/// The outcome of a successful [`QueueMigration`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MigrationReport {
+    /// The number of messages moved from the old queue.
+    pub migrated: u64,
+}
+
+impl QueueMigration {
+    /// Creates a new migration of messages from the given queue to the given queue.
+    ///
+    /// Messages are republished to the new queue via the default exchange.
+    pub fn new(from_queue: impl Into<String>, to_queue: impl Into<String>) -> Self {
+        Self {
+            from_queue: from_queue.into(),
+            to_exchange: HandlerConfig::DEFAULT_EXCHANGE.to_string(),
+            to_routing_key: to_queue.into(),
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Republishes messages to the given exchange with the given routing key instead of directly to a queue.
+    pub fn to_exchange(
+        mut self,
+        exchange: impl Into<String>,
+        routing_key: impl Into<String>,
+    ) -> Self {
+        self.to_exchange = exchange.into();
+        self.to_routing_key = routing_key.into();
+        self
+    }
+
+    /// Binds the given queue to the given exchange with the given routing key before migrating messages.
+    ///
+    /// Use this to double-bind the new queue with the bindings of the old queue, so no messages are lost while migrating.
+    /// The queue must already exist.
+    pub fn with_binding(
+        mut self,
+        queue: impl Into<String>,
+        exchange: impl Into<String>,
+        routing_key: impl Into<String>,
+    ) -> Self {
+        self.bindings
+            .push((queue.into(), exchange.into(), routing_key.into()));
+        self
+    }
+
+    /// Runs the migration, moving messages until the old queue is empty.
+    ///
+    /// Each message is republished with its original properties and only acknowledged on the old queue once the broker
+    /// has confirmed the republish, so messages are never lost (but may be duplicated if the migration is interrupted).
+    /// Messages are republished as mandatory, so messages that cannot be routed (e.g. because the new queue does not exist)
+    /// are not acknowledged either.
+    ///
+    /// Progress is reported via the `kanin.migration.messages_migrated` counter and
+    /// the `kanin.migration.messages_remaining` gauge, both labeled with the old queue.
+    ///
+    /// # Errors
+    /// Returns `Err` if communication with the AMQP broker fails, or if the broker does not confirm or returns a republish.
+    /// In the latter cases, the message is requeued on the old queue.
+    pub async fn run(self, conn: &Connection) -> Result<MigrationReport> {
+        let channel = conn.create_channel().await.map_err(Error::from)?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .map_err(Error::from)?;
+
+        for (queue, exchange, routing_key) in &self.bindings {
+            debug!("Binding queue {queue:?} to exchange {exchange:?} on routing key {routing_key:?} before migration...");
+            channel
+                .queue_bind(
+                    queue,
+                    exchange,
+                    routing_key,
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+                .map_err(Error::from)?;
+        }
+
+        info!(
+            "Migrating messages from queue {:?} to exchange {:?} on routing key {:?}...",
+            self.from_queue, self.to_exchange, self.to_routing_key
+        );
+
+        let mut migrated = 0;
+        while let Some(message) = channel
+            .basic_get(&self.from_queue, BasicGetOptions { no_ack: false })
+            .await
+            .map_err(Error::from)?
+        {
+            let delivery = message.delivery;
+            let confirmation = channel
+                .basic_publish(
+                    &self.to_exchange,
+                    &self.to_routing_key,
+                    // Unroutable messages are returned rather than confirmed, so they are not acked and lost.
+                    BasicPublishOptions {
+                        mandatory: true,
+                        ..Default::default()
+                    },
+                    &delivery.data,
+                    delivery.properties.clone(),
+                )
+                .await
+                .map_err(Error::from)?
+                .await
+                .map_err(Error::from)?;
+
+            let error = match confirmation {
+                Confirmation::Ack(Some(returned)) | Confirmation::Nack(Some(returned)) => {
+                    Some(Error::PublishReturned {
+                        routing_key: self.to_routing_key.clone(),
+                        reply_code: returned.reply_code,
+                        reply_text: returned.reply_text.to_string(),
+                    })
+                }
+                Confirmation::Nack(None) => {
+                    Some(Error::PublishNotConfirmed(self.to_routing_key.clone()))
+                }
+                Confirmation::Ack(None) | Confirmation::NotRequested => None,
+            };
+            if let Some(error) = error {
+                delivery
+                    .reject(BasicRejectOptions { requeue: true })
+                    .await
+                    .map_err(Error::from)?;
+                return Err(error);
+            }
+
+            delivery
+                .ack(BasicAckOptions::default())
+                .await
+                .map_err(Error::from)?;
+
+            migrated += 1;
+            counter!("kanin.migration.messages_migrated", "queue" => self.from_queue.clone())
+                .increment(1);
+            gauge!("kanin.migration.messages_remaining", "queue" => self.from_queue.clone())
+                .set(f64::from(message.message_count));
+            debug!(
+                "Migrated message {migrated} from queue {:?} ({} remaining).",
+                self.from_queue, message.message_count
+            );
+        }
+
+        gauge!("kanin.migration.messages_remaining", "queue" => self.from_queue.clone()).set(0.0);
+        info!(
+            "Migrated {migrated} messages from queue {:?}.",
+            self.from_queue
+        );
+
+        Ok(MigrationReport { migrated })
+    }
+}
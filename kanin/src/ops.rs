@@ -0,0 +1,194 @@
+//! Operational helpers for inspecting and managing queues, e.g. from ops binaries or admin endpoints.
+//!
+//! [`message_count`] and [`Peek`] only inspect queues, while [`Purge`] permanently deletes messages
+//! and must therefore be [confirmed](Purge::confirm) before it is run.
+
+use lapin::{
+    options::{BasicGetOptions, BasicNackOptions, QueueDeclareOptions, QueuePurgeOptions},
+    protocol::basic::AMQPProperties,
+    types::FieldTable,
+    Connection,
+};
+use tracing::{debug, info, warn};
+
+use crate::{Error, Result};
+
+/// Returns the number of messages ready to be delivered from the given queue.
+///
+/// Messages that have been delivered to consumers but not yet acknowledged are not counted.
+///
+/// # Errors
+/// Returns `Err` if communication with the AMQP broker fails or if the queue does not exist.
+pub async fn message_count(conn: &Connection, queue: &str) -> Result<u32> {
+    let channel = conn.create_channel().await.map_err(Error::from)?;
+    // A passive declaration only checks that the queue exists, and reports its message count.
+    let declared = channel
+        .queue_declare(
+            queue,
+            QueueDeclareOptions {
+                passive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(Error::from)?;
+
+    Ok(declared.message_count())
+}
+
+/// Purges a queue, permanently deleting all messages that are ready to be delivered from it.
+///
+/// As purging cannot be undone, the purge must be [confirmed](Purge::confirm) by repeating the name of the queue,
+/// otherwise [`Purge::run`] returns [`Error::NotConfirmed`] without touching the queue.
+#[derive(Clone, Debug)]
+#[must_use = "The queue will not be purged unless you call `.run`."]
+pub struct Purge {
+    /// The queue to purge.
+    queue: String,
+    /// The name of the queue given as confirmation, if any.
+    confirmation: Option<String>,
+}
+
+impl Purge {
+    /// Creates an unconfirmed purge of the given queue.
+    pub fn new(queue: impl Into<String>) -> Self {
+        Self {
+            queue: queue.into(),
+            confirmation: None,
+        }
+    }
+
+    /// Confirms the purge by repeating the name of the queue to purge.
+    ///
+    /// The purge is only confirmed if the given name is exactly the name of the queue,
+    /// which protects against purging the wrong queue e.g. due to a typo in a configuration.
+    pub fn confirm(mut self, queue: impl Into<String>) -> Self {
+        self.confirmation = Some(queue.into());
+        self
+    }
+
+    /// Returns true if the purge has been confirmed with the name of the queue.
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmation.as_deref() == Some(self.queue.as_str())
+    }
+
+    /// Runs the purge, returning the number of messages that were deleted.
+    ///
+    /// Messages that have been delivered to consumers but not yet acknowledged are not deleted.
+    ///
+    /// # Errors
+    /// Returns [`Error::NotConfirmed`] if the purge has not been [confirmed](Purge::confirm).
+    /// Returns `Err` if communication with the AMQP broker fails or if the queue does not exist.
+    pub async fn run(self, conn: &Connection) -> Result<u32> {
+        if !self.is_confirmed() {
+            warn!(
+                "Refusing to purge queue {:?} as the purge was not confirmed with the name of the queue (got {:?}).",
+                self.queue, self.confirmation
+            );
+            return Err(Error::NotConfirmed(self.queue));
+        }
+
+        let channel = conn.create_channel().await.map_err(Error::from)?;
+        let purged = channel
+            .queue_purge(&self.queue, QueuePurgeOptions::default())
+            .await
+            .map_err(Error::from)?;
+
+        info!("Purged {purged} messages from queue {:?}.", self.queue);
+        Ok(purged)
+    }
+}
+
+/// A message that was peeked at with [`Peek`].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct PeekedMessage {
+    /// The exchange the message was published to.
+    pub exchange: String,
+    /// The routing key the message was published with.
+    pub routing_key: String,
+    /// Whether the message had been delivered before it was peeked at.
+    pub redelivered: bool,
+    /// The properties of the message.
+    pub properties: AMQPProperties,
+    /// The payload of the message.
+    pub data: Vec<u8>,
+}
+
+/// Peeks at the first messages of a queue without consuming them.
+///
+/// Messages are fetched one by one and requeued together once all of them have been fetched.
+/// While peeking, the fetched messages are not delivered to other consumers.
+/// Note that requeued messages are marked as redelivered, and count towards the
+/// [delivery limit](https://www.rabbitmq.com/docs/quorum-queues#poison-message-handling) of quorum queues.
+#[derive(Clone, Debug)]
+#[must_use = "The queue will not be peeked at unless you call `.run`."]
+pub struct Peek {
+    /// The queue to peek at.
+    queue: String,
+    /// The maximum number of messages to peek at.
+    limit: usize,
+}
+
+impl Peek {
+    /// Creates a peek at (at most) the first `limit` messages of the given queue.
+    pub fn new(queue: impl Into<String>, limit: usize) -> Self {
+        Self {
+            queue: queue.into(),
+            limit,
+        }
+    }
+
+    /// Runs the peek, returning the messages in the order they were fetched. Fewer messages are returned if the queue has fewer messages.
+    ///
+    /// # Errors
+    /// Returns `Err` if communication with the AMQP broker fails or if the queue does not exist.
+    /// Any messages fetched before the failure are requeued by the broker once the channel is closed.
+    pub async fn run(self, conn: &Connection) -> Result<Vec<PeekedMessage>> {
+        let channel = conn.create_channel().await.map_err(Error::from)?;
+
+        let mut messages = Vec::new();
+        let mut last_delivery_tag = None;
+        while messages.len() < self.limit {
+            let Some(message) = channel
+                .basic_get(&self.queue, BasicGetOptions { no_ack: false })
+                .await
+                .map_err(Error::from)?
+            else {
+                break;
+            };
+
+            let delivery = message.delivery;
+            last_delivery_tag = Some(delivery.delivery_tag);
+            messages.push(PeekedMessage {
+                exchange: delivery.exchange.to_string(),
+                routing_key: delivery.routing_key.to_string(),
+                redelivered: delivery.redelivered,
+                properties: delivery.properties,
+                data: delivery.data,
+            });
+        }
+
+        // Requeue all the fetched messages at once, so none of them is redelivered to us while peeking.
+        if let Some(delivery_tag) = last_delivery_tag {
+            channel
+                .basic_nack(
+                    delivery_tag,
+                    BasicNackOptions {
+                        multiple: true,
+                        requeue: true,
+                    },
+                )
+                .await
+                .map_err(Error::from)?;
+        }
+
+        debug!(
+            "Peeked at {} messages of queue {:?}.",
+            messages.len(),
+            self.queue
+        );
+        Ok(messages)
+    }
+}
@@ -0,0 +1,164 @@
+//! Health probing of kanin services.
+//!
+//! Apps can register a standardized ping handler via [`App::with_ping`](crate::App::with_ping).
+//! The [`ping`] function can then be used to measure the round-trip latency to the app.
+
+use std::{
+    convert::Infallible,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use lapin::{
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions,
+        QueueDeleteOptions,
+    },
+    types::{FieldTable, ShortString},
+    BasicProperties, Connection,
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    error::FromError, extract::Deadline, Error, Extract, HandlerConfig, HandlerError, Request,
+    Respond, Result,
+};
+
+/// The raw payload of a ping request.
+pub(crate) struct PingPayload(Vec<u8>);
+
+#[async_trait]
+impl<S> Extract<S> for PingPayload
+where
+    S: Send + Sync,
+{
+    type Error = Infallible;
+
+    async fn extract(req: &mut Request<S>) -> std::result::Result<Self, Self::Error> {
+        Ok(Self(req.delivery().data.clone()))
+    }
+}
+
+/// The reply to a ping request, which is simply the payload of the request.
+#[derive(Debug)]
+pub(crate) struct Pong(Vec<u8>);
+
+impl Respond for Pong {
+    fn respond(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl FromError<HandlerError> for Pong {
+    fn from_error(error: HandlerError) -> Self {
+        // Extracting the payload is infallible, but we'll reply with an empty pong anyway in this case.
+        warn!("Ping handler failed: {error:#}");
+        Self(Vec::new())
+    }
+}
+
+/// The standardized ping handler. It echoes the payload back to the caller.
+pub(crate) async fn ping_handler(PingPayload(payload): PingPayload) -> Pong {
+    Pong(payload)
+}
+
+/// Sends a ping to the app listening on the given routing key and returns the round-trip latency.
+///
+/// The app must have registered a ping handler on the routing key via [`App::with_ping`](crate::App::with_ping).
+/// The ping is published to the default exchange, so the routing key must be the name of the ping handler's queue (which it is by default).
+///
+/// This function waits indefinitely for a reply. Wrap it in [`tokio::time::timeout`] to put a limit on how long to wait.
+///
+/// # Errors
+/// Returns [`Error::DeadlineExceeded`] if the deadline of the request currently being handled has been exceeded.
+/// Returns `Err` if communication with the AMQP broker fails or if the reply does not match the ping.
+pub async fn ping(conn: &Connection, routing_key: &str) -> Result<Duration> {
+    Deadline::current().check()?;
+
+    let channel = conn.create_channel().await.map_err(Error::from)?;
+
+    // We declare a temporary, server-named queue to receive the reply on.
+    let reply_queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map_err(Error::from)?;
+    let reply_to = reply_queue.name().clone();
+
+    let mut consumer = channel
+        .basic_consume(
+            reply_to.as_str(),
+            "kanin.probe",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .map_err(Error::from)?;
+
+    let correlation_id = Uuid::new_v4().to_string();
+    let nonce = Uuid::new_v4().as_bytes().to_vec();
+
+    let start = Instant::now();
+    channel
+        .basic_publish(
+            HandlerConfig::DEFAULT_EXCHANGE,
+            routing_key,
+            BasicPublishOptions::default(),
+            &nonce,
+            Deadline::propagate_current(
+                BasicProperties::default()
+                    .with_reply_to(reply_to.clone())
+                    .with_correlation_id(ShortString::from(correlation_id.clone())),
+            ),
+        )
+        .await
+        .map_err(Error::from)?;
+
+    let latency = loop {
+        let delivery = match consumer.next().await {
+            Some(delivery) => delivery.map_err(Error::from)?,
+            None => return Err(Error::ConsumerCancelled(reply_to.to_string())),
+        };
+        delivery
+            .ack(BasicAckOptions::default())
+            .await
+            .map_err(Error::from)?;
+
+        let is_reply = delivery
+            .properties
+            .correlation_id()
+            .as_ref()
+            .map_or(false, |id| id.as_str() == correlation_id);
+
+        if !is_reply {
+            debug!("Ignoring unrelated message on ping reply queue {reply_to}.");
+            continue;
+        }
+
+        if delivery.data != nonce {
+            return Err(Error::InvalidPingReply(routing_key.to_string()));
+        }
+
+        break start.elapsed();
+    };
+
+    debug!("Ping to {routing_key:?} took {latency:?}.");
+
+    if let Err(e) = channel
+        .queue_delete(reply_to.as_str(), QueueDeleteOptions::default())
+        .await
+    {
+        warn!("Failed to delete ping reply queue {reply_to}: {e:#}");
+    }
+
+    Ok(latency)
+}
@@ -0,0 +1,137 @@
+//! Detection of redelivery storms, i.e. messages that are redelivered over and over again.
+//!
+//! A message that makes its handler panic is requeued, redelivered, and makes the handler panic again.
+//! Such requeue loops are easy to miss, as the handler keeps "working" on other messages while burning resources on the looping message.
+//! See [`HandlerConfig::with_redelivery_storm_detection`](crate::HandlerConfig::with_redelivery_storm_detection).
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use lapin::protocol::basic::AMQPProperties;
+use sha2::{Digest, Sha256};
+
+/// Detects messages that are redelivered more than a threshold number of times within a window.
+///
+/// See [`HandlerConfig::with_redelivery_storm_detection`](crate::HandlerConfig::with_redelivery_storm_detection).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedeliveryStorm {
+    /// The number of redeliveries within the window after which a message is considered part of a storm.
+    pub(crate) threshold: usize,
+    /// The window in which redeliveries are counted.
+    pub(crate) window: Duration,
+    /// Whether messages that are part of a storm are dead-lettered rather than handled.
+    pub(crate) dead_letter: bool,
+}
+
+impl RedeliveryStorm {
+    /// Detects messages that are redelivered more than `threshold` times within `window`.
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            dead_letter: false,
+        }
+    }
+
+    /// Rejects messages that are part of a storm without requeueing them, instead of handling them.
+    ///
+    /// The messages are dead-lettered if the queue has a dead letter exchange (see [`HandlerConfig::with_dead_letter_exchange`](crate::HandlerConfig::with_dead_letter_exchange)),
+    /// and dropped otherwise. By default, messages that are part of a storm are only reported and still handled.
+    pub fn with_dead_lettering(mut self) -> Self {
+        self.dead_letter = true;
+        self
+    }
+}
+
+/// Returns the key that identifies a message across its redeliveries.
+///
+/// Messages are identified by their `message_id` property, falling back to their `correlation_id` property.
+/// Messages with neither are identified by a hash of their routing key and payload,
+/// so identical messages without either property are counted as the same message.
+pub(crate) fn message_key(
+    properties: &AMQPProperties,
+    routing_key: &str,
+    payload: &[u8],
+) -> String {
+    if let Some(id) = properties
+        .message_id()
+        .as_ref()
+        .or(properties.correlation_id().as_ref())
+    {
+        return id.to_string();
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(routing_key);
+    hasher.update([0]);
+    hasher.update(payload);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Keeps track of recent redeliveries of messages on a single queue.
+#[derive(Debug)]
+pub(crate) struct RedeliveryTracker {
+    /// The storm detection settings.
+    pub(crate) storm: RedeliveryStorm,
+    /// The instants of recent redeliveries, keyed by message.
+    redeliveries: HashMap<String, VecDeque<Instant>>,
+    /// When messages were requeued by kanin itself, e.g. to limit the requests in flight per caller, keyed by message.
+    /// Their next redelivery is not counted.
+    requeued: HashMap<String, Instant>,
+    /// The instant after which messages without recent redeliveries are forgotten.
+    next_sweep: Option<Instant>,
+}
+
+impl RedeliveryTracker {
+    /// Creates a tracker with the given storm detection settings.
+    pub(crate) fn new(storm: RedeliveryStorm) -> Self {
+        Self {
+            storm,
+            redeliveries: HashMap::new(),
+            requeued: HashMap::new(),
+            next_sweep: None,
+        }
+    }
+
+    /// Notes that the message with the given key was requeued on purpose at the given instant,
+    /// so its next redelivery is not counted towards a storm.
+    pub(crate) fn ignore_next(&mut self, key: &str, now: Instant) {
+        self.requeued.insert(key.to_string(), now);
+    }
+
+    /// Records a redelivery of the message with the given key at the given instant.
+    ///
+    /// Returns the number of redeliveries of the message within the window if it exceeds the threshold, i.e. if the message is part of a storm.
+    /// Redeliveries of messages passed to [`RedeliveryTracker::ignore_next`] are not counted.
+    pub(crate) fn record(&mut self, key: &str, now: Instant) -> Option<usize> {
+        let window = self.storm.window;
+        let is_recent = |instant: &Instant| now.saturating_duration_since(*instant) < window;
+
+        // Messages that are no longer redelivered would otherwise be remembered forever.
+        // Requeued messages may be redelivered to another consumer, so they are forgotten as well.
+        if self.next_sweep.map_or(true, |sweep| sweep <= now) {
+            self.redeliveries
+                .retain(|_, instants| instants.back().map_or(false, is_recent));
+            self.requeued.retain(|_, instant| is_recent(instant));
+            self.next_sweep = Some(now + window);
+        }
+
+        if self.requeued.remove(key).is_some() {
+            return None;
+        }
+
+        let instants = self.redeliveries.entry(key.to_string()).or_default();
+        while instants
+            .front()
+            .map_or(false, |instant| !is_recent(instant))
+        {
+            instants.pop_front();
+        }
+        instants.push_back(now);
+
+        let count = instants.len();
+        (count > self.storm.threshold).then_some(count)
+    }
+}
@@ -0,0 +1,89 @@
+//! Deduplication of replies to redelivered requests.
+//!
+//! Requests are delivered at least once, so a request may be redelivered after its reply was already published,
+//! e.g. if the app lost its connection before acknowledging the request. Handlers that are idempotent (for instance because they
+//! cache their results) then reply to the same request twice. With a [`ReplyDedupStore`] set via
+//! [`App::with_reply_dedup`](crate::App::with_reply_dedup), the replies to redelivered requests are skipped if a reply with the
+//! same correlation ID was already published to the same caller.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+};
+
+/// A store of the replies that were published, identified by the routing key they were published to (the `reply_to` property of the request)
+/// and their correlation ID.
+///
+/// Implement this to share the published replies between instances of the app, e.g. in a cache with a time-to-live.
+/// See [`MemoryReplyDedupStore`] for a store that keeps them in memory.
+pub trait ReplyDedupStore: Send + Sync {
+    /// Records that a reply with the given correlation ID was published to the given routing key.
+    fn insert(&self, reply_to: &str, correlation_id: &str);
+
+    /// Returns true if a reply with the given correlation ID was already published to the given routing key.
+    fn contains(&self, reply_to: &str, correlation_id: &str) -> bool;
+}
+
+/// A [`ReplyDedupStore`] that keeps the most recently published replies in memory, up to a maximum number of replies.
+///
+/// When the store is full, the oldest replies are forgotten. As requests are usually redelivered shortly after their first delivery,
+/// a capacity of a few times the number of requests handled per minute is usually plenty.
+/// Only redeliveries to this instance of the app are deduplicated.
+#[derive(Debug)]
+pub struct MemoryReplyDedupStore {
+    /// The maximum number of replies to remember.
+    capacity: usize,
+    /// The remembered replies.
+    replies: Mutex<DedupEntries>,
+}
+
+/// The replies remembered by a [`MemoryReplyDedupStore`].
+#[derive(Debug, Default)]
+struct DedupEntries {
+    /// The routing keys and correlation IDs of the replies.
+    keys: HashSet<(String, String)>,
+    /// The same keys, oldest first.
+    order: VecDeque<(String, String)>,
+}
+
+impl MemoryReplyDedupStore {
+    /// Creates a store that remembers at most `capacity` replies.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            replies: Mutex::default(),
+        }
+    }
+}
+
+impl ReplyDedupStore for MemoryReplyDedupStore {
+    fn insert(&self, reply_to: &str, correlation_id: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut replies = self
+            .replies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let key = (reply_to.to_string(), correlation_id.to_string());
+        if !replies.keys.insert(key.clone()) {
+            return;
+        }
+        replies.order.push_back(key);
+
+        while replies.order.len() > self.capacity {
+            if let Some(oldest) = replies.order.pop_front() {
+                replies.keys.remove(&oldest);
+            }
+        }
+    }
+
+    fn contains(&self, reply_to: &str, correlation_id: &str) -> bool {
+        self.replies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .keys
+            .contains(&(reply_to.to_string(), correlation_id.to_string()))
+    }
+}
@@ -0,0 +1,363 @@
+//! Reply queues for callers: declaring a temporary queue to receive replies on, and routing replies to their requests by correlation id.
+
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use lapin::{
+    options::{BasicConsumeOptions, QueueDeclareOptions},
+    types::{FieldTable, ShortString},
+    BasicProperties, Channel, Connection,
+};
+use prost::{DecodeError, Message};
+use tokio::{sync::oneshot, task::JoinHandle};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    extract::{Deadline, ReqId},
+    Error, Result,
+};
+
+/// Declares an exclusive, auto-delete queue that replies can be received on.
+///
+/// The queue is named by the broker and is deleted when its channel is closed.
+/// By default, replies expire after [`ReplyQueue::DEFAULT_MESSAGE_TTL`] if they are not consumed, and the queue itself expires
+/// after [`ReplyQueue::DEFAULT_EXPIRES`] without consumers, so neither are left behind if the caller goes away without cleaning up.
+///
+/// ```no_run
+/// # use kanin::reply_queue::ReplyQueue;
+/// # async fn example(conn: &kanin::Connection) -> kanin::Result<()> {
+/// let replies = ReplyQueue::new().declare(conn).await?;
+/// let (properties, reply) = replies.expect::<String>();
+/// // Publish the request with the given properties, then wait for the reply.
+/// let reply = reply.recv().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplyQueue {
+    /// The arguments (aka. x-arguments) to declare the queue with.
+    arguments: FieldTable,
+}
+
+impl ReplyQueue {
+    /// The default time that replies live on the queue before they expire.
+    pub const DEFAULT_MESSAGE_TTL: Duration = Duration::from_secs(60);
+
+    /// The default time after which the queue expires if it has no consumers.
+    pub const DEFAULT_EXPIRES: Duration = Duration::from_secs(5 * 60);
+
+    /// Creates a new reply queue with the default message TTL and expiry.
+    pub fn new() -> Self {
+        Self {
+            arguments: FieldTable::default(),
+        }
+        .with_message_ttl(Self::DEFAULT_MESSAGE_TTL)
+        .with_expires(Self::DEFAULT_EXPIRES)
+    }
+
+    /// Replies expire if not consumed within `message_ttl`.
+    /// See [documentation](https://www.rabbitmq.com/ttl.html#message-ttl-using-x-args).
+    // Panic is extremely unlikely, let's not bother.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn with_message_ttl(mut self, message_ttl: Duration) -> Self {
+        let millis: i64 = message_ttl
+            .as_millis()
+            .try_into()
+            .expect("Duration too long to fit milliseconds in i64");
+
+        self.arguments.insert("x-message-ttl".into(), millis.into());
+        self
+    }
+
+    /// The queue expires after a period of time when it does not have consumers.
+    /// See [documentation](https://www.rabbitmq.com/ttl.html#queue-ttl).
+    // Panic is extremely unlikely, let's not bother.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn with_expires(mut self, expires: Duration) -> Self {
+        let millis: i64 = expires
+            .as_millis()
+            .try_into()
+            .expect("Duration too long to fit milliseconds in i64");
+
+        self.arguments.insert("x-expires".into(), millis.into());
+        self
+    }
+
+    /// Declares the queue on a new channel and starts consuming replies from it.
+    ///
+    /// # Errors
+    /// Returns `Err` if communication with the AMQP broker fails.
+    pub async fn declare(&self, conn: &Connection) -> Result<Replies> {
+        let channel = conn.create_channel().await.map_err(Error::from)?;
+
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                self.arguments.clone(),
+            )
+            .await
+            .map_err(Error::from)?;
+        let name = queue.name().clone();
+
+        let mut consumer = channel
+            .basic_consume(
+                name.as_str(),
+                "kanin.reply_queue",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(Error::from)?;
+
+        let waiters = Waiters::default();
+        let dispatch = {
+            let waiters = waiters.clone();
+            let name = name.clone();
+            tokio::spawn(async move {
+                while let Some(delivery) = consumer.next().await {
+                    let delivery = match delivery {
+                        Ok(delivery) => delivery,
+                        Err(e) => {
+                            warn!("Reply queue {name} failed to receive reply: {e:#}");
+                            break;
+                        }
+                    };
+
+                    let correlation_id = delivery.properties.correlation_id().clone();
+                    if !waiters.dispatch(
+                        correlation_id.as_ref().map(ShortString::as_str),
+                        delivery.properties,
+                        delivery.data,
+                    ) {
+                        debug!("Ignoring unexpected reply on reply queue {name} with correlation id {correlation_id:?}.");
+                    }
+                }
+                debug!("Consumer on reply queue {name} stopped.");
+                // Dropping the waiters wakes up anyone still waiting for a reply.
+                waiters.clear();
+            })
+        };
+
+        debug!("Declared reply queue {name}.");
+
+        Ok(Replies {
+            channel,
+            name,
+            waiters,
+            dispatch,
+        })
+    }
+}
+
+impl Default for ReplyQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A declared reply queue that replies are consumed from. See [`ReplyQueue`].
+///
+/// Dropping this stops consuming replies. The queue is deleted once the channel is closed, see [`Replies::close`].
+#[derive(Debug)]
+pub struct Replies {
+    /// The channel the queue was declared on.
+    channel: Channel,
+    /// The name of the queue, as given by the broker.
+    name: ShortString,
+    /// The requests that are waiting for replies.
+    waiters: Waiters,
+    /// The task that dispatches replies to the waiters.
+    dispatch: JoinHandle<()>,
+}
+
+impl Replies {
+    /// The name of the queue, i.e. the value for the `reply_to` property of requests.
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Registers a new request that expects a reply on this queue.
+    ///
+    /// Returns the properties to publish the request with, which have `reply_to`, a unique `correlation_id` and a request ID set
+    /// (that of the request currently being handled, or a new one), along with the deadline of the request currently being handled, if any (see [`Deadline`]),
+    /// and the pending reply to wait on. The reply is registered before the request is published, so it cannot be missed.
+    pub fn expect<T>(&self) -> (BasicProperties, PendingReply<T>) {
+        let correlation_id = Uuid::new_v4().to_string();
+        let properties = Deadline::propagate_current(ReqId::propagate_current(
+            BasicProperties::default()
+                .with_reply_to(self.name.clone())
+                .with_correlation_id(correlation_id.clone().into()),
+        ));
+
+        (properties, self.expect_correlation_id(correlation_id))
+    }
+
+    /// Registers a new request with the given correlation id that expects a reply on this queue.
+    ///
+    /// The request must be published with `reply_to` set to [`Replies::name`] and the same correlation id.
+    /// When called while handling a request, the reply is only waited for until the deadline of that request (see [`Deadline`]).
+    pub fn expect_correlation_id<T>(&self, correlation_id: impl Into<String>) -> PendingReply<T> {
+        let correlation_id = correlation_id.into();
+        let reply = self.waiters.register(correlation_id.clone());
+
+        PendingReply {
+            reply,
+            correlation_id,
+            queue: self.name.to_string(),
+            deadline: Deadline::current(),
+            waiters: self.waiters.clone(),
+            _reply_type: PhantomData,
+        }
+    }
+
+    /// Stops consuming replies and closes the channel, which deletes the queue.
+    ///
+    /// # Errors
+    /// Returns `Err` if the channel could not be closed.
+    pub async fn close(self) -> Result<()> {
+        self.dispatch.abort();
+        self.waiters.clear();
+        self.channel
+            .close(200, "Reply queue closed")
+            .await
+            .map_err(Error::from)
+    }
+}
+
+impl Drop for Replies {
+    fn drop(&mut self) {
+        self.dispatch.abort();
+    }
+}
+
+/// A reply that has not been received yet. See [`Replies::expect`].
+///
+/// Dropping this unregisters the request, so a late reply is ignored.
+#[derive(Debug)]
+pub struct PendingReply<T> {
+    /// Receives the properties and payload of the reply.
+    reply: oneshot::Receiver<RawReply>,
+    /// The correlation id of the request.
+    correlation_id: String,
+    /// The name of the queue the reply is received on.
+    queue: String,
+    /// The deadline of the request that was being handled when the reply was registered, after which the reply is no longer waited for.
+    deadline: Deadline,
+    /// The requests that are waiting for replies, to unregister from when dropped.
+    waiters: Waiters,
+    /// The type the reply is decoded as.
+    _reply_type: PhantomData<fn() -> T>,
+}
+
+impl<T> PendingReply<T> {
+    /// The correlation id of the request.
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+
+    /// Waits for the reply and decodes it.
+    ///
+    /// This waits indefinitely, unless the reply was registered while handling a request with a deadline (see [`Deadline`]);
+    /// wrap it in e.g. [`tokio::time::timeout`] to stop waiting after some time.
+    ///
+    /// # Errors
+    /// Returns [`Error::ConsumerCancelled`] if the reply queue stopped consuming before the reply was received.
+    /// Returns [`Error::DeadlineExceeded`] if the deadline was exceeded before the reply was received.
+    /// Decoding errors are returned in the inner result.
+    pub async fn recv(self) -> Result<std::result::Result<T, DecodeError>>
+    where
+        T: Message + Default,
+    {
+        let (_properties, payload) = self.recv_raw().await?;
+        Ok(T::decode(&payload[..]))
+    }
+
+    /// Waits for the reply without decoding it, returning the properties it was published with along with its payload.
+    ///
+    /// # Errors
+    /// Returns [`Error::ConsumerCancelled`] if the reply queue stopped consuming before the reply was received.
+    /// Returns [`Error::DeadlineExceeded`] if the deadline was exceeded before the reply was received.
+    pub async fn recv_raw(mut self) -> Result<(BasicProperties, Vec<u8>)> {
+        let reply = match self.deadline.remaining() {
+            Some(remaining) => tokio::time::timeout(remaining, &mut self.reply)
+                .await
+                .map_err(|_elapsed| Error::DeadlineExceeded)?,
+            None => (&mut self.reply).await,
+        };
+
+        reply.map_err(|_closed| Error::ConsumerCancelled(self.queue.clone()))
+    }
+}
+
+impl<T> Drop for PendingReply<T> {
+    fn drop(&mut self) {
+        self.waiters.unregister(&self.correlation_id);
+    }
+}
+
+/// The properties and payload of a reply.
+pub(crate) type RawReply = (BasicProperties, Vec<u8>);
+
+/// The requests waiting for replies, keyed by correlation id.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Waiters(Arc<Mutex<HashMap<String, oneshot::Sender<RawReply>>>>);
+
+impl Waiters {
+    /// Registers a request with the given correlation id, returning a receiver for its reply.
+    pub(crate) fn register(&self, correlation_id: String) -> oneshot::Receiver<RawReply> {
+        let (sender, receiver) = oneshot::channel();
+        self.lock().insert(correlation_id, sender);
+        receiver
+    }
+
+    /// Unregisters the request with the given correlation id, if it is still waiting.
+    pub(crate) fn unregister(&self, correlation_id: &str) {
+        self.lock().remove(correlation_id);
+    }
+
+    /// Sends the properties and payload of a reply to the request with the given correlation id.
+    ///
+    /// Returns false if no request with the correlation id is waiting for a reply.
+    pub(crate) fn dispatch(
+        &self,
+        correlation_id: Option<&str>,
+        properties: BasicProperties,
+        payload: Vec<u8>,
+    ) -> bool {
+        let sender = match correlation_id {
+            Some(correlation_id) => self.lock().remove(correlation_id),
+            None => None,
+        };
+
+        match sender {
+            Some(sender) => sender.send((properties, payload)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Unregisters all requests, waking them up.
+    pub(crate) fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Locks the waiters. Poisoning is ignored, as the map is never left in an inconsistent state.
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, oneshot::Sender<RawReply>>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,146 @@
+//! Store-and-forward of replies that could not be published.
+//!
+//! If the broker is unreachable or the channel is closed when a handler replies, the reply would normally be lost for good.
+//! With a [`ReplyStore`] set via [`App::with_reply_store`](crate::App::with_reply_store), such replies are kept in the store instead
+//! and re-published once publishing works again.
+
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use lapin::{options::BasicPublishOptions, BasicProperties, Connection};
+use metrics::counter;
+use tracing::{debug, info, warn};
+
+/// How often the app tries to re-publish stored replies while running.
+pub const REPUBLISH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A reply that could not be published.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct StoredReply {
+    /// The exchange the reply should be published to. This is the default exchange unless the handler has another [`ReplyMode`](crate::handler_config::ReplyMode).
+    pub exchange: String,
+    /// The routing key the reply should be published to, i.e. the `reply_to` property of the request.
+    pub routing_key: String,
+    /// The properties of the reply message.
+    pub properties: BasicProperties,
+    /// The payload of the reply message.
+    pub payload: Vec<u8>,
+}
+
+/// A store for replies that could not be published.
+///
+/// Implement this to keep replies somewhere that survives restarts of the app, e.g. on local disk.
+/// See [`MemoryReplyStore`] for a store that keeps replies in memory.
+pub trait ReplyStore: Send + Sync {
+    /// Stores a reply that could not be published.
+    fn store(&self, reply: StoredReply);
+
+    /// Removes and returns all stored replies, in the order they were stored.
+    fn take_all(&self) -> Vec<StoredReply>;
+}
+
+/// A [`ReplyStore`] that keeps replies in memory, up to a maximum number of replies.
+///
+/// When the store is full, the oldest replies are dropped, as their callers have most likely given up on them.
+/// Replies are lost if the app stops before they could be re-published.
+#[derive(Debug)]
+pub struct MemoryReplyStore {
+    /// The maximum number of replies to keep.
+    capacity: usize,
+    /// The stored replies, oldest first.
+    replies: Mutex<VecDeque<StoredReply>>,
+}
+
+impl MemoryReplyStore {
+    /// Creates a store that keeps at most `capacity` replies.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            replies: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl ReplyStore for MemoryReplyStore {
+    fn store(&self, reply: StoredReply) {
+        let mut replies = self
+            .replies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        while !replies.is_empty() && replies.len() >= self.capacity {
+            if let Some(dropped) = replies.pop_front() {
+                warn!(
+                    "Reply store is full, dropping stored reply to routing key {:?}.",
+                    dropped.routing_key
+                );
+            }
+        }
+        if self.capacity > 0 {
+            replies.push_back(reply);
+        }
+    }
+
+    fn take_all(&self) -> Vec<StoredReply> {
+        self.replies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .drain(..)
+            .collect()
+    }
+}
+
+/// Re-publishes all stored replies on a new channel of the given connection.
+///
+/// Replies that still cannot be published are put back in the store.
+pub(crate) async fn republish(store: &dyn ReplyStore, conn: &Connection) {
+    let replies = store.take_all();
+    if replies.is_empty() {
+        return;
+    }
+
+    let channel = match conn.create_channel().await {
+        Ok(channel) => channel,
+        Err(e) => {
+            warn!("Could not create channel to re-publish stored replies, will try again later: {e:#}");
+            replies.into_iter().for_each(|reply| store.store(reply));
+            return;
+        }
+    };
+
+    let total = replies.len();
+    let mut republished = 0;
+    for reply in replies {
+        let publish = channel
+            .basic_publish(
+                &reply.exchange,
+                &reply.routing_key,
+                BasicPublishOptions::default(),
+                &reply.payload,
+                reply.properties.clone(),
+            )
+            .await;
+
+        match publish {
+            Ok(_confirm) => {
+                debug!(
+                    "Re-published stored reply to routing key {:?}.",
+                    reply.routing_key
+                );
+                republished += 1;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to re-publish stored reply to routing key {:?}, will try again later: {e:#}",
+                    reply.routing_key
+                );
+                store.store(reply);
+            }
+        }
+    }
+    counter!("kanin.replies_republished").increment(republished);
+    info!("Re-published {republished} of {total} stored replies.");
+
+    if let Err(e) = channel.close(0, "Re-published stored replies").await {
+        debug!("Failed to close channel used to re-publish stored replies: {e:#}");
+    }
+}
@@ -1,41 +1,114 @@
 //! AMQP requests.
 
-use std::sync::Arc;
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    error::Error as StdError,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use lapin::options::{BasicAckOptions, BasicRejectOptions};
 use lapin::protocol::basic::AMQPProperties;
+use lapin::types::{AMQPValue, FieldTable, ShortString};
 
 use lapin::{message::Delivery, Channel};
+use metrics::histogram;
 use tracing::{debug, error, warn};
 
-use crate::extract::ReqId;
+use crate::{
+    app::reply::ReplySettings,
+    error::{ExtractFailure, HandlerExtractErrorHook, ServerError},
+    extract::{ChannelPool, ReqId, ReqIdPolicy},
+    handler_config::{ReplyMode, REPLY_ROUTING_KEY_HEADER},
+    spawn::BackgroundTasks,
+    Extract, HandlerConfig, HandlerError,
+};
 
 /// An AMQP request.
 #[derive(Debug)]
 pub struct Request<S> {
     /// The app state. This is added to the app at construction in [`crate::App::new`] and given to each request.
     state: Arc<S>,
-    /// Request ID. This is a unique ID for every request. Either a newly created UUID or whatever
-    /// is found in the `req_id` header of the incoming AMQP message.
+    /// Request ID. This is a unique ID for every request. Either a newly created ID or whatever
+    /// is found in the request ID header of the incoming AMQP message, see [`ReqIdPolicy`].
     req_id: ReqId,
     /// Has this message been (n)ack'ed?
     // This has to be pub within kanin so that the acker extractor can set it.
     pub(crate) acked: bool,
+    /// Should this message be rejected and requeued due to a transient error? In that case, no reply should be sent.
+    // This has to be pub within kanin so that handlers can set it.
+    pub(crate) requeued: bool,
+    /// Has the reply been deferred to a [`ReplyHandle`](crate::extract::ReplyHandle)? In that case, the handler's response is not published.
+    // This has to be pub within kanin so that the reply handle extractor can set it.
+    pub(crate) reply_deferred: bool,
+    /// The number of payload bytes to include in diagnostics for undecodable messages, if diagnostics are enabled.
+    /// See [`HandlerConfig::with_payload_diagnostics`](crate::HandlerConfig::with_payload_diagnostics).
+    pub(crate) payload_diagnostics: Option<usize>,
+    /// Whether the reply is also published to the routing keys in the `CC` header of the request.
+    /// See [`HandlerConfig::with_reply_cc`](crate::HandlerConfig::with_reply_cc).
+    pub(crate) reply_cc: bool,
+    /// Where the reply to this request is published. See [`HandlerConfig::with_reply_mode`](crate::HandlerConfig::with_reply_mode).
+    pub(crate) reply_mode: ReplyMode,
+    /// How long each extractor may take. See [`HandlerConfig::with_extract_timeout`](crate::HandlerConfig::with_extract_timeout).
+    pub(crate) extract_timeout: Option<Duration>,
+    /// Called when extraction fails. See [`App::on_extract_error`](crate::App::on_extract_error).
+    pub(crate) on_extract_error: Option<HandlerExtractErrorHook>,
+    /// The publisher channel pool of the app, if it has one. See [`PublisherChannel`](crate::extract::PublisherChannel).
+    pub(crate) publisher_channels: Option<Arc<ChannelPool>>,
+    /// The settings replies to this request are published with, if it is handled by an app. See [`ReplyHandle`](crate::extract::ReplyHandle).
+    pub(crate) reply_settings: Option<Arc<ReplySettings>>,
+    /// The background tasks of the handler handling this request, if it is handled by an app. See [`Spawner`](crate::spawn::Spawner).
+    pub(crate) background: Option<Arc<BackgroundTasks>>,
+    /// Tracks the time from receiving the request until it is acknowledged, if it is handled by an app.
+    // This has to be pub within kanin so that the acker extractor can take it.
+    pub(crate) ack_timing: Option<AckTiming>,
+    /// Logs the reply to this request, if wire debugging is enabled for its handler. See [`App::with_wire_debug`](crate::App::with_wire_debug).
+    #[cfg(feature = "wire-debug")]
+    pub(crate) wire_debug: Option<Arc<crate::wire_debug::WireDebug>>,
     /// The channel the message was received on.
     channel: Channel,
     /// The message delivery.
     delivery: Delivery,
+    /// Values scoped to this request.
+    scope: Scope,
 }
 
 impl<S> Request<S> {
     /// Constructs a new request from a [`Channel`] and [`Delivery`].
+    ///
+    /// The request ID is read according to the default [`ReqIdPolicy`].
     pub fn new(channel: Channel, delivery: Delivery, state: Arc<S>) -> Self {
+        Self::with_req_id_policy(channel, delivery, state, &ReqIdPolicy::default())
+    }
+
+    /// Constructs a new request from a [`Channel`] and [`Delivery`], reading the request ID according to the given [`ReqIdPolicy`].
+    pub fn with_req_id_policy(
+        channel: Channel,
+        delivery: Delivery,
+        state: Arc<S>,
+        req_id_policy: &ReqIdPolicy,
+    ) -> Self {
         Self {
             state,
             channel,
             acked: false,
-            req_id: ReqId::from_delivery(&delivery),
+            requeued: false,
+            reply_deferred: false,
+            payload_diagnostics: None,
+            reply_cc: false,
+            reply_mode: ReplyMode::default(),
+            extract_timeout: None,
+            on_extract_error: None,
+            publisher_channels: None,
+            reply_settings: None,
+            background: None,
+            ack_timing: None,
+            #[cfg(feature = "wire-debug")]
+            wire_debug: None,
+            req_id: req_id_policy.req_id(&delivery),
             delivery,
+            scope: Scope::default(),
         }
     }
 
@@ -64,6 +137,21 @@ impl<S> Request<S> {
         self.state.as_ref().into()
     }
 
+    /// Returns a reference to the shared app state.
+    pub(crate) fn app_state(&self) -> &Arc<S> {
+        &self.state
+    }
+
+    /// Returns a reference to the values scoped to this request.
+    pub fn scope(&self) -> &Scope {
+        &self.scope
+    }
+
+    /// Returns a mutable reference to the values scoped to this request.
+    pub fn scope_mut(&mut self) -> &mut Scope {
+        &mut self.scope
+    }
+
     /// Returns a reference to the [`Channel`] the message was delivered on.
     pub fn channel(&self) -> &Channel {
         &self.channel
@@ -82,12 +170,244 @@ impl<S> Request<S> {
             .map(|app_id| app_id.as_str())
     }
 
+    /// Returns the exchange and routing key the reply to this request is published to, according to its reply mode.
+    ///
+    /// Returns `None` if the request gives no routing key to reply on, i.e. if it should not be replied to.
+    pub(crate) fn reply_target(&self) -> Option<(ShortString, ShortString)> {
+        match self.reply_mode {
+            ReplyMode::ReplyTo => self
+                .properties()
+                .reply_to()
+                .clone()
+                .map(|reply_to| (HandlerConfig::DEFAULT_EXCHANGE.into(), reply_to)),
+            ReplyMode::OriginalExchange => {
+                let header = self
+                    .properties()
+                    .headers()
+                    .as_ref()?
+                    .inner()
+                    .get(REPLY_ROUTING_KEY_HEADER)?;
+                let routing_key = match header {
+                    AMQPValue::LongString(routing_key) => routing_key.to_string(),
+                    AMQPValue::ShortString(routing_key) => routing_key.to_string(),
+                    _ => {
+                        warn!("Ignoring {REPLY_ROUTING_KEY_HEADER:?} header of request as it is not a string: {header:?}");
+                        return None;
+                    }
+                };
+                Some((self.delivery.exchange.clone(), routing_key.into()))
+            }
+        }
+    }
+
+    /// Extracts `T` from the request, unless it takes longer than the extract timeout of the handler.
+    /// See [`HandlerConfig::with_extract_timeout`](crate::HandlerConfig::with_extract_timeout).
+    ///
+    /// The outer result fails with [`ServerError::ExtractTimeout`] if the extraction timed out, and the inner result is the result of the extraction.
+    pub(crate) async fn extract_with_timeout<T>(
+        &mut self,
+    ) -> Result<Result<T, T::Error>, HandlerError>
+    where
+        T: Extract<S>,
+    {
+        let timeout = match self.extract_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(T::extract(self).await),
+        };
+
+        tokio::time::timeout(timeout, T::extract(self))
+            .await
+            .map_err(|_elapsed| {
+                HandlerError::InternalError(ServerError::ExtractTimeout {
+                    extractor: type_name::<T>(),
+                    timeout,
+                })
+            })
+    }
+
+    /// Calls the extraction error hook of the app, if any, with the error of extracting `T`.
+    /// See [`App::on_extract_error`](crate::App::on_extract_error).
+    pub(crate) fn report_extract_error<T>(&self, error: &dyn StdError, transient: bool) {
+        if let Some(hook) = &self.on_extract_error {
+            hook.call(
+                error,
+                &ExtractFailure {
+                    extractor: type_name::<T>(),
+                    transient,
+                    req_id: &self.req_id,
+                    app_id: self.app_id(),
+                    properties: self.properties(),
+                },
+            );
+        }
+    }
+
     /// Acks the request, letting the AMQP broker know that it was received and processed successfully.
     pub(crate) async fn ack(&mut self, options: BasicAckOptions) -> Result<(), lapin::Error> {
         self.delivery.ack(options).await?;
         self.acked = true;
+        if let Some(ack_timing) = &self.ack_timing {
+            ack_timing.record();
+        }
         Ok(())
     }
+
+    /// Rejects and requeues the request, so it may be retried later.
+    pub(crate) async fn requeue(&mut self) -> Result<(), lapin::Error> {
+        self.reject(BasicRejectOptions { requeue: true }).await
+    }
+
+    /// Rejects the request, letting the AMQP broker know that it was not processed.
+    pub(crate) async fn reject(&mut self, options: BasicRejectOptions) -> Result<(), lapin::Error> {
+        self.delivery.reject(options).await?;
+        self.acked = true;
+        if let Some(ack_timing) = &self.ack_timing {
+            ack_timing.record();
+        }
+        Ok(())
+    }
+}
+
+/// Copies the `CC` header of a request into the given reply headers.
+///
+/// RabbitMQ routes a message with this header to each of the listed routing keys in addition to its own routing key.
+/// See [sender-selected distribution](https://www.rabbitmq.com/sender-selected.html).
+/// The header is only copied if it is an array, as required by RabbitMQ.
+pub(crate) fn copy_cc_headers(properties: &AMQPProperties, reply_headers: &mut FieldTable) {
+    let headers = match properties.headers() {
+        Some(headers) => headers.inner(),
+        None => return,
+    };
+
+    if let Some(value @ AMQPValue::FieldArray(_)) = headers.get("CC") {
+        reply_headers.insert("CC".into(), value.clone());
+    }
+}
+
+/// Tracks the time between receiving a request and acknowledging (or rejecting) it.
+///
+/// If a message is not acknowledged within the consumer timeout, the broker closes the channel,
+/// so requests that come close to the timeout are flagged before that happens.
+#[derive(Debug, Clone)]
+pub(crate) struct AckTiming {
+    /// When the request was received.
+    received: Instant,
+    /// The routing key of the handler handling the request.
+    routing_key: String,
+    /// The type name of the handler handling the request.
+    handler: &'static str,
+    /// The ID of the request.
+    req_id: ReqId,
+    /// The consumer timeout of the queue.
+    consumer_timeout: Duration,
+}
+
+impl AckTiming {
+    /// The consumer timeout of RabbitMQ, unless configured otherwise on the broker or queue.
+    pub(crate) const DEFAULT_CONSUMER_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+    /// Starts tracking a request that was received at the given instant.
+    pub(crate) fn new(
+        received: Instant,
+        routing_key: String,
+        handler: &'static str,
+        req_id: ReqId,
+        consumer_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            received,
+            routing_key,
+            handler,
+            req_id,
+            consumer_timeout: consumer_timeout.unwrap_or(Self::DEFAULT_CONSUMER_TIMEOUT),
+        }
+    }
+
+    /// The time after which a request is considered close to the consumer timeout, which is 80% of the timeout.
+    pub(crate) fn warning_threshold(&self) -> Duration {
+        self.consumer_timeout * 4 / 5
+    }
+
+    /// The instant at which the request becomes close to the consumer timeout.
+    pub(crate) fn warning_deadline(&self) -> Instant {
+        self.received + self.warning_threshold()
+    }
+
+    /// Warns that the request is still being handled, even though it is close to the consumer timeout.
+    pub(crate) fn warn_unacked(&self) {
+        warn!(
+            "Request {} on handler {} is still being handled after {:?}, close to the consumer timeout of {:?} on routing key {:?}. The broker closes the channel if the request is not acknowledged within the timeout.",
+            self.req_id,
+            self.handler,
+            self.received.elapsed(),
+            self.consumer_timeout,
+            self.routing_key,
+        );
+    }
+
+    /// Records that the request was acknowledged (or rejected) now.
+    pub(crate) fn record(&self) {
+        let elapsed = self.received.elapsed();
+        histogram!("kanin.ack_latency_seconds", "routing_key" => self.routing_key.clone())
+            .record(elapsed);
+
+        if elapsed >= self.warning_threshold() {
+            warn!(
+                "Request {} on handler {} was acknowledged after {elapsed:?}, close to the consumer timeout of {:?} on routing key {:?}.",
+                self.req_id, self.handler, self.consumer_timeout, self.routing_key,
+            );
+        }
+    }
+}
+
+/// A map of values scoped to a single request, keyed by their type.
+///
+/// Values inserted in the scope live until the request has been handled.
+/// See [`Provider`](crate::extract::Provider) for how this can be used for dependency injection.
+#[derive(Default)]
+pub struct Scope {
+    /// The values of the scope.
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Scope {
+    /// Returns a reference to the value of the given type, if one was inserted.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Returns a mutable reference to the value of the given type, if one was inserted.
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.values
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    /// Inserts a value into the scope, returning the previous value of the same type if there was one.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// Removes the value of the given type from the scope and returns it, if there was one.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+}
+
+impl std::fmt::Debug for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scope")
+            .field("values", &self.values.len())
+            .finish()
+    }
 }
 
 /// We implement [`Drop`] on [`Request`] to ensure that requests that were not explicitly acknowledged will be rejected.
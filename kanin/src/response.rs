@@ -2,10 +2,13 @@
 //!
 //! Any type that implements [`Respond`] can be used as the return type of a handler.
 
-use std::fmt;
+use std::{collections::BTreeMap, fmt, time::Duration};
 
+use lapin::types::{AMQPValue, FieldTable};
 use prost::Message;
 
+use crate::{error::FromError, HandlerError};
+
 /// A trait for types that may produce responses.
 ///
 /// This really just means they can be converted into a byte-stream.
@@ -14,11 +17,167 @@ use prost::Message;
 pub trait Respond: fmt::Debug + Send {
     /// Creates the bytes payload of the response.
     fn respond(self) -> Vec<u8>;
+
+    /// The time-to-live of the reply message, set as its `expiration` property.
+    ///
+    /// Overrides [`HandlerConfig::with_reply_ttl`](crate::HandlerConfig::with_reply_ttl) if set. Defaults to `None`.
+    fn reply_ttl(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Headers to set on the reply message, in addition to the body.
+    ///
+    /// See [`WithMeta`] for a convenient way to attach metadata to a response. Defaults to no headers.
+    fn reply_headers(&self) -> FieldTable {
+        FieldTable::default()
+    }
+
+    /// The content type of the reply message, set as its `content_type` property.
+    ///
+    /// Defaults to `application/octet-stream`, as responses are usually encoded protobuf.
+    fn content_type(&self) -> &str {
+        OCTET_STREAM_CONTENT_TYPE
+    }
 }
 
+/// The content type of binary responses, such as encoded protobuf. See [`Respond::content_type`].
+pub const OCTET_STREAM_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// The content type of JSON responses. See [`Respond::content_type`].
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
 /// This impl ensures that protobuf messages can be used as the return type of handlers.
 impl<D: Message> Respond for D {
     fn respond(self) -> Vec<u8> {
         self.encode_to_vec()
     }
 }
+
+/// A response whose reply message expires after the given time-to-live.
+///
+/// Use this to set the TTL of individual replies. See also [`HandlerConfig::with_reply_ttl`](crate::HandlerConfig::with_reply_ttl).
+/// Responses constructed from errors have no TTL of their own.
+#[derive(Debug)]
+pub struct Expiring<T> {
+    /// The response.
+    pub response: T,
+    /// The time-to-live of the reply message.
+    pub ttl: Option<Duration>,
+}
+
+impl<T> Expiring<T> {
+    /// Creates a response whose reply expires after the given time-to-live.
+    pub fn new(response: T, ttl: Duration) -> Self {
+        Self {
+            response,
+            ttl: Some(ttl),
+        }
+    }
+}
+
+impl<T: Respond> Respond for Expiring<T> {
+    fn respond(self) -> Vec<u8> {
+        self.response.respond()
+    }
+
+    fn reply_ttl(&self) -> Option<Duration> {
+        self.ttl.or_else(|| self.response.reply_ttl())
+    }
+
+    fn reply_headers(&self) -> FieldTable {
+        self.response.reply_headers()
+    }
+
+    fn content_type(&self) -> &str {
+        self.response.content_type()
+    }
+}
+
+impl<T> FromError<HandlerError> for Expiring<T>
+where
+    T: FromError<HandlerError>,
+{
+    fn from_error(error: HandlerError) -> Self {
+        Self {
+            response: T::from_error(error),
+            ttl: None,
+        }
+    }
+}
+
+/// Encodes the given response as JSON. Used by `#[derive(Respond)]` with `#[respond(json)]`.
+///
+/// If the response cannot be serialized, the error is logged and the payload is empty.
+#[cfg(feature = "serde")]
+pub fn encode_json<T: serde::Serialize>(response: &T) -> Vec<u8> {
+    serde_json::to_vec(response).unwrap_or_else(|e| {
+        tracing::error!("Failed to serialize response as JSON: {e:#}");
+        Vec::new()
+    })
+}
+
+/// The prefix of the headers that response metadata is stored in. See [`WithMeta`].
+pub const META_HEADER_PREFIX: &str = "x-meta-";
+
+/// A response with metadata attached, such as timings, the server version or cache status.
+///
+/// The metadata is sent alongside the body of the reply, with each entry in a header named by [`META_HEADER_PREFIX`] followed by its key.
+/// Callers can read the metadata with the [`Meta`](crate::extract::Meta) extractor or [`Meta::from_headers`](crate::extract::Meta::from_headers).
+/// Responses constructed from errors have no metadata.
+#[derive(Debug)]
+pub struct WithMeta<T> {
+    /// The response.
+    pub response: T,
+    /// The metadata of the response.
+    pub meta: BTreeMap<String, String>,
+}
+
+impl<T> WithMeta<T> {
+    /// Creates a response without any metadata yet.
+    pub fn new(response: T) -> Self {
+        Self {
+            response,
+            meta: BTreeMap::new(),
+        }
+    }
+
+    /// Attaches the given metadata entry to the response.
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.meta.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl<T: Respond> Respond for WithMeta<T> {
+    fn respond(self) -> Vec<u8> {
+        self.response.respond()
+    }
+
+    fn reply_ttl(&self) -> Option<Duration> {
+        self.response.reply_ttl()
+    }
+
+    fn reply_headers(&self) -> FieldTable {
+        let mut headers = self.response.reply_headers();
+        for (key, value) in &self.meta {
+            headers.insert(
+                format!("{META_HEADER_PREFIX}{key}").into(),
+                AMQPValue::LongString(value.as_str().into()),
+            );
+        }
+        headers
+    }
+
+    fn content_type(&self) -> &str {
+        self.response.content_type()
+    }
+}
+
+impl<T> FromError<HandlerError> for WithMeta<T>
+where
+    T: FromError<HandlerError>,
+{
+    fn from_error(error: HandlerError) -> Self {
+        Self::new(T::from_error(error))
+    }
+}
@@ -0,0 +1,197 @@
+//! Scatter-gather requests: publishing a single request to many apps and collecting their replies.
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use lapin::{
+    options::{BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions, QueueDeleteOptions},
+    types::{FieldTable, ShortString},
+    BasicProperties, Connection,
+};
+use prost::{DecodeError, Message};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    extract::{Baggage, Deadline, ReqId},
+    Error, Respond, Result,
+};
+
+/// A client for scatter-gather requests.
+///
+/// The request is published to an exchange (typically a fanout or topic exchange) so that it reaches every app bound to it.
+/// Replies are collected on a temporary, exclusive queue until either the expected number of replies has been received
+/// or the timeout elapses, whichever comes first.
+///
+/// ```no_run
+/// # use kanin::scatter_gather::ScatterGather;
+/// # async fn example(conn: &kanin::Connection) -> kanin::Result<()> {
+/// let replies = ScatterGather::new("inventory.fanout")
+///     .with_expected_replies(3)
+///     .send::<String>(conn, "inventory.count", "item-187".to_string())
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScatterGather {
+    /// The exchange to publish requests to.
+    exchange: String,
+    /// The number of replies after which to stop waiting, if any.
+    expected_replies: Option<usize>,
+    /// How long to wait for replies.
+    timeout: Duration,
+}
+
+impl ScatterGather {
+    /// The default time to wait for replies.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a new scatter-gather client publishing requests to the given exchange.
+    ///
+    /// By default, replies are collected until [`ScatterGather::DEFAULT_TIMEOUT`] elapses.
+    pub fn new(exchange: impl Into<String>) -> Self {
+        Self {
+            exchange: exchange.into(),
+            expected_replies: None,
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Stops collecting replies once the given number of replies has been received, even if the timeout has not yet elapsed.
+    pub fn with_expected_replies(mut self, expected_replies: usize) -> Self {
+        self.expected_replies = Some(expected_replies);
+        self
+    }
+
+    /// Sets how long to wait for replies.
+    ///
+    /// The timeout is also set as the expiration of the request, so apps that are too slow to pick up the request in time will not handle it.
+    /// When sent while handling a request, the timeout is shortened to the time remaining until the deadline of that request (see [`Deadline`]).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Publishes the request with the given routing key and collects the replies.
+    ///
+    /// Replies are returned in the order they were received. Each reply is decoded individually,
+    /// so a single reply that fails to decode does not prevent the other replies from being returned.
+    ///
+    /// # Errors
+    /// Returns [`Error::DeadlineExceeded`] if the deadline of the request currently being handled has been exceeded.
+    /// Returns `Err` if communication with the AMQP broker fails.
+    pub async fn send<T>(
+        &self,
+        conn: &Connection,
+        routing_key: &str,
+        request: impl Respond,
+    ) -> Result<Vec<std::result::Result<T, DecodeError>>>
+    where
+        T: Message + Default,
+    {
+        // There's no point in gathering replies that the caller of the current request won't wait for.
+        let request_deadline = Deadline::current();
+        request_deadline.check()?;
+        let timeout = request_deadline
+            .remaining()
+            .map_or(self.timeout, |remaining| remaining.min(self.timeout));
+
+        let channel = conn.create_channel().await.map_err(Error::from)?;
+
+        // We declare a temporary, server-named queue to receive the replies on.
+        let reply_queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(Error::from)?;
+        let reply_to = reply_queue.name().clone();
+
+        let mut consumer = channel
+            .basic_consume(
+                reply_to.as_str(),
+                "kanin.scatter_gather",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(Error::from)?;
+
+        let correlation_id = Uuid::new_v4().to_string();
+
+        channel
+            .basic_publish(
+                &self.exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                &request.respond(),
+                Deadline::propagate_current(ReqId::propagate_current(Baggage::propagate_current(
+                    BasicProperties::default()
+                        .with_reply_to(reply_to.clone())
+                        .with_correlation_id(ShortString::from(correlation_id.clone()))
+                        .with_expiration(timeout.as_millis().to_string().into())
+                        .with_content_type("application/octet-stream".into()),
+                ))),
+            )
+            .await
+            .map_err(Error::from)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut replies = Vec::new();
+
+        while self
+            .expected_replies
+            .map_or(true, |expected| replies.len() < expected)
+        {
+            let delivery = match tokio::time::timeout_at(deadline, consumer.next()).await {
+                Ok(Some(delivery)) => delivery.map_err(Error::from)?,
+                Ok(None) => return Err(Error::ConsumerCancelled(reply_to.to_string())),
+                Err(_elapsed) => {
+                    debug!(
+                        "Timed out after {timeout:?} while gathering replies to {routing_key:?}."
+                    );
+                    break;
+                }
+            };
+
+            let is_reply = delivery
+                .properties
+                .correlation_id()
+                .as_ref()
+                .map_or(false, |id| id.as_str() == correlation_id);
+
+            if !is_reply {
+                debug!("Ignoring unrelated message on scatter-gather reply queue {reply_to}.");
+                continue;
+            }
+
+            replies.push(T::decode(&delivery.data[..]));
+        }
+
+        debug!(
+            "Gathered {} replies to {routing_key:?} on exchange {:?}.",
+            replies.len(),
+            self.exchange
+        );
+
+        if let Err(e) = channel
+            .queue_delete(reply_to.as_str(), QueueDeleteOptions::default())
+            .await
+        {
+            warn!("Failed to delete scatter-gather reply queue {reply_to}: {e:#}");
+        }
+
+        Ok(replies)
+    }
+}
@@ -0,0 +1,189 @@
+//! Schema governance: validating incoming messages against a schema registry.
+//!
+//! Publishers put the ID of the schema their message was encoded with in the [`SCHEMA_ID_HEADER`] header.
+//! Handlers configured with an expected schema (see [`HandlerConfig::with_expected_schema`](crate::HandlerConfig::with_expected_schema))
+//! then check the schema ID against the registry set with [`App::with_schema_registry`](crate::App::with_schema_registry)
+//! before handling the message. Messages with incompatible schemas are rejected without being requeued
+//! (so they are dead-lettered, if the queue has a dead letter exchange).
+//!
+//! Every check is recorded in the `kanin.schema_checks` counter, labelled with the queue and the outcome
+//! (`compatible`, `incompatible`, `missing`, `invalid` or `error`). Messages without a valid schema ID and checks that fail
+//! due to registry errors are let through, so that the registry can be rolled out gradually without becoming a single point of failure.
+
+use std::error::Error as StdError;
+
+use async_trait::async_trait;
+use lapin::{message::Delivery, types::AMQPValue};
+use metrics::counter;
+use tracing::{error, warn};
+
+/// The header that holds the schema ID of a message.
+///
+/// Schema IDs are non-negative 32-bit integers, given either as an integer or as a string of decimal digits.
+pub const SCHEMA_ID_HEADER: &str = "x-schema-id";
+
+/// An error from a schema registry.
+pub type SchemaRegistryError = Box<dyn StdError + Send + Sync>;
+
+/// A registry of schemas that incoming messages can be validated against.
+#[async_trait]
+pub trait SchemaRegistry: Send + Sync {
+    /// Returns true if the schema with the given ID is compatible with the expected schema.
+    ///
+    /// What the expected schema is (e.g. a subject or a fully qualified message name) is up to the registry.
+    ///
+    /// # Errors
+    /// Returns an error if the registry could not be queried.
+    async fn is_compatible(
+        &self,
+        schema_id: &str,
+        expected: &str,
+    ) -> Result<bool, SchemaRegistryError>;
+}
+
+/// Checks the schema of the given delivery against the expected schema, returning false if the message should be rejected.
+pub(crate) async fn check(
+    registry: &dyn SchemaRegistry,
+    delivery: &Delivery,
+    expected: &str,
+    queue: &str,
+) -> bool {
+    let schema_id = delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(SCHEMA_ID_HEADER));
+
+    let (outcome, accept) = match schema_id.map(parse_schema_id) {
+        None => ("missing", true),
+        Some(None) => {
+            warn!("Message has an invalid schema ID {schema_id:?} (expected a non-negative 32-bit integer), handling it without checking its schema.");
+            ("invalid", true)
+        }
+        Some(Some(schema_id)) => {
+            match registry
+                .is_compatible(&schema_id.to_string(), expected)
+                .await
+            {
+                Ok(true) => ("compatible", true),
+                Ok(false) => {
+                    warn!("Rejecting message with schema ID {schema_id}, as it is not compatible with the expected schema {expected:?}.");
+                    ("incompatible", false)
+                }
+                Err(e) => {
+                    error!("Failed to check schema ID {schema_id} against the expected schema {expected:?} (the message will be handled regardless): {e:#}");
+                    ("error", true)
+                }
+            }
+        }
+    };
+
+    counter!("kanin.schema_checks", "queue" => queue.to_string(), "outcome" => outcome)
+        .increment(1);
+
+    accept
+}
+
+/// Parses the value of the [`SCHEMA_ID_HEADER`] header. Schema IDs are non-negative 32-bit integers,
+/// given either as an integer or as a string of decimal digits. Returns `None` for anything else.
+pub(crate) fn parse_schema_id(value: &AMQPValue) -> Option<u32> {
+    match value {
+        AMQPValue::LongString(schema_id) => {
+            std::str::from_utf8(schema_id.as_bytes()).ok()?.parse().ok()
+        }
+        AMQPValue::ShortString(schema_id) => schema_id.as_str().parse().ok(),
+        AMQPValue::LongLongInt(schema_id) => u32::try_from(*schema_id).ok(),
+        AMQPValue::LongInt(schema_id) => u32::try_from(*schema_id).ok(),
+        AMQPValue::LongUInt(schema_id) => Some(*schema_id),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "schema-registry-http")]
+pub use http::HttpSchemaRegistry;
+
+/// A schema registry client using the HTTP API of the [Confluent schema registry](https://docs.confluent.io/platform/current/schema-registry/develop/api.html).
+#[cfg(feature = "schema-registry-http")]
+mod http {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use async_trait::async_trait;
+
+    use super::{SchemaRegistry, SchemaRegistryError};
+
+    /// A [`SchemaRegistry`] backed by the HTTP API of a Confluent-compatible schema registry.
+    ///
+    /// A schema ID is compatible with the expected schema if the schema is registered under the expected subject.
+    /// The subjects of each schema ID are cached, as schemas are immutable once registered.
+    #[derive(Debug, Clone)]
+    pub struct HttpSchemaRegistry {
+        /// The base URL of the registry, e.g. `http://schema-registry:8081`.
+        base_url: String,
+        /// The HTTP client.
+        client: reqwest::Client,
+        /// The subjects of the schema IDs that have been looked up so far.
+        subjects: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    }
+
+    impl HttpSchemaRegistry {
+        /// Creates a new client for the registry at the given base URL.
+        pub fn new(base_url: impl Into<String>) -> Self {
+            Self::with_client(base_url, reqwest::Client::new())
+        }
+
+        /// Creates a new client for the registry at the given base URL, using the given HTTP client (e.g. to configure timeouts or authentication).
+        pub fn with_client(base_url: impl Into<String>, client: reqwest::Client) -> Self {
+            Self {
+                base_url: base_url.into().trim_end_matches('/').to_string(),
+                client,
+                subjects: Default::default(),
+            }
+        }
+
+        /// Returns the subjects that the schema with the given ID is registered under.
+        async fn subjects(&self, schema_id: &str) -> Result<Vec<String>, SchemaRegistryError> {
+            if let Some(subjects) = self
+                .subjects
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get(schema_id)
+            {
+                return Ok(subjects.clone());
+            }
+
+            let subjects: Vec<String> = self
+                .client
+                .get(format!(
+                    "{}/schemas/ids/{schema_id}/subjects",
+                    self.base_url
+                ))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            self.subjects
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .insert(schema_id.to_string(), subjects.clone());
+
+            Ok(subjects)
+        }
+    }
+
+    #[async_trait]
+    impl SchemaRegistry for HttpSchemaRegistry {
+        async fn is_compatible(
+            &self,
+            schema_id: &str,
+            expected: &str,
+        ) -> Result<bool, SchemaRegistryError> {
+            let subjects = self.subjects(schema_id).await?;
+            Ok(subjects.iter().any(|subject| subject == expected))
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Shadow traffic, i.e. copying incoming requests to another destination, for instance to test a new version of a service.
+
+use std::sync::Arc;
+
+use lapin::{message::Delivery, options::BasicPublishOptions, types::AMQPValue, BasicProperties};
+use metrics::counter;
+use tracing::{debug, warn};
+
+use crate::extract::ChannelPool;
+
+/// The destination that a shadow copy of a request is published to. See [`App::with_shadow`](crate::App::with_shadow).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowTarget {
+    /// The exchange to publish the copy to.
+    pub exchange: String,
+    /// The routing key to publish the copy with.
+    pub routing_key: String,
+}
+
+impl ShadowTarget {
+    /// The header that is set on shadow copies, so the receiver can tell them apart from regular requests.
+    pub const HEADER: &'static str = "x-shadow";
+
+    /// Creates a new shadow target with the given exchange and routing key.
+    pub fn new(exchange: impl Into<String>, routing_key: impl Into<String>) -> Self {
+        Self {
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+        }
+    }
+}
+
+/// A function that decides whether and where to publish a shadow copy of an incoming request.
+///
+/// See [`App::with_shadow`](crate::App::with_shadow).
+pub type ShadowSampler = Arc<dyn Fn(&Delivery) -> Option<ShadowTarget> + Send + Sync>;
+
+/// Returns the properties of the shadow copy of a request with the given properties.
+///
+/// The `reply_to` property is removed, so the shadowing service doesn't reply to the original caller.
+/// The `user_id` property is also removed, as the broker rejects messages whose `user_id` doesn't match the publishing user.
+pub(crate) fn shadow_properties(properties: &BasicProperties) -> BasicProperties {
+    let mut shadow = BasicProperties::default();
+
+    if let Some(content_type) = properties.content_type() {
+        shadow = shadow.with_content_type(content_type.clone());
+    }
+    if let Some(content_encoding) = properties.content_encoding() {
+        shadow = shadow.with_content_encoding(content_encoding.clone());
+    }
+    if let Some(delivery_mode) = properties.delivery_mode() {
+        shadow = shadow.with_delivery_mode(*delivery_mode);
+    }
+    if let Some(priority) = properties.priority() {
+        shadow = shadow.with_priority(*priority);
+    }
+    if let Some(correlation_id) = properties.correlation_id() {
+        shadow = shadow.with_correlation_id(correlation_id.clone());
+    }
+    if let Some(expiration) = properties.expiration() {
+        shadow = shadow.with_expiration(expiration.clone());
+    }
+    if let Some(message_id) = properties.message_id() {
+        shadow = shadow.with_message_id(message_id.clone());
+    }
+    if let Some(timestamp) = properties.timestamp() {
+        shadow = shadow.with_timestamp(*timestamp);
+    }
+    if let Some(kind) = properties.kind() {
+        shadow = shadow.with_type(kind.clone());
+    }
+    if let Some(app_id) = properties.app_id() {
+        shadow = shadow.with_app_id(app_id.clone());
+    }
+    if let Some(cluster_id) = properties.cluster_id() {
+        shadow = shadow.with_cluster_id(cluster_id.clone());
+    }
+
+    let mut headers = properties.headers().clone().unwrap_or_default();
+    headers.insert(ShadowTarget::HEADER.into(), AMQPValue::Boolean(true));
+
+    shadow.with_headers(headers)
+}
+
+/// Publishes a shadow copy of the given delivery if the sampler selects it.
+///
+/// The copy is published in a separate task on the shadow channel, never on the consumer channel,
+/// so it doesn't affect the handling of the request. Failures are only logged.
+pub(crate) fn shadow(
+    sampler: &ShadowSampler,
+    channel: Option<&ChannelPool>,
+    delivery: &Delivery,
+    queue: &str,
+) {
+    let target = match sampler(delivery) {
+        Some(target) => target,
+        None => return,
+    };
+
+    let channel = match channel.and_then(ChannelPool::get) {
+        Some(channel) => channel,
+        None => {
+            warn!(
+                "Not publishing shadow copy of request to exchange {:?} with routing key {:?}, as the shadow channel is closed.",
+                target.exchange, target.routing_key
+            );
+            return;
+        }
+    };
+    let payload = delivery.data.clone();
+    let properties = shadow_properties(&delivery.properties);
+    let queue = queue.to_string();
+
+    tokio::spawn(async move {
+        let publish = channel
+            .basic_publish(
+                &target.exchange,
+                &target.routing_key,
+                BasicPublishOptions::default(),
+                &payload,
+                properties,
+            )
+            .await;
+
+        match publish {
+            Ok(_confirm) => {
+                debug!(
+                    "Published shadow copy of request to exchange {:?} with routing key {:?}",
+                    target.exchange, target.routing_key
+                );
+                counter!("kanin.shadow_requests", "queue" => queue).increment(1);
+            }
+            Err(e) => warn!(
+                "Failed to publish shadow copy of request to exchange {:?} with routing key {:?}: {e:#}",
+                target.exchange, target.routing_key
+            ),
+        }
+    });
+}
@@ -0,0 +1,185 @@
+//! Background tasks started from handlers.
+//!
+//! Tasks spawned with [`spawn`] are tied to the request that spawned them: they run within the span and context of the request,
+//! and graceful shutdown waits for them to finish, just like it waits for requests in flight.
+
+use std::{future::Future, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{Instrument, Span};
+
+use crate::{
+    context::{RequestContext, REQUEST_CONTEXT},
+    extract::{Baggage, ReqId, BAGGAGE},
+    Extract, Request,
+};
+
+/// Spawns the given future as a background task of the request the spawner was extracted from.
+///
+/// This is a shorthand for [`Spawner::spawn`].
+pub fn spawn<F>(spawner: &Spawner, future: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    spawner.spawn(future)
+}
+
+/// An extractor for spawning background tasks from a handler. See [`spawn`].
+///
+/// Spawned tasks run within the span of the request (which includes its request ID) and see the request's [`RequestContext`] and [`Baggage`],
+/// so client-side helpers used from them propagate the request ID, baggage and deadline of the request. The handler's graceful shutdown waits
+/// for them to finish. Long-running tasks should watch the [`ShutdownToken`] to stop early when the app shuts down.
+#[derive(Debug, Clone)]
+pub struct Spawner {
+    /// The span of the request the spawner was extracted from.
+    pub(crate) span: Span,
+    /// The request ID of the request the spawner was extracted from.
+    pub(crate) req_id: ReqId,
+    /// The context and baggage of the request the spawner was extracted from, if it is handled by an app.
+    pub(crate) scope: Option<(RequestContext, Baggage)>,
+    /// The background tasks of the handler, if the request is handled by an app.
+    pub(crate) background: Option<Arc<BackgroundTasks>>,
+}
+
+impl Spawner {
+    /// Spawns the given future as a background task of the request the spawner was extracted from.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        // The guard is moved into the task, so the task counts as running until it finishes or is aborted.
+        let guard = self.background.as_ref().map(BackgroundTasks::track);
+        let scope = self.scope.clone();
+        tokio::spawn(
+            async move {
+                // Task-local values aren't inherited by spawned tasks, so the context of the request is set up again.
+                let output = match scope {
+                    Some((context, baggage)) => {
+                        REQUEST_CONTEXT
+                            .scope(context, BAGGAGE.scope(baggage, future))
+                            .await
+                    }
+                    None => future.await,
+                };
+                drop(guard);
+                output
+            }
+            .instrument(self.span.clone()),
+        )
+    }
+
+    /// Returns the request ID of the request the spawner was extracted from.
+    pub fn req_id(&self) -> &ReqId {
+        &self.req_id
+    }
+
+    /// Returns a token that is cancelled when the handler starts shutting down.
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        ShutdownToken(
+            self.background
+                .as_ref()
+                .map(|background| background.shutdown.subscribe()),
+        )
+    }
+}
+
+#[async_trait]
+impl<S> Extract<S> for Spawner
+where
+    S: Send + Sync,
+{
+    type Error = std::convert::Infallible;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            // Extraction happens within the span of the request.
+            span: Span::current(),
+            req_id: req.req_id().clone(),
+            // Extraction happens within the context of the request as well.
+            scope: RequestContext::current()
+                .map(|context| (context, Baggage::current().unwrap_or_default())),
+            background: req.background.clone(),
+        })
+    }
+}
+
+/// Signals when the handler that spawned a background task starts shutting down. See [`Spawner::shutdown_token`].
+#[derive(Debug, Clone)]
+pub struct ShutdownToken(Option<watch::Receiver<bool>>);
+
+impl ShutdownToken {
+    /// Returns true if the handler has started shutting down.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.as_ref().map_or(false, |shutdown| *shutdown.borrow())
+    }
+
+    /// Waits until the handler starts shutting down.
+    ///
+    /// Never returns for requests that are not handled by an app.
+    pub async fn cancelled(&mut self) {
+        if let Some(shutdown) = &mut self.0 {
+            while !*shutdown.borrow_and_update() {
+                if shutdown.changed().await.is_err() {
+                    // The handler is gone, so it certainly isn't running anymore.
+                    return;
+                }
+            }
+            return;
+        }
+
+        std::future::pending().await
+    }
+}
+
+/// The background tasks spawned by the requests of a handler.
+#[derive(Debug)]
+pub(crate) struct BackgroundTasks {
+    /// The number of background tasks that are still running.
+    running: watch::Sender<usize>,
+    /// Set to true when the handler starts shutting down.
+    shutdown: watch::Sender<bool>,
+}
+
+impl BackgroundTasks {
+    /// Creates an empty set of background tasks.
+    pub(crate) fn new() -> Self {
+        Self {
+            running: watch::channel(0).0,
+            shutdown: watch::channel(false).0,
+        }
+    }
+
+    /// Tracks a new background task, which counts as running until the returned guard is dropped.
+    fn track(background: &Arc<Self>) -> TaskGuard {
+        background.running.send_modify(|running| *running += 1);
+        TaskGuard(background.clone())
+    }
+
+    /// Returns the number of background tasks that are still running.
+    pub(crate) fn running(&self) -> usize {
+        *self.running.borrow()
+    }
+
+    /// Signals the shutdown to the background tasks, cancelling their shutdown tokens.
+    pub(crate) fn shut_down(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Waits for all background tasks to finish.
+    pub(crate) async fn finished(&self) {
+        let mut running = self.running.subscribe();
+        // The sender lives as long as self, so this can't fail.
+        let _ = running.wait_for(|running| *running == 0).await;
+    }
+}
+
+/// Marks a background task as running until dropped.
+struct TaskGuard(Arc<BackgroundTasks>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.running.send_modify(|running| *running -= 1);
+    }
+}
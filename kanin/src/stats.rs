@@ -0,0 +1,199 @@
+//! Snapshots of request statistics, served over AMQP by the route registered with [`App::with_metrics_route`](crate::App::with_metrics_route).
+//!
+//! kanin records its metrics through the [`metrics`] facade, whose values can't be read back by the app itself.
+//! The statistics in this module are therefore kept separately, and only while a metrics route is registered.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use lapin::BasicProperties;
+use tracing::warn;
+
+use crate::{error::FromError, HandlerError, Respond};
+
+/// A snapshot of the request statistics of an app, as replied by the metrics route.
+///
+/// The snapshot is encoded as protobuf, or as JSON if the request has the `application/json` content type and the `serde` feature is enabled.
+#[derive(Clone, PartialEq, prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MetricsSnapshot {
+    /// The statistics of each handler that received requests, ordered by routing key.
+    #[prost(message, repeated, tag = "1")]
+    pub handlers: Vec<HandlerMetrics>,
+}
+
+/// The request statistics of a single handler.
+#[derive(Clone, PartialEq, prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HandlerMetrics {
+    /// The routing key of the handler.
+    #[prost(string, tag = "1")]
+    pub routing_key: String,
+    /// The number of requests currently being handled.
+    #[prost(uint64, tag = "2")]
+    pub in_flight: u64,
+    /// The number of requests received since the app started.
+    #[prost(uint64, tag = "3")]
+    pub requests_total: u64,
+    /// The mean time it took to handle a request, in seconds.
+    #[prost(double, tag = "4")]
+    pub latency_mean_seconds: f64,
+    /// The longest time it took to handle a request, in seconds.
+    #[prost(double, tag = "5")]
+    pub latency_max_seconds: f64,
+}
+
+/// The statistics of a handler while they are being recorded.
+#[derive(Debug, Default)]
+struct HandlerStats {
+    /// The number of requests currently being handled.
+    in_flight: u64,
+    /// The number of requests received.
+    requests_total: u64,
+    /// The number of requests that were handled to completion, i.e. that the latency was recorded for.
+    completed: u64,
+    /// The total time it took to handle the completed requests.
+    latency_total: Duration,
+    /// The longest time it took to handle a request.
+    latency_max: Duration,
+}
+
+/// Records the request statistics of all handlers of an app.
+#[derive(Debug, Default)]
+pub(crate) struct RequestStats {
+    /// The statistics of each handler, keyed by routing key.
+    handlers: Mutex<BTreeMap<String, HandlerStats>>,
+}
+
+impl RequestStats {
+    /// Records the start of a request on the given routing key. The request is recorded as handled when the guard is dropped.
+    pub(crate) fn start(self: &Arc<Self>, routing_key: &str) -> RequestStatsGuard {
+        let mut handlers = self.lock();
+        let stats = handlers.entry(routing_key.to_string()).or_default();
+        stats.in_flight += 1;
+        stats.requests_total += 1;
+
+        RequestStatsGuard {
+            stats: self.clone(),
+            routing_key: routing_key.to_string(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Returns a snapshot of the statistics recorded so far.
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        let handlers = self
+            .lock()
+            .iter()
+            .map(|(routing_key, stats)| {
+                let latency_mean = u32::try_from(stats.completed)
+                    .ok()
+                    .and_then(|completed| stats.latency_total.checked_div(completed))
+                    .unwrap_or_default();
+
+                HandlerMetrics {
+                    routing_key: routing_key.clone(),
+                    in_flight: stats.in_flight,
+                    requests_total: stats.requests_total,
+                    latency_mean_seconds: latency_mean.as_secs_f64(),
+                    latency_max_seconds: stats.latency_max.as_secs_f64(),
+                }
+            })
+            .collect();
+
+        MetricsSnapshot { handlers }
+    }
+
+    /// Locks the statistics. Poisoning is ignored, as the statistics are never left inconsistent.
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, HandlerStats>> {
+        self.handlers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Records a request as handled when dropped, including if the handler panics or is aborted.
+pub(crate) struct RequestStatsGuard {
+    /// The statistics to record the request in.
+    stats: Arc<RequestStats>,
+    /// The routing key of the handler handling the request.
+    routing_key: String,
+    /// When the request started being handled.
+    started: Instant,
+}
+
+impl Drop for RequestStatsGuard {
+    fn drop(&mut self) {
+        let latency = self.started.elapsed();
+        let mut handlers = self.stats.lock();
+        if let Some(stats) = handlers.get_mut(&self.routing_key) {
+            stats.in_flight = stats.in_flight.saturating_sub(1);
+            stats.completed += 1;
+            stats.latency_total += latency;
+            stats.latency_max = stats.latency_max.max(latency);
+        }
+    }
+}
+
+/// The reply of the metrics route, encoded according to the content type of the request.
+#[derive(Debug)]
+pub(crate) struct MetricsReply {
+    /// The snapshot to reply with.
+    snapshot: MetricsSnapshot,
+    /// Whether the snapshot is encoded as JSON instead of protobuf.
+    json: bool,
+}
+
+impl MetricsReply {
+    /// Replies with the given snapshot, encoded as JSON if the request with the given properties asked for it.
+    pub(crate) fn new(snapshot: MetricsSnapshot, properties: &BasicProperties) -> Self {
+        let json = cfg!(feature = "serde")
+            && properties
+                .content_type()
+                .as_ref()
+                .map_or(false, |content_type| {
+                    content_type
+                        .as_str()
+                        .split(';')
+                        .next()
+                        .unwrap_or_default()
+                        .trim()
+                        .eq_ignore_ascii_case("application/json")
+                });
+
+        Self { snapshot, json }
+    }
+}
+
+impl FromError<HandlerError> for MetricsReply {
+    fn from_error(error: HandlerError) -> Self {
+        // Extracting the properties is infallible, but we'll reply with an empty snapshot anyway in this case.
+        warn!("Metrics route failed: {error:#}");
+        Self {
+            snapshot: MetricsSnapshot::default(),
+            json: false,
+        }
+    }
+}
+
+impl Respond for MetricsReply {
+    fn respond(self) -> Vec<u8> {
+        if self.json {
+            #[cfg(feature = "serde")]
+            return crate::response::encode_json(&self.snapshot);
+        }
+
+        prost::Message::encode_to_vec(&self.snapshot)
+    }
+
+    fn content_type(&self) -> &str {
+        if self.json {
+            crate::response::JSON_CONTENT_TYPE
+        } else {
+            crate::response::OCTET_STREAM_CONTENT_TYPE
+        }
+    }
+}
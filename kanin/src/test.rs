@@ -0,0 +1,12 @@
+//! Utilities for testing kanin apps.
+//!
+//! Requires the `test-util` feature. Testing against a real broker additionally requires the `test-broker` feature,
+//! see [`broker`].
+
+#[cfg(feature = "test-broker")]
+mod broker;
+mod flaky;
+
+#[cfg(feature = "test-broker")]
+pub use broker::{broker, CapturedReply, TestBroker};
+pub use flaky::{Flaky, FlakyBehavior, FlakyControl, InjectedFailure};
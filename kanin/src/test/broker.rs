@@ -0,0 +1,214 @@
+//! Testing kanin apps against a real broker.
+//!
+//! Requires the `test-broker` feature and a running Docker daemon.
+
+use std::time::Duration;
+
+use lapin::{
+    options::BasicPublishOptions,
+    types::{AMQPValue, ShortString},
+    BasicProperties, Connection, ConnectionProperties,
+};
+use prost::{DecodeError, Message};
+use testcontainers_modules::{
+    rabbitmq::RabbitMq,
+    testcontainers::{runners::AsyncRunner, ContainerAsync},
+};
+use tracing::{debug, error};
+
+use crate::{reply_queue::ReplyQueue, HandlerConfig};
+
+/// The port RabbitMQ listens for AMQP connections on inside the container.
+const AMQP_PORT: u16 = 5672;
+
+/// How long [`TestBroker::request`] waits for a reply.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A RabbitMQ broker running in a container, started by [`broker`].
+///
+/// The container is stopped and removed when this is dropped or [`stopped`](TestBroker::stop).
+pub struct TestBroker {
+    /// The container running the broker.
+    container: ContainerAsync<RabbitMq>,
+    /// The address of the broker, reachable from the host.
+    amqp_addr: String,
+    /// A connection to the broker.
+    connection: Connection,
+}
+
+impl TestBroker {
+    /// The address of the broker, e.g. for use with [`App::run`](crate::App::run).
+    pub fn amqp_addr(&self) -> &str {
+        &self.amqp_addr
+    }
+
+    /// A connection to the broker, e.g. for use with [`App::run_with_connection`](crate::App::run_with_connection).
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Opens another connection to the broker.
+    ///
+    /// # Panics
+    /// Panics if the connection could not be established.
+    pub async fn connect(&self) -> Connection {
+        Connection::connect(&self.amqp_addr, ConnectionProperties::default())
+            .await
+            .unwrap_or_else(|e| {
+                panic!(
+                    "Failed to connect to test broker on {}: {e:#}",
+                    self.amqp_addr
+                )
+            })
+    }
+
+    /// Publishes a request to the handler on the given routing key and waits for its reply.
+    ///
+    /// The request is published on the default exchange of handlers (see [`HandlerConfig::with_exchange`])
+    /// with `reply_to` and `correlation_id` set, and the reply is captured along with all the properties it was published with.
+    /// This allows asserting on the metadata of replies, not only their payload.
+    ///
+    /// # Panics
+    /// Panics if the request could not be published, or if no reply was received within 10 seconds.
+    pub async fn request(&self, routing_key: &str, request: &impl Message) -> CapturedReply {
+        let replies = ReplyQueue::new()
+            .declare(&self.connection)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to declare reply queue on test broker: {e:#}"));
+        let (properties, reply) = replies.expect::<()>();
+
+        let channel = self
+            .connection
+            .create_channel()
+            .await
+            .unwrap_or_else(|e| panic!("Failed to open channel on test broker: {e:#}"));
+        channel
+            .basic_publish(
+                &HandlerConfig::default().exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                &request.encode_to_vec(),
+                properties,
+            )
+            .await
+            .unwrap_or_else(|e| panic!("Failed to publish request to {routing_key:?}: {e:#}"));
+
+        let (properties, payload) = tokio::time::timeout(REPLY_TIMEOUT, reply.recv_raw())
+            .await
+            .unwrap_or_else(|_| {
+                panic!("No reply to request to {routing_key:?} within {REPLY_TIMEOUT:?}")
+            })
+            .unwrap_or_else(|e| {
+                panic!("Failed to receive reply to request to {routing_key:?}: {e:#}")
+            });
+
+        if let Err(e) = replies.close().await {
+            debug!("Failed to close reply queue on test broker: {e:#}");
+        }
+
+        CapturedReply {
+            properties,
+            payload,
+        }
+    }
+
+    /// Closes the connection to the broker and removes its container.
+    ///
+    /// Dropping the broker removes the container as well, but this allows waiting for the removal to finish.
+    pub async fn stop(self) {
+        if let Err(e) = self.connection.close(0, "test broker stopped").await {
+            debug!("Failed to close connection to test broker (the broker will be removed regardless): {e:#}");
+        }
+
+        if let Err(e) = self.container.rm().await {
+            error!("Failed to remove test broker container: {e:#}");
+        }
+    }
+}
+
+/// Starts a RabbitMQ broker in a container and connects to it.
+///
+/// Every call starts a new broker, so tests using this don't interfere with each other or with any other broker
+/// running on the machine. The broker is removed when the returned [`TestBroker`] is dropped.
+///
+/// ```no_run
+/// # async fn test() {
+/// let broker = kanin::test::broker().await;
+///
+/// kanin::App::new(())
+///     .handler("my_routing_key", || async {})
+///     .run_with_connection(broker.connection())
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+///
+/// # Panics
+/// Panics if the container could not be started, e.g. if Docker is not running, or if the connection could not be established.
+pub async fn broker() -> TestBroker {
+    let container = RabbitMq::default().start().await.unwrap_or_else(|e| {
+        panic!("Failed to start test broker container. Is Docker running? Error: {e:#}")
+    });
+
+    let host = container
+        .get_host()
+        .await
+        .unwrap_or_else(|e| panic!("Failed to get host of test broker container: {e:#}"));
+    let port = container
+        .get_host_port_ipv4(AMQP_PORT)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to get AMQP port of test broker container: {e:#}"));
+    let amqp_addr = format!("amqp://{host}:{port}");
+    debug!("Started test broker on {amqp_addr}");
+
+    let connection = Connection::connect(&amqp_addr, ConnectionProperties::default())
+        .await
+        .unwrap_or_else(|e| panic!("Failed to connect to test broker on {amqp_addr}: {e:#}"));
+
+    TestBroker {
+        container,
+        amqp_addr,
+        connection,
+    }
+}
+
+/// A reply captured by [`TestBroker::request`], with all the properties it was published with.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct CapturedReply {
+    /// The properties of the reply, e.g. its correlation id, content type and headers.
+    pub properties: BasicProperties,
+    /// The payload of the reply.
+    pub payload: Vec<u8>,
+}
+
+impl CapturedReply {
+    /// The `correlation_id` property of the reply.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.properties
+            .correlation_id()
+            .as_ref()
+            .map(ShortString::as_str)
+    }
+
+    /// The `content_type` property of the reply.
+    pub fn content_type(&self) -> Option<&str> {
+        self.properties
+            .content_type()
+            .as_ref()
+            .map(ShortString::as_str)
+    }
+
+    /// The value of the given header of the reply.
+    pub fn header(&self, key: &str) -> Option<&AMQPValue> {
+        self.properties.headers().as_ref()?.inner().get(key)
+    }
+
+    /// Decodes the payload of the reply.
+    ///
+    /// # Errors
+    /// Returns an error if the payload is not a valid `T`.
+    pub fn decode<T: Message + Default>(&self) -> Result<T, DecodeError> {
+        T::decode(&self.payload[..])
+    }
+}
@@ -0,0 +1,202 @@
+//! Failure injection for extractors.
+
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use thiserror::Error as ThisError;
+
+use crate::{
+    error::{HandlerError, ServerError},
+    Extract, Request,
+};
+
+/// Wraps the extractor `T`, failing its extraction as configured by the [`FlakyControl<T>`] in the app state.
+///
+/// This allows exercising error paths deterministically in tests, e.g. the response produced via [`FromError`](crate::error::FromError)
+/// or requeueing on transient errors (see [`Extract::is_transient`]). Injected failures are returned as
+/// [`ServerError::Other`] containing an [`InjectedFailure`]. When the extraction is not failed, `T` is extracted as usual.
+///
+/// The control is taken from the app state like [`State`](crate::extract::State), so the state must implement `From<&S>` for it,
+/// e.g. by deriving [`AppState`](crate::AppState):
+///
+/// ```
+/// use kanin::{extract::Msg, test::{Flaky, FlakyBehavior, FlakyControl}, AppState};
+///
+/// #[derive(AppState)]
+/// struct TestState {
+///     flaky: FlakyControl<Msg<String>>,
+/// }
+///
+/// async fn handler(Flaky(Msg(_message)): Flaky<Msg<String>>) {}
+///
+/// let flaky = FlakyControl::new(FlakyBehavior::FailFirst(2)).with_transient(true);
+/// let app = kanin::App::new(TestState { flaky: flaky.clone() }).handler("my_routing_key", handler);
+/// // Run the app and send requests, then check how often extraction was attempted.
+/// assert_eq!(0, flaky.attempts());
+/// ```
+#[derive(Debug)]
+pub struct Flaky<T>(pub T);
+
+/// Determines when a [`Flaky`] extractor fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FlakyBehavior {
+    /// Extraction is never failed.
+    Succeed,
+    /// The first given number of extractions are failed, after which extraction succeeds.
+    FailFirst(usize),
+    /// Extraction is always failed.
+    AlwaysFail,
+}
+
+/// The error injected by a [`Flaky`] extractor, contained in [`ServerError::Other`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ThisError)]
+#[error("Injected extraction failure (transient: {transient})")]
+#[non_exhaustive]
+pub struct InjectedFailure {
+    /// Whether the failure is transient, in which case the request is requeued. See [`FlakyControl::with_transient`].
+    pub transient: bool,
+}
+
+/// Controls the behavior of the [`Flaky<T>`] extractor from tests, and counts its extractions.
+///
+/// Clones share the same behavior and counts, so keep a clone in the test after putting one in the app state.
+pub struct FlakyControl<T> {
+    /// The behavior and counts, shared between clones.
+    inner: Arc<Mutex<FlakyState>>,
+    /// The extractor this controls.
+    _extractor: PhantomData<fn() -> T>,
+}
+
+/// The behavior and counts of a [`FlakyControl`].
+#[derive(Debug)]
+struct FlakyState {
+    /// When extraction fails.
+    behavior: FlakyBehavior,
+    /// Whether injected failures are transient.
+    transient: bool,
+    /// The number of extractions.
+    attempts: usize,
+    /// The number of extractions that were failed.
+    failures: usize,
+}
+
+impl<T> FlakyControl<T> {
+    /// Creates a control with the given behavior. Injected failures are permanent by default.
+    pub fn new(behavior: FlakyBehavior) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(FlakyState {
+                behavior,
+                transient: false,
+                attempts: 0,
+                failures: 0,
+            })),
+            _extractor: PhantomData,
+        }
+    }
+
+    /// Sets whether injected failures are transient, in which case the request is requeued instead of replied to.
+    pub fn with_transient(self, transient: bool) -> Self {
+        self.lock().transient = transient;
+        self
+    }
+
+    /// Changes the behavior, e.g. to start failing midway through a test. The counts are kept.
+    pub fn set_behavior(&self, behavior: FlakyBehavior) {
+        self.lock().behavior = behavior;
+    }
+
+    /// The number of times extraction was attempted.
+    pub fn attempts(&self) -> usize {
+        self.lock().attempts
+    }
+
+    /// The number of times extraction was failed.
+    pub fn failures(&self) -> usize {
+        self.lock().failures
+    }
+
+    /// Records an extraction attempt, returning the failure to inject, if any.
+    pub(crate) fn attempt(&self) -> Option<InjectedFailure> {
+        let mut state = self.lock();
+        state.attempts += 1;
+
+        let fail = match state.behavior {
+            FlakyBehavior::Succeed => false,
+            FlakyBehavior::FailFirst(count) => state.failures < count,
+            FlakyBehavior::AlwaysFail => true,
+        };
+        if !fail {
+            return None;
+        }
+
+        state.failures += 1;
+        Some(InjectedFailure {
+            transient: state.transient,
+        })
+    }
+
+    /// Locks the state. Poisoning is ignored, as the state is never left inconsistent.
+    fn lock(&self) -> std::sync::MutexGuard<'_, FlakyState> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T> Clone for FlakyControl<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _extractor: PhantomData,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for FlakyControl<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FlakyControl")
+            .field("extractor", &std::any::type_name::<T>())
+            .field("state", &*self.lock())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<S, T> Extract<S> for Flaky<T>
+where
+    S: Send + Sync,
+    T: Extract<S, Error = HandlerError> + Send,
+    FlakyControl<T>: for<'a> From<&'a S>,
+{
+    type Error = HandlerError;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        let control: FlakyControl<T> = req.state();
+        if let Some(failure) = control.attempt() {
+            return Err(HandlerError::InternalError(ServerError::Other(Box::new(
+                failure,
+            ))));
+        }
+
+        T::extract(req).await.map(Self)
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        match injected_failure(error) {
+            Some(failure) => failure.transient,
+            None => T::is_transient(error),
+        }
+    }
+}
+
+/// Returns the failure injected by a [`Flaky`] extractor, if the given error is one.
+pub(crate) fn injected_failure(error: &HandlerError) -> Option<&InjectedFailure> {
+    match error {
+        HandlerError::InternalError(ServerError::Other(error)) => error.downcast_ref(),
+        _ => None,
+    }
+}
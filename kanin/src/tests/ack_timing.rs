@@ -0,0 +1,25 @@
+use std::time::{Duration, Instant};
+
+use crate::{extract::ReqId, request::AckTiming};
+
+fn ack_timing(consumer_timeout: Option<Duration>) -> AckTiming {
+    AckTiming::new(
+        Instant::now(),
+        "routing_key".to_string(),
+        "handler",
+        ReqId::new(),
+        consumer_timeout,
+    )
+}
+
+#[test]
+fn ack_deadline_warning_is_close_to_the_consumer_timeout() {
+    let timing = ack_timing(Some(Duration::from_secs(100)));
+    assert_eq!(Duration::from_secs(80), timing.warning_threshold());
+
+    let timing = ack_timing(None);
+    assert_eq!(
+        AckTiming::DEFAULT_CONSUMER_TIMEOUT * 4 / 5,
+        timing.warning_threshold()
+    );
+}
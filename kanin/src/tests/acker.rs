@@ -0,0 +1,85 @@
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{error::ServerError, extract::Acker, ops, App, HandlerConfig, HandlerError};
+
+async fn handler_with_two_ackers(acker: Acker, second: Result<Acker, HandlerError>) -> Reply {
+    acker.ack().await.unwrap();
+
+    match second {
+        Err(HandlerError::InternalError(ServerError::AckerAlreadyTaken)) => {
+            Reply("already taken".into())
+        }
+        Err(e) => Reply(format!("error: {e}")),
+        Ok(_acker) => Reply("taken twice".into()),
+    }
+}
+
+async fn handler_acking_multiple(acker: Acker) -> Reply {
+    acker
+        .ack_multiple_up_to(acker.delivery_tag())
+        .await
+        .unwrap();
+    Reply("acked".into())
+}
+
+#[tokio::test]
+async fn acker_can_only_be_extracted_once() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler("kanin.tests.acker.twice", handler_with_two_ackers);
+
+    let (_properties, payload) = while_running(
+        app,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.acker.twice",
+            b"",
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+
+    assert_eq!(b"already taken".as_slice(), payload);
+}
+
+#[tokio::test]
+async fn acker_acks_multiple_messages_up_to_a_delivery_tag() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    // The queue outlives the app, so we can check that nothing was requeued when it shut down.
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.acker.multiple",
+        handler_acking_multiple,
+        HandlerConfig::new().with_auto_delete(false),
+    );
+
+    while_running(app, &conn, async {
+        for _ in 0..3 {
+            let (_properties, payload) = request(
+                &conn,
+                "kanin.tests.acker.multiple",
+                b"",
+                BasicProperties::default(),
+            )
+            .await;
+            assert_eq!(b"acked".as_slice(), payload);
+        }
+    })
+    .await;
+
+    let remaining = ops::message_count(&conn, "kanin.tests.acker.multiple")
+        .await
+        .unwrap();
+    let channel = conn.create_channel().await.unwrap();
+    channel
+        .queue_delete("kanin.tests.acker.multiple", Default::default())
+        .await
+        .unwrap();
+    assert_eq!(0, remaining);
+}
@@ -0,0 +1,97 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use crate::audit::{AuditGuard, AuditOutcome, AuditRecord, AuditSink, FileAuditSink};
+
+/// Forwards audit records to a channel.
+struct ChannelSink(mpsc::UnboundedSender<AuditRecord>);
+
+#[async_trait]
+impl AuditSink for ChannelSink {
+    async fn record(&self, record: AuditRecord) {
+        self.0.send(record).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn audit_guard_records_outcome_on_drop() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let sink: Arc<dyn AuditSink> = Arc::new(ChannelSink(tx));
+
+    let mut guard = AuditGuard::new(
+        sink.clone(),
+        "my_routing_key".into(),
+        "abc".into(),
+        Some("my_app".into()),
+        b"hello",
+    );
+    guard.set_outcome(AuditOutcome::Replied);
+    drop(guard);
+
+    let record = rx.recv().await.unwrap();
+    assert_eq!("my_routing_key", record.routing_key);
+    assert_eq!("abc", record.req_id);
+    assert_eq!(Some("my_app"), record.app_id.as_deref());
+    assert_eq!(
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        record.payload_sha256
+    );
+    assert_eq!(AuditOutcome::Replied, record.outcome);
+
+    // Guards dropped without an outcome were aborted.
+    drop(AuditGuard::new(
+        sink,
+        "my_routing_key".into(),
+        "def".into(),
+        None,
+        b"",
+    ));
+
+    let record = rx.recv().await.unwrap();
+    assert_eq!(AuditOutcome::Aborted, record.outcome);
+}
+
+#[test]
+fn audit_records_display_as_logfmt() {
+    let record = AuditRecord {
+        routing_key: "my_routing_key".into(),
+        req_id: "abc".into(),
+        app_id: None,
+        payload_sha256: "00ff".into(),
+        outcome: AuditOutcome::ReplyFailed,
+        latency: Duration::from_millis(12),
+    };
+
+    assert_eq!(
+        r#"routing_key="my_routing_key" req_id="abc" app_id="" payload_sha256=00ff outcome=reply_failed latency_ms=12"#,
+        record.to_string()
+    );
+}
+
+#[tokio::test]
+async fn file_sink_appends_one_line_per_record() {
+    let path = std::env::temp_dir().join(format!("kanin-audit-{}.log", uuid::Uuid::new_v4()));
+    let sink = FileAuditSink::open(&path).unwrap();
+
+    for outcome in [AuditOutcome::Replied, AuditOutcome::Requeued] {
+        sink.record(AuditRecord {
+            routing_key: "my_routing_key".into(),
+            req_id: "abc".into(),
+            app_id: None,
+            payload_sha256: "00ff".into(),
+            outcome,
+            latency: Duration::from_millis(12),
+        })
+        .await;
+    }
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let outcomes: Vec<_> = contents
+        .lines()
+        .map(|line| line.split(' ').nth(4).unwrap())
+        .collect();
+    assert_eq!(vec!["outcome=replied", "outcome=requeued"], outcomes);
+}
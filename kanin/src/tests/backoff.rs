@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use crate::handler_config::RedeliveryBackoff;
+
+#[test]
+fn redelivery_backoff_doubles_up_to_the_max() {
+    let backoff = RedeliveryBackoff {
+        base: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+    };
+
+    assert_eq!(Duration::ZERO, backoff.max_delay(0));
+    assert_eq!(Duration::from_millis(100), backoff.max_delay(1));
+    assert_eq!(Duration::from_millis(200), backoff.max_delay(2));
+    assert_eq!(Duration::from_millis(800), backoff.max_delay(4));
+    assert_eq!(Duration::from_secs(1), backoff.max_delay(5));
+    assert_eq!(Duration::from_secs(1), backoff.max_delay(u64::MAX));
+
+    let delay = backoff.delay(3);
+    assert!(Duration::from_millis(200) <= delay && delay <= Duration::from_millis(400));
+}
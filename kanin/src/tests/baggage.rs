@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+use lapin::{
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use crate::extract::{Baggage, BAGGAGE};
+
+fn baggage(members: &[(&str, &str)]) -> Baggage {
+    Baggage(
+        members
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    )
+}
+
+#[test]
+fn baggage_is_parsed_leniently() {
+    assert_eq!(
+        baggage(&[("tenant", "acme"), ("experiment", "new-search")]),
+        Baggage::parse(" tenant = acme ,experiment=new-search;ttl=30,malformed,=empty-key")
+    );
+    assert_eq!(Baggage(BTreeMap::new()), Baggage::parse(""));
+}
+
+#[test]
+fn baggage_round_trips_through_headers() {
+    let original = baggage(&[("tenant", "acme"), ("flag", "on")]);
+    assert_eq!("flag=on,tenant=acme", original.header_value());
+
+    let mut headers = FieldTable::default();
+    original.propagate(&mut headers);
+    assert_eq!(original, Baggage::from_headers(&headers));
+
+    // Existing baggage is not overridden.
+    baggage(&[("tenant", "other")]).propagate(&mut headers);
+    assert_eq!(original, Baggage::from_headers(&headers));
+}
+
+#[tokio::test]
+async fn current_baggage_is_propagated_while_handling_a_request() {
+    assert_eq!(None, Baggage::current());
+    let properties = Baggage::propagate_current(BasicProperties::default());
+    assert_eq!(&None, properties.headers());
+
+    let properties = BAGGAGE
+        .scope(baggage(&[("tenant", "acme")]), async {
+            Baggage::propagate_current(BasicProperties::default())
+        })
+        .await;
+    let headers = properties.headers().clone().unwrap_or_default();
+    assert_eq!(
+        Some(&AMQPValue::LongString("tenant=acme".into())),
+        headers.inner().get(Baggage::HEADER)
+    );
+}
@@ -1,11 +1,12 @@
 use std::sync::{Arc, Mutex};
 
-use lapin::Channel;
+use lapin::{BasicProperties, Channel, Connection};
 
+use super::{amqp_connect, init_logging, request, test_broker};
 use crate::{
     error::FromError,
     extract::{AppId, State},
-    App, AppState, HandlerError, Respond,
+    App, AppState, Error, HandlerError, Respond,
 };
 
 #[derive(Debug)]
@@ -21,6 +22,7 @@ impl FromError<HandlerError> for MyResponse {
     fn from_error(error: HandlerError) -> Self {
         match error {
             HandlerError::InvalidRequest(e) => MyResponse(format!("Invalid request: {:#?}", e)),
+            HandlerError::InternalError(e) => MyResponse(format!("Internal error: {e:#?}")),
         }
     }
 }
@@ -61,6 +63,47 @@ struct MyNamedAppState {
     my_state: Arc<Mutex<u32>>,
 }
 
+mod nested {
+    use std::sync::{Arc, Mutex};
+
+    #[derive(crate::AppState)]
+    pub(super) struct CounterState {
+        pub(super) counter: Arc<Mutex<u32>>,
+        pub(super) name: String,
+    }
+}
+
+#[derive(AppState)]
+struct MyComposedAppState {
+    limit: u64,
+    #[app_state(flatten)]
+    nested: nested::CounterState,
+}
+
+#[derive(AppState)]
+struct MyDeeplyComposedAppState(#[app_state(flatten)] MyComposedAppState);
+
+#[test]
+fn it_composes_nested_app_states() {
+    let state = MyDeeplyComposedAppState(MyComposedAppState {
+        limit: 10,
+        nested: nested::CounterState {
+            counter: Arc::new(Mutex::new(5)),
+            name: "nested".into(),
+        },
+    });
+
+    let limit: u64 = (&state).into();
+    let counter: Arc<Mutex<u32>> = (&state).into();
+    let name: String = (&state).into();
+    assert_eq!(10, limit);
+    assert_eq!(5, *counter.lock().unwrap());
+    assert_eq!("nested", name);
+
+    let name: String = (&state.0).into();
+    assert_eq!("nested", name);
+}
+
 /// At the moment, this just verifies that the above handlers compile and work as handlers.
 #[tokio::test]
 async fn it_compiles() {
@@ -71,3 +114,67 @@ async fn it_compiles() {
         .handler("routing_key_4", handler_with_state_extractor)
         .handler("routing_key_5", listener);
 }
+
+#[tokio::test]
+async fn it_compiles_with_async_state() {
+    let _ignore = App::new_with(async { MyAppState(Arc::new(Mutex::new(187))) })
+        .handler("routing_key_0", listener);
+
+    let _ignore = App::try_new(async {
+        let count = "187".parse::<u32>()?;
+        Ok::<_, std::num::ParseIntError>(MyAppState(Arc::new(Mutex::new(count))))
+    })
+    .handler("routing_key_0", listener);
+}
+
+// Never run, as it requires a broker. This just checks that handlers can be registered on a running app.
+#[allow(dead_code)]
+async fn it_compiles_with_running_app(conn: &Connection) -> crate::Result<()> {
+    let mut app = App::new(MyAppState(Arc::new(Mutex::new(0))))
+        .handler("routing_key_0", listener)
+        .start(conn)
+        .await?;
+
+    app.handler("routing_key_1", listener).await?;
+    app.handle().set_prefetch("routing_key_1", 8).await?;
+    app.wait().await
+}
+
+#[tokio::test]
+async fn set_prefetch_fails_before_handlers_are_set_up() {
+    let app = App::new(MyAppState(Arc::new(Mutex::new(0)))).handler("routing_key_0", listener);
+    let handle = app.handle();
+
+    let result = handle.set_prefetch("routing_key_0", 8).await;
+    assert!(matches!(result, Err(Error::NoSuchHandler(rk)) if rk == "routing_key_0"));
+}
+
+#[tokio::test]
+async fn handlers_added_to_a_running_app_reply_to_requests() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let mut app = App::new(MyAppState(Arc::new(Mutex::new(0))))
+        .handler("kanin.tests.running_app.initial", handler)
+        .start(&conn)
+        .await
+        .expect("failed to start app");
+    let shutdown = app.shutdown_channel();
+
+    app.handler("kanin.tests.running_app.added", handler)
+        .await
+        .expect("failed to add handler");
+
+    let (_properties, payload) = request(
+        &conn,
+        "kanin.tests.running_app.added",
+        b"",
+        BasicProperties::default(),
+    )
+    .await;
+    assert_eq!(b"hello".as_slice(), payload);
+
+    shutdown.send(()).expect("failed to shut down app");
+    app.wait().await.expect("app failed");
+}
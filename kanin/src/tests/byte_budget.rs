@@ -0,0 +1,37 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::{app::task::InFlightBytesGuard, tests::TestRecorder, HandlerConfig};
+
+#[test]
+fn payload_bytes_are_in_flight_until_the_request_finishes() {
+    let recorder = TestRecorder::default();
+    let in_flight_bytes = Arc::new(AtomicUsize::new(0));
+
+    metrics::with_local_recorder(&recorder, || {
+        let first = InFlightBytesGuard::new(100, "routing_key", &in_flight_bytes, None);
+        let _second = InFlightBytesGuard::new(50, "routing_key", &in_flight_bytes, None);
+        assert_eq!(150, in_flight_bytes.load(Ordering::Relaxed));
+        assert_eq!(
+            150.0,
+            recorder.gauge("kanin.in_flight_bytes{routing_key=routing_key}")
+        );
+
+        drop(first);
+        assert_eq!(50, in_flight_bytes.load(Ordering::Relaxed));
+        assert_eq!(
+            50.0,
+            recorder.gauge("kanin.in_flight_bytes{routing_key=routing_key}")
+        );
+    });
+
+    assert_eq!(0, in_flight_bytes.load(Ordering::Relaxed));
+}
+
+#[test]
+fn an_empty_byte_budget_still_lets_requests_through_one_at_a_time() {
+    let config = HandlerConfig::new().with_in_flight_byte_budget(0);
+    assert_eq!(Some(1), config.in_flight_byte_budget);
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{app::task::CallerLimiter, extract::AppId, App, HandlerConfig};
+
+async fn slow_handler(AppId(app_id): AppId) -> Reply {
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    Reply(app_id.unwrap_or_default())
+}
+
+#[test]
+fn caller_limiter_limits_requests_in_flight_per_caller() {
+    let limiter = CallerLimiter::new(2);
+
+    let first = limiter.try_acquire("a").unwrap();
+    let _second = limiter.try_acquire("a").unwrap();
+    assert!(limiter.try_acquire("a").is_none());
+
+    // Other callers have their own limit.
+    let _other = limiter.try_acquire("b").unwrap();
+
+    // Finished requests make room for new ones.
+    drop(first);
+    let _third = limiter.try_acquire("a").unwrap();
+    assert!(limiter.try_acquire("a").is_none());
+}
+
+#[tokio::test]
+async fn callers_over_their_limit_leave_room_for_other_callers() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.caller_limit",
+        slow_handler,
+        HandlerConfig::new().with_per_caller_limit(1),
+    );
+    let from = |app_id: &str| BasicProperties::default().with_app_id(app_id.into());
+
+    let elapsed = while_running(app, &conn, async {
+        // The flooding caller's requests are handled one at a time, so they take at least 4 seconds in total.
+        let flood = join_all(
+            (0..4).map(|_| request(&conn, "kanin.tests.caller_limit", b"", from("flood"))),
+        );
+        tokio::pin!(flood);
+
+        let other = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let start = Instant::now();
+            let (_properties, payload) =
+                request(&conn, "kanin.tests.caller_limit", b"", from("other")).await;
+            assert_eq!(b"other".as_slice(), payload);
+            start.elapsed()
+        };
+
+        tokio::select! {
+            _ = &mut flood => panic!("the flooding caller was not limited"),
+            elapsed = other => elapsed,
+        }
+    })
+    .await;
+
+    // The other caller's request is handled right away, rather than after the flooding caller's requests.
+    assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+}
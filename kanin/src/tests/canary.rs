@@ -0,0 +1,18 @@
+use crate::canary::Canary;
+
+#[test]
+fn canary_receives_the_given_percentage_of_requests() {
+    let never = Canary::new((), (), 0);
+    assert!((0..1000).all(|_| !never.pick_canary()));
+
+    let always = Canary::new((), (), 100);
+    assert!((0..1000).all(|_| always.pick_canary()));
+
+    // Percentages above 100 are clamped rather than panicking.
+    let clamped = Canary::new((), (), 150);
+    assert!(clamped.pick_canary());
+
+    let half = Canary::new((), (), 50);
+    let canaries = (0..1000).filter(|_| half.pick_canary()).count();
+    assert!((350..650).contains(&canaries), "{canaries} canaries");
+}
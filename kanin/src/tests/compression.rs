@@ -0,0 +1,42 @@
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use lapin::{
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use crate::compression::{accepts_gzip, gzip, ACCEPT_ENCODING_HEADER};
+
+fn with_accept_encoding(accept_encoding: &str) -> BasicProperties {
+    let mut headers = FieldTable::default();
+    headers.insert(
+        ACCEPT_ENCODING_HEADER.into(),
+        AMQPValue::LongString(accept_encoding.into()),
+    );
+    BasicProperties::default().with_headers(headers)
+}
+
+#[test]
+fn gzip_is_negotiated_from_accept_encoding_header() {
+    assert!(!accepts_gzip(&BasicProperties::default()));
+    assert!(accepts_gzip(&with_accept_encoding("gzip")));
+    assert!(accepts_gzip(&with_accept_encoding("br, GZIP;q=0.5")));
+    assert!(accepts_gzip(&with_accept_encoding("*")));
+    assert!(!accepts_gzip(&with_accept_encoding("br, deflate")));
+    assert!(!accepts_gzip(&with_accept_encoding("gzip;q=0")));
+    assert!(!accepts_gzip(&with_accept_encoding("gzipped")));
+}
+
+#[test]
+fn gzip_compresses_payloads() {
+    let payload = b"hello hello hello hello hello hello hello hello".repeat(10);
+    let compressed = gzip(&payload).unwrap();
+    assert!(compressed.len() < payload.len());
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(payload, decompressed);
+}
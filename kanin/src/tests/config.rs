@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use lapin::{options::BasicPublishOptions, types::AMQPValue};
+
+use crate::{
+    config::HandlerOverlay, handler_config::ConfigIssue, instance::Instance, HandlerConfig,
+};
+
+#[test]
+fn overlay_overrides_only_given_values() {
+    let config = HandlerConfig::new()
+        .with_prefetch(8)
+        .with_durable(true)
+        .with_message_ttl(Duration::from_secs(10));
+
+    let overlay = HandlerOverlay {
+        prefetch: Some(16),
+        consumer_timeout_ms: Some(60_000),
+        per_caller_limit: Some(2),
+        ..HandlerOverlay::new()
+    };
+    let config = overlay.apply(config);
+
+    assert_eq!(16, config.prefetch);
+    assert_eq!(Some(2), config.per_caller_limit);
+    assert!(config.options.durable);
+    assert_eq!(
+        Some(Duration::from_secs(10)),
+        config.duration_argument("x-message-ttl")
+    );
+    assert_eq!(
+        Some(Duration::from_secs(60)),
+        config.duration_argument("x-consumer-timeout")
+    );
+}
+
+#[test]
+fn overlay_overrides_max_rate() {
+    let config = HandlerConfig::new().with_max_rate(100);
+
+    let overlay = HandlerOverlay {
+        max_rate: Some(0),
+        ..HandlerOverlay::new()
+    };
+    let config = overlay.apply(config);
+
+    // A rate of 0 is treated as 1, so the handler still makes progress.
+    assert_eq!(Some(1), config.max_rate);
+}
+
+#[test]
+fn empty_overlay_changes_nothing() {
+    let config = HandlerOverlay::new().apply(HandlerConfig::new());
+    let default = HandlerConfig::new();
+
+    assert_eq!(default.prefetch, config.prefetch);
+    assert_eq!(default.options, config.options);
+    assert_eq!(default.arguments, config.arguments);
+}
+
+#[test]
+fn consumer_args_are_passed_to_the_consumer() {
+    let config = HandlerConfig::new()
+        .with_consumer_arg("x-stream-offset", AMQPValue::LongString("first".into()))
+        .with_consumer_arg("x-priority", 1)
+        .with_consumer_priority(5);
+
+    let arguments = config.consumer_arguments(Instance::new(0));
+    assert_eq!(
+        Some(&AMQPValue::LongString("first".into())),
+        arguments.inner().get("x-stream-offset")
+    );
+    assert_eq!(
+        Some(&AMQPValue::LongInt(5)),
+        arguments.inner().get("x-priority")
+    );
+}
+
+#[test]
+fn validation_catches_configurations_that_drop_messages() {
+    assert!(HandlerConfig::new().validate().is_empty());
+    // Queues that aren't durable are meant to go away.
+    assert!(HandlerConfig::new()
+        .with_expires(Duration::from_secs(60))
+        .validate()
+        .is_empty());
+
+    let issues = HandlerConfig::new()
+        .with_durable(true)
+        .with_expires(Duration::from_secs(60))
+        .validate();
+    assert_eq!(
+        vec![ConfigIssue::DurableQueueExpires {
+            expires: Duration::from_secs(60)
+        }],
+        issues
+    );
+}
+
+#[test]
+fn durable_queues_may_expire_after_long_enough_without_consumers() {
+    assert!(HandlerConfig::new()
+        .with_durable(true)
+        .with_expires(HandlerConfig::MIN_DURABLE_QUEUE_EXPIRES)
+        .validate()
+        .is_empty());
+
+    let issues = HandlerConfig::new()
+        .with_durable(true)
+        .with_expires(HandlerConfig::MIN_DURABLE_QUEUE_EXPIRES - Duration::from_secs(1))
+        .validate();
+    assert_eq!(1, issues.len());
+}
+
+#[test]
+fn immediate_replies_are_not_supported() {
+    let config = HandlerConfig::new();
+    assert_eq!(BasicPublishOptions::default(), config.reply_publish_options);
+
+    // RabbitMQ closes the channel on immediate publishes, so the flag is cleared.
+    let config = HandlerConfig::new().with_reply_publish_options(BasicPublishOptions {
+        mandatory: true,
+        immediate: true,
+    });
+    assert_eq!(
+        BasicPublishOptions {
+            mandatory: true,
+            immediate: false,
+        },
+        config.reply_publish_options
+    );
+
+    let config = HandlerConfig::new().with_mandatory_replies(true);
+    assert!(config.reply_publish_options.mandatory);
+}
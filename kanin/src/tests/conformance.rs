@@ -0,0 +1,89 @@
+use crate::{
+    app::conformance::{check, ConformanceIssue},
+    error::FromError,
+    extract::AppId,
+    Handler, HandlerConfig, HandlerError, Respond,
+};
+
+/// A response that replies with the error it was created from.
+#[derive(Debug)]
+struct ErrorMessage(String);
+
+impl Respond for ErrorMessage {
+    fn respond(self) -> Vec<u8> {
+        self.0.into_bytes()
+    }
+}
+
+impl FromError<HandlerError> for ErrorMessage {
+    fn from_error(error: HandlerError) -> Self {
+        Self(error.to_string())
+    }
+}
+
+/// A response that replies with an empty payload to errors.
+#[derive(Debug)]
+struct Silent;
+
+impl Respond for Silent {
+    fn respond(self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+impl FromError<HandlerError> for Silent {
+    fn from_error(_error: HandlerError) -> Self {
+        Self
+    }
+}
+
+/// Checks the given handler with the given configuration.
+fn issues<H, Args, Res>(_handler: H, config: &HandlerConfig) -> Vec<ConformanceIssue>
+where
+    H: Handler<Args, Res, ()>,
+    Res: Respond,
+{
+    check::<H, Args, Res, ()>(config)
+}
+
+async fn listener(_app_id: AppId) {}
+
+async fn responder(AppId(app_id): AppId) -> ErrorMessage {
+    ErrorMessage(app_id.unwrap_or_default())
+}
+
+async fn silent_responder(_app_id: AppId) -> Silent {
+    Silent
+}
+
+async fn infallible_silent_responder() -> Silent {
+    Silent
+}
+
+#[test]
+fn unit_responses_with_replies_are_reported() {
+    assert_eq!(
+        vec![ConformanceIssue::UnitResponseWithReplies],
+        issues(listener, &HandlerConfig::new())
+    );
+    assert!(issues(listener, &HandlerConfig::new().with_replies(false)).is_empty());
+}
+
+#[test]
+fn empty_error_responses_are_reported() {
+    assert!(issues(responder, &HandlerConfig::new()).is_empty());
+
+    let reported = issues(silent_responder, &HandlerConfig::new());
+    assert!(!reported.is_empty());
+    assert!(reported.iter().all(|issue| matches!(
+        issue,
+        ConformanceIssue::EmptyErrorResponse { response, .. } if response.ends_with("Silent")
+    )));
+    assert!(issues(silent_responder, &HandlerConfig::new().with_replies(false)).is_empty());
+}
+
+#[test]
+fn handlers_without_extractors_are_checked() {
+    // Handlers without extractors still respond to errors, e.g. when they panic.
+    assert!(!issues(infallible_silent_responder, &HandlerConfig::new()).is_empty());
+}
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use lapin::uri::AMQPUri;
+
+use crate::{App, Error, KaninConnectionOptions};
+
+#[test]
+fn connection_options_override_address_parameters() {
+    let mut uri: AMQPUri = "amqp://localhost?heartbeat=60&frame_max=8192"
+        .parse()
+        .unwrap();
+
+    KaninConnectionOptions::new()
+        .with_heartbeat(Duration::from_secs(10))
+        .with_connection_timeout(Duration::from_secs(5))
+        .apply_to_uri(&mut uri);
+
+    assert_eq!(Some(10), uri.query.heartbeat);
+    assert_eq!(Some(8192), uri.query.frame_max);
+    assert_eq!(None, uri.query.channel_max);
+    assert_eq!(Some(5000), uri.query.connection_timeout);
+}
+
+#[tokio::test]
+async fn reconnecting_app_retries_until_shut_down() {
+    let app = App::new(())
+        .handler("reconnect_test", || async {})
+        .with_reconnect(Duration::from_millis(10), Duration::from_millis(50));
+    let shutdown = app.shutdown_channel();
+
+    // Nothing listens on this port, so every attempt to connect fails.
+    let run = tokio::spawn(async move { app.run("amqp://127.0.0.1:1").await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!run.is_finished());
+
+    shutdown.send(()).unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(1), run).await;
+    assert!(matches!(result, Ok(Ok(Ok(())))));
+}
+
+#[tokio::test]
+async fn reconnecting_app_returns_errors_that_are_not_retryable() {
+    let app = App::new(())
+        .handler("reconnect_test", || async {})
+        .with_reconnect(Duration::from_millis(10), Duration::from_millis(50));
+
+    let result = app.run("not an address").await;
+    assert!(matches!(result, Err(Error::InvalidAddress(_))));
+}
@@ -0,0 +1,32 @@
+use lapin::types::AMQPValue;
+
+use crate::{
+    context::{ContextAware, RequestContext, REQUEST_CONTEXT},
+    extract::{Deadline, ReqId},
+};
+
+struct Client;
+
+impl ContextAware for Client {}
+
+#[tokio::test]
+async fn clients_pick_up_the_current_request_context() {
+    assert_eq!(None, Client.request_context());
+    assert_eq!(None, Client.correlation_header());
+
+    let context = RequestContext {
+        req_id: ReqId(AMQPValue::LongString("req-187".into())),
+        req_id_header: "req_id".to_string(),
+        routing_key: "routing_key".to_string(),
+        app_id: Some("caller".to_string()),
+        deadline: Deadline::default(),
+    };
+    let (req_id, header) = REQUEST_CONTEXT
+        .scope(context.clone(), async {
+            (Client.current_req_id(), Client.correlation_header())
+        })
+        .await;
+
+    assert_eq!(Some(context.req_id), req_id);
+    assert_eq!(Some(("req_id".to_string(), "req-187".to_string())), header);
+}
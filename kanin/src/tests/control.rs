@@ -0,0 +1,113 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::{broadcast, Notify};
+
+use crate::control::{self, ControlMessage, MAX_CLOCK_SKEW, RELOAD, SHUTDOWN};
+
+/// Returns the current Unix time in seconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Returns a control message with the given payload, signed with the given secret at the given time.
+fn signed(secret: &[u8], timestamp: u64, payload: &[u8]) -> ControlMessage {
+    ControlMessage {
+        payload: payload.to_vec(),
+        timestamp: Some(timestamp),
+        signature: Some(control::sign(secret, timestamp, payload)),
+    }
+}
+
+#[test]
+fn sign_computes_hmac_sha256_of_timestamp_and_payload() {
+    // HMAC-SHA256 of "1700000000.what do ya want for nothing?" with key "Jefe", the key of test case 2 from RFC 4231.
+    assert_eq!(
+        "1cdd0650c8be1cb0974b1788d458b1e781206cfef59b85faafc582d2e182c57e",
+        control::sign(b"Jefe", 1_700_000_000, b"what do ya want for nothing?")
+    );
+}
+
+#[test]
+fn verify_rejects_invalid_signatures() {
+    let signature = control::sign(b"secret", 1, SHUTDOWN);
+    assert!(control::verify(b"secret", 1, SHUTDOWN, &signature));
+    assert!(!control::verify(b"other secret", 1, SHUTDOWN, &signature));
+    assert!(!control::verify(b"secret", 1, b"drain", &signature));
+    assert!(!control::verify(b"secret", 2, SHUTDOWN, &signature));
+    assert!(!control::verify(b"secret", 1, SHUTDOWN, "not hex"));
+    assert!(!control::verify(b"secret", 1, SHUTDOWN, ""));
+}
+
+#[test]
+fn is_fresh_allows_clock_skew_in_both_directions() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let skew = MAX_CLOCK_SKEW.as_secs();
+
+    assert!(control::is_fresh(1_000_000, now));
+    assert!(control::is_fresh(1_000_000 - skew, now));
+    assert!(control::is_fresh(1_000_000 + skew, now));
+    assert!(!control::is_fresh(1_000_000 - skew - 1, now));
+    assert!(!control::is_fresh(1_000_000 + skew + 1, now));
+}
+
+#[tokio::test]
+async fn control_handler_shuts_down_on_signed_shutdown() {
+    let (shutdown, mut receiver) = broadcast::channel(1);
+
+    let unsigned = ControlMessage {
+        payload: SHUTDOWN.to_vec(),
+        timestamp: Some(now()),
+        signature: None,
+    };
+    control::control_handler(unsigned, b"secret", &shutdown, &Notify::new()).await;
+    let forged = signed(b"guess", now(), SHUTDOWN);
+    control::control_handler(forged, b"secret", &shutdown, &Notify::new()).await;
+    assert!(receiver.try_recv().is_err());
+
+    control::control_handler(
+        signed(b"secret", now(), SHUTDOWN),
+        b"secret",
+        &shutdown,
+        &Notify::new(),
+    )
+    .await;
+    assert!(receiver.try_recv().is_ok());
+}
+
+#[tokio::test]
+async fn control_handler_ignores_replayed_messages() {
+    let (shutdown, mut receiver) = broadcast::channel(1);
+
+    // A validly signed message that was observed a while ago.
+    let replayed = signed(b"secret", now() - 2 * MAX_CLOCK_SKEW.as_secs(), SHUTDOWN);
+    control::control_handler(replayed, b"secret", &shutdown, &Notify::new()).await;
+    // A validly signed message whose timestamp was removed.
+    let mut untimed = signed(b"secret", now(), SHUTDOWN);
+    untimed.timestamp = None;
+    control::control_handler(untimed, b"secret", &shutdown, &Notify::new()).await;
+
+    assert!(receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn control_handler_requests_reload_on_signed_reload() {
+    let (shutdown, mut receiver) = broadcast::channel(1);
+    let reload = Notify::new();
+
+    control::control_handler(
+        signed(b"secret", now(), RELOAD),
+        b"secret",
+        &shutdown,
+        &reload,
+    )
+    .await;
+
+    // The reload request is stored until someone waits for it.
+    tokio::time::timeout(std::time::Duration::from_secs(1), reload.notified())
+        .await
+        .unwrap();
+    assert!(receiver.try_recv().is_err());
+}
@@ -0,0 +1,96 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lapin::{
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use crate::{
+    context::{RequestContext, REQUEST_CONTEXT},
+    extract::{Deadline, ReqId},
+    Error,
+};
+
+#[test]
+fn unbounded_deadline_is_never_exceeded() {
+    let deadline = Deadline::default();
+
+    assert_eq!(None, deadline.remaining());
+    assert!(!deadline.is_exceeded());
+    assert!(deadline.check().is_ok());
+
+    let mut headers = FieldTable::default();
+    deadline.propagate(&mut headers);
+    assert!(headers.inner().is_empty());
+}
+
+#[test]
+fn exceeded_deadline_fails_check() {
+    let deadline = Deadline(Some(SystemTime::now() - Duration::from_secs(1)));
+
+    assert_eq!(Some(Duration::ZERO), deadline.remaining());
+    assert!(matches!(deadline.check(), Err(Error::DeadlineExceeded)));
+}
+
+#[test]
+fn deadline_is_propagated_as_epoch_millis() {
+    let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+    let mut headers = FieldTable::default();
+    Deadline(Some(at)).propagate(&mut headers);
+
+    assert_eq!(
+        Some(&AMQPValue::LongLongInt(1_700_000_000_123)),
+        headers.inner().get(Deadline::HEADER)
+    );
+
+    let remaining = Deadline::after(Duration::from_secs(60))
+        .remaining()
+        .unwrap();
+    assert!(remaining > Duration::from_secs(59));
+}
+
+#[tokio::test]
+async fn deadline_of_current_request_is_propagated() {
+    // Outside of a request, there is no deadline to propagate.
+    assert_eq!(Deadline::default(), Deadline::current());
+    assert_eq!(
+        BasicProperties::default(),
+        Deadline::propagate_current(BasicProperties::default())
+    );
+
+    let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+    let context = RequestContext {
+        req_id: ReqId::new(),
+        req_id_header: "req_id".to_string(),
+        routing_key: "routing_key".to_string(),
+        app_id: None,
+        deadline: Deadline(Some(at)),
+    };
+    let mut own_deadline = FieldTable::default();
+    own_deadline.insert(Deadline::HEADER.into(), AMQPValue::LongLongInt(187));
+
+    let (current, propagated, kept) = REQUEST_CONTEXT
+        .scope(context, async {
+            (
+                Deadline::current(),
+                Deadline::propagate_current(BasicProperties::default()),
+                Deadline::propagate_current(
+                    BasicProperties::default().with_headers(own_deadline.clone()),
+                ),
+            )
+        })
+        .await;
+
+    assert_eq!(Deadline(Some(at)), current);
+    // The current request has long exceeded its deadline, so client-side helpers fail rather than publish.
+    assert!(matches!(current.check(), Err(Error::DeadlineExceeded)));
+    assert_eq!(
+        Some(&AMQPValue::LongLongInt(1_700_000_000_123)),
+        propagated
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(Deadline::HEADER))
+    );
+    // Deadlines set by the caller are not overwritten.
+    assert_eq!(Some(own_deadline), kept.headers().clone());
+}
@@ -0,0 +1,62 @@
+use lapin::{
+    acker::Acker,
+    message::Delivery,
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use crate::{
+    extract::{delivery_count, DeliveryCount},
+    HandlerConfig,
+};
+
+/// Returns a delivery with the given headers.
+fn delivery(headers: FieldTable) -> Delivery {
+    Delivery {
+        delivery_tag: 1,
+        exchange: "".into(),
+        routing_key: "routing_key".into(),
+        redelivered: true,
+        properties: BasicProperties::default().with_headers(headers),
+        data: Vec::new(),
+        acker: Acker::default(),
+    }
+}
+
+#[test]
+fn quorum_queue_with_delivery_limit_sets_queue_arguments() {
+    let config = HandlerConfig::new()
+        .with_quorum_queue()
+        .with_delivery_limit(3);
+
+    assert!(config.options.durable);
+    assert!(!config.options.auto_delete);
+    assert_eq!(
+        Some(&AMQPValue::LongString("quorum".into())),
+        config.arguments.inner().get("x-queue-type")
+    );
+    assert_eq!(
+        Some(&AMQPValue::LongLongInt(3)),
+        config.arguments.inner().get("x-delivery-limit")
+    );
+}
+
+#[test]
+fn delivery_count_is_read_from_header() {
+    let mut headers = FieldTable::default();
+    headers.insert("x-delivery-count".into(), AMQPValue::LongLongInt(2));
+    assert_eq!(2, delivery_count(&delivery(headers)));
+
+    let mut headers = FieldTable::default();
+    headers.insert("x-delivery-count".into(), AMQPValue::LongString("2".into()));
+    assert_eq!(0, delivery_count(&delivery(headers)));
+    assert_eq!(0, delivery_count(&delivery(FieldTable::default())));
+}
+
+#[test]
+fn last_attempt_is_at_the_delivery_limit() {
+    assert!(!DeliveryCount(0).is_last_attempt(3));
+    assert!(!DeliveryCount(2).is_last_attempt(3));
+    assert!(DeliveryCount(3).is_last_attempt(3));
+    assert!(DeliveryCount(0).is_last_attempt(0));
+}
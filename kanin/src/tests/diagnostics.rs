@@ -0,0 +1,20 @@
+use crate::error::PayloadDiagnostics;
+
+#[test]
+fn payload_diagnostics_sample_the_start_of_the_payload() {
+    let diagnostics =
+        PayloadDiagnostics::new(b"{\"value\": 1}", 4, Some("application/json".into()));
+
+    assert_eq!(12, diagnostics.length);
+    assert_eq!(b"{\"va", &diagnostics.sample[..]);
+    assert_eq!(
+        "payload of 12 bytes, content type application/json, starting with 7b227661",
+        diagnostics.to_string()
+    );
+
+    let diagnostics = PayloadDiagnostics::new(&[0x0a], 16, None);
+    assert_eq!(
+        "payload of 1 bytes, content type <none>, starting with 0a",
+        diagnostics.to_string()
+    );
+}
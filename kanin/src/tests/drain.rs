@@ -0,0 +1,139 @@
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+
+use crate::{
+    app::task::{drain, drain_deadline, requeue_expired, RequestTask},
+    tests::TestRecorder,
+    HandlerConfig,
+};
+
+#[test]
+fn drain_deadline_is_the_consumer_timeout_minus_the_margin() {
+    let received = Instant::now();
+    let config = HandlerConfig::new()
+        .with_drain_safety_margin(Duration::from_secs(5))
+        .with_consumer_timeout(Duration::from_secs(60))
+        .with_message_ttl(Duration::from_secs(30));
+
+    // The message TTL doesn't count, as requeueing the message close to it would let it expire.
+    assert_eq!(
+        Some(received + Duration::from_secs(55)),
+        drain_deadline(&config, received)
+    );
+}
+
+#[test]
+fn drain_deadline_is_immediate_if_the_margin_exceeds_the_consumer_timeout() {
+    let received = Instant::now();
+    let config = HandlerConfig::new()
+        .with_drain_safety_margin(Duration::from_secs(5))
+        .with_consumer_timeout(Duration::from_secs(1));
+
+    assert_eq!(Some(received), drain_deadline(&config, received));
+}
+
+#[test]
+fn requests_have_no_drain_deadline_without_a_margin_or_a_consumer_timeout() {
+    let received = Instant::now();
+
+    let without_margin = HandlerConfig::new().with_consumer_timeout(Duration::from_secs(60));
+    assert_eq!(None, drain_deadline(&without_margin, received));
+
+    let without_consumer_timeout = HandlerConfig::new()
+        .with_drain_safety_margin(Duration::from_secs(5))
+        .with_message_ttl(Duration::from_secs(30));
+    assert_eq!(None, drain_deadline(&without_consumer_timeout, received));
+}
+
+#[tokio::test]
+async fn only_requests_past_their_drain_deadline_are_requeued() {
+    let now = Instant::now();
+    let mut tasks = FuturesUnordered::new();
+    for drain_deadline in [None, Some(now + Duration::from_secs(60)), Some(now)] {
+        tasks.push(RequestTask {
+            handle: tokio::spawn(std::future::pending()),
+            drain_deadline,
+            handled: Arc::default(),
+        });
+    }
+
+    requeue_expired(&mut tasks);
+
+    // Only the aborted task finishes, and the others are left running.
+    let aborted = tasks.next().await.unwrap();
+    assert!(aborted.unwrap_err().is_cancelled());
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), tasks.next())
+            .await
+            .is_err()
+    );
+    assert_eq!(2, tasks.len());
+}
+
+#[tokio::test]
+async fn requests_whose_handler_returned_are_not_requeued() {
+    let mut tasks = FuturesUnordered::new();
+    tasks.push(RequestTask {
+        handle: tokio::spawn(std::future::pending()),
+        drain_deadline: Some(Instant::now()),
+        handled: Arc::new(AtomicBool::new(true)),
+    });
+
+    requeue_expired(&mut tasks);
+
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), tasks.next())
+            .await
+            .is_err()
+    );
+    // The task is not considered for requeueing again.
+    assert!(tasks.iter().all(|task| task.drain_deadline.is_none()));
+}
+
+#[tokio::test]
+async fn drain_outcomes_are_counted_per_queue() {
+    let recorder = TestRecorder::default();
+    let _recorder = metrics::set_default_local_recorder(&recorder);
+
+    let now = Instant::now();
+    let mut tasks: FuturesUnordered<_> = [
+        RequestTask {
+            handle: tokio::spawn(async {}),
+            drain_deadline: None,
+            handled: Arc::default(),
+        },
+        RequestTask {
+            handle: tokio::spawn(async { panic!("handler failed") }),
+            drain_deadline: None,
+            handled: Arc::default(),
+        },
+        RequestTask {
+            handle: tokio::spawn(std::future::pending()),
+            drain_deadline: Some(now + Duration::from_millis(50)),
+            handled: Arc::default(),
+        },
+    ]
+    .into_iter()
+    .collect();
+
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        drain(&mut tasks, "handler", "queue"),
+    )
+    .await
+    .expect("drain did not requeue the request past its deadline");
+
+    assert!(tasks.is_empty());
+    assert_eq!(
+        1,
+        recorder.counter("kanin.shutdown_requests_finished{queue=queue}")
+    );
+    assert_eq!(
+        2,
+        recorder.counter("kanin.shutdown_requests_aborted{queue=queue}")
+    );
+}
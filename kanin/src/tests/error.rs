@@ -0,0 +1,53 @@
+use std::{io, sync::Arc};
+
+use lapin::protocol::{AMQPError, AMQPErrorKind, AMQPHardError, AMQPSoftError};
+
+use crate::Error;
+
+/// Creates an error as if the broker had closed the connection or channel with the given error.
+fn broker_error(kind: AMQPErrorKind) -> lapin::Error {
+    lapin::Error::ProtocolError(AMQPError::new(kind, "closed by broker".into()))
+}
+
+#[test]
+fn unreachable_broker_is_retryable() {
+    let error = Error::from(lapin::Error::IOError(Arc::new(io::Error::from(
+        io::ErrorKind::ConnectionRefused,
+    ))));
+    assert!(matches!(error, Error::Connection(_)));
+    assert!(error.is_retryable());
+
+    let error = Error::from(broker_error(AMQPErrorKind::Hard(
+        AMQPHardError::CONNECTIONFORCED,
+    )));
+    assert!(matches!(error, Error::Connection(_)));
+    assert!(error.is_retryable());
+}
+
+#[test]
+fn refused_operations_are_not_retryable() {
+    for kind in [
+        AMQPErrorKind::Soft(AMQPSoftError::ACCESSREFUSED),
+        AMQPErrorKind::Soft(AMQPSoftError::PRECONDITIONFAILED),
+        AMQPErrorKind::Hard(AMQPHardError::NOTALLOWED),
+    ] {
+        let error = Error::from(broker_error(kind));
+        assert!(matches!(error, Error::Protocol(_)), "{error}");
+        assert!(!error.is_retryable(), "{error}");
+    }
+}
+
+#[test]
+fn handler_setup_is_retryable_if_the_connection_failed() {
+    let setup_error = |source| Error::HandlerSetup {
+        routing_key: "routing_key".into(),
+        queue: "queue".into(),
+        source,
+    };
+
+    assert!(setup_error(lapin::Error::MissingHeartbeatError).is_retryable());
+    assert!(!setup_error(broker_error(AMQPErrorKind::Soft(
+        AMQPSoftError::PRECONDITIONFAILED
+    )))
+    .is_retryable());
+}
@@ -0,0 +1,45 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lapin::BasicProperties;
+
+use crate::extract::{expired_in_flight, Expiration};
+
+#[test]
+fn expiration_is_parsed_from_milliseconds() {
+    let properties = BasicProperties::default().with_expiration("1500".into());
+    assert_eq!(
+        Expiration(Some(Duration::from_millis(1500))),
+        Expiration::of_properties(&properties)
+    );
+
+    let properties = BasicProperties::default().with_expiration("soon".into());
+    assert_eq!(Expiration(None), Expiration::of_properties(&properties));
+    assert_eq!(
+        Expiration(None),
+        Expiration::of_properties(&BasicProperties::default())
+    );
+}
+
+#[test]
+fn messages_expire_after_timestamp_plus_expiration() {
+    let published = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let properties = BasicProperties::default()
+        .with_timestamp(1_700_000_000)
+        .with_expiration("5000".into());
+
+    // A second is added to account for the precision of the timestamp.
+    assert!(!expired_in_flight(
+        &properties,
+        published + Duration::from_millis(5500)
+    ));
+    assert!(expired_in_flight(
+        &properties,
+        published + Duration::from_secs(6)
+    ));
+
+    // Messages without a timestamp or expiration never expire.
+    let no_timestamp = BasicProperties::default().with_expiration("0".into());
+    assert!(!expired_in_flight(&no_timestamp, SystemTime::now()));
+    let no_expiration = BasicProperties::default().with_timestamp(0);
+    assert!(!expired_in_flight(&no_expiration, SystemTime::now()));
+}
@@ -0,0 +1,206 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{
+    error::{ExtractFailure, HandlerExtractErrorHook, RequestError, ServerError},
+    extract::{Msg, ReqId},
+    App, Extract, HandlerConfig, HandlerError, Request,
+};
+
+/// An extractor that depends on an external resource that is momentarily unavailable.
+struct ExternalResource;
+
+#[async_trait]
+impl<S> Extract<S> for ExternalResource
+where
+    S: Send + Sync,
+{
+    type Error = HandlerError;
+
+    async fn extract(_req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Err(ServerError::Other("resource unavailable".into()).into())
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        matches!(error, HandlerError::InternalError(_))
+    }
+}
+
+/// The number of times [`FlakyResource`] has been extracted.
+static FLAKY_ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+
+/// An extractor that is unavailable the first time it is extracted.
+struct FlakyResource;
+
+#[async_trait]
+impl<S> Extract<S> for FlakyResource
+where
+    S: Send + Sync,
+{
+    type Error = HandlerError;
+
+    async fn extract(req: &mut Request<S>) -> Result<Self, Self::Error> {
+        if FLAKY_ATTEMPTS.fetch_add(1, Ordering::SeqCst) == 0 {
+            return ExternalResource::extract(req).await.map(|_| FlakyResource);
+        }
+        Ok(FlakyResource)
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        <ExternalResource as Extract<S>>::is_transient(error)
+    }
+}
+
+/// An extractor that takes longer than the extract timeout of the handler.
+struct SlowResource;
+
+#[async_trait]
+impl<S> Extract<S> for SlowResource
+where
+    S: Send + Sync,
+{
+    type Error = HandlerError;
+
+    async fn extract(_req: &mut Request<S>) -> Result<Self, Self::Error> {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        Ok(SlowResource)
+    }
+}
+
+async fn handler_with_flaky_resource(_resource: FlakyResource) -> Reply {
+    Reply("hello".into())
+}
+
+async fn handler_with_slow_resource(_resource: SlowResource) -> Reply {
+    Reply("hello".into())
+}
+
+#[test]
+fn hook_is_called_with_routing_key_of_handler() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let hook = {
+        let calls = calls.clone();
+        HandlerExtractErrorHook::new(
+            Arc::new(
+                move |routing_key: &str,
+                      error: &dyn std::error::Error,
+                      failure: &ExtractFailure<'_>| {
+                    calls.lock().unwrap().push(format!(
+                        "{routing_key}: {} from {:?}: {error}",
+                        failure.extractor, failure.app_id
+                    ));
+                },
+            ),
+            "routing_key_0",
+        )
+    };
+
+    let req_id = ReqId::new();
+    let properties = BasicProperties::default();
+    hook.call(
+        &RequestError::DefaultMessage,
+        &ExtractFailure {
+            extractor: "Msg",
+            transient: false,
+            req_id: &req_id,
+            app_id: Some("caller"),
+            properties: &properties,
+        },
+    );
+
+    assert_eq!(
+        vec![format!(
+            "routing_key_0: Msg from Some(\"caller\"): {}",
+            RequestError::DefaultMessage
+        )],
+        *calls.lock().unwrap()
+    );
+}
+
+#[test]
+fn extract_timeout_names_the_extractor() {
+    let error = HandlerError::InternalError(ServerError::ExtractTimeout {
+        extractor: std::any::type_name::<ReqId>(),
+        timeout: Duration::from_millis(250),
+    });
+
+    assert_eq!(
+        "Internal Error: Extractor kanin::extract::req_id::ReqId timed out after 250ms",
+        error.to_string()
+    );
+}
+
+#[test]
+fn extraction_errors_are_permanent_by_default() {
+    let error = HandlerError::InternalError(ServerError::Other("unavailable".into()));
+
+    assert!(<ExternalResource as Extract<()>>::is_transient(&error));
+    assert!(!<Msg<()> as Extract<()>>::is_transient(&error));
+}
+
+#[tokio::test]
+async fn transient_extraction_errors_requeue_the_request() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler(
+        "kanin.tests.extract_error.transient",
+        handler_with_flaky_resource,
+    );
+
+    let (_properties, payload) = while_running(
+        app,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.extract_error.transient",
+            b"",
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+
+    // The first attempt is requeued without a reply, so the reply comes from the second attempt.
+    assert_eq!(b"hello".as_slice(), payload);
+    assert_eq!(2, FLAKY_ATTEMPTS.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn extractors_that_time_out_are_responded_to_with_an_error() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.extract_error.timeout",
+        handler_with_slow_resource,
+        HandlerConfig::new().with_extract_timeout(Duration::from_millis(100)),
+    );
+
+    let (_properties, payload) = while_running(
+        app,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.extract_error.timeout",
+            b"",
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+
+    assert_eq!(
+        "error: Internal Error: Extractor kanin::tests::extract_error::SlowResource timed out after 100ms",
+        String::from_utf8(payload).unwrap()
+    );
+}
@@ -0,0 +1,48 @@
+use crate::{
+    error::{HandlerError, ServerError},
+    extract::Msg,
+    test::{Flaky, FlakyBehavior, FlakyControl},
+    AppState, Extract,
+};
+
+#[derive(AppState)]
+struct FlakyState {
+    flaky: FlakyControl<Msg<String>>,
+}
+
+#[test]
+fn fails_first_given_number_of_attempts() {
+    let control = FlakyControl::<Msg<String>>::new(FlakyBehavior::FailFirst(2));
+
+    assert!(control.attempt().is_some());
+    assert!(control.attempt().is_some());
+    assert!(control.attempt().is_none());
+    assert_eq!(3, control.attempts());
+    assert_eq!(2, control.failures());
+
+    control.set_behavior(FlakyBehavior::AlwaysFail);
+    assert!(control.clone().attempt().is_some());
+    assert_eq!(3, control.failures());
+
+    control.set_behavior(FlakyBehavior::Succeed);
+    assert!(control.attempt().is_none());
+}
+
+#[test]
+fn injected_failures_are_transient_if_configured() {
+    let state = FlakyState {
+        flaky: FlakyControl::new(FlakyBehavior::AlwaysFail).with_transient(true),
+    };
+    let failure = state.flaky.attempt().unwrap();
+    assert!(failure.transient);
+
+    let error = HandlerError::InternalError(ServerError::Other(Box::new(failure)));
+    assert!(<Flaky<Msg<String>> as Extract<FlakyState>>::is_transient(
+        &error
+    ));
+
+    let other = HandlerError::InternalError(ServerError::AckerAlreadyTaken);
+    assert!(!<Flaky<Msg<String>> as Extract<FlakyState>>::is_transient(
+        &other
+    ));
+}
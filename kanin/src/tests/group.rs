@@ -0,0 +1,80 @@
+use lapin::BasicProperties;
+use prost::Message;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{error::RequestError, extract::NonDefault, App, HandlerConfig, HandlerError};
+
+async fn handler() -> Reply {
+    Reply("hello".into())
+}
+
+#[tokio::test]
+async fn group_config_applies_to_all_handlers_of_the_group() {
+    let app = App::new(())
+        .handler("kanin.tests.group.outside", handler)
+        .group(|group| {
+            group
+                .handler("kanin.tests.group.first", handler)
+                // The config applies to handlers added before it, too.
+                .with_config(HandlerConfig::new().with_exchange("kanin.tests.group"))
+                .handler("kanin.tests.group.second", handler)
+        });
+
+    let report = app.dry_run().await.unwrap();
+    let mut exchanges: Vec<_> = report
+        .topology
+        .handlers
+        .iter()
+        .map(|handler| (handler.queue.as_str(), handler.exchange.as_str()))
+        .collect();
+    exchanges.sort_unstable();
+    assert_eq!(
+        vec![
+            ("kanin.tests.group.first", "kanin.tests.group"),
+            ("kanin.tests.group.outside", HandlerConfig::DIRECT_EXCHANGE),
+            ("kanin.tests.group.second", "kanin.tests.group"),
+        ],
+        exchanges
+    );
+}
+
+#[tokio::test]
+async fn group_layers_run_before_each_handler() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).group(|group| {
+        group
+            .with_layer::<NonDefault<String>>()
+            .handler("kanin.tests.group.layered", handler)
+    });
+
+    let (named, empty) = while_running(app, &conn, async {
+        let named = request(
+            &conn,
+            "kanin.tests.group.layered",
+            &"name".to_string().encode_to_vec(),
+            BasicProperties::default(),
+        )
+        .await;
+        let empty = request(
+            &conn,
+            "kanin.tests.group.layered",
+            b"",
+            BasicProperties::default(),
+        )
+        .await;
+        (named.1, empty.1)
+    })
+    .await;
+
+    assert_eq!(b"hello".as_slice(), named);
+    assert_eq!(
+        format!(
+            "error: {}",
+            HandlerError::InvalidRequest(RequestError::DefaultMessage)
+        ),
+        String::from_utf8(empty).unwrap()
+    );
+}
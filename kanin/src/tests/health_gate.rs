@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use lapin::BasicProperties;
+use tokio::sync::watch;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::App;
+
+async fn handler() -> Reply {
+    Reply("hello".into())
+}
+
+#[tokio::test]
+async fn requests_are_not_handled_while_unhealthy() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let (health, gate) = watch::channel(false);
+    let app = App::new(())
+        .handler("kanin.tests.health_gate", handler)
+        .with_health_gate(gate);
+
+    let payload = while_running(app, &conn, async {
+        let reply = request(
+            &conn,
+            "kanin.tests.health_gate",
+            b"",
+            BasicProperties::default(),
+        );
+        tokio::pin!(reply);
+
+        // The app starts paused, so the request waits in the queue.
+        let paused = tokio::time::timeout(Duration::from_secs(1), &mut reply).await;
+        assert!(paused.is_err(), "request was handled while unhealthy");
+
+        health.send(true).unwrap();
+        reply.await.1
+    })
+    .await;
+
+    assert_eq!(b"hello".as_slice(), payload);
+}
@@ -0,0 +1,23 @@
+use lapin::{types::AMQPValue, ConnectionProperties};
+
+use crate::{connection_identity, identity::ConnectionIdentity};
+
+#[test]
+fn identity_sets_connection_name_and_client_properties() {
+    let identity = connection_identity!().with_host("my-host");
+    assert_eq!("kanin@my-host", identity.connection_name());
+
+    let properties = identity.apply(ConnectionProperties::default());
+    let client_properties = properties.client_properties.inner();
+    assert_eq!(
+        Some(&AMQPValue::LongString("kanin@my-host".into())),
+        client_properties.get("connection_name")
+    );
+    assert_eq!(
+        Some(&AMQPValue::LongString(env!("CARGO_PKG_VERSION").into())),
+        client_properties.get("service_version")
+    );
+
+    let identity = ConnectionIdentity::new("my-service").with_connection_name("my-connection");
+    assert_eq!("my-connection", identity.connection_name());
+}
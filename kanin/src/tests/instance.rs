@@ -0,0 +1,33 @@
+use lapin::types::AMQPValue;
+
+use crate::{instance::Instance, HandlerConfig};
+
+#[test]
+fn instance_index_is_read_from_variable_or_hostname() {
+    assert_eq!(
+        Some(3),
+        Instance::from_env(Some("3"), Some("my-service-1")).index()
+    );
+    assert_eq!(
+        Some(1),
+        Instance::from_env(None, Some("my-service-1")).index()
+    );
+    assert_eq!(None, Instance::from_env(None, Some("my-service")).index());
+    assert_eq!(None, Instance::from_env(None, None).index());
+}
+
+#[test]
+fn preferred_instance_gets_a_higher_consumer_priority() {
+    let config = HandlerConfig::new().with_preferred_instance(2);
+
+    let preferred = config.consumer_arguments(Instance::new(2));
+    assert_eq!(
+        Some(&AMQPValue::LongInt(
+            HandlerConfig::PREFERRED_CONSUMER_PRIORITY
+        )),
+        preferred.inner().get("x-priority")
+    );
+
+    let other = config.consumer_arguments(Instance::new(1));
+    assert_eq!(None, other.inner().get("x-priority"));
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use lapin::BasicProperties;
+use tokio::sync::mpsc;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::App;
+
+async fn handler() -> Reply {
+    Reply("hello".into())
+}
+
+#[tokio::test]
+async fn instrumentation_wraps_the_handling_of_each_request() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let (handled, mut handled_requests) = mpsc::unbounded_channel();
+    let app = App::new(())
+        .handler("kanin.tests.instrumentation", handler)
+        .with_instrumentation(move |context, request| {
+            let routing_key = context.routing_key.clone();
+            let handled = handled.clone();
+            Box::pin(async move {
+                request.await;
+                handled.send(routing_key).unwrap();
+            })
+        });
+
+    let (routing_key, payload) = while_running(app, &conn, async {
+        let (_properties, payload) = request(
+            &conn,
+            "kanin.tests.instrumentation",
+            b"",
+            BasicProperties::default(),
+        )
+        .await;
+        let routing_key = tokio::time::timeout(Duration::from_secs(10), handled_requests.recv())
+            .await
+            .expect("request was not instrumented within 10 seconds");
+        (routing_key, payload)
+    })
+    .await;
+
+    assert_eq!(b"hello".as_slice(), payload);
+    assert_eq!(Some("kanin.tests.instrumentation".to_string()), routing_key);
+}
@@ -0,0 +1,23 @@
+use serde::Serialize;
+
+use crate::{extract::Json, response::WithMeta, Respond};
+
+#[derive(Debug, Serialize)]
+struct Greeting {
+    message: String,
+}
+
+#[test]
+fn json_responses_are_serialized_with_json_content_type() {
+    let response = WithMeta::new(Json(Greeting {
+        message: "hello".into(),
+    }));
+
+    assert_eq!("application/json", response.content_type());
+    assert_eq!(br#"{"message":"hello"}"#.to_vec(), response.respond());
+}
+
+#[test]
+fn protobuf_responses_have_octet_stream_content_type() {
+    assert_eq!("application/octet-stream", ().content_type());
+}
@@ -0,0 +1,127 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use lapin::BasicProperties;
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+use super::{amqp_connect, request, test_broker, while_running, Reply};
+use crate::{App, HandlerConfig};
+
+async fn noisy_handler() -> Reply {
+    Reply("hello".into())
+}
+
+async fn default_handler() -> Reply {
+    Reply("hello".into())
+}
+
+/// A logged event: its level, its message and the log target recorded on its span, if any.
+type Logged = (Level, String, Option<String>);
+
+/// The log target recorded on a span.
+struct LogTarget(String);
+
+/// Reads the message and log target fields of events and spans.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    log_target: Option<String>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "log_target" {
+            self.log_target = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        }
+    }
+}
+
+/// A layer that captures all events, along with the log target of the span they were logged in.
+struct Capture(Arc<Mutex<Vec<Logged>>>);
+
+impl<S> Layer<S> for Capture
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let (Some(log_target), Some(span)) = (fields.log_target, ctx.span(id)) {
+            span.extensions_mut().insert(LogTarget(log_target));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let log_target = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .from_root()
+                .find_map(|span| span.extensions().get::<LogTarget>().map(|t| t.0.clone()))
+        });
+        self.0
+            .lock()
+            .unwrap()
+            .push((*event.metadata().level(), fields.message, log_target));
+    }
+}
+
+#[tokio::test]
+async fn requests_are_logged_at_the_level_and_target_of_their_handler() {
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    // The test runs on a single thread, so this captures the logs of the handlers too.
+    let _subscriber = tracing::subscriber::set_default(
+        tracing_subscriber::registry().with(Capture(logged.clone())),
+    );
+
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(())
+        .handler_with_config(
+            "kanin.tests.log_level.noisy",
+            noisy_handler,
+            HandlerConfig::new()
+                .with_log_level(Level::DEBUG)
+                .with_log_target("noisy"),
+        )
+        .handler("kanin.tests.log_level.default", default_handler);
+
+    while_running(app, &conn, async {
+        for routing_key in [
+            "kanin.tests.log_level.noisy",
+            "kanin.tests.log_level.default",
+        ] {
+            request(&conn, routing_key, b"", BasicProperties::default()).await;
+        }
+    })
+    .await;
+
+    let received = |handler: &str| {
+        logged
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, message, _)| {
+                message.starts_with("Received request on handler") && message.contains(handler)
+            })
+            .map(|(level, _, log_target)| (*level, log_target.clone()))
+    };
+    assert_eq!(
+        Some((Level::DEBUG, Some("noisy".to_string()))),
+        received("noisy_handler")
+    );
+    assert_eq!(Some((Level::INFO, None)), received("default_handler"));
+}
@@ -0,0 +1,50 @@
+use std::{
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
+
+use crate::app::task::{memory_released, within_memory_budget, InFlightBytesGuard, MemoryBudget};
+
+#[test]
+fn requests_of_all_handlers_count_towards_the_memory_budget() {
+    let memory_budget = Arc::new(MemoryBudget::new(100));
+    let mut memory = Some(memory_budget.watch());
+    let (first_handler, second_handler) =
+        (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+
+    let first = InFlightBytesGuard::new(60, "first", &first_handler, Some(&memory_budget));
+    assert!(within_memory_budget(&mut memory));
+
+    let _second = InFlightBytesGuard::new(40, "second", &second_handler, Some(&memory_budget));
+    assert!(!within_memory_budget(&mut memory));
+
+    drop(first);
+    assert!(within_memory_budget(&mut memory));
+}
+
+#[test]
+fn requests_are_always_within_no_memory_budget() {
+    assert!(within_memory_budget(&mut None));
+}
+
+#[tokio::test]
+async fn memory_is_released_when_a_request_finishes() {
+    let memory_budget = Arc::new(MemoryBudget::new(100));
+    let mut memory = Some(memory_budget.watch());
+    let in_flight_bytes = Arc::new(AtomicUsize::new(0));
+
+    let guard = InFlightBytesGuard::new(100, "routing_key", &in_flight_bytes, Some(&memory_budget));
+    assert!(!within_memory_budget(&mut memory));
+    // Nothing has changed since the payloads in flight were last seen.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), memory_released(&mut memory))
+            .await
+            .is_err()
+    );
+
+    drop(guard);
+    tokio::time::timeout(Duration::from_secs(1), memory_released(&mut memory))
+        .await
+        .unwrap();
+    assert!(within_memory_budget(&mut memory));
+}
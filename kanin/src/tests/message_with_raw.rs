@@ -0,0 +1,33 @@
+use lapin::BasicProperties;
+use prost::Message;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{extract::MsgWithRaw, App};
+
+async fn handler(MsgWithRaw { msg, raw }: MsgWithRaw<String>) -> Reply {
+    Reply(format!("{msg} ({} bytes)", raw.len()))
+}
+
+#[tokio::test]
+async fn it_extracts_the_message_with_its_raw_payload() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler("kanin.tests.message_with_raw", handler);
+    let payload = "hello".to_string().encode_to_vec();
+
+    let (_properties, reply) = while_running(
+        app,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.message_with_raw",
+            &payload,
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+
+    assert_eq!(format!("hello ({} bytes)", payload.len()).as_bytes(), reply);
+}
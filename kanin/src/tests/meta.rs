@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use lapin::types::{AMQPValue, FieldTable};
+
+use crate::{
+    extract::Meta,
+    response::{Expiring, WithMeta},
+    Respond,
+};
+
+#[test]
+fn meta_round_trips_through_reply_headers() {
+    let response = WithMeta::new(Expiring::new((), Duration::from_secs(5)))
+        .with("server_version", "1.2.3")
+        .with("cache", "hit");
+    assert_eq!(Some(Duration::from_secs(5)), response.reply_ttl());
+
+    let mut headers = response.reply_headers();
+    assert_eq!(
+        Some(&AMQPValue::LongString("hit".into())),
+        headers.inner().get("x-meta-cache")
+    );
+
+    // Other headers are not metadata.
+    headers.insert("req_id".into(), AMQPValue::LongString("abc".into()));
+    let Meta(meta) = Meta::from_headers(&headers);
+    assert_eq!(2, meta.len());
+    assert_eq!(
+        Some("1.2.3"),
+        meta.get("server_version").map(String::as_str)
+    );
+    assert_eq!(Some("hit"), meta.get("cache").map(String::as_str));
+}
+
+#[test]
+fn responses_have_no_headers_by_default() {
+    assert!(().reply_headers().inner().is_empty());
+    assert_eq!(Meta::default(), Meta::from_headers(&FieldTable::default()));
+}
@@ -0,0 +1,60 @@
+use async_trait::async_trait;
+
+use crate::{
+    error::ServerError,
+    middleware::{EncodedResponse, ErasedMiddleware},
+    App, HandlerConfig, HandlerError, Middleware, Request,
+};
+
+/// Fails every request with an internal error.
+struct Deny;
+
+#[async_trait]
+impl Middleware<u32> for Deny {
+    async fn before(&self, _req: &mut Request<u32>) -> Result<(), HandlerError> {
+        Err(ServerError::Other("denied".into()).into())
+    }
+}
+
+/// Adds a header to every response, for any state.
+struct Tag;
+
+#[async_trait]
+impl<S> Middleware<S> for Tag
+where
+    S: Send + Sync + 'static,
+{
+    async fn after(&self, _req: &mut Request<S>, response: &mut EncodedResponse) {
+        response
+            .headers
+            .insert("x-tag".into(), lapin::types::AMQPValue::Boolean(true));
+    }
+}
+
+#[test]
+fn middleware_is_only_given_back_for_its_own_state() {
+    let middleware = ErasedMiddleware::new::<u32, _>(Deny);
+
+    assert!(middleware.downcast::<u32>().is_some());
+    assert!(middleware.downcast::<u64>().is_none());
+    assert_eq!("u32", middleware.state());
+}
+
+#[test]
+fn middleware_for_the_app_state_can_be_registered() {
+    let _app = App::new(0u32).layer(Tag).handler_with_config(
+        "routing_key_0",
+        || async {},
+        HandlerConfig::new().layer(Deny).layer::<u32, _>(Tag),
+    );
+}
+
+#[test]
+#[should_panic(expected = "for state type u64, but the app has state type u32")]
+fn middleware_for_another_state_panics_on_registration() {
+    let _app = App::new(0u32).handler_with_config(
+        "routing_key_0",
+        || async {},
+        HandlerConfig::new().layer::<u64, _>(Tag),
+    );
+}
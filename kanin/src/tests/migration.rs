@@ -0,0 +1,73 @@
+use lapin::{
+    options::{BasicPublishOptions, QueueDeclareOptions, QueueDeleteOptions},
+    types::FieldTable,
+    BasicProperties, Channel,
+};
+
+use super::{amqp_connect, init_logging, test_broker};
+use crate::{migration::QueueMigration, ops, Error};
+
+/// Declares a fresh, non-durable queue with the given name.
+async fn declare(channel: &Channel, queue: &str) {
+    let _ = channel
+        .queue_delete(queue, QueueDeleteOptions::default())
+        .await;
+    channel
+        .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+        .await
+        .expect("failed to declare queue");
+}
+
+#[tokio::test]
+async fn it_moves_messages_and_keeps_unroutable_ones() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+    let channel = conn
+        .create_channel()
+        .await
+        .expect("failed to create channel");
+
+    declare(&channel, "migration_from").await;
+    declare(&channel, "migration_to").await;
+    for payload in [b"a", b"b"] {
+        channel
+            .basic_publish(
+                "",
+                "migration_from",
+                BasicPublishOptions::default(),
+                payload,
+                BasicProperties::default(),
+            )
+            .await
+            .expect("failed to publish")
+            .await
+            .expect("failed to publish");
+    }
+
+    // The new queue does not exist, so no messages may be acked on the old queue.
+    let result = QueueMigration::new("migration_from", "migration_missing")
+        .run(&conn)
+        .await;
+    assert!(
+        matches!(result, Err(Error::PublishReturned { .. })),
+        "{result:?}"
+    );
+    // Wait for the rejected message to be requeued.
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(
+        2,
+        ops::message_count(&conn, "migration_from").await.unwrap()
+    );
+
+    let report = QueueMigration::new("migration_from", "migration_to")
+        .run(&conn)
+        .await
+        .expect("migration failed");
+    assert_eq!(2, report.migrated);
+    assert_eq!(
+        0,
+        ops::message_count(&conn, "migration_from").await.unwrap()
+    );
+    assert_eq!(2, ops::message_count(&conn, "migration_to").await.unwrap());
+}
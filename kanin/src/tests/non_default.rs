@@ -0,0 +1,47 @@
+use lapin::BasicProperties;
+use prost::Message;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{error::RequestError, extract::NonDefault, App, HandlerError};
+
+async fn handler(NonDefault(name): NonDefault<String>) -> Reply {
+    Reply(format!("hello {name}"))
+}
+
+#[tokio::test]
+async fn messages_with_only_default_fields_are_rejected() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler("kanin.tests.non_default", handler);
+
+    let (named, empty) = while_running(app, &conn, async {
+        let named = request(
+            &conn,
+            "kanin.tests.non_default",
+            &"world".to_string().encode_to_vec(),
+            BasicProperties::default(),
+        )
+        .await;
+        // An empty string is the default, so it is encoded as an empty payload.
+        let empty = request(
+            &conn,
+            "kanin.tests.non_default",
+            &String::new().encode_to_vec(),
+            BasicProperties::default(),
+        )
+        .await;
+        (named.1, empty.1)
+    })
+    .await;
+
+    assert_eq!(b"hello world".as_slice(), named);
+    assert_eq!(
+        format!(
+            "error: {}",
+            HandlerError::InvalidRequest(RequestError::DefaultMessage)
+        ),
+        String::from_utf8(empty).unwrap()
+    );
+}
@@ -0,0 +1,8 @@
+use crate::ops::Purge;
+
+#[test]
+fn purge_is_only_confirmed_with_the_queue_name() {
+    assert!(!Purge::new("my_queue").is_confirmed());
+    assert!(!Purge::new("my_queue").confirm("my_queue2").is_confirmed());
+    assert!(Purge::new("my_queue").confirm("my_queue").is_confirmed());
+}
@@ -0,0 +1,130 @@
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running};
+use crate::{
+    app::panic::{capture, catch, install_hook, panic_message},
+    error::{FromError, ServerError},
+    extract::Provider,
+    request::Scope,
+    App, Handler, HandlerConfig, HandlerError, Respond,
+};
+
+/// A response that replies with the error it was created from.
+#[derive(Debug, PartialEq)]
+struct ErrorMessage(String);
+
+impl Respond for ErrorMessage {
+    fn respond(self) -> Vec<u8> {
+        self.0.into_bytes()
+    }
+}
+
+impl FromError<HandlerError> for ErrorMessage {
+    fn from_error(error: HandlerError) -> Self {
+        Self(error.to_string())
+    }
+}
+
+async fn handler() -> ErrorMessage {
+    panic!("handler failed on request {}", 187);
+}
+
+/// Returns the response of the given handler to the given error.
+fn error_response<H, Args, Res>(_handler: H, error: HandlerError) -> Option<Res>
+where
+    H: Handler<Args, Res, ()>,
+    Res: Respond,
+{
+    H::error_response(error)
+}
+
+#[tokio::test]
+async fn panics_in_request_tasks_are_captured_and_resumed() {
+    install_hook();
+
+    let ok = tokio::spawn(capture("handler", async { 187 })).await;
+    assert_eq!(187, ok.unwrap());
+
+    let panicked = tokio::spawn(capture("handler", async {
+        panic!("handler failed on request {}", 187);
+    }))
+    .await;
+    let payload = panicked.unwrap_err().into_panic();
+    assert_eq!(
+        "handler failed on request 187",
+        panic_message(payload.as_ref())
+    );
+}
+
+#[test]
+fn panic_messages_are_read_from_string_payloads() {
+    assert_eq!("static", panic_message(&"static"));
+    assert_eq!("owned", panic_message(&String::from("owned")));
+    assert_eq!("<non-string panic payload>", panic_message(&187));
+}
+
+#[tokio::test]
+async fn caught_panics_are_turned_into_error_responses() {
+    install_hook();
+
+    let payload = catch("handler", handler()).await.unwrap_err();
+    let error = HandlerError::InternalError(ServerError::HandlerPanicked(
+        panic_message(payload.as_ref()).to_string(),
+    ));
+
+    assert_eq!(
+        Some(ErrorMessage(
+            "Internal Error: The handler panicked: handler failed on request 187".into()
+        )),
+        error_response(handler, error)
+    );
+}
+
+/// App state whose conversion into [`Connection`] panics, as if a lock inside it was poisoned.
+struct PoisonedState;
+
+struct Connection;
+
+impl From<&PoisonedState> for Connection {
+    fn from(_state: &PoisonedState) -> Self {
+        panic!("lock poisoned");
+    }
+}
+
+#[tokio::test]
+async fn state_conversion_panics_are_turned_into_internal_errors() {
+    let result = Provider::<Connection>::provide(&PoisonedState, &mut Scope::default()).await;
+
+    match result {
+        Err(HandlerError::InternalError(ServerError::StatePanicked { state, message })) => {
+            assert_eq!("kanin::tests::panic::Connection", state);
+            assert_eq!("lock poisoned", message);
+        }
+        _ => panic!("expected the panic to be turned into an internal error"),
+    }
+}
+
+#[tokio::test]
+async fn panics_are_replied_to_with_panic_replies() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.panic",
+        handler,
+        HandlerConfig::new().with_panic_replies(true),
+    );
+
+    let (_properties, payload) = while_running(
+        app,
+        &conn,
+        request(&conn, "kanin.tests.panic", b"", BasicProperties::default()),
+    )
+    .await;
+
+    assert_eq!(
+        "Internal Error: The handler panicked: handler failed on request 187",
+        String::from_utf8(payload).unwrap()
+    );
+}
@@ -0,0 +1,65 @@
+use std::thread::{self, ThreadId};
+
+use prost::{
+    bytes::{Buf, BufMut},
+    encoding::{self, DecodeContext, WireType},
+    DecodeError, Message,
+};
+
+use crate::{extract::parallel_message::decode_parallel, HandlerError};
+
+/// A message that remembers the thread it was decoded on, ignoring its fields.
+#[derive(Debug, Default)]
+struct DecodedOn(Option<ThreadId>);
+
+impl Message for DecodedOn {
+    fn encode_raw<B: BufMut>(&self, _buf: &mut B) {}
+
+    fn merge_field<B: Buf>(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        self.0 = Some(thread::current().id());
+        encoding::skip_field(wire_type, tag, buf, ctx)
+    }
+
+    fn encoded_len(&self) -> usize {
+        0
+    }
+
+    fn clear(&mut self) {
+        self.0 = None;
+    }
+}
+
+#[tokio::test]
+async fn payloads_over_the_threshold_are_decoded_on_a_blocking_thread() {
+    let payload = "a large payload".to_string().encode_to_vec();
+
+    let small = decode_parallel::<DecodedOn>(&payload, payload.len())
+        .await
+        .unwrap();
+    assert_eq!(Some(thread::current().id()), small.0);
+
+    let large = decode_parallel::<DecodedOn>(&payload, payload.len() - 1)
+        .await
+        .unwrap();
+    assert!(large.0.is_some());
+    assert_ne!(Some(thread::current().id()), large.0);
+}
+
+#[tokio::test]
+async fn decode_errors_are_invalid_requests_on_both_paths() {
+    let invalid = [0xff];
+
+    for threshold in [1, 0] {
+        let result = decode_parallel::<String>(&invalid, threshold).await;
+        assert!(
+            matches!(result, Err(HandlerError::InvalidRequest(_))),
+            "{result:?}"
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use lapin::{
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+use prost::Message;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{extract::Parts, App};
+
+async fn handler(Parts(msg, headers, properties, _req_id): Parts<String>) -> Reply {
+    let app_id = properties.app_id().as_ref().map(|id| id.to_string());
+    let tenant = match headers.inner().get("tenant") {
+        Some(AMQPValue::LongString(tenant)) => tenant.to_string(),
+        _ => String::new(),
+    };
+    Reply(format!(
+        "{msg} from {} for {tenant}",
+        app_id.unwrap_or_default()
+    ))
+}
+
+#[tokio::test]
+async fn it_extracts_the_message_headers_and_properties_at_once() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler("kanin.tests.parts", handler);
+    let mut headers = FieldTable::default();
+    headers.insert("tenant".into(), AMQPValue::LongString("acme".into()));
+
+    let (_properties, reply) = while_running(
+        app,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.parts",
+            &"hello".to_string().encode_to_vec(),
+            BasicProperties::default()
+                .with_app_id("caller".into())
+                .with_headers(headers),
+        ),
+    )
+    .await;
+
+    assert_eq!(
+        "hello from caller for acme",
+        String::from_utf8(reply).unwrap()
+    );
+}
@@ -0,0 +1,34 @@
+use crate::{app::task::PayloadSizes, tests::TestRecorder};
+
+#[test]
+fn payload_sizes_are_recorded_and_large_payloads_flagged() {
+    let recorder = TestRecorder::default();
+    let sizes = PayloadSizes {
+        routing_key: "routing_key".into(),
+        large_message_threshold: Some(100),
+    };
+
+    metrics::with_local_recorder(&recorder, || {
+        sizes.record_request(10);
+        sizes.record_request(1000);
+        sizes.record_response(100);
+    });
+
+    assert_eq!(
+        vec![10.0, 1000.0],
+        recorder.histogram("kanin.request_size_bytes{routing_key=routing_key}")
+    );
+    assert_eq!(
+        vec![100.0],
+        recorder.histogram("kanin.response_size_bytes{routing_key=routing_key}")
+    );
+    // Payloads are only flagged if they exceed the threshold.
+    assert_eq!(
+        1,
+        recorder.counter("kanin.large_messages{routing_key=routing_key,direction=request}")
+    );
+    assert_eq!(
+        0,
+        recorder.counter("kanin.large_messages{routing_key=routing_key,direction=response}")
+    );
+}
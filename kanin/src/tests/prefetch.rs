@@ -0,0 +1,69 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use futures::future::join_all;
+use lapin::{BasicProperties, Connection};
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{extract::AppId, App, HandlerConfig};
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+static MAX_IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Tracks the maximum number of requests it handles concurrently.
+async fn tracked_handler(_app_id: AppId) -> Reply {
+    let in_flight = IN_FLIGHT.fetch_add(1, Ordering::SeqCst) + 1;
+    MAX_IN_FLIGHT.fetch_max(in_flight, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    Reply("done".into())
+}
+
+/// Sends 4 requests at once and returns the maximum number of them that were handled concurrently.
+async fn max_in_flight_of_burst(conn: &Connection) -> usize {
+    MAX_IN_FLIGHT.store(0, Ordering::SeqCst);
+    join_all((0..4).map(|_| {
+        request(
+            conn,
+            "kanin.tests.prefetch",
+            b"",
+            BasicProperties::default(),
+        )
+    }))
+    .await;
+    MAX_IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn raising_the_prefetch_lets_more_deliveries_be_in_flight() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.prefetch",
+        tracked_handler,
+        HandlerConfig::new().with_prefetch(1),
+    );
+    let handle = app.handle();
+
+    let (before, after) = while_running(app, &conn, async {
+        let before = max_in_flight_of_burst(&conn).await;
+
+        handle
+            .set_prefetch("kanin.tests.prefetch", 4)
+            .await
+            .expect("failed to set prefetch");
+        // The handler consumes again with the new prefetch in the background.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let after = max_in_flight_of_burst(&conn).await;
+        (before, after)
+    })
+    .await;
+
+    assert_eq!(1, before);
+    assert!(after > 1, "{after}");
+}
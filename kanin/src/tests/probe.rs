@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use lapin::BasicProperties;
+
+use crate::{probe, tests::init_logging, App};
+
+use super::{amqp_connect, request, test_broker, while_running};
+
+#[tokio::test]
+async fn ping_handler_echoes_the_payload() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).with_ping("kanin.tests.probe.ping");
+
+    let (latency, (_properties, payload)) = while_running(app, &conn, async {
+        let latency = probe::ping(&conn, "kanin.tests.probe.ping")
+            .await
+            .expect("ping failed");
+        let reply = request(
+            &conn,
+            "kanin.tests.probe.ping",
+            b"nonce",
+            BasicProperties::default(),
+        )
+        .await;
+        (latency, reply)
+    })
+    .await;
+
+    assert!(latency < Duration::from_secs(10));
+    assert_eq!(b"nonce".as_slice(), payload);
+}
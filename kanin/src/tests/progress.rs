@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use lapin::{
+    options::{BasicGetOptions, BasicPublishOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use super::{amqp_connect, init_logging, test_broker, while_running};
+use crate::{error::FromError, extract::Progress, App, HandlerError, Respond};
+
+/// A text reply.
+#[derive(Debug)]
+struct Reply(&'static str);
+
+impl Respond for Reply {
+    fn respond(self) -> Vec<u8> {
+        self.0.into()
+    }
+
+    fn content_type(&self) -> &str {
+        "text/plain"
+    }
+}
+
+impl FromError<HandlerError> for Reply {
+    fn from_error(_error: HandlerError) -> Self {
+        Reply("error")
+    }
+}
+
+async fn handler(progress: Progress) -> Reply {
+    progress.publish(Reply("halfway")).await.unwrap();
+    progress.publish(Reply("almost")).await.unwrap();
+    Reply("done")
+}
+
+#[tokio::test]
+async fn progress_messages_are_published_before_the_reply() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler("kanin.tests.progress", handler);
+
+    let messages = while_running(app, &conn, async {
+        let channel = conn
+            .create_channel()
+            .await
+            .expect("failed to create channel");
+        let reply_queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .expect("failed to declare reply queue");
+
+        channel
+            .basic_publish(
+                "",
+                "kanin.tests.progress",
+                BasicPublishOptions::default(),
+                &[],
+                BasicProperties::default()
+                    .with_reply_to(reply_queue.name().clone())
+                    .with_correlation_id("abc".into()),
+            )
+            .await
+            .expect("failed to publish request");
+
+        // Collect the progress messages and the final reply.
+        let mut messages = Vec::new();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while messages.len() < 3 {
+                let message = channel
+                    .basic_get(
+                        reply_queue.name().as_str(),
+                        BasicGetOptions { no_ack: true },
+                    )
+                    .await
+                    .expect("failed to get reply");
+                match message {
+                    Some(message) => messages.push(message.delivery),
+                    None => tokio::time::sleep(Duration::from_millis(50)).await,
+                }
+            }
+        })
+        .await
+        .expect("no reply within 10 seconds");
+        messages
+    })
+    .await;
+
+    let is_progress = |properties: &BasicProperties| {
+        properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(Progress::HEADER).cloned())
+            == Some(AMQPValue::Boolean(true))
+    };
+    let payloads: Vec<_> = messages
+        .iter()
+        .map(|message| (message.data.as_slice(), is_progress(&message.properties)))
+        .collect();
+    assert_eq!(
+        vec![
+            (b"halfway".as_slice(), true),
+            (b"almost".as_slice(), true),
+            (b"done".as_slice(), false)
+        ],
+        payloads
+    );
+
+    for message in &messages {
+        assert_eq!(Some("abc".into()), *message.properties.correlation_id());
+        assert_eq!(
+            Some("text/plain".into()),
+            *message.properties.content_type()
+        );
+    }
+}
@@ -0,0 +1,32 @@
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{extract::Properties, App};
+
+async fn handler(Properties(properties): Properties) -> Reply {
+    let message_id = properties.message_id().as_ref().map(|id| id.to_string());
+    Reply(message_id.unwrap_or_default())
+}
+
+#[tokio::test]
+async fn it_extracts_the_properties_of_the_request() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler("kanin.tests.properties", handler);
+
+    let (_properties, payload) = while_running(
+        app,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.properties",
+            b"",
+            BasicProperties::default().with_message_id("my_message_id".into()),
+        ),
+    )
+    .await;
+
+    assert_eq!(b"my_message_id".as_slice(), payload);
+}
@@ -0,0 +1,72 @@
+use lapin::{BasicProperties, Channel};
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{
+    extract::{ChannelPool, PublisherChannel},
+    App,
+};
+
+async fn handler(consumer: Channel, PublisherChannel(publisher): PublisherChannel) -> Reply {
+    if publisher.id() == consumer.id() {
+        Reply("consumer channel".into())
+    } else {
+        Reply("publisher channel".into())
+    }
+}
+
+#[tokio::test]
+async fn channel_pool_gives_out_open_channels_round_robin() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let pool = ChannelPool::open(&conn, 2).await.unwrap();
+    let first = pool.get().unwrap();
+    let second = pool.get().unwrap();
+    assert_ne!(first.id(), second.id());
+    assert_eq!(first.id(), pool.get().unwrap().id());
+
+    // Closed channels are skipped.
+    first.close(200, "closed by test").await.unwrap();
+    assert_eq!(second.id(), pool.get().unwrap().id());
+    assert_eq!(second.id(), pool.get().unwrap().id());
+}
+
+#[tokio::test]
+async fn handlers_publish_on_the_publisher_channels_of_the_app() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let pooled = App::new(())
+        .handler("kanin.tests.publisher_channel.pooled", handler)
+        .with_publisher_channels(2);
+    let (_properties, pooled) = while_running(
+        pooled,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.publisher_channel.pooled",
+            b"",
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+
+    // Without a pool, the consumer channel is used instead.
+    let unpooled = App::new(()).handler("kanin.tests.publisher_channel.unpooled", handler);
+    let (_properties, unpooled) = while_running(
+        unpooled,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.publisher_channel.unpooled",
+            b"",
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+
+    assert_eq!(b"publisher channel".as_slice(), pooled);
+    assert_eq!(b"consumer channel".as_slice(), unpooled);
+}
@@ -0,0 +1,148 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use lapin::{
+    options::{BasicGetOptions, BasicPublishOptions, QueueDeclareOptions},
+    types::FieldTable,
+    BasicProperties,
+};
+
+use super::{amqp_connect, init_logging, test_broker};
+use crate::{
+    error::{redact, FromError, ServerError, ERROR_REDACTION},
+    App, Extract, HandlerError, Request, Respond,
+};
+
+fn server_error() -> ServerError {
+    ServerError::Other("connection to postgres://user:password@db failed".into())
+}
+
+#[tokio::test]
+async fn it_formats_errors_in_full_by_default() {
+    assert_eq!(
+        "connection to postgres://user:password@db failed",
+        redact(&server_error())
+    );
+
+    let formatted = ERROR_REDACTION
+        .scope(None, async { redact(&server_error()) })
+        .await;
+    assert_eq!(
+        "connection to postgres://user:password@db failed",
+        formatted
+    );
+}
+
+#[tokio::test]
+async fn it_uses_the_error_redaction_when_set() {
+    let formatted = ERROR_REDACTION
+        .scope(
+            Some(Arc::new(|_: &_| "internal details redacted".to_string())),
+            async { redact(&server_error()) },
+        )
+        .await;
+
+    assert_eq!("internal details redacted", formatted);
+}
+
+/// A reply carrying the formatted error, if any.
+#[derive(Debug)]
+struct Reply(String);
+
+impl Respond for Reply {
+    fn respond(self) -> Vec<u8> {
+        self.0.into()
+    }
+}
+
+impl FromError<HandlerError> for Reply {
+    fn from_error(error: HandlerError) -> Self {
+        // This is what the FromError derive macro does for InvalidRequest and InternalError messages.
+        match error {
+            HandlerError::InvalidRequest(e) => Reply(redact(&e)),
+            HandlerError::InternalError(e) => Reply(redact(&e)),
+        }
+    }
+}
+
+/// An extractor that always fails with an error containing secrets.
+struct Database;
+
+#[async_trait]
+impl<S> Extract<S> for Database
+where
+    S: Send + Sync,
+{
+    type Error = HandlerError;
+
+    async fn extract(_req: &mut Request<S>) -> Result<Self, Self::Error> {
+        Err(HandlerError::InternalError(server_error()))
+    }
+}
+
+async fn database_handler(_database: Database) -> Reply {
+    Reply("unreachable".to_string())
+}
+
+#[tokio::test]
+async fn it_redacts_errors_in_replies() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+    let channel = conn
+        .create_channel()
+        .await
+        .expect("failed to create channel");
+    let reply_queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .expect("failed to declare reply queue");
+
+    let app = App::new(())
+        .handler("redacted_database", database_handler)
+        .with_error_redaction(|_| "internal details redacted".to_string());
+    let shutdown = app.shutdown_channel();
+    let app_conn = amqp_connect(&amqp_addr).await;
+    let app = app.run_with_connection(&app_conn);
+
+    let request = async {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        channel
+            .basic_publish(
+                "",
+                "redacted_database",
+                BasicPublishOptions::default(),
+                b"",
+                BasicProperties::default().with_reply_to(reply_queue.name().clone()),
+            )
+            .await
+            .expect("failed to publish");
+
+        let reply = loop {
+            let message = channel
+                .basic_get(
+                    reply_queue.name().as_str(),
+                    BasicGetOptions { no_ack: true },
+                )
+                .await
+                .expect("failed to get reply");
+            match message {
+                Some(message) => break message.delivery.data,
+                None => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        };
+        shutdown.send(()).unwrap();
+        reply
+    };
+
+    let (result, reply) = tokio::join!(app, request);
+    assert!(result.is_ok(), "{result:?}");
+    assert_eq!(b"internal details redacted".to_vec(), reply);
+}
@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant};
+
+use lapin::BasicProperties;
+
+use crate::redelivery::{message_key, RedeliveryStorm, RedeliveryTracker};
+
+#[test]
+fn redeliveries_above_threshold_within_window_are_a_storm() {
+    let mut tracker = RedeliveryTracker::new(RedeliveryStorm::new(2, Duration::from_secs(10)));
+    let start = Instant::now();
+
+    assert_eq!(None, tracker.record("a", start));
+    assert_eq!(None, tracker.record("a", start + Duration::from_secs(1)));
+    // Other messages are counted separately.
+    assert_eq!(None, tracker.record("b", start + Duration::from_secs(1)));
+    assert_eq!(Some(3), tracker.record("a", start + Duration::from_secs(2)));
+    assert_eq!(Some(4), tracker.record("a", start + Duration::from_secs(3)));
+}
+
+#[test]
+fn redeliveries_outside_window_are_forgotten() {
+    let mut tracker = RedeliveryTracker::new(RedeliveryStorm::new(2, Duration::from_secs(10)));
+    let start = Instant::now();
+
+    assert_eq!(None, tracker.record("a", start));
+    assert_eq!(None, tracker.record("a", start + Duration::from_secs(5)));
+    // The first redelivery is now outside the window.
+    assert_eq!(None, tracker.record("a", start + Duration::from_secs(11)));
+    assert_eq!(
+        Some(3),
+        tracker.record("a", start + Duration::from_secs(12))
+    );
+
+    // Long after, the message starts over.
+    assert_eq!(None, tracker.record("a", start + Duration::from_secs(60)));
+}
+
+#[test]
+fn redeliveries_of_messages_requeued_on_purpose_are_not_counted() {
+    let mut tracker = RedeliveryTracker::new(RedeliveryStorm::new(1, Duration::from_secs(10)));
+    let start = Instant::now();
+
+    assert_eq!(None, tracker.record("a", start));
+    tracker.ignore_next("a", start + Duration::from_secs(1));
+    assert_eq!(None, tracker.record("a", start + Duration::from_secs(2)));
+    // Only the next redelivery is ignored.
+    assert_eq!(Some(2), tracker.record("a", start + Duration::from_secs(3)));
+}
+
+#[test]
+fn messages_are_keyed_by_message_id_then_correlation_id() {
+    let properties = BasicProperties::default()
+        .with_message_id("message".into())
+        .with_correlation_id("correlation".into());
+    assert_eq!("message", message_key(&properties, "rk", b"payload"));
+
+    let properties = BasicProperties::default().with_correlation_id("correlation".into());
+    assert_eq!("correlation", message_key(&properties, "rk", b"payload"));
+}
+
+#[test]
+fn messages_without_ids_are_keyed_by_routing_key_and_payload() {
+    let properties = BasicProperties::default();
+    let key = message_key(&properties, "rk", b"payload");
+
+    // Redeliveries of the same message have the same key.
+    assert_eq!(key, message_key(&properties, "rk", b"payload"));
+    assert_ne!(key, message_key(&properties, "rk", b"other payload"));
+    assert_ne!(key, message_key(&properties, "other.rk", b"payload"));
+}
@@ -0,0 +1,46 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{broadcast, Notify};
+
+use crate::app::{reload, AppHandle};
+
+#[tokio::test]
+async fn hook_is_called_on_reload_requests_until_shutdown() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let hook: reload::ReloadHook = {
+        let calls = calls.clone();
+        Arc::new(move |_handle| {
+            let calls = calls.clone();
+            Box::pin(async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+            })
+        })
+    };
+    let requests = Arc::new(Notify::new());
+    let (shutdown, receiver) = broadcast::channel(1);
+
+    let task = tokio::spawn(reload::reload_on_request(
+        hook,
+        AppHandle::default(),
+        requests.clone(),
+        receiver,
+    ));
+
+    requests.notify_one();
+    while calls.load(Ordering::SeqCst) == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    shutdown.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(1), task)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+}
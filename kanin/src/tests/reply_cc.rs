@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use lapin::{
+    options::{BasicGetOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldArray, FieldTable},
+    BasicProperties,
+};
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{extract::AppId, request::copy_cc_headers, App, HandlerConfig};
+
+async fn handler(_app_id: AppId) -> Reply {
+    Reply("cc'd".into())
+}
+
+#[test]
+fn cc_headers_are_copied_to_the_reply() {
+    let recipients = AMQPValue::FieldArray(FieldArray::from(vec![AMQPValue::LongString(
+        "audit.replies".into(),
+    )]));
+    let mut headers = FieldTable::default();
+    headers.insert("CC".into(), recipients.clone());
+    headers.insert("x-other".into(), AMQPValue::Boolean(true));
+    let properties = BasicProperties::default().with_headers(headers);
+
+    let mut reply_headers = FieldTable::default();
+    copy_cc_headers(&properties, &mut reply_headers);
+    assert_eq!(1, reply_headers.inner().len());
+    assert_eq!(Some(&recipients), reply_headers.inner().get("CC"));
+}
+
+#[test]
+fn cc_headers_that_are_not_arrays_are_ignored() {
+    let mut headers = FieldTable::default();
+    headers.insert("CC".into(), AMQPValue::LongString("audit.replies".into()));
+    let properties = BasicProperties::default().with_headers(headers);
+
+    let mut reply_headers = FieldTable::default();
+    copy_cc_headers(&properties, &mut reply_headers);
+    assert!(reply_headers.inner().is_empty());
+}
+
+#[tokio::test]
+async fn cc_recipients_receive_the_reply() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let channel = conn
+        .create_channel()
+        .await
+        .expect("failed to create channel");
+    let cc_queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .expect("failed to declare CC queue");
+
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.reply_cc",
+        handler,
+        HandlerConfig::new().with_reply_cc(true),
+    );
+
+    let mut headers = FieldTable::default();
+    headers.insert(
+        "CC".into(),
+        AMQPValue::FieldArray(FieldArray::from(vec![AMQPValue::LongString(
+            cc_queue.name().as_str().into(),
+        )])),
+    );
+    let properties = BasicProperties::default().with_headers(headers);
+
+    let (_properties, payload) = while_running(app, &conn, async {
+        request(&conn, "kanin.tests.reply_cc", b"", properties).await
+    })
+    .await;
+    assert_eq!(b"cc'd".as_slice(), payload);
+
+    // The reply is routed to the CC queue as well as to the caller.
+    let cc_reply = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let message = channel
+                .basic_get(cc_queue.name().as_str(), BasicGetOptions { no_ack: true })
+                .await
+                .expect("failed to get CC reply");
+            match message {
+                Some(message) => break message.delivery.data,
+                None => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    })
+    .await
+    .expect("no CC reply within 10 seconds");
+    assert_eq!(b"cc'd".as_slice(), cc_reply);
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use crate::{
+    app::reply::{ReplySettings, ReplyTarget},
+    reply_dedup::{MemoryReplyDedupStore, ReplyDedupStore},
+    HandlerConfig,
+};
+
+#[test]
+fn memory_store_remembers_replies_by_reply_to_and_correlation_id() {
+    let store = MemoryReplyDedupStore::new(10);
+    assert!(!store.contains("reply_queue", "correlation_id"));
+
+    store.insert("reply_queue", "correlation_id");
+    assert!(store.contains("reply_queue", "correlation_id"));
+    assert!(!store.contains("other_reply_queue", "correlation_id"));
+    assert!(!store.contains("reply_queue", "other_correlation_id"));
+}
+
+#[test]
+fn memory_store_forgets_the_oldest_replies_when_full() {
+    let store = MemoryReplyDedupStore::new(2);
+    store.insert("reply_queue", "1");
+    store.insert("reply_queue", "2");
+    // Inserting a reply again doesn't make it newer.
+    store.insert("reply_queue", "1");
+    store.insert("reply_queue", "3");
+
+    assert!(!store.contains("reply_queue", "1"));
+    assert!(store.contains("reply_queue", "2"));
+    assert!(store.contains("reply_queue", "3"));
+}
+
+#[test]
+fn memory_store_with_no_capacity_remembers_nothing() {
+    let store = MemoryReplyDedupStore::new(0);
+    store.insert("reply_queue", "1");
+    assert!(!store.contains("reply_queue", "1"));
+}
+
+/// Returns the target of a reply to the given reply queue with the given correlation ID.
+fn target(correlation_id: Option<&str>, redelivered: bool) -> ReplyTarget {
+    ReplyTarget {
+        exchange: "".into(),
+        reply_to: "reply_queue".into(),
+        correlation_id: correlation_id.map(Into::into),
+        redelivered,
+        #[cfg(feature = "gzip")]
+        accepts_gzip: false,
+        #[cfg(feature = "wire-debug")]
+        wire_debug: None,
+    }
+}
+
+#[test]
+fn only_redelivered_requests_that_were_replied_to_are_skipped() {
+    let store: Arc<dyn ReplyDedupStore> = Arc::new(MemoryReplyDedupStore::new(10));
+    store.insert("reply_queue", "1");
+    let settings = ReplySettings::new("routing_key", &HandlerConfig::new(), None, Some(store));
+
+    assert!(settings.already_replied(&target(Some("1"), true)));
+    // The first delivery of a request is always replied to, even if its correlation ID was seen before.
+    assert!(!settings.already_replied(&target(Some("1"), false)));
+    assert!(!settings.already_replied(&target(Some("2"), true)));
+    assert!(!settings.already_replied(&target(None, true)));
+}
+
+#[test]
+fn requests_are_not_skipped_without_a_dedup_store() {
+    let settings = ReplySettings::new("routing_key", &HandlerConfig::new(), None, None);
+
+    assert!(!settings.already_replied(&target(Some("1"), true)));
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{
+    extract::{ReplyHandle, Spawner},
+    App,
+};
+
+async fn handler_with_reply_handle(spawner: Spawner, reply: ReplyHandle) -> Reply {
+    crate::spawn(&spawner, async move {
+        // Reply after the handler has returned.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        reply.reply(Reply("deferred".into())).await.unwrap();
+    });
+
+    Reply("immediate".into())
+}
+
+#[tokio::test]
+async fn deferred_replies_replace_the_response_of_the_handler() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler("kanin.tests.reply_handle", handler_with_reply_handle);
+
+    let (properties, payload) = while_running(
+        app,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.reply_handle",
+            b"",
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+
+    assert_eq!(b"deferred".as_slice(), payload);
+    assert!(properties.correlation_id().is_some());
+}
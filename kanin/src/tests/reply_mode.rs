@@ -0,0 +1,39 @@
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request_on, test_broker, while_running, Reply};
+use crate::{extract::Properties, handler_config::ReplyMode, App, HandlerConfig};
+
+async fn handler(Properties(properties): Properties) -> Reply {
+    let app_id = properties.app_id().as_ref().map(|id| id.to_string());
+    Reply(format!("hello {}", app_id.unwrap_or_default()))
+}
+
+#[tokio::test]
+async fn original_exchange_replies_are_published_to_the_exchange_of_the_request() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.reply_mode",
+        handler,
+        HandlerConfig::new()
+            .with_exchange(HandlerConfig::TOPIC_EXCHANGE)
+            .with_reply_mode(ReplyMode::OriginalExchange),
+    );
+
+    let (_properties, payload) = while_running(
+        app,
+        &conn,
+        request_on(
+            &conn,
+            HandlerConfig::TOPIC_EXCHANGE,
+            "kanin.tests.reply_mode",
+            b"",
+            BasicProperties::default().with_app_id("caller".into()),
+        ),
+    )
+    .await;
+
+    assert_eq!("hello caller", String::from_utf8(payload).unwrap());
+}
@@ -0,0 +1,33 @@
+use lapin::BasicProperties;
+
+use crate::reply_queue::Waiters;
+
+#[tokio::test]
+async fn replies_are_dispatched_by_correlation_id() {
+    let waiters = Waiters::default();
+    let first = waiters.register("first".to_string());
+    let second = waiters.register("second".to_string());
+
+    let properties =
+        BasicProperties::default().with_content_type("application/octet-stream".into());
+    assert!(waiters.dispatch(Some("second"), properties.clone(), b"two".to_vec()));
+    assert!(waiters.dispatch(Some("first"), BasicProperties::default(), b"one".to_vec()));
+    assert_eq!(b"one".to_vec(), first.await.unwrap().1);
+    assert_eq!((properties, b"two".to_vec()), second.await.unwrap());
+
+    // Each request only receives a single reply.
+    assert!(!waiters.dispatch(Some("first"), BasicProperties::default(), b"again".to_vec()));
+}
+
+#[tokio::test]
+async fn unexpected_replies_are_ignored() {
+    let waiters = Waiters::default();
+    let reply = waiters.register("id".to_string());
+
+    assert!(!waiters.dispatch(None, BasicProperties::default(), b"no id".to_vec()));
+    assert!(!waiters.dispatch(Some("other"), BasicProperties::default(), b"other".to_vec()));
+
+    waiters.unregister("id");
+    assert!(!waiters.dispatch(Some("id"), BasicProperties::default(), b"late".to_vec()));
+    assert!(reply.await.is_err());
+}
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use lapin::{options::BasicPublishOptions, BasicProperties};
+use tokio::sync::mpsc;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{App, HandlerConfig};
+
+async fn handler() -> Reply {
+    Reply("hello".into())
+}
+
+/// What the reply result hook was called with. Errors are kept in their displayed form.
+#[derive(Debug)]
+struct Reported {
+    routing_key: String,
+    correlation_id: Option<String>,
+    result: Result<(), String>,
+}
+
+/// Returns a handler config that reports the results of publishing replies on the returned channel.
+fn reporting_config() -> (HandlerConfig, mpsc::UnboundedReceiver<Reported>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let config = HandlerConfig::new().on_reply_result(move |reply| {
+        let _ = sender.send(Reported {
+            routing_key: reply.routing_key.clone(),
+            correlation_id: reply.correlation_id.clone(),
+            result: reply.result.as_ref().map_err(ToString::to_string).copied(),
+        });
+    });
+    (config, receiver)
+}
+
+#[tokio::test]
+async fn reply_result_hook_is_called_with_published_replies() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let (config, mut results) = reporting_config();
+    let app = App::new(()).handler_with_config("kanin.tests.reply_result.ok", handler, config);
+
+    let (properties, payload) = while_running(
+        app,
+        &conn,
+        request(
+            &conn,
+            "kanin.tests.reply_result.ok",
+            b"",
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+    assert_eq!(b"hello".as_slice(), payload);
+
+    let reply = results
+        .recv()
+        .await
+        .expect("reply result hook was not called");
+    assert!(reply.result.is_ok());
+    assert_eq!(
+        properties
+            .correlation_id()
+            .as_ref()
+            .map(|id| id.to_string()),
+        reply.correlation_id
+    );
+}
+
+#[tokio::test]
+async fn reply_result_hook_is_called_with_returned_mandatory_replies() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let (config, mut results) = reporting_config();
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.reply_result.returned",
+        handler,
+        config.with_mandatory_replies(true),
+    );
+
+    let reply = while_running(app, &conn, async {
+        let channel = conn
+            .create_channel()
+            .await
+            .expect("failed to create channel");
+        channel
+            .basic_publish(
+                "",
+                "kanin.tests.reply_result.returned",
+                BasicPublishOptions::default(),
+                &[],
+                BasicProperties::default()
+                    .with_reply_to("kanin.tests.reply_result.missing".into())
+                    .with_correlation_id("abc".into()),
+            )
+            .await
+            .expect("failed to publish request");
+
+        tokio::time::timeout(Duration::from_secs(10), results.recv())
+            .await
+            .expect("reply result hook was not called within 10 seconds")
+            .expect("reply result hook was dropped")
+    })
+    .await;
+
+    assert_eq!("kanin.tests.reply_result.missing", reply.routing_key);
+    assert_eq!(Some("abc".to_string()), reply.correlation_id);
+    assert_eq!(
+        Err("Reply was returned by the broker (312): NO_ROUTE".to_string()),
+        reply.result
+    );
+}
@@ -0,0 +1,46 @@
+use lapin::BasicProperties;
+
+use crate::reply_store::{MemoryReplyStore, ReplyStore, StoredReply};
+
+fn reply(routing_key: &str) -> StoredReply {
+    StoredReply {
+        exchange: String::new(),
+        routing_key: routing_key.to_string(),
+        properties: BasicProperties::default(),
+        payload: routing_key.as_bytes().to_vec(),
+    }
+}
+
+#[test]
+fn memory_store_returns_replies_in_order_and_empties() {
+    let store = MemoryReplyStore::new(10);
+    store.store(reply("a"));
+    store.store(reply("b"));
+
+    let routing_keys: Vec<_> = store
+        .take_all()
+        .into_iter()
+        .map(|reply| reply.routing_key)
+        .collect();
+    assert_eq!(vec!["a", "b"], routing_keys);
+    assert!(store.take_all().is_empty());
+}
+
+#[test]
+fn memory_store_drops_oldest_replies_when_full() {
+    let store = MemoryReplyStore::new(2);
+    store.store(reply("a"));
+    store.store(reply("b"));
+    store.store(reply("c"));
+
+    let routing_keys: Vec<_> = store
+        .take_all()
+        .into_iter()
+        .map(|reply| reply.routing_key)
+        .collect();
+    assert_eq!(vec!["b", "c"], routing_keys);
+
+    let store = MemoryReplyStore::new(0);
+    store.store(reply("a"));
+    assert!(store.take_all().is_empty());
+}
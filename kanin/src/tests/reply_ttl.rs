@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{
+    error::{FromError, RequestError},
+    response::Expiring,
+    App, HandlerConfig, HandlerError, Respond,
+};
+
+async fn handler() -> Reply {
+    Reply("hello".into())
+}
+
+async fn expiring_handler() -> Expiring<Reply> {
+    Expiring::new(Reply("hello".into()), Duration::from_secs(30))
+}
+
+#[test]
+fn expiring_responses_have_their_own_ttl() {
+    let response = Expiring::new(Reply("hello".into()), Duration::from_secs(30));
+    assert_eq!(Some(Duration::from_secs(30)), response.reply_ttl());
+    assert_eq!(None, Reply("hello".into()).reply_ttl());
+
+    // Errors fall back to the default TTL of the handler.
+    let response =
+        Expiring::<Reply>::from_error(HandlerError::InvalidRequest(RequestError::DefaultMessage));
+    assert_eq!(None, response.reply_ttl());
+}
+
+#[tokio::test]
+async fn replies_expire_after_their_ttl() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let config = HandlerConfig::new().with_reply_ttl(Duration::from_secs(60));
+    let app = App::new(())
+        .handler_with_config("kanin.tests.reply_ttl.default", handler, config.clone())
+        .handler_with_config("kanin.tests.reply_ttl.expiring", expiring_handler, config);
+
+    let (default, expiring) = while_running(app, &conn, async {
+        let default = request(
+            &conn,
+            "kanin.tests.reply_ttl.default",
+            b"",
+            BasicProperties::default(),
+        )
+        .await;
+        let expiring = request(
+            &conn,
+            "kanin.tests.reply_ttl.expiring",
+            b"",
+            BasicProperties::default(),
+        )
+        .await;
+        (default.0, expiring.0)
+    })
+    .await;
+
+    // The TTL of the response takes precedence over the default of the handler.
+    assert_eq!(Some("60000".into()), *default.expiration());
+    assert_eq!(Some("30000".into()), *expiring.expiration());
+}
@@ -0,0 +1,73 @@
+use lapin::{
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use crate::{
+    extract::{ReqId, ReqIdFormat, ReqIdPolicy},
+    App,
+};
+
+#[test]
+fn generated_req_ids_are_valid_in_their_own_format_only() {
+    let formats = [ReqIdFormat::UuidV4, ReqIdFormat::UuidV7, ReqIdFormat::Ulid];
+
+    for generated in formats {
+        let req_id = generated.generate();
+        for format in formats {
+            assert_eq!(
+                generated == format,
+                format.is_valid(&req_id.0),
+                "{generated:?} request ID {req_id} validated as {format:?}"
+            );
+        }
+        assert!(ReqIdFormat::PropagateAny.is_valid(&req_id.0));
+    }
+}
+
+#[test]
+fn non_string_req_ids_are_only_valid_when_propagating_any() {
+    let req_id = AMQPValue::LongLongInt(42);
+
+    assert!(ReqIdFormat::PropagateAny.is_valid(&req_id));
+    assert!(!ReqIdFormat::UuidV4.is_valid(&req_id));
+    assert!(!ReqIdFormat::Ulid.is_valid(&req_id));
+}
+
+/// Generates request IDs that are easy to recognize.
+fn snowflake() -> AMQPValue {
+    AMQPValue::LongLongInt(187)
+}
+
+#[test]
+fn generator_replaces_random_req_ids_only() {
+    let policy = ReqIdPolicy::default().with_generator(Some(snowflake));
+    assert_eq!(ReqId(snowflake()), policy.generate());
+
+    // Request IDs of a specific format are still generated in that format.
+    let policy = ReqIdPolicy::new(ReqIdFormat::UuidV4).with_generator(Some(snowflake));
+    let req_id = policy.generate();
+    assert!(ReqIdFormat::UuidV4.is_valid(&req_id.0));
+}
+
+#[test]
+fn generator_only_applies_to_its_own_app() {
+    let _app = App::new(()).with_req_id_generator(snowflake);
+
+    assert_ne!(ReqId(snowflake()), ReqId::new());
+    assert_ne!(ReqId(snowflake()), ReqIdFormat::PropagateAny.generate());
+
+    // Outside of a request, client-side helpers set a new random request ID, unless one is already set.
+    let properties = ReqId::propagate_current(BasicProperties::default());
+    let headers = properties.headers().clone().unwrap_or_default();
+    let req_id = headers.inner().get("req_id").expect("request ID was set");
+    assert!(ReqIdFormat::UuidV4.is_valid(req_id));
+
+    let mut headers = FieldTable::default();
+    headers.insert("req_id".into(), AMQPValue::LongString("abc".into()));
+    let properties = BasicProperties::default().with_headers(headers.clone());
+    assert_eq!(
+        Some(headers),
+        ReqId::propagate_current(properties).headers().clone()
+    );
+}
@@ -0,0 +1,41 @@
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request_on, test_broker, while_running, Reply};
+use crate::{extract::RoutingKey, App, HandlerConfig};
+
+async fn handler(RoutingKey(routing_key): RoutingKey) -> Reply {
+    Reply(format!("received on {routing_key}"))
+}
+
+#[tokio::test]
+async fn it_extracts_the_routing_key_matching_the_binding_key() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.routing_key",
+        handler,
+        HandlerConfig::new()
+            .with_exchange(HandlerConfig::TOPIC_EXCHANGE)
+            .with_binding_key("kanin.tests.routing_key.*.created"),
+    );
+
+    let (_properties, payload) = while_running(
+        app,
+        &conn,
+        request_on(
+            &conn,
+            HandlerConfig::TOPIC_EXCHANGE,
+            "kanin.tests.routing_key.orders.created",
+            b"",
+            BasicProperties::default(),
+        ),
+    )
+    .await;
+
+    assert_eq!(
+        "received on kanin.tests.routing_key.orders.created",
+        String::from_utf8(payload).unwrap()
+    );
+}
@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use lapin::{
+    acker::Acker,
+    message::Delivery,
+    types::{AMQPValue, FieldTable, LongString, ShortString},
+    BasicProperties,
+};
+
+use crate::schema::{self, parse_schema_id, SchemaRegistry, SchemaRegistryError, SCHEMA_ID_HEADER};
+
+/// A schema registry that knows a single schema, and fails to look up schema ID 0.
+struct SingleSchemaRegistry;
+
+#[async_trait]
+impl SchemaRegistry for SingleSchemaRegistry {
+    async fn is_compatible(
+        &self,
+        schema_id: &str,
+        expected: &str,
+    ) -> Result<bool, SchemaRegistryError> {
+        if schema_id == "0" {
+            return Err("registry unavailable".into());
+        }
+        Ok(schema_id == "1" && expected == "my.Request")
+    }
+}
+
+/// Returns a delivery with the given schema ID header, if any.
+fn delivery(schema_id: Option<AMQPValue>) -> Delivery {
+    let mut headers = FieldTable::default();
+    if let Some(schema_id) = schema_id {
+        headers.insert(SCHEMA_ID_HEADER.into(), schema_id);
+    }
+
+    Delivery {
+        delivery_tag: 1,
+        exchange: "".into(),
+        routing_key: "routing_key".into(),
+        redelivered: false,
+        properties: BasicProperties::default().with_headers(headers),
+        data: Vec::new(),
+        acker: Acker::default(),
+    }
+}
+
+/// Checks the schema of a delivery with the given schema ID header against `my.Request`.
+async fn check(schema_id: Option<AMQPValue>) -> bool {
+    schema::check(
+        &SingleSchemaRegistry,
+        &delivery(schema_id),
+        "my.Request",
+        "queue",
+    )
+    .await
+}
+
+#[tokio::test]
+async fn only_incompatible_schemas_are_rejected() {
+    assert!(check(Some(AMQPValue::LongUInt(1))).await);
+    assert!(check(Some(AMQPValue::LongString("1".into()))).await);
+    assert!(!check(Some(AMQPValue::LongUInt(2))).await);
+
+    // Messages are let through if their schema can't be checked.
+    assert!(check(None).await);
+    assert!(check(Some(AMQPValue::LongString("not a schema ID".into()))).await);
+    assert!(check(Some(AMQPValue::LongUInt(0))).await);
+}
+
+#[test]
+fn schema_ids_are_parsed_from_integers_and_strings() {
+    assert_eq!(Some(42), parse_schema_id(&AMQPValue::LongUInt(42)));
+    assert_eq!(Some(42), parse_schema_id(&AMQPValue::LongInt(42)));
+    assert_eq!(Some(42), parse_schema_id(&AMQPValue::LongLongInt(42)));
+    assert_eq!(
+        Some(42),
+        parse_schema_id(&AMQPValue::ShortString(ShortString::from("42")))
+    );
+    assert_eq!(
+        Some(42),
+        parse_schema_id(&AMQPValue::LongString(LongString::from("42")))
+    );
+}
+
+#[test]
+fn invalid_schema_ids_are_not_parsed() {
+    // Strings that aren't plain numbers must not end up in registry URLs.
+    assert_eq!(
+        None,
+        parse_schema_id(&AMQPValue::LongString(LongString::from("1/../../config")))
+    );
+    assert_eq!(
+        None,
+        parse_schema_id(&AMQPValue::ShortString(ShortString::from("")))
+    );
+    assert_eq!(None, parse_schema_id(&AMQPValue::LongInt(-1)));
+    assert_eq!(
+        None,
+        parse_schema_id(&AMQPValue::LongLongInt(i64::from(u32::MAX) + 1))
+    );
+    assert_eq!(None, parse_schema_id(&AMQPValue::Boolean(true)));
+}
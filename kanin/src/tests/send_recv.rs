@@ -20,7 +20,7 @@ use crate::{
     App, Extract, HandlerError, Request, Respond,
 };
 
-use super::amqp_connect;
+use super::{amqp_connect, test_broker};
 
 #[derive(Debug)]
 struct MyResponse(String);
@@ -35,6 +35,7 @@ impl FromError<HandlerError> for MyResponse {
     fn from_error(error: HandlerError) -> Self {
         match error {
             HandlerError::InvalidRequest(e) => MyResponse(format!("Invalid request: {e:#?}")),
+            HandlerError::InternalError(e) => MyResponse(format!("Internal error: {e:#?}")),
         }
     }
 }
@@ -131,7 +132,8 @@ impl From<&RecvState> for Arc<Mutex<Vec<String>>> {
 async fn it_receives_various_messages_and_works_as_expected() {
     init_logging();
     info!("Connecting to AMQP...");
-    let conn = amqp_connect().await;
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
 
     // We use a shared state to recall the calls that happened.
     let send_state = SendState(Arc::new(Mutex::new(Vec::<String>::new())));
@@ -148,7 +150,7 @@ async fn it_receives_various_messages_and_works_as_expected() {
         .handler("listener", listener);
 
     let send_app_shutdown = send_app.shutdown_channel();
-    let send_conn = amqp_connect().await;
+    let send_conn = amqp_connect(&amqp_addr).await;
     let send_app = send_app.run_with_connection(&send_conn);
 
     info!("Setting up recv app...");
@@ -157,7 +159,7 @@ async fn it_receives_various_messages_and_works_as_expected() {
         .handler("handler_message_reply_to", handler_message);
 
     let recv_app_shutdown = recv_app.shutdown_channel();
-    let recv_conn = amqp_connect().await;
+    let recv_conn = amqp_connect(&amqp_addr).await;
     let recv_app = recv_app.run_with_connection(&recv_conn);
 
     let requests = async {
@@ -0,0 +1,107 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use lapin::{
+    options::BasicPublishOptions,
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use super::{amqp_connect, init_logging, test_broker};
+use crate::{
+    shadow::{shadow_properties, ShadowTarget},
+    App,
+};
+
+#[test]
+fn shadow_copies_do_not_reply_to_the_caller() {
+    let mut headers = FieldTable::default();
+    headers.insert("req_id".into(), AMQPValue::LongString("abc".into()));
+    let properties = BasicProperties::default()
+        .with_reply_to("caller_queue".into())
+        .with_correlation_id("correlation".into())
+        .with_user_id("guest".into())
+        .with_headers(headers);
+
+    let shadow = shadow_properties(&properties);
+
+    assert_eq!(&None, shadow.reply_to());
+    assert_eq!(&None, shadow.user_id());
+    assert_eq!(&Some("correlation".into()), shadow.correlation_id());
+
+    let headers = shadow.headers().as_ref().unwrap().inner();
+    assert_eq!(
+        Some(&AMQPValue::LongString("abc".into())),
+        headers.get("req_id")
+    );
+    assert_eq!(
+        Some(&AMQPValue::Boolean(true)),
+        headers.get(ShadowTarget::HEADER)
+    );
+}
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+static SAMPLED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn shadowed_listener() {
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+}
+
+#[tokio::test]
+async fn shadowing_to_a_missing_exchange_does_not_affect_handling() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(())
+        .handler("shadowed_listener", shadowed_listener)
+        .with_control_queue("shadowed_control", "secret")
+        .with_shadow(|delivery| {
+            SAMPLED
+                .lock()
+                .unwrap()
+                .push(delivery.routing_key.to_string());
+            // The broker closes the channel the copy is published on, as the exchange does not exist.
+            Some(ShadowTarget::new("shadow_missing_exchange", "shadowed"))
+        });
+    let shutdown = app.shutdown_channel();
+    let app_conn = amqp_connect(&amqp_addr).await;
+    let app = app.run_with_connection(&app_conn);
+
+    let requests = async {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let channel = conn
+            .create_channel()
+            .await
+            .expect("failed to create channel");
+        for routing_key in ["shadowed_listener", "shadowed_control", "shadowed_listener"] {
+            channel
+                .basic_publish(
+                    "",
+                    routing_key,
+                    BasicPublishOptions::default(),
+                    b"payload",
+                    BasicProperties::default(),
+                )
+                .await
+                .expect("failed to publish");
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        shutdown.send(()).unwrap();
+    };
+
+    let (result, ()) = tokio::join!(app, requests);
+    assert!(result.is_ok(), "{result:?}");
+    // Both requests were handled, even though the first shadow copy killed the shadow channel.
+    assert_eq!(2, HANDLED.load(Ordering::SeqCst));
+    // Control messages are never sampled.
+    assert_eq!(
+        vec!["shadowed_listener", "shadowed_listener"],
+        *SAMPLED.lock().unwrap()
+    );
+}
@@ -0,0 +1,19 @@
+use crate::{app::ShutdownSignal, App};
+
+#[tokio::test]
+async fn signals_that_do_not_exist_on_the_platform_are_ignored() {
+    let app = App::new(())
+        .graceful_shutdown_on_signals(&[ShutdownSignal::Hangup, ShutdownSignal::CtrlShutdown])
+        .unwrap();
+    let mut shutdown = app.shutdown_channel().subscribe();
+
+    // Let the listener task run, which must neither panic nor shut down the app.
+    tokio::task::yield_now().await;
+    assert!(shutdown.try_recv().is_err());
+}
+
+#[test]
+fn signals_are_displayed_by_name() {
+    assert_eq!("SIGTERM", ShutdownSignal::Terminate.to_string());
+    assert_eq!("CTRL_CLOSE", ShutdownSignal::CtrlClose.to_string());
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use tracing::Span;
+
+use crate::{
+    context::RequestContext,
+    extract::{Baggage, Deadline, ReqId},
+    spawn::{BackgroundTasks, Spawner},
+};
+
+#[tokio::test]
+async fn shutdown_waits_for_background_tasks() {
+    let background = Arc::new(BackgroundTasks::new());
+    let spawner = Spawner {
+        span: Span::none(),
+        req_id: ReqId::new(),
+        scope: None,
+        background: Some(background.clone()),
+    };
+
+    let mut token = spawner.shutdown_token();
+    assert!(!token.is_cancelled());
+
+    let handle = spawner.spawn(async move {
+        token.cancelled().await;
+        42
+    });
+    assert_eq!(1, background.running());
+
+    background.shut_down();
+    background.finished().await;
+    assert_eq!(0, background.running());
+    assert_eq!(42, handle.await.unwrap());
+}
+
+#[tokio::test]
+async fn background_tasks_see_the_context_of_their_request() {
+    let context = RequestContext {
+        req_id: ReqId::new(),
+        req_id_header: "req_id".to_string(),
+        routing_key: "routing_key".to_string(),
+        app_id: Some("caller".to_string()),
+        deadline: Deadline::default(),
+    };
+    let baggage = Baggage::parse("tenant=acme");
+    let spawner = Spawner {
+        span: Span::none(),
+        req_id: context.req_id.clone(),
+        scope: Some((context.clone(), baggage.clone())),
+        background: None,
+    };
+
+    let (task_context, task_baggage) = spawner
+        .spawn(async { (RequestContext::current(), Baggage::current()) })
+        .await
+        .unwrap();
+
+    assert_eq!(Some(context), task_context);
+    assert_eq!(Some(baggage), task_baggage);
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use lapin::BasicProperties;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running, Reply};
+use crate::{App, HandlerConfig};
+
+async fn handler() -> Reply {
+    Reply("hello".into())
+}
+
+#[tokio::test]
+async fn handlers_start_consuming_after_their_start_delay() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(()).handler_with_config(
+        "kanin.tests.start_delay",
+        handler,
+        HandlerConfig::new().with_start_delay(Duration::from_secs(2)),
+    );
+    let startup_report = app.startup_report();
+
+    let payload = while_running(app, &conn, async {
+        let report = startup_report
+            .borrow()
+            .clone()
+            .expect("app was not started");
+        assert_eq!(Some(Duration::from_secs(2)), report.handlers[0].start_delay);
+
+        let reply = request(
+            &conn,
+            "kanin.tests.start_delay",
+            b"",
+            BasicProperties::default(),
+        );
+        tokio::pin!(reply);
+
+        // The queue is declared right away, so the request waits in it until the handler starts consuming.
+        let delayed = tokio::time::timeout(Duration::from_secs(1), &mut reply).await;
+        assert!(
+            delayed.is_err(),
+            "request was handled before the start delay"
+        );
+
+        reply.await.1
+    })
+    .await;
+
+    assert_eq!(b"hello".as_slice(), payload);
+}
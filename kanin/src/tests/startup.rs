@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use super::{amqp_connect, init_logging, test_broker};
+use crate::{
+    app::{task::TaskFactory, BindingReport},
+    App, HandlerConfig,
+};
+
+async fn listener() {}
+
+#[test]
+fn handler_reports_describe_the_queue_bindings_and_consumer() {
+    let factory = TaskFactory::<()>::new(
+        "orders.created".to_string(),
+        listener,
+        HandlerConfig::new()
+            .with_queue("orders")
+            .with_exchange(HandlerConfig::TOPIC_EXCHANGE)
+            .with_binding_key("orders.*.created")
+            .with_prefetch(8)
+            .with_start_delay(Duration::from_secs(2)),
+    );
+
+    let report = factory.report(None);
+    assert!(report.handler.ends_with("listener"), "{}", report.handler);
+    assert_eq!("orders.created", report.routing_key);
+    assert_eq!("orders", report.queue);
+    assert_eq!(
+        vec![BindingReport {
+            exchange: HandlerConfig::TOPIC_EXCHANGE.to_string(),
+            routing_key: "orders.*.created".to_string(),
+        }],
+        report.bindings
+    );
+    assert_eq!(8, report.prefetch);
+    assert_eq!(Some(Duration::from_secs(2)), report.start_delay);
+    // Without a consumer, the report has the tag the consumer will be created with.
+    assert_eq!("orders.created", report.consumer_tag);
+    assert_eq!(
+        "ctag",
+        factory.report(Some("ctag".to_string())).consumer_tag
+    );
+}
+
+#[tokio::test]
+async fn handlers_set_up_after_shutdown_still_shut_down() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    // The handlers are set up one at a time, slowly, so the shutdown is sent while most of them are still waiting to be set up.
+    let app = App::new(())
+        .handler("startup_listener_1", listener)
+        .handler("startup_listener_2", listener)
+        .handler("startup_listener_3", listener)
+        .handler("startup_listener_4", listener)
+        .with_startup_concurrency(1)
+        .with_startup_stagger(Duration::from_millis(300));
+    let shutdown = app.shutdown_channel();
+    let app = app.run_with_connection(&conn);
+
+    let shutdown = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.send(()).unwrap();
+    };
+
+    let (result, ()) = tokio::time::timeout(Duration::from_secs(10), async {
+        tokio::join!(app, shutdown)
+    })
+    .await
+    .expect("app did not shut down");
+    assert!(result.is_ok(), "{result:?}");
+}
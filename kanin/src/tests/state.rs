@@ -0,0 +1,109 @@
+use std::{
+    convert::Infallible,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    error::ServerError,
+    extract::{FromStateAsync, Provider},
+    request::Scope,
+    HandlerError,
+};
+
+struct Pool {
+    available: bool,
+}
+
+struct Connection;
+
+#[async_trait]
+impl FromStateAsync<Pool> for Connection {
+    type Error = std::io::Error;
+
+    async fn from_state(pool: &Pool) -> Result<Self, Self::Error> {
+        if pool.available {
+            Ok(Connection)
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "pool exhausted",
+            ))
+        }
+    }
+}
+
+#[tokio::test]
+async fn async_state_errors_are_turned_into_internal_errors() {
+    let pool = Pool { available: true };
+    let result = Provider::<Connection>::provide(&pool, &mut Scope::default()).await;
+    assert!(result.is_ok());
+
+    let pool = Pool { available: false };
+    let result = Provider::<Connection>::provide(&pool, &mut Scope::default()).await;
+    match result {
+        Err(HandlerError::InternalError(ServerError::Other(error))) => {
+            assert_eq!("pool exhausted", error.to_string());
+        }
+        _ => panic!("expected the error to be turned into an internal error"),
+    }
+}
+
+#[test]
+fn scopes_hold_one_value_per_type() {
+    let mut scope = Scope::default();
+    assert_eq!(None, scope.get::<u64>());
+
+    assert_eq!(None, scope.insert(1_u64));
+    assert_eq!(Some(1), scope.insert(2_u64));
+    scope.insert("name");
+    assert_eq!(Some(&2), scope.get::<u64>());
+    assert_eq!(Some(&"name"), scope.get::<&str>());
+
+    *scope.get_mut::<u64>().unwrap() += 1;
+    assert_eq!(Some(3), scope.remove::<u64>());
+    assert_eq!(None, scope.get::<u64>());
+    assert_eq!(Some(&"name"), scope.get::<&str>());
+}
+
+/// A database transaction, identified by the order it was started in.
+#[derive(Clone, Debug, PartialEq)]
+struct Transaction(u64);
+
+/// App state that starts a transaction per request.
+struct Database {
+    started: AtomicU64,
+}
+
+#[async_trait]
+impl Provider<Transaction> for Database {
+    type Error = Infallible;
+
+    async fn provide(&self, scope: &mut Scope) -> Result<Transaction, Self::Error> {
+        if let Some(transaction) = scope.get::<Transaction>() {
+            return Ok(transaction.clone());
+        }
+
+        let transaction = Transaction(self.started.fetch_add(1, Ordering::SeqCst));
+        scope.insert(transaction.clone());
+        Ok(transaction)
+    }
+}
+
+#[tokio::test]
+async fn providers_can_share_values_within_the_scope_of_a_request() {
+    let database = Database {
+        started: AtomicU64::new(0),
+    };
+
+    let mut first_request = Scope::default();
+    let first = Provider::<Transaction>::provide(&database, &mut first_request).await;
+    let again = Provider::<Transaction>::provide(&database, &mut first_request).await;
+    assert_eq!(Ok(Transaction(0)), first);
+    assert_eq!(Ok(Transaction(0)), again);
+
+    let mut second_request = Scope::default();
+    let second = Provider::<Transaction>::provide(&database, &mut second_request).await;
+    assert_eq!(Ok(Transaction(1)), second);
+}
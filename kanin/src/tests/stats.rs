@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use lapin::BasicProperties;
+use prost::Message;
+
+use super::{amqp_connect, init_logging, request, test_broker, while_running};
+use crate::{
+    stats::{MetricsReply, MetricsSnapshot, RequestStats},
+    App, Respond,
+};
+
+#[test]
+fn stats_count_requests_in_flight_and_in_total() {
+    let stats = Arc::new(RequestStats::default());
+    assert!(stats.snapshot().handlers.is_empty());
+
+    let first = stats.start("routing_key_1");
+    let second = stats.start("routing_key_1");
+    let other = stats.start("routing_key_0");
+    drop(first);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(2, snapshot.handlers.len());
+    // Handlers are ordered by routing key.
+    assert_eq!("routing_key_0", snapshot.handlers[0].routing_key);
+    assert_eq!(1, snapshot.handlers[0].in_flight);
+    assert_eq!("routing_key_1", snapshot.handlers[1].routing_key);
+    assert_eq!(1, snapshot.handlers[1].in_flight);
+    assert_eq!(2, snapshot.handlers[1].requests_total);
+    assert!(snapshot.handlers[1].latency_max_seconds >= snapshot.handlers[1].latency_mean_seconds);
+
+    drop((second, other));
+    assert!(stats
+        .snapshot()
+        .handlers
+        .iter()
+        .all(|handler| handler.in_flight == 0));
+}
+
+#[test]
+fn metrics_reply_is_encoded_as_protobuf_by_default() {
+    let stats = Arc::new(RequestStats::default());
+    drop(stats.start("routing_key_0"));
+    let snapshot = stats.snapshot();
+
+    let payload = MetricsReply::new(snapshot.clone(), &BasicProperties::default()).respond();
+    assert_eq!(
+        snapshot,
+        MetricsSnapshot::decode(payload.as_slice()).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn metrics_route_replies_with_the_stats_of_the_app() {
+    init_logging();
+    let (amqp_addr, _broker) = test_broker().await;
+    let conn = amqp_connect(&amqp_addr).await;
+
+    let app = App::new(())
+        .with_ping("kanin.tests.stats.ping")
+        .with_metrics_route("kanin.tests.stats.metrics");
+
+    let (_properties, payload) = while_running(app, &conn, async {
+        request(
+            &conn,
+            "kanin.tests.stats.ping",
+            b"ping",
+            BasicProperties::default(),
+        )
+        .await;
+        request(
+            &conn,
+            "kanin.tests.stats.metrics",
+            b"",
+            BasicProperties::default(),
+        )
+        .await
+    })
+    .await;
+
+    let snapshot = MetricsSnapshot::decode(payload.as_slice()).unwrap();
+    let ping = snapshot
+        .handlers
+        .iter()
+        .find(|handler| handler.routing_key == "kanin.tests.stats.ping")
+        .expect("no stats for the ping handler");
+    assert_eq!(1, ping.requests_total);
+    assert_eq!(0, ping.in_flight);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn metrics_reply_is_encoded_as_json_on_request() {
+    let stats = Arc::new(RequestStats::default());
+    drop(stats.start("routing_key_0"));
+
+    let properties = BasicProperties::default().with_content_type("application/json".into());
+    let reply = MetricsReply::new(stats.snapshot(), &properties);
+    assert_eq!("application/json", reply.content_type());
+    let payload = reply.respond();
+    let json: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+    assert_eq!("routing_key_0", json["handlers"][0]["routing_key"]);
+    assert_eq!(1, json["handlers"][0]["requests_total"]);
+}
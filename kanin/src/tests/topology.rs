@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use crate::{config::HandlerOverlay, App, Error, HandlerConfig, KaninConfig};
+
+async fn handler() {}
+
+#[test]
+fn topology_graph_describes_registered_handlers() {
+    let app = App::new(())
+        .handler("routing_key_0", handler)
+        .handler_with_config(
+            "routing_key_1",
+            handler,
+            HandlerConfig::new()
+                .with_queue("my_queue")
+                .with_dead_letter_exchange("my_dlx"),
+        );
+
+    let topology = app.topology_graph();
+    assert_eq!(2, topology.handlers.len());
+    assert_eq!("routing_key_0", topology.handlers[0].queue);
+    assert_eq!("amq.direct", topology.handlers[0].exchange);
+    assert_eq!("my_queue", topology.handlers[1].queue);
+    assert_eq!("routing_key_1", topology.handlers[1].binding_key);
+    assert_eq!(
+        Some("my_dlx"),
+        topology.handlers[1].dead_letter_exchange.as_deref()
+    );
+
+    let handler = "kanin::tests::topology::handler";
+    let dot = topology.to_dot();
+    assert_eq!(
+        format!(
+            r#"digraph kanin {{
+    rankdir=LR;
+    n0 [label="amq.direct", shape=box];
+    n1 [label="routing_key_0", shape=cylinder];
+    n2 [label="{handler}", shape=component];
+    n3 [label="my_queue", shape=cylinder];
+    n4 [label="my_dlx", shape=box];
+    n0 -> n1 [label="routing_key_0"];
+    n1 -> n2;
+    n0 -> n3 [label="routing_key_1"];
+    n3 -> n2;
+    n3 -> n4 [label="dead letters", style=dashed];
+}}
+"#
+        ),
+        dot
+    );
+
+    let mermaid = topology.to_mermaid();
+    assert!(mermaid.starts_with("flowchart LR\n    n0[[\"amq.direct\"]]\n"));
+    assert!(mermaid.contains("    n3 -.->|\"dead letters\"| n4\n"));
+}
+
+#[test]
+fn topology_graph_uses_binding_key_pattern() {
+    let app = App::new(()).handler_with_config(
+        "orders",
+        handler,
+        HandlerConfig::new()
+            .with_exchange(HandlerConfig::TOPIC_EXCHANGE)
+            .with_binding_key("orders.*.created"),
+    );
+
+    let topology = app.topology_graph();
+    assert_eq!("amq.topic", topology.handlers[0].exchange);
+    assert_eq!("orders.*.created", topology.handlers[0].binding_key);
+    assert_eq!("orders", topology.handlers[0].queue);
+}
+
+#[test]
+fn topology_graph_includes_extra_bindings() {
+    let app = App::new(()).handler_with_config(
+        "user.created",
+        handler,
+        HandlerConfig::new()
+            .with_queue("users")
+            .with_extra_binding("user.updated")
+            .with_extra_binding("user.deleted"),
+    );
+
+    let topology = app.topology_graph();
+    assert_eq!("user.created", topology.handlers[0].binding_key);
+    assert_eq!(
+        vec!["user.updated", "user.deleted"],
+        topology.handlers[0].extra_binding_keys
+    );
+
+    let dot = topology.to_dot();
+    assert!(dot.contains("    n0 -> n1 [label=\"user.created\"];\n"));
+    assert!(dot.contains("    n0 -> n1 [label=\"user.updated\"];\n"));
+    assert!(dot.contains("    n0 -> n1 [label=\"user.deleted\"];\n"));
+}
+
+#[test]
+fn consistent_hash_ignores_extra_bindings() {
+    let app = App::new(()).handler_with_config(
+        "routing_key_0",
+        handler,
+        HandlerConfig::new()
+            .with_consistent_hash("my_hash_exchange", 3)
+            .with_extra_binding("ignored"),
+    );
+
+    let topology = app.topology_graph();
+    assert_eq!("3", topology.handlers[0].binding_key);
+    assert!(topology.handlers[0].extra_binding_keys.is_empty());
+}
+
+#[tokio::test]
+async fn dry_run_reports_topology_and_unknown_overlay_routing_keys() {
+    let overlay = KaninConfig::new()
+        .with_handler("routing_key_0", HandlerOverlay::new())
+        .with_handler("routing_key_typo", HandlerOverlay::new());
+    let app = App::new(())
+        .handler("routing_key_0", handler)
+        .with_config_overlay(overlay);
+
+    let report = app.dry_run().await.unwrap();
+    assert_eq!(1, report.topology.handlers.len());
+    assert_eq!("routing_key_0", report.topology.handlers[0].queue);
+    assert_eq!(
+        vec!["routing_key_typo"],
+        report.unknown_overlay_routing_keys
+    );
+    assert!(report.missing_exchanges.is_empty());
+    assert!(report.config_issues.is_empty());
+    assert!(!report.is_ok());
+}
+
+#[tokio::test]
+async fn dry_run_reports_config_issues() {
+    let app = App::new(()).handler_with_config(
+        "routing_key_0",
+        handler,
+        HandlerConfig::new()
+            .with_durable(true)
+            .with_expires(Duration::from_secs(60)),
+    );
+
+    let report = app.dry_run().await.unwrap();
+    assert_eq!(1, report.config_issues.len());
+    assert_eq!("routing_key_0", report.config_issues[0].0);
+    assert!(!report.is_ok());
+}
+
+#[tokio::test]
+async fn dry_run_fails_on_state_initialization_error() {
+    let app =
+        App::<()>::try_new(async { Err("database unavailable") }).handler("routing_key_0", handler);
+
+    let result = app.dry_run().await;
+    assert!(matches!(result, Err(Error::StateInitialization(_))));
+
+    let result = App::new(()).dry_run().await;
+    assert!(matches!(result, Err(Error::NoHandlers)));
+}
+
+#[test]
+fn handler_groups_share_configuration() {
+    let app = App::new(()).group(|group| {
+        group
+            .handler("routing_key_0", handler)
+            .with_config(HandlerConfig::new().with_dead_letter_exchange("my_dlx"))
+            .handler("routing_key_1", handler)
+    });
+
+    let topology = app.topology_graph();
+    assert_eq!(2, topology.handlers.len());
+    assert_eq!("routing_key_0", topology.handlers[0].queue);
+    assert_eq!("routing_key_1", topology.handlers[1].queue);
+    // The configuration applies to handlers added before it as well.
+    assert!(topology
+        .handlers
+        .iter()
+        .all(|handler| handler.dead_letter_exchange.as_deref() == Some("my_dlx")));
+}
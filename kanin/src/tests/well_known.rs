@@ -0,0 +1,64 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    error::RequestError,
+    well_known::{to_enum, to_std_duration, to_system_time},
+    HandlerError,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Color {
+    Red,
+}
+
+impl TryFrom<i32> for Color {
+    type Error = ();
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Color::Red),
+            _ => Err(()),
+        }
+    }
+}
+
+#[test]
+fn well_known_types_convert_to_std_types() {
+    let timestamp = prost_types::Timestamp {
+        seconds: 10,
+        nanos: 5,
+    };
+    assert_eq!(
+        SystemTime::UNIX_EPOCH + Duration::new(10, 5),
+        to_system_time(timestamp).unwrap()
+    );
+
+    let duration = prost_types::Duration {
+        seconds: 3,
+        nanos: 0,
+    };
+    assert_eq!(Duration::from_secs(3), to_std_duration(duration).unwrap());
+
+    assert_eq!(Color::Red, to_enum::<Color>(0).unwrap());
+}
+
+#[test]
+fn invalid_well_known_types_are_invalid_requests() {
+    let duration = prost_types::Duration {
+        seconds: -3,
+        nanos: 0,
+    };
+    assert!(matches!(
+        to_std_duration(duration),
+        Err(HandlerError::InvalidRequest(RequestError::InvalidDuration(
+            _
+        )))
+    ));
+
+    assert!(matches!(
+        to_enum::<Color>(7),
+        Err(HandlerError::InvalidRequest(
+            RequestError::UnknownEnumValue(7)
+        ))
+    ));
+}
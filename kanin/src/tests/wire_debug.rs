@@ -0,0 +1,43 @@
+use lapin::{
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+
+use crate::wire_debug::WireDebug;
+
+#[test]
+fn wire_debug_matches_routing_keys() {
+    assert!(WireDebug::all().matches("anything"));
+
+    let wire_debug = WireDebug::for_routing_keys(["routing_key_0"]);
+    assert!(wire_debug.matches("routing_key_0"));
+    assert!(!wire_debug.matches("routing_key_1"));
+}
+
+#[test]
+fn wire_debug_redacts_headers() {
+    let mut headers = FieldTable::default();
+    headers.insert(
+        "Authorization".into(),
+        AMQPValue::LongString("secret".into()),
+    );
+    headers.insert("x-api-key".into(), AMQPValue::LongString("key".into()));
+    headers.insert("req_id".into(), AMQPValue::LongString("187".into()));
+    let properties = BasicProperties::default()
+        .with_headers(headers)
+        .with_app_id("caller".into());
+
+    let redacted = WireDebug::all()
+        .with_redacted_header("X-Api-Key")
+        .redact(&properties);
+    let headers = redacted.headers().as_ref().unwrap().inner();
+
+    let redacted_value = AMQPValue::LongString(WireDebug::REDACTED.into());
+    assert_eq!(Some(&redacted_value), headers.get("Authorization"));
+    assert_eq!(Some(&redacted_value), headers.get("x-api-key"));
+    assert_eq!(
+        Some(&AMQPValue::LongString("187".into())),
+        headers.get("req_id")
+    );
+    assert_eq!(properties.app_id(), redacted.app_id());
+}
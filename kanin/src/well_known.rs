@@ -0,0 +1,40 @@
+//! Conversions of protobuf well-known types and enums into their Rust counterparts.
+//!
+//! Invalid values are reported as [`HandlerError::InvalidRequest`], so the conversions can be used with `?` in handlers
+//! whose responses can be created from a [`HandlerError`] (e.g. via the [`FromError`](crate::FromError) derive macro).
+
+use std::time::{Duration, SystemTime};
+
+use crate::{error::RequestError, HandlerError};
+
+/// Converts a protobuf [`Timestamp`](prost_types::Timestamp) into a [`SystemTime`].
+///
+/// # Errors
+/// Returns [`RequestError::InvalidTimestamp`] if the timestamp is not normalized or is out of the range of [`SystemTime`].
+pub fn to_system_time(timestamp: prost_types::Timestamp) -> Result<SystemTime, HandlerError> {
+    SystemTime::try_from(timestamp)
+        .map_err(|e| HandlerError::InvalidRequest(RequestError::InvalidTimestamp(e)))
+}
+
+/// Converts a protobuf [`Duration`](prost_types::Duration) into a [`std::time::Duration`].
+///
+/// # Errors
+/// Returns [`RequestError::InvalidDuration`] if the duration is negative or out of range.
+pub fn to_std_duration(duration: prost_types::Duration) -> Result<Duration, HandlerError> {
+    Duration::try_from(duration)
+        .map_err(|e| HandlerError::InvalidRequest(RequestError::InvalidDuration(e)))
+}
+
+/// Converts the raw value of a protobuf enum field into the enum, as generated by [`prost`].
+///
+/// Unlike the generated `unwrap_or_default`-style accessors, unknown values are rejected instead of silently falling back to the default variant.
+///
+/// # Errors
+/// Returns [`RequestError::UnknownEnumValue`] if the value is not a known variant of the enum.
+pub fn to_enum<E>(value: i32) -> Result<E, HandlerError>
+where
+    E: TryFrom<i32>,
+{
+    E::try_from(value)
+        .map_err(|_| HandlerError::InvalidRequest(RequestError::UnknownEnumValue(value)))
+}
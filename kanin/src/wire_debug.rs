@@ -0,0 +1,147 @@
+//! Wire debugging: logging the AMQP properties and payloads of messages, e.g. to debug interop issues with producers
+//! in other languages that set properties differently.
+//!
+//! Messages are logged at trace level with the `kanin::wire` target, so they can be enabled separately from other logs,
+//! e.g. with an `EnvFilter` directive like `kanin::wire=trace`. Frames are logged by `lapin` itself at trace level,
+//! which can be enabled along with this via `lapin=trace`.
+
+use std::collections::HashSet;
+
+use lapin::{
+    message::Delivery,
+    types::{AMQPValue, FieldTable},
+    BasicProperties,
+};
+use tracing::trace;
+
+use crate::{control, error::PayloadDiagnostics};
+
+/// Configures which messages are logged by wire debugging, and what is redacted. See [`App::with_wire_debug`](crate::App::with_wire_debug).
+///
+/// The values of the `authorization` and [`control::SIGNATURE_HEADER`] headers are always redacted.
+/// Payloads are not logged unless a sample size is set with [`WireDebug::with_payload_sample`].
+#[derive(Debug, Clone)]
+pub struct WireDebug {
+    /// The routing keys of the handlers whose messages are logged. All handlers if `None`.
+    routing_keys: Option<HashSet<String>>,
+    /// The headers whose values are redacted, in lowercase.
+    redacted_headers: HashSet<String>,
+    /// The number of payload bytes to log.
+    payload_sample: usize,
+}
+
+impl WireDebug {
+    /// The value that redacted header values are replaced with.
+    pub const REDACTED: &'static str = "<redacted>";
+
+    /// Logs the messages of all handlers.
+    pub fn all() -> Self {
+        Self {
+            routing_keys: None,
+            redacted_headers: ["authorization", control::SIGNATURE_HEADER]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            payload_sample: 0,
+        }
+    }
+
+    /// Logs the messages of the handlers on the given routing keys.
+    pub fn for_routing_keys(routing_keys: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            routing_keys: Some(routing_keys.into_iter().map(Into::into).collect()),
+            ..Self::all()
+        }
+    }
+
+    /// Redacts the value of the given header (case-insensitively) in the logged properties.
+    pub fn with_redacted_header(mut self, header: impl Into<String>) -> Self {
+        self.redacted_headers
+            .insert(header.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Logs up to the given number of bytes of each payload, as hex.
+    pub fn with_payload_sample(mut self, bytes: usize) -> Self {
+        self.payload_sample = bytes;
+        self
+    }
+
+    /// Returns true if the messages of the handler on the given routing key are logged.
+    pub(crate) fn matches(&self, routing_key: &str) -> bool {
+        self.routing_keys
+            .as_ref()
+            .map_or(true, |routing_keys| routing_keys.contains(routing_key))
+    }
+
+    /// Returns the given properties with the values of redacted headers replaced.
+    pub(crate) fn redact(&self, properties: &BasicProperties) -> BasicProperties {
+        let headers = match properties.headers() {
+            Some(headers) => headers,
+            None => return properties.clone(),
+        };
+
+        let mut redacted = FieldTable::default();
+        for (key, value) in headers.inner() {
+            let value = if self
+                .redacted_headers
+                .contains(&key.as_str().to_ascii_lowercase())
+            {
+                AMQPValue::LongString(Self::REDACTED.into())
+            } else {
+                value.clone()
+            };
+            redacted.insert(key.clone(), value);
+        }
+
+        properties.clone().with_headers(redacted)
+    }
+
+    /// Describes the given payload according to the payload sample size.
+    fn describe_payload(&self, payload: &[u8], properties: &BasicProperties) -> PayloadDiagnostics {
+        let content_type = properties
+            .content_type()
+            .as_ref()
+            .map(|content_type| content_type.to_string());
+        PayloadDiagnostics::new(payload, self.payload_sample, content_type)
+    }
+
+    /// Logs a delivery received by the handler on the given routing key, if it matches.
+    pub(crate) fn log_delivery(&self, routing_key: &str, delivery: &Delivery) {
+        if !self.matches(routing_key) {
+            return;
+        }
+
+        trace!(
+            target: "kanin::wire",
+            "Received delivery on handler {routing_key:?}: exchange={:?}, routing_key={:?}, delivery_tag={}, redelivered={}, properties={:?}, {}",
+            delivery.exchange.as_str(),
+            delivery.routing_key.as_str(),
+            delivery.delivery_tag,
+            delivery.redelivered,
+            self.redact(&delivery.properties),
+            self.describe_payload(&delivery.data, &delivery.properties),
+        );
+    }
+
+    /// Logs a message published by the handler on the given routing key, if it matches.
+    pub(crate) fn log_publish(
+        &self,
+        routing_key: &str,
+        exchange: &str,
+        publish_routing_key: &str,
+        properties: &BasicProperties,
+        payload: &[u8],
+    ) {
+        if !self.matches(routing_key) {
+            return;
+        }
+
+        trace!(
+            target: "kanin::wire",
+            "Publishing from handler {routing_key:?}: exchange={exchange:?}, routing_key={publish_routing_key:?}, properties={:?}, {}",
+            self.redact(properties),
+            self.describe_payload(payload, properties),
+        );
+    }
+}
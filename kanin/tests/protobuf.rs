@@ -1,4 +1,7 @@
-use kanin::{extract::Msg, App};
+use kanin::{
+    extract::{Msg, ParallelMsg},
+    App,
+};
 
 use self::generated::{
     echo_response::{Result, Success},
@@ -13,14 +16,187 @@ async fn proto_handler(Msg(request): Msg<EchoRequest>) -> EchoResponse {
     }
 }
 
+async fn parallel_proto_handler(
+    ParallelMsg(request): ParallelMsg<EchoRequest, 4096>,
+) -> EchoResponse {
+    EchoResponse {
+        result: Some(Result::Success(Success {
+            value: request.value,
+        })),
+    }
+}
+
 #[tokio::test]
 async fn it_compiles() {
     let _ignore = App::new(())
         .handler("routing_key", proto_handler)
+        .handler("parallel_routing_key", parallel_proto_handler)
         .run("amqp_addr")
         .await;
 }
 
+/// Error details with a code, for testing the `#[from_error(with = "...")]` attribute.
+#[derive(Debug, PartialEq)]
+struct ErrorDetails {
+    code: u32,
+    message: String,
+}
+
+fn error_details<E: std::error::Error + 'static>(error: &E) -> Option<ErrorDetails> {
+    Some(ErrorDetails {
+        code: 42,
+        message: kanin::error::redact(error),
+    })
+}
+
+#[derive(kanin_derive::FromError)]
+struct DetailedInvalidRequest {
+    #[from_error(with = "error_details")]
+    error: Option<ErrorDetails>,
+}
+
+#[derive(kanin_derive::FromError)]
+struct DetailedInternalError {
+    source: String,
+    #[from_error(with = "error_details")]
+    error: Option<ErrorDetails>,
+}
+
+#[test]
+fn from_error_with_converter() {
+    use kanin::error::{FromError, RequestError, ServerError};
+
+    let invalid = DetailedInvalidRequest::from_error(RequestError::UnsupportedContentType(
+        "text/plain".into(),
+    ));
+    let details = invalid.error.expect("converter should return details");
+    assert_eq!(details.code, 42);
+    assert!(details.message.contains("text/plain"));
+
+    let internal = DetailedInternalError::from_error(ServerError::AckerAlreadyTaken);
+    assert_eq!(internal.source, env!("CARGO_PKG_NAME"));
+    assert_eq!(
+        internal
+            .error
+            .expect("converter should return details")
+            .code,
+        42
+    );
+}
+
+/// A domain error whose variants carry data other than a single protobuf message.
+#[derive(Debug, kanin_derive::FromError)]
+enum DomainError {
+    InvalidRequest(Box<generated::InvalidRequest>),
+    InternalError {
+        source: String,
+        error: String,
+        retryable: bool,
+    },
+    #[allow(dead_code)]
+    NotFound,
+}
+
+/// A domain error with tuple variants, converted with converters.
+#[derive(Debug, kanin_derive::FromError)]
+enum TupleError {
+    InvalidRequest(#[from_error(with = "error_details")] Option<ErrorDetails>),
+    InternalError(u16, #[from_error(with = "kanin::error::redact")] String),
+}
+
+#[test]
+fn from_error_for_enums_with_data_carrying_variants() {
+    use kanin::{
+        error::{FromError, RequestError, ServerError},
+        HandlerError,
+    };
+
+    let invalid =
+        DomainError::from_error(HandlerError::InvalidRequest(RequestError::DefaultMessage));
+    match invalid {
+        DomainError::InvalidRequest(invalid) => assert!(invalid.error.contains("default fields")),
+        other => panic!("unexpected error {other:?}"),
+    }
+
+    let internal =
+        DomainError::from_error(HandlerError::InternalError(ServerError::AckerAlreadyTaken));
+    match internal {
+        DomainError::InternalError {
+            source,
+            error,
+            retryable,
+        } => {
+            assert_eq!(source, env!("CARGO_PKG_NAME"));
+            assert!(!error.is_empty());
+            assert!(!retryable);
+        }
+        other => panic!("unexpected error {other:?}"),
+    }
+
+    match TupleError::from_error(HandlerError::InvalidRequest(RequestError::DefaultMessage)) {
+        TupleError::InvalidRequest(details) => {
+            assert_eq!(42, details.expect("converter should return details").code)
+        }
+        other => panic!("unexpected error {other:?}"),
+    }
+    match TupleError::from_error(HandlerError::InternalError(ServerError::AckerAlreadyTaken)) {
+        TupleError::InternalError(code, error) => {
+            assert_eq!(0, code);
+            assert!(!error.is_empty());
+        }
+        other => panic!("unexpected error {other:?}"),
+    }
+}
+
+/// A domain error without an InternalError variant, as supported before kanin reported internal errors.
+#[derive(Debug, kanin_derive::FromError)]
+enum InvalidOnlyError {
+    InvalidRequest(generated::InvalidRequest),
+}
+
+#[test]
+fn from_error_for_enums_without_internal_error_variant() {
+    use kanin::{
+        error::{FromError, ServerError},
+        HandlerError,
+    };
+
+    let InvalidOnlyError::InvalidRequest(invalid) =
+        InvalidOnlyError::from_error(HandlerError::InternalError(ServerError::AckerAlreadyTaken));
+    assert!(invalid.error.contains("acker"), "{}", invalid.error);
+}
+
+#[derive(Debug, kanin::Respond)]
+#[respond(with = "encode_greeting")]
+struct Greeting<T: std::fmt::Display + std::fmt::Debug + Send>(T);
+
+fn encode_greeting<T: std::fmt::Display + std::fmt::Debug + Send>(
+    greeting: &Greeting<T>,
+) -> Vec<u8> {
+    format!("Hello, {}!", greeting.0).into_bytes()
+}
+
+#[test]
+fn respond_derive_with_encoder() {
+    use kanin::Respond;
+
+    assert_eq!(b"Hello, world!".to_vec(), Greeting("world").respond());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn respond_derive_json() {
+    use kanin::Respond;
+
+    #[derive(Debug, serde::Serialize, kanin::Respond)]
+    #[respond(json)]
+    struct Count {
+        count: u32,
+    }
+
+    assert_eq!(br#"{"count":187}"#.to_vec(), Count { count: 187 }.respond());
+}
+
 #[allow(clippy::derive_partial_eq_without_eq)]
 mod generated {
     //! Normally this would be generated by prost but we'll just write it directly for the purposes of this test.
@@ -29,7 +205,7 @@ mod generated {
 
     /// An internal error. This is used for any error that can't be handled in any other way.
     /// Consider it a last resort when no other more specific error can be returned.
-    #[derive(Clone, PartialEq, ::prost::Message)]
+    #[derive(FromError, Clone, PartialEq, ::prost::Message)]
     pub struct InternalError {
         /// The source is an a1pp ID that specifies the service in which the error originated.
         #[prost(string, tag = "1")]
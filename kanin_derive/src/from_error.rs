@@ -1,15 +1,23 @@
 use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use syn::{punctuated::Punctuated, token::Comma, Field, Ident, Variant};
+use syn::{
+    punctuated::Punctuated, token::Comma, Field, Fields, FieldsNamed, FieldsUnnamed, Ident, LitStr,
+    Path, Type, TypePath, Variant,
+};
 
 /// Derives the FromError trait for a struct with named fields.
 ///
-/// If the struct is called "InvalidRequest", it will be handled in a special way.
+/// If the struct is called "InvalidRequest" or "InternalError", it will be handled in a special way.
 pub(crate) fn derive_named(name: Ident, fields: Punctuated<Field, Comma>) -> TokenStream {
     let name_s = name.to_string();
 
     if name_s.contains("InvalidRequest") {
-        return derive_invalid_request(name);
+        return derive_invalid_request(name, error_conversion(&fields));
+    }
+
+    if name_s.contains("InternalError") {
+        return derive_internal_error(name, error_conversion(&fields));
     }
 
     let num_fields = fields.len();
@@ -28,13 +36,70 @@ pub(crate) fn derive_named(name: Ident, fields: Punctuated<Field, Comma>) -> Tok
     derive_named_newtype(name, field_name)
 }
 
+/// Returns the expression converting the kanin error into the value of the `error` field.
+///
+/// By default, the error is formatted using `kanin::error::redact`. If the `error` field is marked with
+/// `#[from_error(with = "path::to::converter")]`, the converter is called with a reference to the error instead.
+fn error_conversion(fields: &Punctuated<Field, Comma>) -> TokenStream2 {
+    let converter = fields
+        .iter()
+        .find(|field| field.ident.as_ref().map_or(false, |ident| ident == "error"))
+        .and_then(converter);
+
+    match converter {
+        Some(converter) => quote! { #converter(&error) },
+        None => quote! { ::kanin::error::redact(&error) },
+    }
+}
+
+/// Returns the converter given to the field with `#[from_error(with = "path::to::converter")]`, if any.
+fn converter(field: &Field) -> Option<Path> {
+    let mut converter = None;
+
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("from_error"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("with") {
+                let path: LitStr = meta.value()?.parse()?;
+                converter = Some(path.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported from_error attribute, expected `with`"))
+            }
+        })
+        .expect("could not parse from_error attribute");
+    }
+
+    converter
+}
+
 /// Derives the FromError for the InvalidRequest struct. It will use RequestError in kanin for this instead of the more general error type.
-fn derive_invalid_request(name: Ident) -> TokenStream {
+fn derive_invalid_request(name: Ident, error_conversion: TokenStream2) -> TokenStream {
     quote! {
         impl ::kanin::error::FromError<::kanin::error::RequestError> for #name {
             fn from_error(error: ::kanin::error::RequestError) -> Self {
                 #name {
-                    error: format!("{:#}", error)
+                    error: #error_conversion
+                }
+            }
+        }
+    }
+    .into()
+}
+
+/// Derives the FromError for the InternalError struct. It will use ServerError in kanin for this instead of the more general error type.
+///
+/// The source is set to the name of the package deriving the trait, i.e. the service in which the error originated.
+fn derive_internal_error(name: Ident, error_conversion: TokenStream2) -> TokenStream {
+    quote! {
+        impl ::kanin::error::FromError<::kanin::error::ServerError> for #name {
+            fn from_error(error: ::kanin::error::ServerError) -> Self {
+                #name {
+                    source: ::std::env!("CARGO_PKG_NAME").to_string(),
+                    error: #error_conversion
                 }
             }
         }
@@ -76,24 +141,109 @@ fn derive_named_newtype(name: Ident, field_name: &Ident) -> TokenStream {
     .into()
 }
 
-/// Derives the FromError trait for an enum with InvalidRequest variants.
+/// Derives the FromError trait for an enum with an InvalidRequest variant and optionally an InternalError variant.
+///
+/// Enums without an InternalError variant report internal errors through their InvalidRequest variant,
+/// wrapped in `RequestError::Internal`.
 pub(crate) fn derive_enum(name: Ident, variants: Punctuated<Variant, Comma>) -> TokenStream {
-    let invalid_request_name = &variants
+    let invalid_request = variants
         .iter()
         .find(|v| v.ident.to_string().contains("InvalidRequest"))
-        .expect("enum missing a variant containing \"InvalidRequest\"")
-        .ident;
+        .expect("enum missing a variant containing \"InvalidRequest\"");
+
+    let internal_error = variants
+        .iter()
+        .find(|v| v.ident.to_string().contains("InternalError"));
+
+    let invalid_request = variant_construction(invalid_request, false);
+    let internal_error = match internal_error {
+        Some(internal_error) => variant_construction(internal_error, true),
+        None => quote! {{
+            let error = ::kanin::error::RequestError::Internal(error);
+            #invalid_request
+        }},
+    };
 
     quote! {
         impl ::kanin::error::FromError<::kanin::HandlerError> for #name {
             fn from_error(error: ::kanin::HandlerError) -> Self {
                 match error {
-                    ::kanin::HandlerError::InvalidRequest(e) => {
-                        Self::#invalid_request_name(::kanin::error::FromError::from_error(e))
-                    },
+                    ::kanin::HandlerError::InvalidRequest(error) => #invalid_request,
+                    ::kanin::HandlerError::InternalError(error) => #internal_error,
                 }
             }
         }
     }
     .into()
 }
+
+/// Returns the expression constructing the given variant from the kanin `error`, depending on the data the variant carries:
+///
+/// - A variant with a single unnamed field is constructed from the field's implementation of FromError,
+///   or from the converter if the field is marked with `#[from_error(with = "path::to::converter")]`. Boxed fields are boxed after conversion.
+/// - A variant with named fields is constructed like the InvalidRequest and InternalError structs: the `error` field is converted
+///   (see [`error_conversion`]), the `source` field is set to the package name for internal errors, and other fields are defaulted.
+/// - A variant with several unnamed fields is constructed by calling the converters of the fields marked with `#[from_error(with = "...")]`.
+///   Other fields are defaulted.
+/// - A unit variant is constructed as is, discarding the error.
+fn variant_construction(variant: &Variant, internal_error: bool) -> TokenStream2 {
+    let variant_name = &variant.ident;
+
+    match &variant.fields {
+        Fields::Unit => quote! { Self::#variant_name },
+        Fields::Unnamed(FieldsUnnamed { unnamed, .. }) if unnamed.len() == 1 => {
+            let field = unnamed
+                .first()
+                .expect("we just checked that there is exactly 1 field");
+            let value = match converter(field) {
+                Some(converter) => quote! { #converter(&error) },
+                None if is_box(&field.ty) => {
+                    quote! { ::std::boxed::Box::new(::kanin::error::FromError::from_error(error)) }
+                }
+                None => quote! { ::kanin::error::FromError::from_error(error) },
+            };
+            quote! { Self::#variant_name(#value) }
+        }
+        Fields::Unnamed(FieldsUnnamed { unnamed, .. }) => {
+            let values: Vec<_> = unnamed
+                .iter()
+                .map(|field| match converter(field) {
+                    Some(converter) => quote! { #converter(&error) },
+                    None => quote! { ::std::default::Default::default() },
+                })
+                .collect();
+            if unnamed.iter().all(|field| converter(field).is_none()) {
+                panic!("variants with several unnamed fields must mark the fields to convert the error into with #[from_error(with = \"...\")]");
+            }
+            quote! { Self::#variant_name(#(#values),*) }
+        }
+        Fields::Named(FieldsNamed { named, .. }) => {
+            let error_conversion = error_conversion(named);
+            let values = named.iter().map(|field| {
+                let field_name = field
+                    .ident
+                    .as_ref()
+                    .expect("field must be named since we matched on named fields");
+                if field_name == "error" {
+                    quote! { #field_name: #error_conversion }
+                } else if field_name == "source" && internal_error {
+                    quote! { #field_name: ::std::env!("CARGO_PKG_NAME").to_string() }
+                } else {
+                    quote! { #field_name: ::std::default::Default::default() }
+                }
+            });
+            quote! { Self::#variant_name { #(#values),* } }
+        }
+    }
+}
+
+/// Returns true if the given type is a `Box`.
+fn is_box(ty: &Type) -> bool {
+    match ty {
+        Type::Path(TypePath { qself: None, path }) => path
+            .segments
+            .last()
+            .map_or(false, |segment| segment.ident == "Box"),
+        _ => false,
+    }
+}
@@ -1,11 +1,19 @@
 mod from_error;
+mod respond;
 mod state;
 
 use proc_macro::TokenStream;
 use syn::{DataEnum, DeriveInput, FieldsNamed, FieldsUnnamed};
 
 /// Derives `From<&S>` for all the fields in the `S` struct.
-#[proc_macro_derive(AppState)]
+///
+/// A field whose type also derives `AppState` can be marked with `#[app_state(flatten)]`.
+/// Instead of deriving `From<&S>` for the field type itself, `From<&S>` is then derived for all the fields of the inner type (transitively).
+/// This allows composing state structs across modules.
+///
+/// The flattened field's type must be referred to by a path through which its module can be reached (such as `crate::db::DbState`),
+/// as the derive uses a hidden macro generated next to the inner type.
+#[proc_macro_derive(AppState, attributes(app_state))]
 pub fn derive_state_from(tokens: TokenStream) -> TokenStream {
     // Parse the input type.
     let abstract_syntax_tree: DeriveInput =
@@ -36,6 +44,22 @@ pub fn derive_state_from(tokens: TokenStream) -> TokenStream {
 /// _except_ if the struct's name contains InternalError or InvalidRequest, in which case FromError will be implemented specially,
 /// by assuming the structure of the type to match the expected structure.
 ///
+/// If the type is an enum, it must have a variant containing InvalidRequest, and may have a variant containing InternalError.
+/// If the InternalError variant is absent, internal errors are given to the InvalidRequest variant as `RequestError::Internal`.
+/// If these variants have a single unnamed field, its type must implement FromError for kanin's `RequestError` and `ServerError` respectively
+/// (boxed types are supported as well). Variants carrying other data are constructed as follows:
+/// - Variants with named fields are constructed like the InvalidRequest and InternalError structs described below.
+///   Any fields other than `source` and `error` are set to their default value.
+/// - Unnamed fields marked with `#[from_error(with = "path::to::converter")]` are constructed by the converter (see below).
+///   In variants with several unnamed fields, at least one field must be marked, and unmarked fields are set to their default value.
+/// - Unit variants are constructed as is, discarding the error.
+///
+/// The error details are formatted using `kanin::error::redact`, which respects the app's error redaction.
+///
+/// If the `error` field is not a `String` (e.g. a message with an error code), mark it with `#[from_error(with = "path::to::converter")]`.
+/// The converter is then called with a reference to kanin's `RequestError` or `ServerError` (for InvalidRequest and InternalError respectively)
+/// and must return the type of the field. A generic converter taking `&E where E: std::error::Error + 'static` works for both.
+///
 /// The expected structure is:
 /// ```
 /// struct InvalidRequest {
@@ -48,7 +72,7 @@ pub fn derive_state_from(tokens: TokenStream) -> TokenStream {
 ///     error: String,
 /// }
 /// ```
-#[proc_macro_derive(FromError)]
+#[proc_macro_derive(FromError, attributes(from_error))]
 pub fn from_error_derive(tokens: TokenStream) -> TokenStream {
     // Parse the input type.
     let abstract_syntax_tree: DeriveInput =
@@ -67,3 +91,29 @@ pub fn from_error_derive(tokens: TokenStream) -> TokenStream {
         _ => panic!("only structs and enums are supported"),
     }
 }
+
+/// Derives the `kanin::Respond` trait for a type that is not a protobuf message.
+///
+/// The encoding of the response must be given with an attribute on the type:
+/// - `#[respond(json)]` serializes the type as JSON. The type must implement `serde::Serialize` and kanin's `serde` feature must be enabled. Replies have the `application/json` content type.
+/// - `#[respond(with = "path::to::encoder")]` encodes the type with the given function, which takes a reference to the type and returns a `Vec<u8>`.
+///
+/// As with any response, the type must also implement `Debug` and `Send`.
+///
+/// ```ignore
+/// #[derive(Debug, kanin::Respond)]
+/// #[respond(with = "encode")]
+/// struct Greeting(String);
+///
+/// fn encode(greeting: &Greeting) -> Vec<u8> {
+///     greeting.0.as_bytes().to_vec()
+/// }
+/// ```
+#[proc_macro_derive(Respond, attributes(respond))]
+pub fn respond_derive(tokens: TokenStream) -> TokenStream {
+    // Parse the input type.
+    let abstract_syntax_tree: DeriveInput =
+        syn::parse(tokens).expect("could not parse derive macro input");
+
+    respond::derive(abstract_syntax_tree)
+}
@@ -0,0 +1,65 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Attribute, DeriveInput, LitStr, Path};
+
+/// How a type is encoded into the payload of its response.
+enum Encoding {
+    /// The type is serialized as JSON via serde.
+    Json,
+    /// The type is encoded with the given function, taking a reference to the type and returning the bytes.
+    With(Path),
+}
+
+pub(crate) fn derive(input: DeriveInput) -> TokenStream {
+    let name = input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let (encode, content_type): (TokenStream2, TokenStream2) = match encoding(&input.attrs) {
+        Encoding::Json => (
+            quote! { ::kanin::response::encode_json(&self) },
+            quote! {
+                fn content_type(&self) -> &str {
+                    ::kanin::response::JSON_CONTENT_TYPE
+                }
+            },
+        ),
+        Encoding::With(encoder) => (quote! { #encoder(&self) }, TokenStream2::new()),
+    };
+
+    quote! {
+        impl #impl_generics ::kanin::Respond for #name #type_generics #where_clause {
+            fn respond(self) -> ::std::vec::Vec<u8> {
+                #encode
+            }
+
+            #content_type
+        }
+    }
+    .into()
+}
+
+/// Reads the encoding from the `#[respond(...)]` attribute.
+fn encoding(attrs: &[Attribute]) -> Encoding {
+    let mut encoding = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("respond")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("json") {
+                encoding = Some(Encoding::Json);
+                Ok(())
+            } else if meta.path.is_ident("with") {
+                let path: LitStr = meta.value()?.parse()?;
+                encoding = Some(Encoding::With(path.parse()?));
+                Ok(())
+            } else {
+                Err(meta.error("unsupported respond attribute, expected `json` or `with`"))
+            }
+        })
+        .expect("could not parse respond attribute");
+    }
+
+    encoding.expect(
+        r#"the encoding must be given with either #[respond(json)] or #[respond(with = "path::to::encoder")]"#,
+    )
+}
@@ -1,45 +1,134 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{punctuated::Punctuated, token::Comma, Field, Ident};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, ToTokens};
+use syn::{punctuated::Punctuated, token::Comma, Field, Ident, Type};
 
 pub(crate) fn derive_named(state_type: Ident, fields: Punctuated<Field, Comma>) -> TokenStream {
-    let from_impls = fields.into_iter().map(|field| {
-        let field_type = field.ty;
-        let field_ident = field.ident;
+    let fields = fields.into_iter().map(|field| {
+        let field_ident = field
+            .ident
+            .as_ref()
+            .expect("field must be named since we matched on named struct")
+            .to_token_stream();
+        (field_ident, field)
+    });
 
-        quote! {
-            impl From<&#state_type> for #field_type {
-                fn from(value: &#state_type) -> Self {
-                    value.#field_ident.clone()
-                }
-            }
+    derive(state_type, fields)
+}
 
-        }
+pub(crate) fn derive_unnamed(state_type: Ident, fields: Punctuated<Field, Comma>) -> TokenStream {
+    let fields = fields.into_iter().enumerate().map(|(field_idx, field)| {
+        let field_idx = syn::Index::from(field_idx).to_token_stream();
+        (field_idx, field)
     });
 
-    quote! {
-        #(#from_impls)*
-    }
-    .into()
+    derive(state_type, fields)
 }
 
-pub(crate) fn derive_unnamed(state_type: Ident, fields: Punctuated<Field, Comma>) -> TokenStream {
-    let from_impls = fields.into_iter().enumerate().map(|(field_idx, field)| {
+/// Derives `From<&S>` for the given fields, where each field is given along with the tokens used to access it.
+///
+/// Besides the `From` implementations, this also generates a hidden macro that allows other state types to flatten this type
+/// into themselves via `#[app_state(flatten)]`. The macro generates the same `From` implementations for the outer type,
+/// accessing the fields through the given field path.
+fn derive(state_type: Ident, fields: impl Iterator<Item = (TokenStream2, Field)>) -> TokenStream {
+    let mut from_impls = Vec::new();
+    let mut flatten_impls = Vec::new();
+
+    for (field_access, field) in fields {
         let field_type = field.ty;
-        let field_idx = syn::Index::from(field_idx);
 
-        quote! {
-            impl From<&#state_type> for #field_type {
-                fn from(value: &#state_type) -> Self {
-                    value.#field_idx.clone()
+        if is_flatten(&field.attrs) {
+            let flatten_macro = flatten_macro_path(&field_type);
+
+            from_impls.push(quote! {
+                #flatten_macro!(#state_type, #field_access);
+            });
+            flatten_impls.push(quote! {
+                #flatten_macro!($outer, $($field)+ . #field_access);
+            });
+        } else {
+            from_impls.push(quote! {
+                impl From<&#state_type> for #field_type {
+                    fn from(value: &#state_type) -> Self {
+                        value.#field_access.clone()
+                    }
+                }
+
+            });
+            flatten_impls.push(quote! {
+                impl From<&$outer> for #field_type {
+                    fn from(value: &$outer) -> Self {
+                        value.$($field)+ . #field_access.clone()
+                    }
                 }
-            }
 
+            });
         }
-    });
+    }
+
+    let flatten_macro = flatten_macro_name(&state_type);
 
     quote! {
         #(#from_impls)*
+
+        #[doc(hidden)]
+        #[allow(unused_macros)]
+        macro_rules! #flatten_macro {
+            ($outer:ty, $($field:tt)+) => {
+                #(#flatten_impls)*
+            };
+        }
+
+        #[doc(hidden)]
+        #[allow(unused_imports)]
+        pub(crate) use #flatten_macro;
     }
     .into()
 }
+
+/// Returns true if the field is marked with `#[app_state(flatten)]`.
+fn is_flatten(attrs: &[syn::Attribute]) -> bool {
+    let mut flatten = false;
+
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("app_state"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("flatten") {
+                flatten = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported app_state attribute, expected `flatten`"))
+            }
+        })
+        .expect("could not parse app_state attribute");
+    }
+
+    flatten
+}
+
+/// The name of the hidden macro generated for flattening the given state type into other state types.
+fn flatten_macro_name(state_type: &Ident) -> Ident {
+    format_ident!("__kanin_app_state_{}", state_type)
+}
+
+/// The path to the hidden flattening macro of the given field type.
+///
+/// The macro lives next to the type, so the path is the path of the type with the last segment replaced.
+fn flatten_macro_path(field_type: &Type) -> syn::Path {
+    let type_path = match field_type {
+        Type::Path(type_path) => type_path,
+        _ => panic!("#[app_state(flatten)] is only supported on fields whose type is a path to a struct deriving AppState"),
+    };
+
+    let mut path = type_path.path.clone();
+    let last = path
+        .segments
+        .last_mut()
+        .expect("type paths have at least one segment");
+    last.ident = flatten_macro_name(&last.ident);
+    last.arguments = syn::PathArguments::None;
+
+    path
+}
@@ -5,6 +5,7 @@ fn main() {
         // as a return type from a handler. So we just list them here.
         // These are paths into the .proto file, not the generated Rust code.
         .type_attribute("InvalidRequest", "#[derive(kanin::FromError)]")
+        .type_attribute("InternalError", "#[derive(kanin::FromError)]")
         .type_attribute("EchoResponse", "#[derive(kanin::FromError)]")
         .type_attribute("EchoResponse.response", "#[derive(kanin::FromError)]")
         .compile_protos(&["src/protobuf/echo.proto"], &["src"])